@@ -0,0 +1,299 @@
+use winit::dpi::LogicalSize;
+use winit::event::{Event, WindowEvent};
+use winit::event_loop::EventLoop;
+use winit::window::WindowBuilder;
+use anyhow::Result;
+use nalgebra_glm as glm;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use rustbbbb::{Renderer, ColorSpace, NodeId, Scene, Transform, SceneFile, LoadedScene, Input, InputMap, Exposure, PostChain};
+use rustbbbb::camera::{Camera, CameraController, CameraInput, OrbitController, Projection};
+use rustbbbb::config::{Config, USAGE};
+use rustbbbb::material::{Material, MaterialTextures};
+
+
+/// Copies of the model that `load_models` loads, the arrow keys show a power of two of them.
+const MAX_MODELS: usize = 4096;
+
+
+fn main() -> Result<()> {
+    pretty_env_logger::init();
+
+    let config = match Config::from_args(std::env::args().skip(1))? {
+        Some(config) => config,
+        None => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+    };
+
+    if let Some(output) = config.headless_output.clone() {
+        return render_headless(config, &output);
+    }
+
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_title("rustbbbb")
+        .with_inner_size(LogicalSize::new(config.renderer.width, config.renderer.height))
+        .build(&event_loop)?;
+
+
+    let mut destroying = false;
+    let mut renderer = unsafe { Renderer::new(&window, config.renderer.clone()) }?;
+    let mut content = unsafe { load_content(&mut renderer, &config) }?;
+    unsafe { load_post_chain(&mut renderer, &config) }?;
+    unsafe { load_environment(&mut renderer, &config) }?;
+    let mut input = Input::new(match &config.input_path {
+        Some(path) => InputMap::load(path)?,
+        None => InputMap::default()
+    });
+    let mut controller = CameraController::Orbit(OrbitController::from_camera(renderer.camera()));
+    let mut models = 1;
+    let start = Instant::now();
+    let mut last_frame = Instant::now();
+    let mut minimized = false;
+
+    event_loop.run(move |event, _, control_flow| {
+        control_flow.set_poll();
+
+        input.handle_event(&event);
+
+        match event {
+            Event::MainEventsCleared if !destroying && !minimized => {
+                let now = Instant::now();
+
+                // Right and left double and halve the copies, they are drawn instanced.
+                if input.pressed("more_models") && models < MAX_MODELS {
+                    models *= 2;
+                }
+
+                if input.pressed("fewer_models") && models > 1 {
+                    models /= 2;
+                }
+
+                if input.pressed("save_scene") {
+                    content.save(&renderer);
+                }
+
+                // F6 reloads the post chain file, so it can be tuned while the example runs.
+                if input.pressed("reload_post_chain") {
+                    if let Err(e) = unsafe { load_post_chain(&mut renderer, &config) } {
+                        eprintln!("Couldn't reload the post chain: {}", e);
+                    }
+                }
+
+                // Tab switches between orbiting (left drag rotates, right or middle drag pans, wheel zooms)
+                // and flying (WASD moves, Space and Shift go up and down, right drag looks around).
+                if input.pressed("toggle_camera") {
+                    controller.toggle(renderer.camera());
+                }
+
+                let mut camera = *renderer.camera();
+
+                // P cycles through the projections.
+                if input.pressed("cycle_projection") {
+                    camera.projection = next_projection(&camera);
+                }
+
+                // T cycles through the tone mapping curves, X toggles the automatic exposure.
+                let mut tone_mapping = renderer.tone_mapping();
+
+                if input.pressed("cycle_tone_mapping") {
+                    tone_mapping.operator = tone_mapping.operator.next();
+                }
+
+                if input.pressed("toggle_auto_exposure") {
+                    tone_mapping.exposure = match tone_mapping.exposure {
+                        Exposure::Manual { .. } => Exposure::automatic(),
+                        Exposure::Automatic { .. } => Exposure::default()
+                    };
+                }
+
+                renderer.set_tone_mapping(tone_mapping);
+
+                controller.update(&mut camera, &CameraInput::from_input(&input, &controller), (now - last_frame).as_secs_f32());
+                renderer.set_camera(camera);
+
+                input.end_frame();
+                last_frame = now;
+
+                content.submit(&mut renderer, models, start.elapsed().as_secs_f32());
+                unsafe { renderer.present(&window) }.unwrap();
+            },
+            
+            Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => {
+                destroying = true;
+                control_flow.set_exit();
+                unsafe { renderer.destroy(); };
+            },
+
+            Event::WindowEvent { event: WindowEvent::Resized(size), .. } => {
+                if size.height == 0 || size.width == 0 {
+                    minimized = true;
+                } else {
+                    minimized = false;
+                }
+            },
+
+            _ => {}
+        }
+    });
+
+}
+
+
+/// Perspective, then reverse-Z, then orthographic, keeping the field of view and the size of the target on screen.
+fn next_projection(camera: &Camera) -> Projection {
+    let distance = glm::distance(&camera.position, &camera.target);
+
+    match camera.projection {
+        Projection::Perspective { fov_y, near, .. } => Projection::InfiniteReverseZ { fov_y, near },
+        Projection::InfiniteReverseZ { fov_y, .. } => Projection::Orthographic { height: 2.0 * distance * (fov_y / 2.0).tan(), near: -1000.0, far: 1000.0 },
+        Projection::Orthographic { height, .. } => Projection::Perspective { fov_y: 2.0 * (height / 2.0 / distance).atan(), near: 0.1, far: 10.0 }
+    }
+}
+
+
+/// What the example draws: a scene file, or copies of a model in a grid.
+enum Content {
+    SceneFile(LoadedScene, PathBuf),
+    Models(Scene, Vec<NodeId>)
+}
+
+
+impl Content {
+    fn submit(&mut self, renderer: &mut Renderer, models: usize, time: f32) {
+        match self {
+            Content::SceneFile(loaded, _) => renderer.submit_scene(&mut loaded.scene),
+            Content::Models(scene, nodes) => {
+                animate_models(scene, nodes, models, time);
+                renderer.submit_scene(scene);
+            }
+        }
+    }
+
+    /// Writes a scene file back with the current camera and lights, models can't be saved.
+    fn save(&self, renderer: &Renderer) {
+        if let Content::SceneFile(loaded, path) = self {
+            if let Err(e) = loaded.save(renderer, path) {
+                eprintln!("Couldn't save the scene: {}", e);
+            }
+        }
+    }
+}
+
+
+unsafe fn load_post_chain(renderer: &mut Renderer, config: &Config) -> Result<()> {
+    match &config.post_path {
+        Some(path) => renderer.set_post_chain(PostChain::load(path)?),
+        None => Ok(())
+    }
+}
+
+
+unsafe fn load_environment(renderer: &mut Renderer, config: &Config) -> Result<()> {
+    match &config.environment_path {
+        Some(path) => renderer.load_environment(path),
+        None => Ok(())
+    }
+}
+
+
+unsafe fn load_content(renderer: &mut Renderer, config: &Config) -> Result<Content> {
+    match &config.scene_path {
+        Some(path) => {
+            let file = SceneFile::load(path)?;
+            let loaded = file.instantiate(renderer, path.parent().unwrap_or_else(|| Path::new("")))?;
+            Ok(Content::SceneFile(loaded, path.clone()))
+        },
+        None => {
+            let (scene, nodes) = load_models(renderer, config)?;
+            Ok(Content::Models(scene, nodes))
+        }
+    }
+}
+
+
+/// Loads `MAX_MODELS` copies of the model of the command line, the `--texture` is used for the parts without an MTL material.
+/// glTF models keep their node hierarchy, every copy is a duplicate of it that shares the meshes.
+unsafe fn load_models(renderer: &mut Renderer, config: &Config) -> Result<(Scene, Vec<NodeId>)> {
+    let mut scene = Scene::new();
+    let grid = scene.add_node("grid", None);
+
+    let model = match config.model_path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+        Some("gltf") | Some("glb") => renderer.load_gltf(&config.model_path, &mut scene, Some(grid))?,
+        _ => {
+            let default_material = match &config.texture_path {
+                Some(path) => {
+                    let texture = renderer.load_texture(path, ColorSpace::Srgb)?;
+                    Some(renderer.create_material(Material::default(), MaterialTextures::base_color(texture))?)
+                },
+                None => None
+            };
+
+            let model = scene.add_node("model", Some(grid));
+            scene.node_mut(model).mesh = Some(renderer.load_mesh(&config.model_path, default_material)?);
+            model
+        }
+    };
+
+    let mut nodes = vec![scene.add_node("model 0", Some(grid))];
+    scene.set_parent(model, Some(nodes[0]))?;
+
+    for i in 1..MAX_MODELS {
+        let copy = scene.duplicate(nodes[0], Some(grid));
+        scene.node_mut(copy).name = format!("model {}", i);
+        nodes.push(copy);
+    }
+
+    for (i, node) in nodes.iter().enumerate() {
+        set_opacity(&mut scene, *node, ((i % 4) + 1) as f32 * 0.25);
+    }
+
+    return Ok((scene, nodes));
+}
+
+
+/// Opacity is per node, so it has to be set on every node of a copy.
+fn set_opacity(scene: &mut Scene, node: NodeId, opacity: f32) {
+    scene.node_mut(node).opacity = opacity;
+
+    for child in scene.node(node).children().to_vec() {
+        set_opacity(scene, child, opacity);
+    }
+}
+
+
+/// Shows the first `models` copies of the model in a square grid on the ground, spinning around the Z axis.
+fn animate_models(scene: &mut Scene, nodes: &[NodeId], models: usize, time: f32) {
+    let columns = (models as f32).sqrt().ceil() as usize;
+    let offset = (columns - 1) as f32 * 1.25;
+
+    for (i, node) in nodes.iter().enumerate() {
+        let x = offset - ((i / columns) as f32) * 2.5;
+        let y = ((i % columns) as f32) * 2.5 - offset;
+
+        let transform = Transform {
+            translation: glm::vec3(x, y, 0.0),
+            ..Transform::from_rotation(time * glm::radians(&glm::vec1(90.0))[0], &glm::vec3(0.0, 0.0, 1.0))
+        };
+
+        scene.set_transform(*node, transform);
+        scene.node_mut(*node).visible = i < models;
+    }
+}
+
+
+/// Renders the first frame, with a single model or the scene file, to `output`.
+fn render_headless(config: Config, output: &Path) -> Result<()> {
+    let mut renderer = unsafe { Renderer::new_headless(config.renderer.clone()) }?;
+
+    let result = unsafe { load_post_chain(&mut renderer, &config).and_then(|_| load_environment(&mut renderer, &config)).and_then(|_| load_content(&mut renderer, &config)) }.and_then(|mut content| {
+        content.submit(&mut renderer, 1, 0.0);
+        unsafe { renderer.render_to_png(output) }
+    });
+
+    unsafe { renderer.destroy(); };
+
+    return result;
+}
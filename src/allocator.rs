@@ -0,0 +1,411 @@
+use vulkanalia::prelude::v1_0::*;
+use anyhow::{Result, anyhow};
+use log::*;
+use std::ptr;
+
+use crate::{app::AppData, buffers::get_memory_type_index};
+
+
+/// Size of the `vk::DeviceMemory` blocks resources are sub-allocated from, bigger resources get a block of their own.
+const DEFAULT_BLOCK_SIZE: u64 = 64 * 1024 * 1024;
+
+
+/// Buffers and optimally tiled images can't share a `bufferImageGranularity` sized page, so the allocator has to know which is which.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResourceKind {
+    /// Buffers and linearly tiled images.
+    Linear,
+    /// Optimally tiled images.
+    Optimal
+}
+
+
+/// A sub-allocated range of a memory block, it's bound with `memory` at `offset`.
+#[derive(Copy, Clone, Debug)]
+pub struct Allocation {
+    pub memory: vk::DeviceMemory,
+    pub offset: u64,
+    /// Start of the allocation in the persistently mapped block, null when the memory isn't host visible.
+    pub mapped: *mut u8,
+    block: usize
+}
+
+
+impl Default for Allocation {
+    fn default() -> Self {
+        Self { memory: vk::DeviceMemory::null(), offset: 0, mapped: ptr::null_mut(), block: usize::MAX }
+    }
+}
+
+
+/// How much device memory is allocated, see `Renderer::memory_stats`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AllocatorStats {
+    pub blocks: usize,
+    pub allocations: usize,
+    /// Bytes allocated from the driver.
+    pub reserved_bytes: u64,
+    /// Bytes handed out to resources, without alignment padding.
+    pub used_bytes: u64
+}
+
+
+#[derive(Debug, Default)]
+pub struct Allocator {
+    /// Freed blocks leave a `None` behind so the block indices of live allocations stay valid.
+    blocks: Vec<Option<MemoryBlock>>,
+    buffer_image_granularity: u64,
+    memory_properties: vk::PhysicalDeviceMemoryProperties
+}
+
+
+#[derive(Debug)]
+struct MemoryBlock {
+    memory: vk::DeviceMemory,
+    memory_type: u32,
+    mapped: *mut u8,
+    layout: BlockLayout
+}
+
+
+impl Allocator {
+    pub unsafe fn new(instance: &Instance, physical_device: vk::PhysicalDevice) -> Self {
+        let properties = instance.get_physical_device_properties(physical_device);
+
+        Self {
+            blocks: vec![],
+            buffer_image_granularity: properties.limits.buffer_image_granularity,
+            memory_properties: instance.get_physical_device_memory_properties(physical_device)
+        }
+    }
+
+
+    unsafe fn allocate(
+        &mut self,
+        device: &Device,
+        memory_type: u32,
+        requirements: vk::MemoryRequirements,
+        kind: ResourceKind
+    ) -> Result<Allocation> {
+
+        let granularity = self.buffer_image_granularity;
+
+        for (index, block) in self.blocks.iter_mut().enumerate() {
+            let block = match block {
+                Some(block) if block.memory_type == memory_type => block,
+                _ => continue
+            };
+
+            if let Some(offset) = block.layout.allocate(requirements.size, requirements.alignment, kind, granularity) {
+                return Ok(block.allocation(index, offset));
+            }
+        }
+
+
+        let heap = self.memory_properties.memory_types[memory_type as usize].heap_index;
+        let heap_size = self.memory_properties.memory_heaps[heap as usize].size;
+        let block_size = DEFAULT_BLOCK_SIZE.min(heap_size / 8).max(requirements.size);
+
+        let allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(block_size)
+            .memory_type_index(memory_type);
+
+        let memory = device.allocate_memory(&allocate_info, None)?;
+
+        let host_visible = self.memory_properties.memory_types[memory_type as usize]
+            .property_flags
+            .contains(vk::MemoryPropertyFlags::HOST_VISIBLE);
+
+        // Host visible blocks stay mapped for their whole lifetime, a memory object can only be mapped once at a time.
+        let mapped = if host_visible {
+            device.map_memory(memory, 0, vk::WHOLE_SIZE as u64, vk::MemoryMapFlags::empty())?.cast::<u8>()
+        } else {
+            ptr::null_mut()
+        };
+
+        let mut block = MemoryBlock { memory, memory_type, mapped, layout: BlockLayout::new(block_size) };
+
+        let offset = block.layout.allocate(requirements.size, requirements.alignment, kind, granularity)
+            .ok_or_else(|| anyhow!("A new memory block of {} bytes can't fit an allocation of {} bytes.", block_size, requirements.size))?;
+
+        let index = match self.blocks.iter().position(|b| b.is_none()) {
+            Some(index) => index,
+            None => {
+                self.blocks.push(None);
+                self.blocks.len() - 1
+            }
+        };
+
+        let allocation = block.allocation(index, offset);
+        self.blocks[index] = Some(block);
+
+        debug!("Allocated memory block {} of {} bytes for memory type {}", index, block_size, memory_type);
+
+        return Ok(allocation);
+    }
+
+
+    /// Returns the allocation to its block, blocks without any allocations left are given back to the driver.
+    pub unsafe fn free(&mut self, device: &Device, allocation: Allocation) {
+        let block = match self.blocks.get_mut(allocation.block) {
+            Some(Some(block)) => block,
+            _ => return
+        };
+
+        block.layout.free(allocation.offset);
+
+        if block.layout.is_empty() {
+            device.free_memory(block.memory, None);
+            self.blocks[allocation.block] = None;
+            debug!("Freed memory block {}", allocation.block);
+        }
+    }
+
+
+    pub fn stats(&self) -> AllocatorStats {
+        self.blocks.iter().flatten().fold(AllocatorStats::default(), |stats, block| AllocatorStats {
+            blocks: stats.blocks + 1,
+            allocations: stats.allocations + block.layout.allocations(),
+            reserved_bytes: stats.reserved_bytes + block.layout.size,
+            used_bytes: stats.used_bytes + block.layout.used_bytes()
+        })
+    }
+
+
+    /// Frees every block, resources that are still alive at this point have leaked.
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        let stats = self.stats();
+        if stats.allocations > 0 {
+            warn!("{} allocations ({} bytes) were never freed", stats.allocations, stats.used_bytes);
+        }
+
+        self.blocks.iter().flatten().for_each(|b| device.free_memory(b.memory, None));
+        self.blocks.clear();
+    }
+}
+
+
+impl MemoryBlock {
+    fn allocation(&self, block: usize, offset: u64) -> Allocation {
+        let mapped = if self.mapped.is_null() { ptr::null_mut() } else { unsafe { self.mapped.add(offset as usize) } };
+
+        Allocation { memory: self.memory, offset, mapped, block }
+    }
+}
+
+
+
+/// Bookkeeping of the used and free ranges of a single block, kept separate from Vulkan so it can be tested.
+#[derive(Debug)]
+struct BlockLayout {
+    size: u64,
+    /// Sorted by offset, without gaps, adjacent free segments are always merged.
+    segments: Vec<Segment>
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Segment {
+    offset: u64,
+    size: u64,
+    /// `None` for free segments.
+    kind: Option<ResourceKind>
+}
+
+
+impl BlockLayout {
+    fn new(size: u64) -> Self {
+        Self { size, segments: vec![Segment { offset: 0, size, kind: None }] }
+    }
+
+
+    /// First fit allocation, returns the offset of the new allocation.
+    fn allocate(&mut self, size: u64, alignment: u64, kind: ResourceKind, granularity: u64) -> Option<u64> {
+
+        for i in 0..self.segments.len() {
+            let segment = self.segments[i];
+            if segment.kind.is_some() || segment.size < size {
+                continue;
+            }
+
+            let mut offset = align_up(segment.offset, alignment);
+
+            // The previous segment is used if it isn't the first one, free segments are always merged.
+            if i > 0 && self.segments[i - 1].kind != Some(kind) {
+                let previous = self.segments[i - 1];
+                if same_page(previous.offset + previous.size - 1, offset, granularity) {
+                    offset = align_up(offset, granularity);
+                }
+            }
+
+            let end = offset + size;
+            if end > segment.offset + segment.size {
+                continue;
+            }
+
+            if let Some(next) = self.segments.get(i + 1) {
+                if next.kind != Some(kind) && same_page(end - 1, next.offset, granularity) {
+                    continue;
+                }
+            }
+
+            let mut replacement = vec![];
+
+            if offset > segment.offset {
+                replacement.push(Segment { offset: segment.offset, size: offset - segment.offset, kind: None });
+            }
+
+            replacement.push(Segment { offset, size, kind: Some(kind) });
+
+            if end < segment.offset + segment.size {
+                replacement.push(Segment { offset: end, size: segment.offset + segment.size - end, kind: None });
+            }
+
+            self.segments.splice(i..i + 1, replacement);
+
+            return Some(offset);
+        }
+
+        return None;
+    }
+
+
+    fn free(&mut self, offset: u64) {
+        let mut i = match self.segments.iter().position(|s| s.offset == offset && s.kind.is_some()) {
+            Some(i) => i,
+            None => return
+        };
+
+        self.segments[i].kind = None;
+
+        if i + 1 < self.segments.len() && self.segments[i + 1].kind.is_none() {
+            self.segments[i].size += self.segments[i + 1].size;
+            self.segments.remove(i + 1);
+        }
+
+        if i > 0 && self.segments[i - 1].kind.is_none() {
+            self.segments[i - 1].size += self.segments[i].size;
+            self.segments.remove(i);
+            i -= 1;
+        }
+
+        debug_assert!(self.segments[i].kind.is_none());
+    }
+
+
+    fn is_empty(&self) -> bool {
+        self.segments.iter().all(|s| s.kind.is_none())
+    }
+
+    fn allocations(&self) -> usize {
+        self.segments.iter().filter(|s| s.kind.is_some()).count()
+    }
+
+    fn used_bytes(&self) -> u64 {
+        self.segments.iter().filter(|s| s.kind.is_some()).map(|s| s.size).sum()
+    }
+}
+
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+    if alignment <= 1 { value } else { value.div_ceil(alignment) * alignment }
+}
+
+
+fn same_page(a: u64, b: u64, page_size: u64) -> bool {
+    page_size > 1 && a / page_size == b / page_size
+}
+
+
+
+/// Sub-allocates memory of the given properties that fits `requirements`.
+pub unsafe fn allocate_memory(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    requirements: vk::MemoryRequirements,
+    properties: vk::MemoryPropertyFlags,
+    kind: ResourceKind
+) -> Result<Allocation> {
+
+    let memory_type = get_memory_type_index(instance, data, properties, requirements)?;
+
+    return data.allocator.allocate(device, memory_type, requirements, kind);
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocations_are_aligned() {
+        let mut layout = BlockLayout::new(1024);
+
+        assert_eq!(layout.allocate(10, 16, ResourceKind::Linear, 1), Some(0));
+        assert_eq!(layout.allocate(10, 16, ResourceKind::Linear, 1), Some(16));
+        assert_eq!(layout.allocate(100, 256, ResourceKind::Linear, 1), Some(256));
+        assert_eq!(layout.allocations(), 3);
+        assert_eq!(layout.used_bytes(), 120);
+    }
+
+    #[test]
+    fn full_blocks_reject_allocations() {
+        let mut layout = BlockLayout::new(256);
+
+        assert_eq!(layout.allocate(200, 1, ResourceKind::Linear, 1), Some(0));
+        assert_eq!(layout.allocate(100, 1, ResourceKind::Linear, 1), None);
+        assert_eq!(layout.allocate(56, 1, ResourceKind::Linear, 1), Some(200));
+    }
+
+    #[test]
+    fn freed_ranges_are_merged_and_reused() {
+        let mut layout = BlockLayout::new(300);
+
+        let a = layout.allocate(100, 1, ResourceKind::Linear, 1).unwrap();
+        let b = layout.allocate(100, 1, ResourceKind::Linear, 1).unwrap();
+        let c = layout.allocate(100, 1, ResourceKind::Linear, 1).unwrap();
+
+        layout.free(a);
+        layout.free(b);
+        assert_eq!(layout.allocate(200, 1, ResourceKind::Linear, 1), Some(0));
+
+        layout.free(0);
+        layout.free(c);
+        assert!(layout.is_empty());
+        assert_eq!(layout.segments, vec![Segment { offset: 0, size: 300, kind: None }]);
+    }
+
+    #[test]
+    fn buffers_and_images_dont_share_granularity_pages() {
+        let mut layout = BlockLayout::new(4096);
+
+        assert_eq!(layout.allocate(100, 4, ResourceKind::Linear, 1024), Some(0));
+        // Same kind can share the page.
+        assert_eq!(layout.allocate(100, 4, ResourceKind::Linear, 1024), Some(100));
+        // An optimal image has to start on the next page.
+        assert_eq!(layout.allocate(100, 4, ResourceKind::Optimal, 1024), Some(1024));
+        // The padding in front of the image is still usable for buffers.
+        assert_eq!(layout.allocate(100, 4, ResourceKind::Linear, 1024), Some(200));
+        // A buffer right after the image has to skip the rest of its page.
+        assert_eq!(layout.allocate(900, 4, ResourceKind::Linear, 1024), Some(2048));
+    }
+
+    #[test]
+    fn allocations_dont_end_on_the_page_of_a_different_kind() {
+        let mut layout = BlockLayout::new(4096);
+
+        let a = layout.allocate(1024, 1, ResourceKind::Linear, 1024).unwrap();
+        assert_eq!(layout.allocate(100, 1, ResourceKind::Optimal, 1024), Some(1024));
+        layout.free(a);
+
+        // A buffer that ends right before the image's page fits in front of it.
+        assert_eq!(layout.allocate(1024, 1, ResourceKind::Linear, 1024), Some(0));
+        layout.free(0);
+
+        // One that would run into the image's page has to go after it.
+        assert_eq!(layout.allocate(1025, 1, ResourceKind::Linear, 1024), Some(2048));
+    }
+}
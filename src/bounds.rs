@@ -0,0 +1,216 @@
+use nalgebra_glm as glm;
+
+use crate::vertex::Vertex;
+
+
+
+/// An axis-aligned bounding box.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: glm::Vec3,
+    pub max: glm::Vec3
+}
+
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Self { min: glm::Vec3::zeros(), max: glm::Vec3::zeros() }
+    }
+}
+
+
+impl Aabb {
+    /// The smallest box around `points`, an empty box at the origin when there are none.
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a glm::Vec3>) -> Self {
+        let mut points = points.into_iter();
+
+        let first = match points.next() {
+            Some(point) => *point,
+            None => return Self::default()
+        };
+
+        points.fold(Self { min: first, max: first }, |aabb, point| Self {
+            min: glm::min2(&aabb.min, point),
+            max: glm::max2(&aabb.max, point)
+        })
+    }
+
+    pub fn center(&self) -> glm::Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Half of the size along every axis.
+    pub fn extents(&self) -> glm::Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    /// The box around this box after `transform`, which is larger than the transformed box unless that stays axis-aligned.
+    pub fn transformed(&self, transform: &glm::Mat4) -> Self {
+        let center = (transform * self.center().push(1.0)).xyz();
+        let extents = glm::abs(&glm::mat4_to_mat3(transform)) * self.extents();
+
+        Self { min: center - extents, max: center + extents }
+    }
+}
+
+
+
+/// A sphere around every vertex of a mesh.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct BoundingSphere {
+    pub center: glm::Vec3,
+    pub radius: f32
+}
+
+
+impl BoundingSphere {
+    /// The sphere around `aabb`'s center that contains all of `points`, which is tighter than the sphere around the box.
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a glm::Vec3>, aabb: &Aabb) -> Self {
+        let center = aabb.center();
+        let radius = points.into_iter().map(|p| glm::distance2(p, &center)).fold(0.0, f32::max).sqrt();
+
+        Self { center, radius }
+    }
+
+    /// The sphere after `transform`, scaled by its largest scale so it still contains the mesh when the scale isn't uniform.
+    pub fn transformed(&self, transform: &glm::Mat4) -> Self {
+        let scale = (0..3).map(|i| glm::length(&transform.column(i).xyz())).fold(0.0, f32::max);
+
+        Self { center: (transform * self.center.push(1.0)).xyz(), radius: self.radius * scale }
+    }
+}
+
+
+
+/// Bounding volumes of a mesh in its local space, computed when it is loaded.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Bounds {
+    pub aabb: Aabb,
+    pub sphere: BoundingSphere
+}
+
+
+impl Bounds {
+    pub fn from_vertices(vertices: &[Vertex]) -> Self {
+        let aabb = Aabb::from_points(vertices.iter().map(|v| &v.pos));
+        let sphere = BoundingSphere::from_points(vertices.iter().map(|v| &v.pos), &aabb);
+
+        Self { aabb, sphere }
+    }
+}
+
+
+
+/// The planes around what a camera sees, extracted from its view-projection matrix.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Frustum {
+    /// xyz is the normal pointing inside and w the distance, a point `p` is inside a plane when `dot(xyz, p) + w >= 0`.
+    /// An infinite far plane has no normal and is left out, an empty frustum sees everything.
+    planes: Vec<glm::Vec4>
+}
+
+
+impl Frustum {
+    /// Extracts the planes of Vulkan's clip space (`-w <= x, y <= w` and `0 <= z <= w`) from `view_proj`.
+    pub fn from_matrix(view_proj: &glm::Mat4) -> Self {
+        let row = |i: usize| view_proj.row(i).transpose();
+
+        let planes = [row(3) + row(0), row(3) - row(0), row(3) + row(1), row(3) - row(1), row(2), row(3) - row(2)];
+
+        Self {
+            planes: planes.into_iter()
+                .filter(|p| glm::length(&p.xyz()) > 1e-6)
+                .map(|p| p / glm::length(&p.xyz()))
+                .collect()
+        }
+    }
+
+    pub fn planes(&self) -> &[glm::Vec4] {
+        &self.planes
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes.iter().all(|p| glm::dot(&p.xyz(), &sphere.center) + p.w >= -sphere.radius)
+    }
+
+    /// Whether the box is at least partly inside, boxes that are outside but cross the planes of a corner count as inside.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        let (center, extents) = (aabb.center(), aabb.extents());
+
+        self.planes.iter().all(|p| glm::dot(&p.xyz(), &center) + p.w >= -glm::dot(&glm::abs(&p.xyz()), &extents))
+    }
+
+    /// Whether a mesh with `bounds` drawn with `transform` can be visible, tested against the sphere first as that is cheaper.
+    pub fn intersects(&self, bounds: &Bounds, transform: &glm::Mat4) -> bool {
+        self.intersects_sphere(&bounds.sphere.transformed(transform)) && self.intersects_aabb(&bounds.aabb.transformed(transform))
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{Camera, Projection};
+
+    fn cube() -> Bounds {
+        let vertices: Vec<Vertex> = [-1.0, 1.0].iter()
+            .flat_map(|x| [-1.0, 1.0].iter().flat_map(move |y| [-1.0, 1.0].iter().map(move |z| glm::vec3(*x, *y, *z))))
+            .map(|pos| Vertex { pos, ..Default::default() })
+            .collect();
+
+        Bounds::from_vertices(&vertices)
+    }
+
+    fn frustum(camera: &Camera) -> Frustum {
+        Frustum::from_matrix(&(camera.projection_matrix(1.5) * camera.view()))
+    }
+
+
+    #[test]
+    fn bounds_contain_the_vertices() {
+        let bounds = cube();
+
+        assert_eq!(bounds.aabb, Aabb { min: glm::vec3(-1.0, -1.0, -1.0), max: glm::vec3(1.0, 1.0, 1.0) });
+        assert_eq!(bounds.sphere.center, glm::Vec3::zeros());
+        assert!((bounds.sphere.radius - 3.0f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn transformed_bounds_follow_translation_and_scale() {
+        let transform = glm::translation(&glm::vec3(5.0, 0.0, 0.0)) * glm::scaling(&glm::vec3(1.0, 2.0, 1.0));
+        let bounds = cube();
+
+        let aabb = bounds.aabb.transformed(&transform);
+        assert_eq!(aabb.min, glm::vec3(4.0, -2.0, -1.0));
+        assert_eq!(aabb.max, glm::vec3(6.0, 2.0, 1.0));
+
+        let sphere = bounds.sphere.transformed(&transform);
+        assert_eq!(sphere.center, glm::vec3(5.0, 0.0, 0.0));
+        assert!((sphere.radius - 2.0 * 3.0f32.sqrt()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn frustum_culls_what_the_camera_cant_see() {
+        let bounds = cube();
+
+        for projection in [Projection::default(), Projection::InfiniteReverseZ { fov_y: 0.8, near: 0.1 }, Projection::Orthographic { height: 4.0, near: 0.1, far: 10.0 }] {
+            let camera = Camera { projection, ..Camera::default() };
+            let frustum = frustum(&camera);
+
+            // The camera is at (6, 0, 2) looking at the origin.
+            assert!(frustum.intersects(&bounds, &glm::identity()), "{:?}", projection);
+            assert!(!frustum.intersects(&bounds, &glm::translation(&glm::vec3(12.0, 0.0, 0.0))), "behind, {:?}", projection);
+            assert!(!frustum.intersects(&bounds, &glm::translation(&glm::vec3(0.0, 40.0, 0.0))), "to the side, {:?}", projection);
+        }
+    }
+
+    #[test]
+    fn infinite_projections_have_no_far_plane() {
+        let camera = Camera { projection: Projection::InfiniteReverseZ { fov_y: 0.8, near: 0.1 }, ..Camera::default() };
+        let far_away = glm::translation(&glm::vec3(-1.0e5, 0.0, -1.0e5 / 3.0));
+
+        assert_eq!(frustum(&camera).planes.len(), 5);
+        assert!(frustum(&camera).intersects(&cube(), &far_away));
+    }
+}
@@ -5,41 +5,19 @@ use std::ptr::copy_nonoverlapping as memcpy;
 use nalgebra_glm as glm;
 
 
+use crate::allocator::{Allocation, ResourceKind, allocate_memory};
+use crate::resources::{Buffer, CommandPool};
 use crate::app::AppData;
 use crate::device::QueueFamilyIndices;
 
 
-pub unsafe fn create_framebuffers(data: &mut AppData, device: &Device) -> Result<()> {
-    
-    data.framebuffers = data.swapchain_image_views.iter().map(|i| {
-        let attachments = &[data.color_image_view, data.depth_image_view, *i];
-
-
-
-        let framebuffer_info = vk::FramebufferCreateInfo::builder()
-            .render_pass(data.render_pass)
-            .attachments(attachments)
-            .width(data.swapchain_extent.width)
-            .height(data.swapchain_extent.height)
-            .layers(1);
-
-        device.create_framebuffer(&framebuffer_info, None)
-    }).collect::<Result<Vec<_>, _>>()?;
-
-    debug!("Created {} framebuffers", data.framebuffers.len());
-
-    return Ok(());
-} 
-
-
-
 pub unsafe fn create_command_pools(device: &Device, data: &mut AppData) -> Result<()> {
 
     let transient_command_pool_info = vk::CommandPoolCreateInfo::builder()
         .flags(vk::CommandPoolCreateFlags::TRANSIENT)
         .queue_family_index(data.queue_family_indicies.graphics);
 
-        data.transient_command_pool = device.create_command_pool(&transient_command_pool_info, None)?;
+        data.transient_command_pool = CommandPool::new(device.create_command_pool(&transient_command_pool_info, None)?, &data.deletion_queue);
 
 
 
@@ -53,13 +31,13 @@ pub unsafe fn create_command_pools(device: &Device, data: &mut AppData) -> Resul
 }
 
 
-unsafe fn create_command_pool(device: &Device, data: &AppData) -> Result<vk::CommandPool> {
+unsafe fn create_command_pool(device: &Device, data: &AppData) -> Result<CommandPool> {
 
     let info = vk::CommandPoolCreateInfo::builder()
         .flags(vk::CommandPoolCreateFlags::TRANSIENT)
         .queue_family_index(data.queue_family_indicies.graphics);
 
-    return Ok(device.create_command_pool(&info, None)?);
+    return Ok(CommandPool::new(device.create_command_pool(&info, None)?, &data.deletion_queue));
 }
 
 
@@ -69,15 +47,13 @@ pub unsafe fn create_command_buffers(device: &Device, data: &mut AppData) -> Res
     for i in 0..data.swapchain_images.len() {
 
         let allocate_info = vk::CommandBufferAllocateInfo::builder()
-        .command_pool(data.command_pools[i])
+        .command_pool(*data.command_pools[i])
         .level(vk::CommandBufferLevel::PRIMARY)
-        .command_buffer_count(data.framebuffers.len() as u32);
+        .command_buffer_count(1);
 
         data.command_buffers.push(device.allocate_command_buffers(&allocate_info)?[0]);
     }
 
-    data.secondary_command_buffers = vec![vec![]; data.swapchain_images.len()];
-
     return Ok(());
 }
 
@@ -85,7 +61,8 @@ pub unsafe fn create_command_buffers(device: &Device, data: &mut AppData) -> Res
 
 
 
-pub unsafe fn create_buffer(size: vk::DeviceSize, usage: vk::BufferUsageFlags, mem_props: vk::MemoryPropertyFlags, device: &Device, instance: &Instance, data: &AppData) -> Result<(vk::Buffer, vk::DeviceMemory)> {
+/// Creates a buffer and binds it to memory sub-allocated from `AppData::allocator`.
+pub unsafe fn create_buffer(size: vk::DeviceSize, usage: vk::BufferUsageFlags, mem_props: vk::MemoryPropertyFlags, device: &Device, instance: &Instance, data: &mut AppData) -> Result<Buffer> {
     let buffer_info = vk::BufferCreateInfo::builder()
         .size(size)
         .usage(usage)
@@ -95,43 +72,71 @@ pub unsafe fn create_buffer(size: vk::DeviceSize, usage: vk::BufferUsageFlags, m
 
     let requirements = device.get_buffer_memory_requirements(buffer);
 
-    let allocate_info = vk::MemoryAllocateInfo::builder()
-        .allocation_size(requirements.size)
-        .memory_type_index(get_memory_type_index(
-            instance, 
-            data, 
-            mem_props, 
-            requirements)?);
-    
-    let device_memory = device.allocate_memory(&allocate_info, None)?;
+    let allocation = allocate_memory(instance, device, data, requirements, mem_props, ResourceKind::Linear)?;
 
+    device.bind_buffer_memory(buffer, allocation.memory, allocation.offset)?;
 
-    
 
     debug!("Created buffer");
 
-    Ok((buffer, device_memory))
+    Ok(Buffer::new(buffer, allocation, &data.deletion_queue))
 }
 
 
 
 
+/// A host visible buffer that is rewritten every frame, like the instance and indirect buffers of a swapchain image.
+#[derive(Debug, Default)]
+pub(crate) struct DynamicBuffer {
+    pub buffer: Buffer,
+    /// In bytes.
+    pub capacity: u64
+}
+
+
+/// Writes `items` to the start of `buffer`, replacing it with a larger one first when they don't fit.
+/// The frame that used the buffer last has to be done, the replaced buffer is dropped right away.
+pub(crate) unsafe fn write_dynamic_buffer<T>(instance: &Instance, device: &Device, data: &mut AppData, buffer: &mut DynamicBuffer, usage: vk::BufferUsageFlags, items: &[T]) -> Result<()> {
+
+    let size = std::mem::size_of_val(items) as u64;
+
+    if size == 0 {
+        return Ok(());
+    }
+
+    if buffer.capacity < size {
+        let capacity = size.next_power_of_two().max(4096);
+
+        buffer.buffer = create_buffer(
+            capacity,
+            usage,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            device,
+            instance,
+            data)?;
+        buffer.capacity = capacity;
+
+        debug!("Grew a dynamic {:?} buffer to {} bytes", usage, capacity);
+    }
+
+    memcpy(items.as_ptr(), buffer.buffer.allocation.mapped.cast(), items.len());
+
+    return Ok(());
+}
+
+
+/// Copies into host visible memory, which stays mapped for as long as it's allocated.
 pub unsafe fn fill_buffer<T>(
-    buffer: &vk::Buffer, 
-    device_memory: &vk::DeviceMemory,
-    size: &vk::DeviceSize,
+    allocation: &Allocation,
     cpy_src: *const T,
-    cpy_count: usize,
-    device: &Device
+    cpy_count: usize
 ) -> Result<()> {
 
-    let memory_ptr = device.map_memory(*device_memory, 0, *size, vk::MemoryMapFlags::empty())?;
-
-    memcpy(cpy_src, memory_ptr.cast(), cpy_count);
-
-    device.unmap_memory(*device_memory);
+    if allocation.mapped.is_null() {
+        return Err(anyhow!("Can't fill a buffer that isn't host visible."));
+    }
 
-    device.bind_buffer_memory(*buffer, *device_memory, 0)?;
+    memcpy(cpy_src, allocation.mapped.cast(), cpy_count);
 
     debug!("Filled buffer!");
     
@@ -139,17 +144,19 @@ pub unsafe fn fill_buffer<T>(
 }
 
 
+/// Copies the first `size` bytes of `src` to `dst_offset` in `dst`.
 pub unsafe fn copy_buffer(
     device: &Device,
     data: &AppData,
     src: vk::Buffer,
     dst: vk::Buffer,
+    dst_offset: u64,
     size: u64
 ) -> Result<()> {
     debug!("Copying buffer");
     let command_buffer = begin_single_time_commands(device, data)?;
 
-    let region = vk::BufferCopy::builder().size(size);
+    let region = vk::BufferCopy::builder().dst_offset(dst_offset).size(size);
 
     device.cmd_copy_buffer(command_buffer, src, dst, &[region]);
 
@@ -164,7 +171,7 @@ pub unsafe fn copy_buffer(
 pub unsafe fn begin_single_time_commands(device: &Device, data: &AppData) -> Result<vk::CommandBuffer> {
 
     let command_buffer_info = vk::CommandBufferAllocateInfo::builder()
-    .command_pool(data.transient_command_pool)
+    .command_pool(*data.transient_command_pool)
     .level(vk::CommandBufferLevel::PRIMARY)
     .command_buffer_count(1);
 
@@ -193,7 +200,7 @@ pub unsafe fn end_single_time_commands(device: &Device, data: &AppData, command_
 
     device.queue_wait_idle(data.graphics_queue)?;
 
-    device.free_command_buffers(data.transient_command_pool, command_buffers);
+    device.free_command_buffers(*data.transient_command_pool, command_buffers);
 
     Ok(())
 }
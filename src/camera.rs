@@ -0,0 +1,456 @@
+use nalgebra_glm as glm;
+
+use crate::input::Input;
+
+
+
+/// How a camera maps what it sees to the screen.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    /// Depth goes from 0 at `near` to 1 at `far`. `fov_y` is the vertical field of view in radians.
+    Perspective { fov_y: f32, near: f32, far: f32 },
+    /// Parallel projection showing `height` units vertically, depth goes from 0 at `near` to 1 at `far`.
+    Orthographic { height: f32, near: f32, far: f32 },
+    /// Perspective without a far plane, depth goes from 1 at `near` to 0 at infinity.
+    /// Floats are most precise close to 0, which evens out the precision over the distance, so large scenes don't z-fight.
+    InfiniteReverseZ { fov_y: f32, near: f32 }
+}
+
+
+impl Default for Projection {
+    fn default() -> Self {
+        Projection::Perspective { fov_y: glm::radians(&glm::vec1(45.0))[0], near: 0.1, far: 10.0 }
+    }
+}
+
+
+impl Projection {
+    /// Projection into Vulkan's clip space, with Y pointing down.
+    pub fn matrix(&self, aspect_ratio: f32) -> glm::Mat4 {
+        let mut proj = match *self {
+            Projection::Perspective { fov_y, near, far } => glm::perspective_rh_zo(aspect_ratio, fov_y, near, far),
+            Projection::Orthographic { height, near, far } => {
+                let (half_width, half_height) = (height * aspect_ratio * 0.5, height * 0.5);
+                glm::ortho_rh_zo(-half_width, half_width, -half_height, half_height, near, far)
+            },
+            Projection::InfiniteReverseZ { fov_y, near } => glm::reversed_infinite_perspective_rh_zo(aspect_ratio, fov_y, near)
+        };
+
+        proj[(1, 1)] *= -1.0;
+
+        return proj;
+    }
+
+    /// Whether closer is a larger depth, the depth buffer has to be cleared to 0 and tested with greater instead of less.
+    pub fn reverse_z(&self) -> bool {
+        matches!(self, Projection::InfiniteReverseZ { .. })
+    }
+
+    /// Distances of the near and far plane, the far plane of `InfiniteReverseZ` is at infinity.
+    pub fn clip_distances(&self) -> (f32, f32) {
+        match *self {
+            Projection::Perspective { near, far, .. } | Projection::Orthographic { near, far, .. } => (near, far),
+            Projection::InfiniteReverseZ { near, .. } => (near, f32::INFINITY)
+        }
+    }
+}
+
+
+
+/// A camera looking from `position` at `target`, the world is Z-up.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Camera {
+    pub position: glm::Vec3,
+    pub target: glm::Vec3,
+    pub up: glm::Vec3,
+    pub projection: Projection
+}
+
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            position: glm::vec3(6.0, 0.0, 2.0),
+            target: glm::vec3(0.0, 0.0, 0.0),
+            up: glm::vec3(0.0, 0.0, 1.0),
+            projection: Projection::default()
+        }
+    }
+}
+
+
+impl Camera {
+    pub fn view(&self) -> glm::Mat4 {
+        glm::look_at(&self.position, &self.target, &self.up)
+    }
+
+    pub fn projection_matrix(&self, aspect_ratio: f32) -> glm::Mat4 {
+        self.projection.matrix(aspect_ratio)
+    }
+
+    /// World space corners of the part of the view between the distances `near` and `far` along the view direction,
+    /// the four at `near` first.
+    pub fn frustum_corners(&self, aspect_ratio: f32, near: f32, far: f32) -> [glm::Vec3; 8] {
+        let forward = glm::normalize(&(self.target - self.position));
+        let right = glm::normalize(&glm::cross(&forward, &self.up));
+        let up = glm::cross(&right, &forward);
+
+        let mut corners = [glm::Vec3::zeros(); 8];
+
+        for (i, distance) in [near, far].into_iter().enumerate() {
+            let half_height = match self.projection {
+                Projection::Perspective { fov_y, .. } | Projection::InfiniteReverseZ { fov_y, .. } => distance * (fov_y * 0.5).tan(),
+                Projection::Orthographic { height, .. } => height * 0.5
+            };
+            let half_width = half_height * aspect_ratio;
+            let center = self.position + forward * distance;
+
+            corners[i * 4] = center - right * half_width - up * half_height;
+            corners[i * 4 + 1] = center + right * half_width - up * half_height;
+            corners[i * 4 + 2] = center + right * half_width + up * half_height;
+            corners[i * 4 + 3] = center - right * half_width + up * half_height;
+        }
+
+        return corners;
+    }
+}
+
+
+
+/// What the user did since the last frame, usually read from an `Input` with `from_input`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct CameraInput {
+    /// Movement keys held down: X is right, Y is forward and Z is up, each between -1 and 1.
+    pub movement: glm::Vec3,
+    /// Mouse movement in pixels while looking around (fly) or rotating (orbit).
+    pub look: glm::Vec2,
+    /// Mouse movement in pixels while panning.
+    pub pan: glm::Vec2,
+    /// Mouse wheel lines, positive zooms in.
+    pub zoom: f32
+}
+
+
+impl CameraInput {
+    /// Reads the camera actions: `move_forward`, `move_back`, `move_left`, `move_right`, `move_up` and `move_down` move,
+    /// the mouse looks around while `look` is held (fly), rotates while `rotate` is held and pans while `pan` is held (orbit).
+    pub fn from_input(input: &Input, controller: &CameraController) -> Self {
+        let mouse = input.mouse_delta();
+        let zero = glm::Vec2::zeros();
+
+        let (look, pan) = match controller {
+            CameraController::Fly(_) => (if input.held("look") { mouse } else { zero }, zero),
+            CameraController::Orbit(_) if input.held("rotate") => (mouse, zero),
+            CameraController::Orbit(_) => (zero, if input.held("pan") { mouse } else { zero })
+        };
+
+        Self {
+            movement: glm::vec3(
+                input.axis("move_right", "move_left"),
+                input.axis("move_forward", "move_back"),
+                input.axis("move_up", "move_down")),
+            look,
+            pan,
+            zoom: input.scroll()
+        }
+    }
+}
+
+
+/// Moves the camera with WASD and looks around with the mouse, like in a first-person game.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FlyController {
+    /// Angle around Z in radians, 0 looks along X.
+    pub yaw: f32,
+    /// Angle above the horizon in radians.
+    pub pitch: f32,
+    /// Units per second.
+    pub speed: f32,
+    /// Radians per pixel.
+    pub sensitivity: f32
+}
+
+
+/// Rotates the camera around a target by dragging, zooms with the wheel and pans the target.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OrbitController {
+    pub target: glm::Vec3,
+    pub distance: f32,
+    /// Angle around Z in radians of the camera as seen from the target.
+    pub yaw: f32,
+    /// Angle above the horizon in radians of the camera as seen from the target.
+    pub pitch: f32,
+    /// Radians per pixel.
+    pub sensitivity: f32,
+    /// Fraction of the distance zoomed per wheel line.
+    pub zoom_speed: f32
+}
+
+
+/// Looking straight up or down makes the view matrix degenerate.
+const MAX_PITCH: f32 = 1.55;
+
+const MIN_DISTANCE: f32 = 0.1;
+
+
+/// Unit vector for a yaw and pitch, Z-up.
+fn direction(yaw: f32, pitch: f32) -> glm::Vec3 {
+    glm::vec3(pitch.cos() * yaw.cos(), pitch.cos() * yaw.sin(), pitch.sin())
+}
+
+/// Inverse of `direction`.
+fn yaw_pitch(direction: &glm::Vec3) -> (f32, f32) {
+    let direction = glm::normalize(direction);
+
+    (direction.y.atan2(direction.x), direction.z.clamp(-1.0, 1.0).asin())
+}
+
+
+impl FlyController {
+    /// Starts looking the way `camera` does.
+    pub fn from_camera(camera: &Camera) -> Self {
+        let (yaw, pitch) = yaw_pitch(&(camera.target - camera.position));
+
+        Self { yaw, pitch: pitch.clamp(-MAX_PITCH, MAX_PITCH), speed: 3.0, sensitivity: 0.003 }
+    }
+
+    /// Moves and turns `camera`, `delta` is the frame time in seconds.
+    pub fn update(&mut self, camera: &mut Camera, input: &CameraInput, delta: f32) {
+        self.yaw -= input.look.x * self.sensitivity;
+        self.pitch = (self.pitch - input.look.y * self.sensitivity).clamp(-MAX_PITCH, MAX_PITCH);
+
+        let forward = direction(self.yaw, self.pitch);
+        let right = glm::normalize(&glm::cross(&forward, &camera.up));
+
+        camera.position += (right * input.movement.x + forward * input.movement.y + camera.up * input.movement.z) * self.speed * delta;
+        camera.target = camera.position + forward;
+    }
+}
+
+
+impl OrbitController {
+    /// Starts orbiting the target of `camera`, from where the camera is.
+    pub fn from_camera(camera: &Camera) -> Self {
+        let offset = camera.position - camera.target;
+        let (yaw, pitch) = yaw_pitch(&offset);
+
+        Self {
+            target: camera.target,
+            distance: offset.norm().max(MIN_DISTANCE),
+            yaw,
+            pitch: pitch.clamp(-MAX_PITCH, MAX_PITCH),
+            sensitivity: 0.005,
+            zoom_speed: 0.1
+        }
+    }
+
+    /// Rotates, zooms and pans `camera`. Mouse input is in pixels, so it doesn't depend on the frame time.
+    pub fn update(&mut self, camera: &mut Camera, input: &CameraInput, _delta: f32) {
+        self.yaw -= input.look.x * self.sensitivity;
+        self.pitch = (self.pitch + input.look.y * self.sensitivity).clamp(-MAX_PITCH, MAX_PITCH);
+
+        let distance = (self.distance * (1.0 - self.zoom_speed).powf(input.zoom)).max(MIN_DISTANCE);
+
+        // Moving closer doesn't make anything bigger without perspective.
+        if let Projection::Orthographic { height, .. } = &mut camera.projection {
+            *height *= distance / self.distance;
+        }
+
+        self.distance = distance;
+
+        let offset = direction(self.yaw, self.pitch);
+        let right = glm::normalize(&glm::cross(&-offset, &camera.up));
+        let up = glm::cross(&right, &-offset);
+
+        // Panning moves the target as far as the mouse moved on screen, roughly.
+        let pan_scale = self.distance * self.sensitivity * 0.2;
+        self.target += (up * input.pan.y - right * input.pan.x) * pan_scale;
+
+        camera.target = self.target;
+        camera.position = self.target + offset * self.distance;
+    }
+}
+
+
+/// The controller the user drives the camera with, switchable at runtime.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CameraController {
+    Fly(FlyController),
+    Orbit(OrbitController)
+}
+
+
+impl CameraController {
+    pub fn update(&mut self, camera: &mut Camera, input: &CameraInput, delta: f32) {
+        match self {
+            CameraController::Fly(fly) => fly.update(camera, input, delta),
+            CameraController::Orbit(orbit) => orbit.update(camera, input, delta)
+        }
+    }
+
+    /// Switches between flying and orbiting, keeping the current view.
+    pub fn toggle(&mut self, camera: &Camera) {
+        *self = match self {
+            CameraController::Fly(_) => CameraController::Orbit(OrbitController::from_camera(camera)),
+            CameraController::Orbit(_) => CameraController::Fly(FlyController::from_camera(camera))
+        };
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_is_in_the_center_of_the_screen() {
+        let camera = Camera::default();
+
+        let clip = camera.projection_matrix(4.0 / 3.0) * camera.view() * camera.target.push(1.0);
+        let ndc = clip.xyz() / clip.w;
+
+        assert!(ndc.x.abs() < 1e-5 && ndc.y.abs() < 1e-5);
+        assert!(ndc.z > 0.0 && ndc.z < 1.0);
+    }
+
+    #[test]
+    fn up_is_the_top_of_the_screen() {
+        let camera = Camera::default();
+
+        let clip = camera.projection_matrix(1.0) * camera.view() * (camera.target + camera.up * 0.5).push(1.0);
+
+        // Vulkan's Y axis points down.
+        assert!(clip.y / clip.w < 0.0);
+    }
+
+    fn depth(camera: &Camera, point: glm::Vec3) -> f32 {
+        let clip = camera.projection_matrix(1.0) * camera.view() * point.push(1.0);
+        clip.z / clip.w
+    }
+
+    #[test]
+    fn reverse_z_puts_the_far_away_at_zero() {
+        let camera = Camera { projection: Projection::InfiniteReverseZ { fov_y: 1.0, near: 0.1 }, ..Default::default() };
+        let forward = glm::normalize(&(camera.target - camera.position));
+
+        let near = depth(&camera, camera.position + forward * 0.1);
+        let middle = depth(&camera, camera.position + forward * 10.0);
+        let far = depth(&camera, camera.position + forward * 1.0e6);
+
+        assert!((near - 1.0).abs() < 1e-5);
+        assert!(middle < near && far < middle);
+        assert!(far > 0.0 && far < 1e-6);
+        assert!(camera.projection.reverse_z());
+    }
+
+    #[test]
+    fn frustum_corners_are_on_the_edge_of_the_screen() {
+        let camera = Camera::default();
+        let proj_view = camera.projection_matrix(2.0) * camera.view();
+
+        for corner in camera.frustum_corners(2.0, 1.0, 5.0) {
+            let clip = proj_view * corner.push(1.0);
+            let ndc = clip.xyz() / clip.w;
+
+            assert!((ndc.x.abs() - 1.0).abs() < 1e-4 && (ndc.y.abs() - 1.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn orthographic_size_doesnt_depend_on_distance() {
+        let camera = Camera { projection: Projection::Orthographic { height: 4.0, near: 0.1, far: 100.0 }, ..Default::default() };
+        let top = |distance: f32| {
+            let forward = glm::normalize(&(camera.target - camera.position));
+            let clip = camera.projection_matrix(1.0) * camera.view() * (camera.position + forward * distance + camera.up * 2.0).push(1.0);
+            clip.y / clip.w
+        };
+
+        assert!((top(1.0) - top(50.0)).abs() < 1e-3);
+        assert!(top(1.0) < -0.9);
+        assert!(!camera.projection.reverse_z());
+    }
+
+    #[test]
+    fn orbit_zoom_scales_orthographic_projections() {
+        let mut camera = Camera { projection: Projection::Orthographic { height: 4.0, near: 0.1, far: 100.0 }, ..Default::default() };
+        let mut orbit = OrbitController::from_camera(&camera);
+
+        orbit.update(&mut camera, &CameraInput { zoom: 2.0, ..Default::default() }, 0.016);
+
+        assert!(matches!(camera.projection, Projection::Orthographic { height, .. } if height < 4.0));
+    }
+
+    fn assert_close(a: &glm::Vec3, b: &glm::Vec3) {
+        assert!((a - b).norm() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn fly_movement_doesnt_depend_on_the_frame_rate() {
+        let input = CameraInput { movement: glm::vec3(0.0, 1.0, 0.0), ..Default::default() };
+
+        let mut once = Camera::default();
+        FlyController::from_camera(&once).update(&mut once, &input, 1.0);
+
+        let mut often = Camera::default();
+        let mut fly = FlyController::from_camera(&often);
+        for _ in 0..10 {
+            fly.update(&mut often, &input, 0.1);
+        }
+
+        assert_close(&once.position, &often.position);
+        assert!(glm::distance(&once.position, &Camera::default().position) > 1.0);
+    }
+
+    #[test]
+    fn fly_pitch_stops_before_straight_up() {
+        let mut camera = Camera::default();
+        let mut fly = FlyController::from_camera(&camera);
+
+        fly.update(&mut camera, &CameraInput { look: glm::vec2(0.0, -100000.0), ..Default::default() }, 0.016);
+
+        assert_eq!(fly.pitch, MAX_PITCH);
+        assert!(glm::normalize(&(camera.target - camera.position)).z < 1.0);
+    }
+
+    #[test]
+    fn orbit_keeps_the_target_and_distance() {
+        let mut camera = Camera::default();
+        let mut orbit = OrbitController::from_camera(&camera);
+        let distance = glm::distance(&camera.position, &camera.target);
+
+        orbit.update(&mut camera, &CameraInput { look: glm::vec2(250.0, 40.0), ..Default::default() }, 0.016);
+
+        assert_close(&camera.target, &Camera::default().target);
+        assert!((glm::distance(&camera.position, &camera.target) - distance).abs() < 1e-4);
+        assert!(glm::distance(&camera.position, &Camera::default().position) > 0.5);
+    }
+
+    #[test]
+    fn orbit_zooms_towards_the_target() {
+        let mut camera = Camera::default();
+        let mut orbit = OrbitController::from_camera(&camera);
+        let distance = orbit.distance;
+
+        orbit.update(&mut camera, &CameraInput { zoom: 3.0, ..Default::default() }, 0.016);
+
+        assert!(orbit.distance < distance);
+        orbit.update(&mut camera, &CameraInput { zoom: 1000.0, ..Default::default() }, 0.016);
+        assert_eq!(orbit.distance, MIN_DISTANCE);
+    }
+
+    #[test]
+    fn switching_controllers_keeps_the_view() {
+        let mut camera = Camera::default();
+        let mut controller = CameraController::Fly(FlyController::from_camera(&camera));
+
+        controller.toggle(&camera);
+        controller.update(&mut camera, &CameraInput::default(), 0.016);
+        assert_close(&camera.position, &Camera::default().position);
+
+        controller.toggle(&camera);
+        controller.update(&mut camera, &CameraInput::default(), 0.016);
+        assert_close(&camera.position, &Camera::default().position);
+        assert_close(&glm::normalize(&(camera.target - camera.position)), &glm::normalize(&-Camera::default().position));
+    }
+}
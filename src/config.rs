@@ -0,0 +1,295 @@
+use vulkanalia::prelude::v1_0::*;
+use anyhow::{Result, anyhow};
+use std::path::PathBuf;
+
+
+pub const USAGE: &str = "Usage: viking_room [model.obj|model.gltf|model.glb] [options]
+
+Options:
+    --texture <file.png>        Single fallback texture for the parts of the model without an MTL material
+    --scene <file.ron>          Load a scene file instead of the model, F5 saves the scene back to it
+    --input <file.ron>          Key and mouse bindings, see resources/input.ron
+    --post <file.ron>           Post-processing chain, see resources/post.ron, F6 reloads it
+    --environment <file.hdr>    Equirectangular environment map for the skybox and image-based lighting, see resources/sky.hdr
+    --size <width>x<height>     Window (or headless image) size, default 800x600
+    --msaa <samples>            MSAA sample count (1, 2, 4, 8, 16, 32 or 64), default is the highest supported
+    --present-mode <mode>       fifo, fifo-relaxed, mailbox or immediate, default is mailbox when supported and fifo otherwise
+    --headless <output.png>     Render a single frame to a PNG file instead of opening a window
+    --help                      Print this message";
+
+
+/// Everything the `Renderer` needs to know before it can be created.
+#[derive(Clone, Debug)]
+pub struct RendererConfig {
+    /// Window (or headless image) size.
+    pub width: u32,
+    pub height: u32,
+    /// `None` picks the highest sample count the device supports.
+    pub msaa_samples: Option<vk::SampleCountFlags>,
+    /// `None` picks mailbox when it's available and FIFO otherwise.
+    pub present_mode: Option<vk::PresentModeKHR>
+}
+
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            width: 800,
+            height: 600,
+            msaa_samples: None,
+            present_mode: None
+        }
+    }
+}
+
+
+/// The command line of the viking room example, filled in by `from_args`.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub model_path: PathBuf,
+    /// A single fallback texture for the parts of the model that don't have an MTL material.
+    pub texture_path: Option<PathBuf>,
+    pub renderer: RendererConfig,
+    /// Replaces the model and textures when set.
+    pub scene_path: Option<PathBuf>,
+    /// `None` uses the default bindings.
+    pub input_path: Option<PathBuf>,
+    /// `None` applies no post-processing.
+    pub post_path: Option<PathBuf>,
+    /// `None` leaves the background black and lights the meshes by the lights and the ambient light alone.
+    pub environment_path: Option<PathBuf>,
+    pub headless_output: Option<PathBuf>
+}
+
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            model_path: PathBuf::from("resources/viking_room.obj"),
+            texture_path: Some(PathBuf::from("resources/texture.png")),
+            renderer: RendererConfig::default(),
+            scene_path: None,
+            input_path: None,
+            post_path: None,
+            environment_path: None,
+            headless_output: None
+        }
+    }
+}
+
+
+impl Config {
+    /// Parses the command line arguments, without the program name. Returns `Ok(None)` when `--help` was passed.
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Option<Self>> {
+
+        let mut config = Config::default();
+        let mut texture = None;
+        let mut model = None;
+
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| anyhow!("{} expects a value.\n\n{}", name, USAGE));
+
+            match arg.as_str() {
+                "--help" | "-h" => return Ok(None),
+                "--texture" if texture.is_some() => return Err(anyhow!("--texture can only be given once.\n\n{}", USAGE)),
+                "--texture" => texture = Some(PathBuf::from(value("--texture")?)),
+                "--size" => (config.renderer.width, config.renderer.height) = parse_size(&value("--size")?)?,
+                "--msaa" => config.renderer.msaa_samples = Some(parse_msaa_samples(&value("--msaa")?)?),
+                "--present-mode" => config.renderer.present_mode = Some(parse_present_mode(&value("--present-mode")?)?),
+                "--scene" => config.scene_path = Some(PathBuf::from(value("--scene")?)),
+                "--input" => config.input_path = Some(PathBuf::from(value("--input")?)),
+                "--post" => config.post_path = Some(PathBuf::from(value("--post")?)),
+                "--environment" => config.environment_path = Some(PathBuf::from(value("--environment")?)),
+                "--headless" => config.headless_output = Some(PathBuf::from(value("--headless")?)),
+                option if option.starts_with("--") => return Err(anyhow!("Unknown option {}.\n\n{}", option, USAGE)),
+                path if model.is_none() => model = Some(PathBuf::from(path)),
+                path => return Err(anyhow!("Unexpected argument {}, only one model can be loaded.\n\n{}", path, USAGE))
+            }
+        }
+
+        if let Some(model) = model {
+            config.model_path = model;
+        }
+
+        if texture.is_some() {
+            config.texture_path = texture;
+        }
+
+        config.validate()?;
+
+        return Ok(Some(config));
+    }
+
+
+    fn validate(&self) -> Result<()> {
+
+        // A scene file replaces the model, so the model isn't loaded.
+        if self.scene_path.is_none() {
+            if !self.model_path.is_file() {
+                return Err(anyhow!("Model {} doesn't exist.", self.model_path.display()));
+            }
+
+            match self.model_path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+                Some("obj") | Some("gltf") | Some("glb") => {},
+                _ => return Err(anyhow!("Model {} isn't an .obj, .gltf or .glb file.", self.model_path.display()))
+            }
+        }
+
+        for (kind, path) in [("Scene", &self.scene_path), ("Input map", &self.input_path), ("Post chain", &self.post_path)] {
+            if let Some(path) = path {
+                if !path.is_file() {
+                    return Err(anyhow!("{} {} doesn't exist.", kind, path.display()));
+                }
+
+                if path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() != Some("ron") {
+                    return Err(anyhow!("{} {} isn't a .ron file.", kind, path.display()));
+                }
+            }
+        }
+
+        if let Some(texture) = &self.texture_path {
+            if !texture.is_file() {
+                return Err(anyhow!("Texture {} doesn't exist.", texture.display()));
+            }
+
+            if texture.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() != Some("png") {
+                return Err(anyhow!("Texture {} isn't a .png file.", texture.display()));
+            }
+        }
+
+        if let Some(environment) = &self.environment_path {
+            if !environment.is_file() {
+                return Err(anyhow!("Environment {} doesn't exist.", environment.display()));
+            }
+
+            if environment.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() != Some("hdr") {
+                return Err(anyhow!("Environment {} isn't an .hdr file.", environment.display()));
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+
+
+fn parse_size(value: &str) -> Result<(u32, u32)> {
+    let error = || anyhow!("Invalid size {}, expected <width>x<height> like 1280x720.", value);
+
+    let (width, height) = value.split_once('x').ok_or_else(error)?;
+    let width = width.parse::<u32>().map_err(|_| error())?;
+    let height = height.parse::<u32>().map_err(|_| error())?;
+
+    if width == 0 || height == 0 {
+        return Err(anyhow!("Invalid size {}, width and height have to be at least 1.", value));
+    }
+
+    return Ok((width, height));
+}
+
+
+fn parse_msaa_samples(value: &str) -> Result<vk::SampleCountFlags> {
+    match value {
+        "1" => Ok(vk::SampleCountFlags::_1),
+        "2" => Ok(vk::SampleCountFlags::_2),
+        "4" => Ok(vk::SampleCountFlags::_4),
+        "8" => Ok(vk::SampleCountFlags::_8),
+        "16" => Ok(vk::SampleCountFlags::_16),
+        "32" => Ok(vk::SampleCountFlags::_32),
+        "64" => Ok(vk::SampleCountFlags::_64),
+        _ => Err(anyhow!("Invalid MSAA sample count {}, expected 1, 2, 4, 8, 16, 32 or 64.", value))
+    }
+}
+
+
+fn parse_present_mode(value: &str) -> Result<vk::PresentModeKHR> {
+    match value.to_ascii_lowercase().as_str() {
+        "fifo" => Ok(vk::PresentModeKHR::FIFO),
+        "fifo-relaxed" => Ok(vk::PresentModeKHR::FIFO_RELAXED),
+        "mailbox" => Ok(vk::PresentModeKHR::MAILBOX),
+        "immediate" => Ok(vk::PresentModeKHR::IMMEDIATE),
+        _ => Err(anyhow!("Invalid present mode {}, expected fifo, fifo-relaxed, mailbox or immediate.", value))
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Config>> {
+        Config::from_args(args.iter().map(|a| a.to_string()))
+    }
+
+
+    #[test]
+    fn defaults_to_the_viking_room() {
+        let config = parse(&[]).unwrap().unwrap();
+
+        assert_eq!(config.model_path, PathBuf::from("resources/viking_room.obj"));
+        assert_eq!(config.texture_path, Some(PathBuf::from("resources/texture.png")));
+        assert_eq!((config.renderer.width, config.renderer.height), (800, 600));
+        assert_eq!(config.renderer.msaa_samples, None);
+        assert_eq!(config.renderer.present_mode, None);
+    }
+
+    #[test]
+    fn parses_all_options() {
+        let config = parse(&[
+            "resources/plane.obj",
+            "--texture", "resources/texture1.png",
+            "--size", "1280x720",
+            "--msaa", "4",
+            "--present-mode", "immediate",
+            "--scene", "resources/viking_room.ron",
+            "--input", "resources/input.ron",
+            "--post", "resources/post.ron",
+            "--environment", "resources/sky.hdr",
+            "--headless", "out.png"
+        ]).unwrap().unwrap();
+
+        assert_eq!(config.model_path, PathBuf::from("resources/plane.obj"));
+        assert_eq!(config.texture_path, Some(PathBuf::from("resources/texture1.png")));
+        assert_eq!((config.renderer.width, config.renderer.height), (1280, 720));
+        assert_eq!(config.renderer.msaa_samples, Some(vk::SampleCountFlags::_4));
+        assert_eq!(config.renderer.present_mode, Some(vk::PresentModeKHR::IMMEDIATE));
+        assert_eq!(config.scene_path, Some(PathBuf::from("resources/viking_room.ron")));
+        assert_eq!(config.input_path, Some(PathBuf::from("resources/input.ron")));
+        assert_eq!(config.post_path, Some(PathBuf::from("resources/post.ron")));
+        assert_eq!(config.environment_path, Some(PathBuf::from("resources/sky.hdr")));
+        assert_eq!(config.headless_output, Some(PathBuf::from("out.png")));
+    }
+
+    #[test]
+    fn help_returns_none() {
+        assert!(parse(&["--help"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn scenes_dont_need_the_model() {
+        let config = parse(&["resources/missing.obj", "--scene", "resources/viking_room.ron"]).unwrap().unwrap();
+
+        assert_eq!(config.scene_path, Some(PathBuf::from("resources/viking_room.ron")));
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        assert!(parse(&["resources/missing.obj"]).is_err());
+        assert!(parse(&["resources/texture.png"]).is_err());
+        assert!(parse(&["--texture", "resources/plane.mtl"]).is_err());
+        assert!(parse(&["--texture", "resources/texture1.png", "--texture", "resources/texture2.png"]).is_err());
+        assert!(parse(&["--scene", "resources/plane.obj"]).is_err());
+        assert!(parse(&["--input", "resources/missing.ron"]).is_err());
+        assert!(parse(&["--post", "resources/warm.cube"]).is_err());
+        assert!(parse(&["--environment", "resources/texture.png"]).is_err());
+        assert!(parse(&["--size", "800"]).is_err());
+        assert!(parse(&["--size", "0x600"]).is_err());
+        assert!(parse(&["--msaa", "3"]).is_err());
+        assert!(parse(&["--present-mode", "vsync"]).is_err());
+        assert!(parse(&["--msaa"]).is_err());
+        assert!(parse(&["--fullscreen"]).is_err());
+    }
+}
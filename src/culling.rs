@@ -0,0 +1,267 @@
+use vulkanalia::prelude::v1_0::*;
+use nalgebra_glm as glm;
+use anyhow::Result;
+use log::*;
+use std::collections::HashMap;
+use std::mem::size_of;
+
+use crate::app::AppData;
+use crate::bounds::Frustum;
+use crate::buffers::{DynamicBuffer, write_dynamic_buffer};
+use crate::descriptors::update_cull_descriptor_set;
+use crate::instancing::{Batch, InstanceData};
+use crate::pipeline::create_compute_pipeline;
+use crate::render_graph::{Access, BufferId, RenderGraph};
+use crate::resources::PipelineLayout;
+use crate::vertex::Mesh;
+
+
+
+/// Invocations per workgroup of the culling shaders, must match `local_size_x` in cull.comp and draw_counts.comp.
+const WORKGROUP_SIZE: u32 = 64;
+
+
+/// An instance before culling, with the bounds of its mesh and where it goes when it is visible.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct CullInstance {
+    pub instance: InstanceData,
+    /// xyz center and w radius of the mesh's bounding sphere.
+    pub sphere: glm::Vec4,
+    pub aabb_center: glm::Vec4,
+    pub aabb_extents: glm::Vec4,
+    /// Counter of the batch in the batch count buffer.
+    pub batch: u32,
+    /// Of the batch in the instance buffer, visible instances are packed behind it.
+    pub first_instance: u32,
+    _padding: [u32; 2]
+}
+
+
+/// What both culling shaders get as push constants.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct CullConstants {
+    planes: [glm::Vec4; 6],
+    plane_count: u32,
+    instance_count: u32,
+    /// Of the main pass in the indirect buffer, the shadow pass commands before it are left alone.
+    first_command: u32,
+    command_count: u32
+}
+
+
+/// The compute inputs of a swapchain image, next to its instance and indirect buffers.
+#[derive(Debug, Default)]
+pub(crate) struct CullBuffers {
+    pub instances: DynamicBuffer,
+    /// A visible instance counter per batch, zeroed every frame.
+    pub batch_counts: DynamicBuffer,
+    /// The batch of every main pass command, whose count becomes the command's instance count.
+    pub command_batches: DynamicBuffer,
+    /// Of the frame that used the buffers last.
+    pub instance_count: usize,
+    pub batch_count: usize
+}
+
+
+/// The instances of `batches` with their bounds, in the order of the instance buffer.
+pub(crate) fn cull_instances(meshes: &[Mesh], batches: &[Batch], instances: &[InstanceData]) -> Vec<CullInstance> {
+    batches.iter().enumerate().flat_map(|(i, batch)| {
+        let bounds = meshes[batch.mesh.0].bounds;
+        let first = batch.first_instance as usize;
+
+        instances[first..first + batch.instance_count as usize].iter().map(move |instance| CullInstance {
+            instance: *instance,
+            sphere: bounds.sphere.center.push(bounds.sphere.radius),
+            aabb_center: bounds.aabb.center().push(0.0),
+            aabb_extents: bounds.aabb.extents().push(0.0),
+            batch: i as u32,
+            first_instance: batch.first_instance,
+            _padding: [0; 2]
+        })
+    }).collect()
+}
+
+
+/// The batch of every command, found through the first instance that a command shares with its batch only.
+pub(crate) fn command_batches(batches: &[Batch], commands: &[vk::DrawIndexedIndirectCommand]) -> Vec<u32> {
+    let batch_indices: HashMap<u32, u32> = batches.iter().enumerate().map(|(i, b)| (b.first_instance, i as u32)).collect();
+
+    commands.iter().map(|c| batch_indices[&c.first_instance]).collect()
+}
+
+
+/// The pipeline layout shared by both culling shaders, the culling pipeline and the pipeline that writes the draw counts.
+pub(crate) unsafe fn create_cull_pipelines(device: &Device, data: &mut AppData) -> Result<()> {
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .offset(0)
+        .size(size_of::<CullConstants>() as u32);
+
+    let set_layouts = &[*data.cull_descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+
+    let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.cull_pipeline_layout = PipelineLayout::new(device.create_pipeline_layout(&pipeline_layout_info, None)?, &data.deletion_queue);
+
+    data.cull_pipeline = create_compute_pipeline(device, data, *data.cull_pipeline_layout, include_bytes!("shaders/cull.spv"))?;
+    data.draw_count_pipeline = create_compute_pipeline(device, data, *data.cull_pipeline_layout, include_bytes!("shaders/draw_counts.spv"))?;
+
+    info!("Created culling pipelines!");
+
+    return Ok(());
+}
+
+
+/// Writes the culling inputs of a frame and points the swapchain image's descriptor set at them.
+/// Has to run after the instance and indirect buffers of the frame were written, as those can be replaced by larger ones.
+pub(crate) unsafe fn update_cull_buffers(instance: &Instance, device: &Device, data: &mut AppData, image_index: usize, instances: &[CullInstance], batch_count: usize, command_batches: &[u32]) -> Result<()> {
+
+    if data.cull_buffers.len() <= image_index {
+        data.cull_buffers.resize_with(image_index + 1, CullBuffers::default);
+    }
+
+    let mut buffers = std::mem::take(&mut data.cull_buffers[image_index]);
+
+    let result = write_dynamic_buffer(instance, device, data, &mut buffers.instances, vk::BufferUsageFlags::STORAGE_BUFFER, instances)
+        .and_then(|_| write_dynamic_buffer(instance, device, data, &mut buffers.batch_counts, vk::BufferUsageFlags::STORAGE_BUFFER, &vec![0u32; batch_count]))
+        .and_then(|_| write_dynamic_buffer(instance, device, data, &mut buffers.command_batches, vk::BufferUsageFlags::STORAGE_BUFFER, command_batches));
+
+    buffers.instance_count = instances.len();
+    buffers.batch_count = batch_count;
+    data.cull_buffers[image_index] = buffers;
+
+    result?;
+
+    if !instances.is_empty() {
+        update_cull_descriptor_set(device, data, image_index);
+    }
+
+    return Ok(());
+}
+
+
+/// Instances the GPU culled in the last frame that used the swapchain image, read back from its batch counts.
+/// That frame's fence has to be signaled.
+pub(crate) unsafe fn culled_instances(data: &AppData, image_index: usize) -> usize {
+    let buffers = match data.cull_buffers.get(image_index) {
+        Some(buffers) if buffers.batch_count > 0 => buffers,
+        _ => return 0
+    };
+
+    let counts = std::slice::from_raw_parts(buffers.batch_counts.buffer.allocation.mapped.cast::<u32>(), buffers.batch_count);
+    let visible: usize = counts.iter().map(|c| *c as usize).sum();
+
+    return buffers.instance_count.saturating_sub(visible);
+}
+
+
+/// Adds the culling of the instances against `frustum` to `graph`, which packs the visible ones into the instance buffer,
+/// then the copy of the visible counts into the instance counts of the main pass commands of the indirect buffer.
+pub(crate) unsafe fn add_cull_passes(
+    graph: &mut RenderGraph,
+    data: &AppData,
+    image_index: usize,
+    frustum: &Frustum,
+    first_command: usize,
+    command_count: usize,
+    instance_buffer: BufferId,
+    indirect_buffer: BufferId
+) {
+
+    let buffers = &data.cull_buffers[image_index];
+
+    if buffers.instance_count == 0 || command_count == 0 {
+        return;
+    }
+
+    let mut constants = CullConstants {
+        plane_count: frustum.planes().len() as u32,
+        instance_count: buffers.instance_count as u32,
+        first_command: first_command as u32,
+        command_count: command_count as u32,
+        ..Default::default()
+    };
+    constants.planes[..frustum.planes().len()].copy_from_slice(frustum.planes());
+
+    let cull_instances = graph.import_buffer("cull instances", *buffers.instances.buffer);
+    let batch_counts = graph.import_buffer("batch counts", *buffers.batch_counts.buffer);
+    let command_batches = graph.import_buffer("command batches", *buffers.command_batches.buffer);
+
+    let dispatch = move |device: &Device, data: &AppData, command_buffer: vk::CommandBuffer, pipeline: vk::Pipeline, group_count: u32| {
+        let constant_bytes = std::slice::from_raw_parts((&constants as *const CullConstants).cast::<u8>(), size_of::<CullConstants>());
+
+        device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::COMPUTE, *data.cull_pipeline_layout, 0, &[data.cull_descriptor_sets[image_index]], &[]);
+        device.cmd_push_constants(command_buffer, *data.cull_pipeline_layout, vk::ShaderStageFlags::COMPUTE, 0, constant_bytes);
+
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, pipeline);
+        device.cmd_dispatch(command_buffer, group_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+    };
+
+    graph.add_pass("cull")
+        .buffer(cull_instances, Access::StorageRead)
+        .buffer(instance_buffer, Access::StorageWrite)
+        .buffer(batch_counts, Access::StorageWrite)
+        .record(move |device, data, _, command_buffer| dispatch(device, data, command_buffer, *data.cull_pipeline, constants.instance_count));
+
+    graph.add_pass("draw counts")
+        .buffer(batch_counts, Access::StorageRead)
+        .buffer(command_batches, Access::StorageRead)
+        .buffer(indirect_buffer, Access::StorageWrite)
+        .record(move |device, data, _, command_buffer| dispatch(device, data, command_buffer, *data.draw_count_pipeline, constants.command_count));
+
+    // Only a barrier, which makes the counts visible to `culled_instances` once the frame's fence is signaled.
+    graph.add_pass("culled readback").buffer(batch_counts, Access::HostRead);
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bounds::{Aabb, BoundingSphere, Bounds};
+    use crate::renderer::MeshHandle;
+
+    #[test]
+    fn cull_instances_match_the_shader_layout() {
+        assert_eq!(size_of::<CullInstance>(), 144);
+        assert_eq!(size_of::<CullConstants>(), 112);
+        assert_eq!(size_of::<vk::DrawIndexedIndirectCommand>(), 20);
+    }
+
+    #[test]
+    fn cull_instances_carry_their_batch_and_bounds() {
+        let bounds = Bounds {
+            aabb: Aabb { min: glm::vec3(0.0, 0.0, 0.0), max: glm::vec3(2.0, 2.0, 2.0) },
+            sphere: BoundingSphere { center: glm::vec3(1.0, 1.0, 1.0), radius: 2.0 }
+        };
+        let meshes = vec![Mesh::default(), Mesh { bounds, ..Default::default() }];
+        let batches = vec![
+            Batch { mesh: MeshHandle(1), material: None, first_instance: 1, instance_count: 2 },
+            Batch { mesh: MeshHandle(0), material: None, first_instance: 3, instance_count: 1 }
+        ];
+        let instances = vec![InstanceData { model: glm::identity(), tint: glm::vec4(1.0, 1.0, 1.0, 1.0) }; 4];
+
+        let culled = cull_instances(&meshes, &batches, &instances);
+
+        assert_eq!(culled.iter().map(|c| (c.batch, c.first_instance)).collect::<Vec<_>>(), vec![(0, 1), (0, 1), (1, 3)]);
+        assert_eq!(culled[0].sphere, glm::vec4(1.0, 1.0, 1.0, 2.0));
+        assert_eq!(culled[0].aabb_extents, glm::vec4(1.0, 1.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn commands_find_their_batch() {
+        let batches = vec![
+            Batch { mesh: MeshHandle(0), material: None, first_instance: 0, instance_count: 2 },
+            Batch { mesh: MeshHandle(1), material: None, first_instance: 2, instance_count: 1 }
+        ];
+        let command = |first_instance| vk::DrawIndexedIndirectCommand { first_instance, ..Default::default() };
+
+        assert_eq!(command_batches(&batches, &[command(2), command(0), command(2)]), vec![1, 0, 1]);
+    }
+}
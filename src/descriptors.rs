@@ -3,33 +3,168 @@ use anyhow::Result;
 use crate::app::AppData;
 use std::mem::size_of;
 use crate::ubo::MVP_UBO;
+use crate::material::{MaterialUniform, TEXTURES_PER_MATERIAL};
+use crate::resources::{DescriptorPool, DescriptorSetLayout};
+use crate::post_processing::MAX_POST_PASSES;
 
 
+/// Materials can be created at any time, their descriptor sets are allocated from pools of this size.
+const MATERIALS_PER_POOL: usize = 64;
 
+/// Set 1 bindings of the base color, metallic-roughness, normal, occlusion and emissive textures.
+const MATERIAL_TEXTURE_BINDINGS: [u32; TEXTURES_PER_MATERIAL] = [0, 2, 3, 4, 5];
 
 
+
+
+/// Set 0 bindings of the irradiance cube, the prefiltered cube, the BRDF LUT and the environment cube, see `environment.rs`.
+const ENVIRONMENT_BINDINGS: [u32; 4] = [3, 4, 5, 6];
+
+
+/// Storage buffers of the culling compute pass, see `culling.rs`.
+const CULL_STORAGE_BUFFERS: u32 = 5;
+
+
+
+
+/// Set 0 holds the per-frame data (camera, lights, the shadow map and the environment) and is bound once per draw call, set 1 holds the material and is bound per draw range.
 pub unsafe fn create_descriptor_set_layout(device: &Device, data: &mut AppData) -> Result<()> {
 
     let mvp_ubo_binding = vk::DescriptorSetLayoutBinding::builder()
         .binding(0)
         .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
         .descriptor_count(1)
-        .stage_flags(vk::ShaderStageFlags::VERTEX);
-    
+        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
+
 
-    let sampler = vk::DescriptorSetLayoutBinding::builder()
+    let lights_binding = vk::DescriptorSetLayoutBinding::builder()
         .binding(1)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+
+    let shadow_map_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(2)
         .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
         .descriptor_count(1)
         .stage_flags(vk::ShaderStageFlags::FRAGMENT);
 
 
-    let bindings = &[mvp_ubo_binding, sampler];
+    let mut bindings = vec![mvp_ubo_binding.build(), lights_binding.build(), shadow_map_binding.build()];
+
+    for binding in ENVIRONMENT_BINDINGS {
+        bindings.push(vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build());
+    }
 
     let create_info = vk::DescriptorSetLayoutCreateInfo::builder()
-        .bindings(bindings);
+        .bindings(&bindings);
+
+    data.descriptor_set_layout = DescriptorSetLayout::new(device.create_descriptor_set_layout(&create_info, None)?, &data.deletion_queue);
+
+
+    let material_ubo_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(1)
+        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .build();
+
+    let mut material_bindings = vec![material_ubo_binding];
+
+    // The base color texture keeps binding 0, the other textures follow the uniform buffer.
+    for binding in MATERIAL_TEXTURE_BINDINGS {
+        material_bindings.push(vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build());
+    }
+
+    let material_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+        .bindings(&material_bindings);
+
+    data.material_descriptor_set_layout = DescriptorSetLayout::new(device.create_descriptor_set_layout(&material_create_info, None)?, &data.deletion_queue);
+
+
+    // The culling inputs, the instance buffer, the batch counts, the indirect buffer and the batch of every command.
+    let cull_bindings = (0..CULL_STORAGE_BUFFERS)
+        .map(|binding| vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build())
+        .collect::<Vec<_>>();
+
+    let cull_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+        .bindings(&cull_bindings);
+
+    data.cull_descriptor_set_layout = DescriptorSetLayout::new(device.create_descriptor_set_layout(&cull_create_info, None)?, &data.deletion_queue);
+
+
+    // The HDR image and the exposure buffer, for the tone mapping pass and the automatic exposure, see `tone_mapping.rs`.
+    let hdr_image_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE);
+
+    let exposure_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(1)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE);
+
+    let tone_mapping_bindings = &[hdr_image_binding, exposure_binding];
+
+    let tone_mapping_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+        .bindings(tone_mapping_bindings);
 
-    data.descriptor_set_layout = device.create_descriptor_set_layout(&create_info, None)?;
+    data.tone_mapping_descriptor_set_layout = DescriptorSetLayout::new(device.create_descriptor_set_layout(&tone_mapping_create_info, None)?, &data.deletion_queue);
+
+
+    // The input of a post-processing pass and a second image, the bloom or the LUT, see `post_processing.rs`.
+    let post_bindings = (0..2)
+        .map(|binding| vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build())
+        .collect::<Vec<_>>();
+
+    let post_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+        .bindings(&post_bindings);
+
+    data.post_descriptor_set_layout = DescriptorSetLayout::new(device.create_descriptor_set_layout(&post_create_info, None)?, &data.deletion_queue);
+
+
+    // The image an IBL shader reads and the one it writes, see `environment.rs`.
+    let ibl_source_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let ibl_target_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(1)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let ibl_bindings = &[ibl_source_binding, ibl_target_binding];
+
+    let ibl_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+        .bindings(ibl_bindings);
+
+    data.ibl_descriptor_set_layout = DescriptorSetLayout::new(device.create_descriptor_set_layout(&ibl_create_info, None)?, &data.deletion_queue);
 
     return Ok(());
 }
@@ -37,11 +172,11 @@ pub unsafe fn create_descriptor_set_layout(device: &Device, data: &mut AppData)
 
 pub unsafe fn create_descriptor_sets(device: &Device, data: &mut AppData) -> Result<()> {
 
-    let descriptor_set_layouts = vec![data.descriptor_set_layout; data.swapchain_images.len()];
+    let descriptor_set_layouts = vec![*data.descriptor_set_layout; data.swapchain_images.len()];
 
 
     let allocate_info = vk::DescriptorSetAllocateInfo::builder()
-        .descriptor_pool(data.descriptor_pool)
+        .descriptor_pool(*data.descriptor_pool)
         .set_layouts(&descriptor_set_layouts);
 
 
@@ -52,13 +187,13 @@ pub unsafe fn create_descriptor_sets(device: &Device, data: &mut AppData) -> Res
         // Descriptors that refer to buffers, like our uniform buffer descriptor, are configured with a vk::DescriptorBufferInfo struct.
         // This structure specifies the buffer and the region within it that contains the data for the descriptor.
         let mvp_ubo_buffer_info = vk::DescriptorBufferInfo::builder()
-            .buffer(data.uniform_buffers[i])
+            .buffer(*data.uniform_buffers[i])
             .offset(0)
             .range(size_of::<MVP_UBO>() as u64).build();
-        
+
 
         let buffer_infos = [mvp_ubo_buffer_info];
-        
+
         let mvp_ubo_write = vk::WriteDescriptorSet::builder()
             .dst_set(data.descriptor_sets[i])
             .dst_binding(0)
@@ -67,48 +202,309 @@ pub unsafe fn create_descriptor_sets(device: &Device, data: &mut AppData) -> Res
             .buffer_info(&buffer_infos);
 
 
-        let texture_image_info = vk::DescriptorImageInfo::builder()
-            .sampler(data.texture_image_sampler)
-            .image_view(data.texture_image_view)
-            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
-        
-        let image_infos = &[texture_image_info];
+        let lights_buffer_info = vk::DescriptorBufferInfo::builder()
+            .buffer(*data.light_buffers[i])
+            .offset(0)
+            .range(vk::WHOLE_SIZE as u64).build();
 
-        let texture_image_write = vk::WriteDescriptorSet::builder()
+        let lights_buffer_infos = [lights_buffer_info];
+
+        let lights_write = vk::WriteDescriptorSet::builder()
             .dst_set(data.descriptor_sets[i])
             .dst_binding(1)
             .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&lights_buffer_infos);
+
+
+        let shadow_map_info = vk::DescriptorImageInfo::builder()
+            .sampler(*data.shadow_sampler)
+            .image_view(*data.shadow_image_view)
+            .image_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL).build();
+
+        let shadow_map_infos = [shadow_map_info];
+
+        let shadow_map_write = vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[i])
+            .dst_binding(2)
+            .dst_array_element(0)
             .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .image_info(image_infos);
-        
+            .image_info(&shadow_map_infos);
 
-        
-        device.update_descriptor_sets(&[mvp_ubo_write, texture_image_write], &[] as &[vk::CopyDescriptorSet]);
+
+        device.update_descriptor_sets(&[mvp_ubo_write, lights_write, shadow_map_write], &[] as &[vk::CopyDescriptorSet]);
 
     }
 
+    update_environment_descriptor_sets(device, data);
+
+
+    // Written every frame by `update_cull_descriptor_set`, the buffers they point to only exist once something is drawn.
+    let cull_descriptor_set_layouts = vec![*data.cull_descriptor_set_layout; data.swapchain_images.len()];
+
+    let cull_allocate_info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(*data.descriptor_pool)
+        .set_layouts(&cull_descriptor_set_layouts);
+
+    data.cull_descriptor_sets = device.allocate_descriptor_sets(&cull_allocate_info)?;
+
+
+    // Written by `update_tone_mapping_descriptor_set` when the passes are recorded, the HDR image is a transient image of the render graph.
+    let tone_mapping_descriptor_set_layouts = vec![*data.tone_mapping_descriptor_set_layout; data.swapchain_images.len()];
+
+    let tone_mapping_allocate_info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(*data.descriptor_pool)
+        .set_layouts(&tone_mapping_descriptor_set_layouts);
+
+    data.tone_mapping_descriptor_sets = device.allocate_descriptor_sets(&tone_mapping_allocate_info)?;
+    data.histogram_descriptor_sets = device.allocate_descriptor_sets(&tone_mapping_allocate_info)?;
+
 
     return Ok(());
 }
 
-pub unsafe fn create_descriptor_pool(device: &Device, data: &mut AppData) -> Result<()> {
+
+/// Points every set 0 at the current environment, which is replaced when one is loaded.
+pub unsafe fn update_environment_descriptor_sets(device: &Device, data: &AppData) {
+
+    let environment = &data.environment;
+    let views = [&environment.irradiance.view, &environment.prefiltered.view, &data.brdf_lut_view, &environment.cube.view];
+
+    let image_infos = views.map(|view| [vk::DescriptorImageInfo::builder()
+        .sampler(*data.environment_sampler)
+        .image_view(**view)
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .build()]);
+
+    for descriptor_set in &data.descriptor_sets {
+        let writes = ENVIRONMENT_BINDINGS.iter()
+            .zip(&image_infos)
+            .map(|(binding, infos)| vk::WriteDescriptorSet::builder()
+                .dst_set(*descriptor_set)
+                .dst_binding(*binding)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(infos))
+            .collect::<Vec<_>>();
+
+        device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+    }
+}
+
+
+/// Points the culling descriptor set of a swapchain image at its current buffers, which are replaced when they grow.
+pub unsafe fn update_cull_descriptor_set(device: &Device, data: &AppData, image_index: usize) {
+
+    let cull_buffers = &data.cull_buffers[image_index];
+
+    let buffers = [
+        *cull_buffers.instances.buffer,
+        *data.instance_buffers[image_index].buffer,
+        *cull_buffers.batch_counts.buffer,
+        *data.indirect_buffers[image_index].buffer,
+        *cull_buffers.command_batches.buffer
+    ];
+
+    let buffer_infos = buffers.map(|buffer| [vk::DescriptorBufferInfo::builder()
+        .buffer(buffer)
+        .offset(0)
+        .range(vk::WHOLE_SIZE as u64)
+        .build()]);
+
+    let writes = buffer_infos.iter().enumerate()
+        .map(|(binding, info)| vk::WriteDescriptorSet::builder()
+            .dst_set(data.cull_descriptor_sets[image_index])
+            .dst_binding(binding as u32)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(info)
+            .build())
+        .collect::<Vec<_>>();
+
+    device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+}
+
+
+/// Points a tone mapping descriptor set at the HDR image of the frame and the exposure buffer.
+pub unsafe fn update_tone_mapping_descriptor_set(device: &Device, data: &AppData, descriptor_set: vk::DescriptorSet, hdr_view: vk::ImageView) {
+
+    let image_info = vk::DescriptorImageInfo::builder()
+        .sampler(*data.hdr_sampler)
+        .image_view(hdr_view)
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+    let image_infos = &[image_info];
+
+    let image_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(image_infos);
+
+
+    let exposure_info = vk::DescriptorBufferInfo::builder()
+        .buffer(*data.exposure_buffer)
+        .offset(0)
+        .range(vk::WHOLE_SIZE as u64);
+
+    let exposure_infos = &[exposure_info];
+
+    let exposure_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(1)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(exposure_infos);
+
+    device.update_descriptor_sets(&[image_write, exposure_write], &[] as &[vk::CopyDescriptorSet]);
+}
+
+
+/// Points a post-processing descriptor set at the input of its pass and, for the passes that read one, the second image.
+pub unsafe fn update_post_descriptor_set(device: &Device, data: &AppData, descriptor_set: vk::DescriptorSet, input_view: vk::ImageView, second_view: Option<vk::ImageView>) {
+
+    let image_infos = [Some(input_view), second_view].map(|view| view.map(|view| [vk::DescriptorImageInfo::builder()
+        .sampler(*data.post_sampler)
+        .image_view(view)
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .build()]));
+
+    let writes = image_infos.iter().enumerate()
+        .filter_map(|(binding, info)| info.as_ref().map(|info| vk::WriteDescriptorSet::builder()
+            .dst_set(descriptor_set)
+            .dst_binding(binding as u32)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(info)
+            .build()))
+        .collect::<Vec<_>>();
+
+    device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+}
+
+
+/// Allocates the set 1 descriptor set of a material, from a new pool when the current one is full.
+pub unsafe fn create_material_descriptor_set(device: &Device, data: &mut AppData, texture_views: [vk::ImageView; TEXTURES_PER_MATERIAL], uniform_buffer: vk::Buffer) -> Result<vk::DescriptorSet> {
+
+    if data.material_descriptor_sets.len() % MATERIALS_PER_POOL == 0 {
+        data.material_descriptor_pools.push(create_material_descriptor_pool(device, data)?);
+    }
+
+
+    let descriptor_set_layouts = &[*data.material_descriptor_set_layout];
+
+    let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(*data.material_descriptor_pools[data.material_descriptor_pools.len() - 1])
+        .set_layouts(descriptor_set_layouts);
+
+
+    let descriptor_set = device.allocate_descriptor_sets(&allocate_info)?[0];
+
+
+    let image_infos = texture_views.map(|view| [vk::DescriptorImageInfo::builder()
+        .sampler(*data.texture_image_sampler)
+        .image_view(view)
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .build()]);
+
+    let mut writes = MATERIAL_TEXTURE_BINDINGS.iter()
+        .zip(image_infos.iter())
+        .map(|(binding, info)| vk::WriteDescriptorSet::builder()
+            .dst_set(descriptor_set)
+            .dst_binding(*binding)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(info)
+            .build())
+        .collect::<Vec<_>>();
+
+
+    let material_buffer_info = vk::DescriptorBufferInfo::builder()
+        .buffer(uniform_buffer)
+        .offset(0)
+        .range(size_of::<MaterialUniform>() as u64);
+
+    let buffer_infos = &[material_buffer_info];
+
+    writes.push(vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(1)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+        .buffer_info(buffer_infos)
+        .build());
+
+
+    device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+
+
+    return Ok(descriptor_set);
+}
+
+
+/// Material descriptor sets live as long as the renderer, so unlike the per-frame pool these pools aren't tied to the swapchain.
+unsafe fn create_material_descriptor_pool(device: &Device, data: &AppData) -> Result<DescriptorPool> {
 
     let ubo_size = vk::DescriptorPoolSize::builder()
         .type_(vk::DescriptorType::UNIFORM_BUFFER)
-        .descriptor_count(data.swapchain_images.len() as u32);
+        .descriptor_count(MATERIALS_PER_POOL as u32);
 
     let sampler_size = vk::DescriptorPoolSize::builder()
         .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-        .descriptor_count(data.swapchain_images.len() as u32);
+        .descriptor_count((MATERIALS_PER_POOL * TEXTURES_PER_MATERIAL) as u32);
 
     let pool_sizes = &[ubo_size, sampler_size];
 
     let pool_create_info = vk::DescriptorPoolCreateInfo::builder()
-        .max_sets(data.swapchain_images.len() as u32)
+        .max_sets(MATERIALS_PER_POOL as u32)
         .pool_sizes(pool_sizes);
 
-    data.descriptor_pool = device.create_descriptor_pool(&pool_create_info, None)?;
+    return Ok(DescriptorPool::new(device.create_descriptor_pool(&pool_create_info, None)?, &data.deletion_queue));
+}
+
+
+pub unsafe fn create_descriptor_pool(device: &Device, data: &mut AppData) -> Result<()> {
+
+    let frames = data.swapchain_images.len() as u32;
+
+    let ubo_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::UNIFORM_BUFFER)
+        .descriptor_count(frames);
+
+    let storage_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(frames * (1 + CULL_STORAGE_BUFFERS + 2));
+
+    let sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(frames * (3 + ENVIRONMENT_BINDINGS.len() as u32));
+
+    let pool_sizes = &[ubo_size, storage_size, sampler_size];
+
+    // A set 0, a culling set, a tone mapping set and a histogram set per swapchain image.
+    let pool_create_info = vk::DescriptorPoolCreateInfo::builder()
+        .max_sets(frames * 4)
+        .pool_sizes(pool_sizes);
+
+    data.descriptor_pool = DescriptorPool::new(device.create_descriptor_pool(&pool_create_info, None)?, &data.deletion_queue);
+
+
+    // The post-processing chain can change every frame, its sets are allocated when a frame is recorded and the pool reset with the next.
+    let post_sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(MAX_POST_PASSES as u32 * 2);
+
+    let post_pool_sizes = &[post_sampler_size];
+
+    let post_pool_create_info = vk::DescriptorPoolCreateInfo::builder()
+        .max_sets(MAX_POST_PASSES as u32)
+        .pool_sizes(post_pool_sizes);
+
+    data.post_descriptor_pools = (0..frames)
+        .map(|_| Ok(DescriptorPool::new(device.create_descriptor_pool(&post_pool_create_info, None)?, &data.deletion_queue)))
+        .collect::<Result<_>>()?;
 
 
     return Ok(());
-}
\ No newline at end of file
+}
@@ -19,14 +19,30 @@ pub unsafe fn pick_physical_device(instance: &Instance, data: &mut AppData) -> R
             warn!("Skipping {}: it doesn't support graphics queue family", props.device_name);
             continue;
         }
-        if let Err(_) = check_device_extentions(instance, &physical_device) {
+        if let Err(_) = check_device_extentions(instance, data, &physical_device) {
             warn!("Skipping {}: it doesn't support all required extensions", props.device_name);
             continue;
         }
 
+        // Instances of indirect draws start at their batch's first instance.
+        let features = instance.get_physical_device_features(physical_device);
+        if features.draw_indirect_first_instance != vk::TRUE {
+            warn!("Skipping {}: it doesn't support indirect draws with a first instance", props.device_name);
+            continue;
+        }
+
         info!("Picked device: {}", props.device_name);
         data.physical_device = physical_device;
-        data.msaa_samples = get_max_msaa_samples(instance, data);
+        data.multi_draw_indirect = features.multi_draw_indirect == vk::TRUE;
+        data.msaa_samples = match data.config.msaa_samples {
+            Some(samples) if get_supported_msaa_samples(instance, data).contains(samples) => samples,
+            Some(samples) => return Err(anyhow!(
+                "{} doesn't support {:?} MSAA samples, the highest supported count is {:?}.",
+                props.device_name,
+                samples,
+                get_max_msaa_samples(instance, data))),
+            None => get_max_msaa_samples(instance, data)
+        };
         debug!("MSAA samples: {:?}", data.msaa_samples);
         return Ok(());
     }
@@ -45,11 +61,13 @@ pub unsafe fn create_logical_device(instance: &Instance, data: &mut AppData) ->
     let layers = [vk::ExtensionName::from_bytes(b"VK_LAYER_KHRONOS_validation").as_ptr()];
     let queue_infos = &[graphics_queue_info];
 
-    let extensions = DEVICE_EXTENSIONS.iter().map(|n| n.as_ptr()).collect::<Vec<_>>();
+    let extensions = required_device_extensions(data).iter().map(|n| n.as_ptr()).collect::<Vec<_>>();
 
 
     let features = vk::PhysicalDeviceFeatures::builder()
-        .sampler_anisotropy(true);
+        .sampler_anisotropy(true)
+        .draw_indirect_first_instance(true)
+        .multi_draw_indirect(data.multi_draw_indirect);
 
 
 
@@ -90,12 +108,17 @@ impl QueueFamilyIndices {
                 graphics = Some(i as u32);
                 debug!("\tgraphics queue family index: {}", i);
             }
-            if instance.get_physical_device_surface_support_khr(*physical_device, i as u32, data.surface)? && present == None {
+            if !data.headless && instance.get_physical_device_surface_support_khr(*physical_device, i as u32, data.surface)? && present == None {
                 present = Some(i as u32);
                 debug!("\tpresent queue family index: {}", i);
             }
         }
 
+        // Nothing gets presented without a surface, so the graphics queue doubles as the present queue.
+        if data.headless {
+            present = graphics;
+        }
+
         if let Some(graphics) = graphics {
             if let Some(present) = present {
                 return Ok(Self { graphics, present });
@@ -111,13 +134,21 @@ impl QueueFamilyIndices {
 
 
 
-unsafe fn get_max_msaa_samples(
+unsafe fn get_supported_msaa_samples(
     instance: &Instance,
     data: &AppData
 ) -> vk::SampleCountFlags {
     let properties = instance.get_physical_device_properties(data.physical_device);
-    let counts = properties.limits.framebuffer_color_sample_counts
-        & properties.limits.framebuffer_depth_sample_counts;
+    properties.limits.framebuffer_color_sample_counts
+        & properties.limits.framebuffer_depth_sample_counts
+}
+
+
+unsafe fn get_max_msaa_samples(
+    instance: &Instance,
+    data: &AppData
+) -> vk::SampleCountFlags {
+    let counts = get_supported_msaa_samples(instance, data);
     [
         vk::SampleCountFlags::_64,
         vk::SampleCountFlags::_32,
@@ -134,8 +165,13 @@ unsafe fn get_max_msaa_samples(
 
 
 
-unsafe fn check_device_extentions(instance: &Instance, physical_device: &vk::PhysicalDevice) -> Result<()> {
-    let required_extensions: &[vk::ExtensionName] = &[vk::KHR_SWAPCHAIN_EXTENSION.name];
+fn required_device_extensions(data: &AppData) -> &'static [vk::ExtensionName] {
+    if data.headless { &[] } else { DEVICE_EXTENSIONS }
+}
+
+
+unsafe fn check_device_extentions(instance: &Instance, data: &AppData, physical_device: &vk::PhysicalDevice) -> Result<()> {
+    let required_extensions = required_device_extensions(data);
     let extensions = instance.enumerate_device_extension_properties(*physical_device, None)?.iter().map(|e| e.extension_name).collect::<Vec<_>>();
     if required_extensions.iter().all(|e| extensions.contains(e)) {
         return Ok(());
@@ -0,0 +1,880 @@
+use vulkanalia::prelude::v1_0::*;
+use anyhow::{Result, anyhow};
+use log::*;
+use std::fs;
+use std::mem::size_of;
+use std::path::Path;
+
+use crate::app::AppData;
+use crate::buffers::{create_buffer, fill_buffer, begin_single_time_commands, end_single_time_commands};
+use crate::descriptors::update_environment_descriptor_sets;
+use crate::images::{create_image, create_image_view};
+use crate::pipeline::{create_compute_pipeline, create_shader_module};
+use crate::render_graph::Access;
+use crate::resources::{DescriptorPool, Image, ImageView, Pipeline, PipelineLayout, Sampler};
+
+
+
+/// Every cube and the BRDF LUT are half floats, the one HDR format that can be filtered, sampled and stored on every device.
+const ENVIRONMENT_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// The largest face of the environment cube, equirectangular images wider than four times this are downsampled.
+const MAX_CUBE_SIZE: u32 = 1024;
+
+const IRRADIANCE_SIZE: u32 = 32;
+
+/// The prefiltered cube has a mip per roughness step, from 0 at mip 0 to 1 at the last one.
+const PREFILTERED_SIZE: u32 = 128;
+const PREFILTERED_MIPS: u32 = 5;
+
+const BRDF_LUT_SIZE: u32 = 256;
+
+/// Samples of the GGX lobe per texel of the prefiltered cube and the BRDF LUT.
+const SAMPLE_COUNT: u32 = 1024;
+
+/// Width and height of the texels a workgroup of the IBL shaders writes, must match their local size.
+const IBL_TILE: u32 = 8;
+
+
+
+/// A cube image with a view of all its mips, sampled with `AppData::environment_sampler`.
+#[derive(Debug, Default)]
+pub(crate) struct Cube {
+    pub image: Image,
+    pub view: ImageView,
+    pub size: u32,
+    pub mip_levels: u32
+}
+
+
+/// The environment the skybox shows and the image-based lighting precomputed from it, bound at set 0.
+#[derive(Debug, Default)]
+pub(crate) struct Environment {
+    /// Without a loaded environment the cubes are black and the skybox isn't drawn.
+    pub loaded: bool,
+    pub cube: Cube,
+    /// The cosine weighted average of the environment around every direction, the diffuse light.
+    pub irradiance: Cube,
+    /// The environment blurred by the GGX lobe of a roughness per mip, the specular light.
+    pub prefiltered: Cube
+}
+
+
+/// The compute pipelines that convert and convolve an environment, all share `AppData::ibl_pipeline_layout`.
+#[derive(Debug, Default)]
+pub(crate) struct IblPipelines {
+    pub equirect_to_cube: Pipeline,
+    pub irradiance: Pipeline,
+    pub prefilter: Pipeline,
+    pub brdf_lut: Pipeline
+}
+
+
+/// Must match ibl.glsl.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct IblConstants {
+    roughness: f32,
+    sample_count: u32,
+    source_size: f32,
+    _padding: f32
+}
+
+
+/// One run of an IBL shader, over every texel of a `size` square image with `layers` layers.
+struct IblDispatch<'a> {
+    pipeline: &'a Pipeline,
+    set: vk::DescriptorSet,
+    constants: IblConstants,
+    size: u32,
+    layers: u32
+}
+
+
+/// An equirectangular image decoded from a Radiance .hdr file, RGB floats row by row from the top.
+#[derive(Debug)]
+struct HdrImage {
+    width: u32,
+    height: u32,
+    pixels: Vec<f32>
+}
+
+
+
+/// Decodes a Radiance .hdr (RGBE) file, flat or run-length encoded, in the usual top to bottom, left to right orientation.
+fn decode_hdr(bytes: &[u8]) -> Result<HdrImage> {
+
+    let mut rest = bytes;
+
+    let magic = next_line(&mut rest)?;
+
+    if magic != "#?RADIANCE" && magic != "#?RGBE" {
+        return Err(anyhow!("Not a Radiance HDR file."));
+    }
+
+    // Variables until an empty line, only the pixel format matters.
+    loop {
+        let line = next_line(&mut rest)?;
+
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(anyhow!("Unsupported pixel format {}.", format));
+            }
+        }
+    }
+
+    let resolution = next_line(&mut rest)?;
+
+    let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", height, "+X", width] => (height.parse::<u32>()?, width.parse::<u32>()?),
+        _ => return Err(anyhow!("Unsupported orientation {}.", resolution))
+    };
+
+    if width == 0 || height == 0 {
+        return Err(anyhow!("The image is empty."));
+    }
+
+    let mut pixels = Vec::with_capacity((width * height * 3) as usize);
+    let mut scanline = vec![[0u8; 4]; width as usize];
+
+    for _ in 0..height {
+        rest = read_scanline(rest, &mut scanline)?;
+        pixels.extend(scanline.iter().flat_map(|&rgbe| rgbe_to_rgb(rgbe)));
+    }
+
+    return Ok(HdrImage { width, height, pixels });
+}
+
+
+/// The text up to the next newline, which `rest` then starts after.
+fn next_line<'a>(rest: &mut &'a [u8]) -> Result<&'a str> {
+    let end = rest.iter().position(|&b| b == b'\n').ok_or_else(|| anyhow!("The header ends early."))?;
+    let line = std::str::from_utf8(&rest[..end]).map_err(|_| anyhow!("The header isn't text."))?;
+    *rest = &rest[end + 1..];
+
+    Ok(line.trim_end_matches('\r'))
+}
+
+
+/// Reads a scanline into `scanline` and returns the bytes after it.
+fn read_scanline<'a>(bytes: &'a [u8], scanline: &mut [[u8; 4]]) -> Result<&'a [u8]> {
+
+    let width = scanline.len();
+    let ends_early = || anyhow!("The pixels end early.");
+
+    // New run-length encoding, the four components one after another, each in runs and literal spans.
+    if (8..0x8000).contains(&width) && bytes.len() >= 4 && bytes[0] == 2 && bytes[1] == 2 && bytes[2] & 0x80 == 0 {
+        if ((bytes[2] as usize) << 8 | bytes[3] as usize) != width {
+            return Err(anyhow!("A scanline has the wrong width."));
+        }
+
+        let mut rest = &bytes[4..];
+
+        for component in 0..4 {
+            let mut x = 0;
+
+            while x < width {
+                let (&count, tail) = rest.split_first().ok_or_else(ends_early)?;
+
+                if count > 128 {
+                    let count = (count - 128) as usize;
+                    let &value = tail.first().ok_or_else(ends_early)?;
+
+                    if x + count > width {
+                        return Err(anyhow!("A run overflows its scanline."));
+                    }
+
+                    scanline[x..x + count].iter_mut().for_each(|pixel| pixel[component] = value);
+                    rest = &tail[1..];
+                    x += count;
+                } else {
+                    let count = count as usize;
+
+                    if count == 0 || x + count > width {
+                        return Err(anyhow!("A span overflows its scanline."));
+                    }
+
+                    let values = tail.get(..count).ok_or_else(ends_early)?;
+
+                    scanline[x..x + count].iter_mut().zip(values).for_each(|(pixel, &value)| pixel[component] = value);
+                    rest = &tail[count..];
+                    x += count;
+                }
+            }
+        }
+
+        return Ok(rest);
+    }
+
+    // Flat pixels, where a pixel of 1, 1, 1 repeats the one before it, with longer counts for consecutive repeats.
+    let mut rest = bytes;
+    let mut x = 0;
+    let mut shift = 0;
+
+    while x < width {
+        let pixel = rest.get(..4).ok_or_else(ends_early)?;
+        rest = &rest[4..];
+
+        if pixel[..3] == [1, 1, 1] && x > 0 {
+            let count = (pixel[3] as usize) << shift;
+            let previous = scanline[x - 1];
+
+            if x + count > width {
+                return Err(anyhow!("A run overflows its scanline."));
+            }
+
+            scanline[x..x + count].iter_mut().for_each(|p| *p = previous);
+            x += count;
+            shift += 8;
+        } else {
+            scanline[x] = [pixel[0], pixel[1], pixel[2], pixel[3]];
+            x += 1;
+            shift = 0;
+        }
+    }
+
+    return Ok(rest);
+}
+
+
+/// The mantissas share the exponent, an exponent of 0 is black.
+fn rgbe_to_rgb([r, g, b, e]: [u8; 4]) -> [f32; 3] {
+    if e == 0 {
+        return [0.0; 3];
+    }
+
+    let scale = 2f32.powi(e as i32 - (128 + 8));
+
+    return [r as f32 * scale, g as f32 * scale, b as f32 * scale];
+}
+
+
+/// Rounds to the nearest half float, values beyond its range become its largest finite value rather than infinity.
+fn f32_to_f16(value: f32) -> u16 {
+
+    let sign = ((value.to_bits() >> 16) & 0x8000) as u16;
+    let value = value.abs();
+
+    if value.is_nan() {
+        return sign | 0x7e00;
+    }
+
+    if value >= 65504.0 {
+        return sign | 0x7bff;
+    }
+
+    // Below the smallest normal half float, in steps of the smallest subnormal one.
+    if value < 6.103_515_6e-5 {
+        return sign | (value / 5.960_464_5e-8).round() as u16;
+    }
+
+    let bits = value.to_bits();
+    let exponent = (bits >> 23) + 15 - 127;
+    let mantissa = bits & 0x7f_ffff;
+
+    // A carry out of the mantissa correctly rounds up into the next exponent.
+    let half = (exponent << 10 | mantissa >> 13) + (mantissa >> 12 & 1);
+
+    return sign | half as u16;
+}
+
+
+/// The face size of the environment cube for an equirectangular image, whose width spans the four faces around the horizon.
+fn cube_size(width: u32) -> u32 {
+    (width / 4).next_power_of_two().clamp(32, MAX_CUBE_SIZE)
+}
+
+
+
+/// The pipelines that precompute the image-based lighting, the sampler of the cubes, the BRDF LUT, which doesn't depend on
+/// the environment, and a black environment to bind until one is loaded.
+pub(crate) unsafe fn create_environment_resources(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .offset(0)
+        .size(size_of::<IblConstants>() as u32);
+
+    let set_layouts = &[*data.ibl_descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+
+    let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.ibl_pipeline_layout = PipelineLayout::new(device.create_pipeline_layout(&pipeline_layout_info, None)?, &data.deletion_queue);
+
+    let layout = *data.ibl_pipeline_layout;
+
+    data.ibl_pipelines = IblPipelines {
+        equirect_to_cube: create_compute_pipeline(device, data, layout, include_bytes!("shaders/equirect_to_cube.spv"))?,
+        irradiance: create_compute_pipeline(device, data, layout, include_bytes!("shaders/irradiance.spv"))?,
+        prefilter: create_compute_pipeline(device, data, layout, include_bytes!("shaders/prefilter.spv"))?,
+        brdf_lut: create_compute_pipeline(device, data, layout, include_bytes!("shaders/brdf_lut.spv"))?
+    };
+
+
+    // Trilinear, the prefiltered cube is sampled between its mips by roughness.
+    let sampler_info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .min_lod(0.0)
+        .max_lod(vk::LOD_CLAMP_NONE);
+
+    data.environment_sampler = Sampler::new(device.create_sampler(&sampler_info, None)?, &data.deletion_queue);
+
+
+    data.brdf_lut = create_image(
+        instance,
+        device,
+        data,
+        BRDF_LUT_SIZE,
+        BRDF_LUT_SIZE,
+        vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+        ENVIRONMENT_FORMAT,
+        1,
+        1,
+        vk::SampleCountFlags::_1,
+        vk::ImageCreateFlags::empty())?;
+
+    data.brdf_lut_view = create_image_view(&data.brdf_lut, device, data, ENVIRONMENT_FORMAT, subresource_range(0, 1, 1), vk::ImageViewType::_2D)?;
+
+    let pool = create_ibl_descriptor_pool(device, data, 1)?;
+    let set = ibl_descriptor_set(device, data, &pool, None, *data.brdf_lut_view)?;
+
+    let black = [
+        create_cube(instance, device, data, 1, 1, vk::ImageUsageFlags::TRANSFER_DST)?,
+        create_cube(instance, device, data, 1, 1, vk::ImageUsageFlags::TRANSFER_DST)?,
+        create_cube(instance, device, data, 1, 1, vk::ImageUsageFlags::TRANSFER_DST)?
+    ];
+
+
+    let command_buffer = begin_single_time_commands(device, data)?;
+
+    cmd_barrier(device, command_buffer, *data.brdf_lut, subresource_range(0, 1, 1), None, Access::StorageWrite);
+
+    let constants = IblConstants { sample_count: SAMPLE_COUNT, ..Default::default() };
+    cmd_dispatch_ibl(device, data, command_buffer, IblDispatch { pipeline: &data.ibl_pipelines.brdf_lut, set, constants, size: BRDF_LUT_SIZE, layers: 1 });
+
+    cmd_barrier(device, command_buffer, *data.brdf_lut, subresource_range(0, 1, 1), Some(Access::StorageWrite), Access::Sampled);
+
+    let clear_color = vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] };
+
+    for cube in &black {
+        cmd_barrier(device, command_buffer, *cube.image, subresource_range(0, 1, 6), None, Access::TransferDst);
+        device.cmd_clear_color_image(command_buffer, *cube.image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &clear_color, &[subresource_range(0, 1, 6)]);
+        cmd_barrier(device, command_buffer, *cube.image, subresource_range(0, 1, 6), Some(Access::TransferDst), Access::Sampled);
+    }
+
+    end_single_time_commands(device, data, command_buffer)?;
+
+
+    let [cube, irradiance, prefiltered] = black;
+
+    data.environment = Environment { loaded: false, cube, irradiance, prefiltered };
+
+    info!("Created environment resources!");
+
+    return Ok(());
+}
+
+
+/// Loads an equirectangular .hdr environment map, converts it to a cube and precomputes its irradiance and prefiltered cubes.
+/// Replaces the environment bound at set 0, the GPU has to be idle for that.
+pub(crate) unsafe fn load_environment(instance: &Instance, device: &Device, data: &mut AppData, path: &Path) -> Result<()> {
+
+    let bytes = fs::read(path).map_err(|e| anyhow!("Couldn't read environment {}: {}", path.display(), e))?;
+    let hdr = decode_hdr(&bytes).map_err(|e| anyhow!("Couldn't decode environment {}: {}", path.display(), e))?;
+
+    let texels = hdr.pixels.chunks_exact(3)
+        .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 1.0])
+        .map(f32_to_f16)
+        .collect::<Vec<_>>();
+
+    let staging_buffer = create_buffer(
+        (texels.len() * size_of::<u16>()) as u64,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        device,
+        instance,
+        data)?;
+
+    fill_buffer(&staging_buffer.allocation, texels.as_ptr(), texels.len())?;
+
+
+    let equirect = create_image(
+        instance,
+        device,
+        data,
+        hdr.width,
+        hdr.height,
+        vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        ENVIRONMENT_FORMAT,
+        1,
+        1,
+        vk::SampleCountFlags::_1,
+        vk::ImageCreateFlags::empty())?;
+
+    let equirect_view = create_image_view(&equirect, device, data, ENVIRONMENT_FORMAT, subresource_range(0, 1, 1), vk::ImageViewType::_2D)?;
+
+    let size = cube_size(hdr.width);
+    let mip_levels = size.trailing_zeros() + 1;
+
+    let cube = create_cube(instance, device, data, size, mip_levels, vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST)?;
+    let irradiance = create_cube(instance, device, data, IRRADIANCE_SIZE, 1, vk::ImageUsageFlags::STORAGE)?;
+    let prefiltered = create_cube(instance, device, data, PREFILTERED_SIZE, PREFILTERED_MIPS, vk::ImageUsageFlags::STORAGE)?;
+
+    // The compute shaders write the faces as the layers of an array, a mip at a time.
+    let face_views = |cube: &Cube, mip_level| create_image_view(&cube.image, device, data, ENVIRONMENT_FORMAT, subresource_range(mip_level, 1, 6), vk::ImageViewType::_2D_ARRAY);
+
+    let cube_face_view = face_views(&cube, 0)?;
+    let irradiance_face_view = face_views(&irradiance, 0)?;
+    let prefiltered_face_views = (0..PREFILTERED_MIPS).map(|mip_level| face_views(&prefiltered, mip_level)).collect::<Result<Vec<_>>>()?;
+
+    let pool = create_ibl_descriptor_pool(device, data, 2 + PREFILTERED_MIPS)?;
+
+    let cube_set = ibl_descriptor_set(device, data, &pool, Some(*equirect_view), *cube_face_view)?;
+    let irradiance_set = ibl_descriptor_set(device, data, &pool, Some(*cube.view), *irradiance_face_view)?;
+    let prefiltered_sets = prefiltered_face_views.iter()
+        .map(|view| ibl_descriptor_set(device, data, &pool, Some(*cube.view), **view))
+        .collect::<Result<Vec<_>>>()?;
+
+
+    let command_buffer = begin_single_time_commands(device, data)?;
+
+    cmd_barrier(device, command_buffer, *equirect, subresource_range(0, 1, 1), None, Access::TransferDst);
+
+    let region = vk::BufferImageCopy::builder()
+        .image_subresource(vk::ImageSubresourceLayers { aspect_mask: vk::ImageAspectFlags::COLOR, mip_level: 0, base_array_layer: 0, layer_count: 1 })
+        .image_extent(vk::Extent3D { width: hdr.width, height: hdr.height, depth: 1 });
+
+    device.cmd_copy_buffer_to_image(command_buffer, *staging_buffer, *equirect, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &[region]);
+
+    cmd_barrier(device, command_buffer, *equirect, subresource_range(0, 1, 1), Some(Access::TransferDst), Access::Sampled);
+
+
+    let constants = IblConstants { roughness: 0.0, sample_count: SAMPLE_COUNT, source_size: size as f32, _padding: 0.0 };
+
+    cmd_barrier(device, command_buffer, *cube.image, subresource_range(0, 1, 6), None, Access::StorageWrite);
+    cmd_dispatch_ibl(device, data, command_buffer, IblDispatch { pipeline: &data.ibl_pipelines.equirect_to_cube, set: cube_set, constants, size, layers: 6 });
+
+    cmd_generate_cube_mipmaps(device, command_buffer, &cube);
+
+
+    cmd_barrier(device, command_buffer, *irradiance.image, subresource_range(0, 1, 6), None, Access::StorageWrite);
+    cmd_dispatch_ibl(device, data, command_buffer, IblDispatch { pipeline: &data.ibl_pipelines.irradiance, set: irradiance_set, constants, size: IRRADIANCE_SIZE, layers: 6 });
+    cmd_barrier(device, command_buffer, *irradiance.image, subresource_range(0, 1, 6), Some(Access::StorageWrite), Access::Sampled);
+
+
+    cmd_barrier(device, command_buffer, *prefiltered.image, subresource_range(0, PREFILTERED_MIPS, 6), None, Access::StorageWrite);
+
+    for (mip_level, set) in prefiltered_sets.into_iter().enumerate() {
+        let roughness = mip_level as f32 / (PREFILTERED_MIPS - 1) as f32;
+
+        cmd_dispatch_ibl(device, data, command_buffer, IblDispatch {
+            pipeline: &data.ibl_pipelines.prefilter,
+            set,
+            constants: IblConstants { roughness, ..constants },
+            size: PREFILTERED_SIZE >> mip_level,
+            layers: 6
+        });
+    }
+
+    cmd_barrier(device, command_buffer, *prefiltered.image, subresource_range(0, PREFILTERED_MIPS, 6), Some(Access::StorageWrite), Access::Sampled);
+
+    end_single_time_commands(device, data, command_buffer)?;
+
+
+    // Earlier frames may still sample the old environment through the set 0 of their swapchain image.
+    device.device_wait_idle()?;
+
+    data.environment = Environment { loaded: true, cube, irradiance, prefiltered };
+
+    update_environment_descriptor_sets(device, data);
+
+    info!("Loaded the {}x{} environment {} into a cube of {}^2 faces", hdr.width, hdr.height, path.display(), size);
+
+    return Ok(());
+}
+
+
+/// A cube that's sampled and written by `usage`.
+unsafe fn create_cube(instance: &Instance, device: &Device, data: &mut AppData, size: u32, mip_levels: u32, usage: vk::ImageUsageFlags) -> Result<Cube> {
+
+    let image = create_image(
+        instance,
+        device,
+        data,
+        size,
+        size,
+        vk::ImageUsageFlags::SAMPLED | usage,
+        ENVIRONMENT_FORMAT,
+        mip_levels,
+        6,
+        vk::SampleCountFlags::_1,
+        vk::ImageCreateFlags::CUBE_COMPATIBLE)?;
+
+    let view = create_image_view(&image, device, data, ENVIRONMENT_FORMAT, subresource_range(0, mip_levels, 6), vk::ImageViewType::CUBE)?;
+
+    return Ok(Cube { image, view, size, mip_levels });
+}
+
+
+fn subresource_range(base_mip_level: u32, level_count: u32, layer_count: u32) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(base_mip_level)
+        .level_count(level_count)
+        .base_array_layer(0)
+        .layer_count(layer_count)
+        .build()
+}
+
+
+/// Transitions part of an image from `old` (or nothing it has to keep) to `new`, waiting for the writes of `old`.
+unsafe fn cmd_barrier(device: &Device, command_buffer: vk::CommandBuffer, image: vk::Image, range: vk::ImageSubresourceRange, old: Option<Access>, new: Access) {
+
+    let (src_stage_mask, src_access_mask, old_layout) = match old {
+        Some(access) => (access.stages(), access.write_access(), access.layout()),
+        None => (vk::PipelineStageFlags::TOP_OF_PIPE, vk::AccessFlags::empty(), vk::ImageLayout::UNDEFINED)
+    };
+
+    let barrier = vk::ImageMemoryBarrier::builder()
+        .src_access_mask(src_access_mask)
+        .dst_access_mask(new.access())
+        .old_layout(old_layout)
+        .new_layout(new.layout())
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(range);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        src_stage_mask,
+        new.stages(),
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[barrier]);
+}
+
+
+/// Fills the mips of a cube whose mip 0 was just written by a compute shader by blitting each from the one before, all faces at once.
+unsafe fn cmd_generate_cube_mipmaps(device: &Device, command_buffer: vk::CommandBuffer, cube: &Cube) {
+
+    cmd_barrier(device, command_buffer, *cube.image, subresource_range(0, 1, 6), Some(Access::StorageWrite), Access::TransferSrc);
+
+    let layers = |mip_level| vk::ImageSubresourceLayers { aspect_mask: vk::ImageAspectFlags::COLOR, mip_level, base_array_layer: 0, layer_count: 6 };
+    let corner = |size: u32| vk::Offset3D { x: size.max(1) as i32, y: size.max(1) as i32, z: 1 };
+
+    for mip_level in 1..cube.mip_levels {
+        cmd_barrier(device, command_buffer, *cube.image, subresource_range(mip_level, 1, 6), None, Access::TransferDst);
+
+        let blit = vk::ImageBlit::builder()
+            .src_subresource(layers(mip_level - 1))
+            .src_offsets([vk::Offset3D::default(), corner(cube.size >> (mip_level - 1))])
+            .dst_subresource(layers(mip_level))
+            .dst_offsets([vk::Offset3D::default(), corner(cube.size >> mip_level)]);
+
+        device.cmd_blit_image(
+            command_buffer,
+            *cube.image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            *cube.image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[blit],
+            vk::Filter::LINEAR);
+
+        cmd_barrier(device, command_buffer, *cube.image, subresource_range(mip_level, 1, 6), Some(Access::TransferDst), Access::TransferSrc);
+    }
+
+    cmd_barrier(device, command_buffer, *cube.image, subresource_range(0, cube.mip_levels, 6), Some(Access::TransferSrc), Access::Sampled);
+}
+
+
+unsafe fn cmd_dispatch_ibl(device: &Device, data: &AppData, command_buffer: vk::CommandBuffer, dispatch: IblDispatch) {
+
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, **dispatch.pipeline);
+    device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::COMPUTE, *data.ibl_pipeline_layout, 0, &[dispatch.set], &[]);
+
+    let constant_bytes = std::slice::from_raw_parts((&dispatch.constants as *const IblConstants).cast::<u8>(), size_of::<IblConstants>());
+    device.cmd_push_constants(command_buffer, *data.ibl_pipeline_layout, vk::ShaderStageFlags::COMPUTE, 0, constant_bytes);
+
+    let groups = dispatch.size.div_ceil(IBL_TILE);
+    device.cmd_dispatch(command_buffer, groups, groups, dispatch.layers);
+}
+
+
+/// A pool for the descriptor sets of one precomputation, dropped once it's done.
+unsafe fn create_ibl_descriptor_pool(device: &Device, data: &AppData, sets: u32) -> Result<DescriptorPool> {
+
+    let sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(sets);
+
+    let storage_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_IMAGE)
+        .descriptor_count(sets);
+
+    let pool_sizes = &[sampler_size, storage_size];
+
+    let pool_create_info = vk::DescriptorPoolCreateInfo::builder()
+        .max_sets(sets)
+        .pool_sizes(pool_sizes);
+
+    return Ok(DescriptorPool::new(device.create_descriptor_pool(&pool_create_info, None)?, &data.deletion_queue));
+}
+
+
+/// A set of the image an IBL shader reads, if any, and the one it writes.
+unsafe fn ibl_descriptor_set(device: &Device, data: &AppData, pool: &DescriptorPool, source: Option<vk::ImageView>, target: vk::ImageView) -> Result<vk::DescriptorSet> {
+
+    let set_layouts = &[*data.ibl_descriptor_set_layout];
+
+    let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(**pool)
+        .set_layouts(set_layouts);
+
+    let set = device.allocate_descriptor_sets(&allocate_info)?[0];
+
+    let source_infos = source.map(|view| [vk::DescriptorImageInfo::builder()
+        .sampler(*data.environment_sampler)
+        .image_view(view)
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .build()]);
+
+    let target_infos = [vk::DescriptorImageInfo::builder()
+        .image_view(target)
+        .image_layout(vk::ImageLayout::GENERAL)
+        .build()];
+
+    let mut writes = vec![vk::WriteDescriptorSet::builder()
+        .dst_set(set)
+        .dst_binding(1)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .image_info(&target_infos)];
+
+    if let Some(source_infos) = &source_infos {
+        writes.push(vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(source_infos));
+    }
+
+    device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+
+    return Ok(set);
+}
+
+
+/// The pipeline of the skybox, drawn in the main pass after the opaque geometry wherever it left the depth at the far plane.
+/// Like the main pipeline it depends on the render pass and the depth convention, see `create_pipeline`.
+pub(crate) unsafe fn create_skybox_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+
+    let vertex_shader_module = create_shader_module(device, include_bytes!("shaders/skybox_vert.spv"))?;
+    let fragment_shader_module = create_shader_module(device, include_bytes!("shaders/skybox_frag.spv"))?;
+
+    let reverse_z = data.reverse_z as vk::Bool32;
+
+    let map_entries = &[vk::SpecializationMapEntry { constant_id: 0, offset: 0, size: size_of::<vk::Bool32>() }];
+
+    let specialization_info = vk::SpecializationInfo::builder()
+        .map_entries(map_entries)
+        .data(std::slice::from_raw_parts((&reverse_z as *const vk::Bool32).cast::<u8>(), size_of::<vk::Bool32>()));
+
+    let vertex_stage_info = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vertex_shader_module)
+        .name(b"main\0")
+        .specialization_info(&specialization_info);
+
+    let fragment_stage_info = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(fragment_shader_module)
+        .name(b"main\0");
+
+    // The full-screen triangle comes from the vertex index alone.
+    let vertex_input_stage = vk::PipelineVertexInputStateCreateInfo::builder();
+
+    let input_assembly_stage = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain_extent.width as f32)
+        .height(data.swapchain_extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain_extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false)
+        .line_width(1.0);
+
+    let multi_sample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(data.msaa_samples);
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(false);
+
+    let attachments = &[attachment];
+
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .attachments(attachments)
+        .logic_op_enable(false);
+
+    // Drawn at exactly the far plane, so only where the depth is still the clear value passes.
+    let depth_stencil_stage = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(false)
+        .depth_compare_op(if data.reverse_z { vk::CompareOp::GREATER_OR_EQUAL } else { vk::CompareOp::LESS_OR_EQUAL })
+        .stencil_test_enable(false);
+
+    let stages = &[vertex_stage_info, fragment_stage_info];
+
+    let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_stage)
+        .input_assembly_state(&input_assembly_stage)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multi_sample_state)
+        .color_blend_state(&color_blend_state)
+        .depth_stencil_state(&depth_stencil_stage)
+        .layout(*data.pipeline_layout)
+        .render_pass(*data.render_pass)
+        .subpass(0);
+
+    let pipeline = device.create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None);
+
+    device.destroy_shader_module(vertex_shader_module, None);
+    device.destroy_shader_module(fragment_shader_module, None);
+
+    data.skybox_pipeline = Pipeline::new(pipeline?.0, &data.deletion_queue);
+
+    return Ok(());
+}
+
+
+/// Draws the skybox of the loaded environment, with set 0 of the swapchain image already bound.
+pub(crate) unsafe fn cmd_draw_skybox(device: &Device, data: &AppData, command_buffer: vk::CommandBuffer, image_index: usize) {
+
+    if !data.environment.loaded {
+        return;
+    }
+
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, *data.skybox_pipeline);
+    device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, *data.pipeline_layout, 0, &[data.descriptor_sets[image_index]], &[]);
+    device.cmd_draw(command_buffer, 3, 1, 0, 0);
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(width: u32, height: u32) -> Vec<u8> {
+        format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", height, width).into_bytes()
+    }
+
+
+    #[test]
+    fn constants_match_the_shader_layout() {
+        assert_eq!(size_of::<IblConstants>(), 16);
+    }
+
+    #[test]
+    fn flat_pixels_share_an_exponent() {
+        let mut bytes = header(2, 1);
+        bytes.extend_from_slice(&[128, 64, 0, 129, 0, 0, 0, 0]);
+
+        let hdr = decode_hdr(&bytes).unwrap();
+
+        assert_eq!((hdr.width, hdr.height), (2, 1));
+        assert_eq!(hdr.pixels, vec![1.0, 0.5, 0.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn run_length_encoded_scanlines_are_expanded() {
+        let mut bytes = header(8, 1);
+        bytes.extend_from_slice(&[2, 2, 0, 8]);
+        // Red, green and blue each a run of 8, the exponent a literal span of 8.
+        bytes.extend_from_slice(&[128 + 8, 128, 128 + 8, 64, 128 + 8, 32]);
+        bytes.extend_from_slice(&[8, 128, 128, 128, 128, 129, 129, 129, 129]);
+
+        let hdr = decode_hdr(&bytes).unwrap();
+
+        assert_eq!(&hdr.pixels[..3], &[0.5, 0.25, 0.125]);
+        assert_eq!(&hdr.pixels[21..], &[1.0, 0.5, 0.25]);
+    }
+
+    #[test]
+    fn truncated_and_rotated_images_are_errors() {
+        let mut bytes = header(2, 2);
+        bytes.extend_from_slice(&[128, 64, 0, 129]);
+        assert!(decode_hdr(&bytes).is_err());
+
+        assert!(decode_hdr(b"#?RADIANCE\n\n+Y 2 +X 2\n").is_err());
+        assert!(decode_hdr(b"P6\n2 2\n255\n").is_err());
+    }
+
+    #[test]
+    fn the_example_sky_decodes() {
+        let hdr = decode_hdr(&fs::read("resources/sky.hdr").unwrap()).unwrap();
+
+        assert_eq!((hdr.width, hdr.height), (128, 64));
+        assert_eq!(hdr.pixels.len(), 128 * 64 * 3);
+        assert!(hdr.pixels.iter().any(|&value| value > 30.0));
+    }
+
+    #[test]
+    fn half_floats_round_and_saturate() {
+        assert_eq!(f32_to_f16(0.0), 0);
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        assert_eq!(f32_to_f16(0.1), 0x2e66);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+        assert_eq!(f32_to_f16(1e9), 0x7bff);
+        assert_eq!(f32_to_f16(5.960_464_5e-8), 1);
+    }
+
+    #[test]
+    fn cube_faces_match_the_equirect_resolution() {
+        assert_eq!(cube_size(2048), 512);
+        assert_eq!(cube_size(3000), 1024);
+        assert_eq!(cube_size(16384), MAX_CUBE_SIZE);
+        assert_eq!(cube_size(8), 32);
+    }
+}
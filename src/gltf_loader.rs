@@ -0,0 +1,383 @@
+use anyhow::{Result, anyhow};
+use log::*;
+use nalgebra_glm as glm;
+use std::path::Path;
+
+use crate::vertex::Vertex;
+
+
+
+/// Everything imported from a .gltf or .glb file, in glTF's own Y-up coordinate system.
+#[derive(Clone, Debug, Default)]
+pub struct GltfScene {
+    pub meshes: Vec<GltfMesh>,
+    pub materials: Vec<GltfMaterial>,
+    pub textures: Vec<GltfTexture>,
+    pub nodes: Vec<GltfNode>,
+    /// Indices into `nodes` of the nodes without a parent in the default scene.
+    pub roots: Vec<usize>
+}
+
+
+#[derive(Clone, Debug, Default)]
+pub struct GltfMesh {
+    pub name: Option<String>,
+    pub primitives: Vec<GltfPrimitive>
+}
+
+
+#[derive(Clone, Debug, Default)]
+pub struct GltfPrimitive {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    /// Index into `GltfScene::materials`, `None` means the glTF default material.
+    pub material: Option<usize>
+}
+
+
+#[derive(Clone, Debug)]
+pub struct GltfNode {
+    pub name: Option<String>,
+    /// Transform relative to the parent node.
+    pub transform: glm::Mat4,
+    pub mesh: Option<usize>,
+    pub children: Vec<usize>
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GltfAlphaMode {
+    Opaque,
+    Mask,
+    Blend
+}
+
+
+/// A metallic-roughness material, texture fields are indices into `GltfScene::textures`.
+#[derive(Clone, Debug)]
+pub struct GltfMaterial {
+    pub name: Option<String>,
+    pub base_color_factor: glm::Vec4,
+    pub base_color_texture: Option<usize>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub metallic_roughness_texture: Option<usize>,
+    pub normal_texture: Option<usize>,
+    pub normal_scale: f32,
+    pub occlusion_texture: Option<usize>,
+    pub occlusion_strength: f32,
+    pub emissive_factor: glm::Vec3,
+    pub emissive_texture: Option<usize>,
+    pub alpha_mode: GltfAlphaMode,
+    pub alpha_cutoff: f32,
+    pub double_sided: bool
+}
+
+
+impl GltfMaterial {
+    /// Base color, metallic-roughness, normal, occlusion and emissive texture, in the order of `MaterialTextures`.
+    pub fn texture_indices(&self) -> [Option<usize>; 5] {
+        [self.base_color_texture, self.metallic_roughness_texture, self.normal_texture, self.occlusion_texture, self.emissive_texture]
+    }
+}
+
+
+/// A decoded texture image, always RGBA8.
+#[derive(Clone, Debug)]
+pub struct GltfTexture {
+    pub pixels: Vec<u8>,
+    pub width: u32,
+    pub height: u32
+}
+
+
+
+pub fn load_gltf(path: &Path) -> Result<GltfScene> {
+
+    // Takes care of .glb containers, external .bin buffers, data URIs and external/embedded images.
+    let (document, buffers, images) = gltf::import(path)?;
+
+    let textures = document.textures()
+        .map(|t| convert_image(&images[t.source().index()]))
+        .collect::<Vec<_>>();
+
+    let materials = document.materials()
+        .map(|m| convert_material(&m))
+        .collect::<Vec<_>>();
+
+    let meshes = document.meshes()
+        .map(|m| convert_mesh(&m, &buffers))
+        .collect::<Result<Vec<_>>>()?;
+
+    let nodes = document.nodes()
+        .map(|n| GltfNode {
+            name: n.name().map(String::from),
+            transform: glm::Mat4::from(n.transform().matrix()),
+            mesh: n.mesh().map(|m| m.index()),
+            children: n.children().map(|c| c.index()).collect()
+        })
+        .collect::<Vec<_>>();
+
+    let scene = document.default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| anyhow!("{} doesn't contain a scene.", path.display()))?;
+
+    let roots = scene.nodes().map(|n| n.index()).collect::<Vec<_>>();
+
+    info!("Loaded {}: {} meshes, {} materials, {} textures, {} nodes", path.display(), meshes.len(), materials.len(), textures.len(), nodes.len());
+
+    return Ok(GltfScene { meshes, materials, textures, nodes, roots });
+}
+
+
+
+impl GltfScene {
+    /// Calls `f` with every node and its world transform, parents before children.
+    pub fn visit_nodes<F: FnMut(&GltfNode, &glm::Mat4)>(&self, mut f: F) {
+        // Pushed in reverse so that siblings are popped in file order.
+        let mut stack = self.roots.iter().rev()
+            .map(|r| (*r, glm::Mat4::identity()))
+            .collect::<Vec<_>>();
+
+        while let Some((index, parent_transform)) = stack.pop() {
+            let node = &self.nodes[index];
+            let world = parent_transform * node.transform;
+
+            f(node, &world);
+
+            stack.extend(node.children.iter().rev().map(|c| (*c, world)));
+        }
+    }
+}
+
+
+
+fn convert_mesh(mesh: &gltf::Mesh, buffers: &[gltf::buffer::Data]) -> Result<GltfMesh> {
+
+    let mut primitives = vec![];
+
+    for primitive in mesh.primitives() {
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            warn!("Skipping primitive of mesh {:?}: {:?} isn't supported", mesh.name(), primitive.mode());
+            continue;
+        }
+
+        let reader = primitive.reader(|b| Some(&buffers[b.index()]));
+
+        let positions = reader.read_positions()
+            .ok_or_else(|| anyhow!("Primitive of mesh {:?} has no positions.", mesh.name()))?
+            .collect::<Vec<_>>();
+
+        let normals = reader.read_normals().map(|n| n.collect::<Vec<_>>());
+        let tex_coords = reader.read_tex_coords(0).map(|t| t.into_f32().collect::<Vec<_>>());
+        let colors = reader.read_colors(0).map(|c| c.into_rgb_f32().collect::<Vec<_>>());
+
+        let indices = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+            None => (0..positions.len() as u32).collect()
+        };
+
+        let mut vertices = positions.iter()
+            .enumerate()
+            .map(|(i, p)| Vertex::new(
+                glm::Vec3::from(*p),
+                colors.as_ref().map(|c| glm::Vec3::from(c[i])).unwrap_or_else(|| glm::vec3(1.0, 1.0, 1.0)),
+                tex_coords.as_ref().map(|t| glm::Vec2::from(t[i])).unwrap_or_else(glm::Vec2::zeros),
+                normals.as_ref().map(|n| glm::Vec3::from(n[i])).unwrap_or_else(glm::Vec3::zeros)
+            ))
+            .collect::<Vec<_>>();
+
+        if normals.is_none() {
+            compute_smooth_normals(&mut vertices, &indices);
+        }
+
+        primitives.push(GltfPrimitive { vertices, indices, material: primitive.material().index() });
+    }
+
+    return Ok(GltfMesh { name: mesh.name().map(String::from), primitives });
+}
+
+
+/// Gives every vertex the average of the normals of the faces around it, for geometry that doesn't provide normals.
+/// glTF asks for flat normals in that case, which these only are for vertices that aren't shared between faces.
+pub(crate) fn compute_smooth_normals(vertices: &mut [Vertex], indices: &[u32]) {
+    for triangle in indices.chunks_exact(3) {
+        let a = vertices[triangle[0] as usize].pos;
+        let b = vertices[triangle[1] as usize].pos;
+        let c = vertices[triangle[2] as usize].pos;
+        let normal = glm::cross(&(b - a), &(c - a));
+
+        for index in triangle {
+            vertices[*index as usize].normal += normal;
+        }
+    }
+
+    for vertex in vertices.iter_mut() {
+        if vertex.normal != glm::Vec3::zeros() {
+            vertex.normal = glm::normalize(&vertex.normal);
+        }
+    }
+}
+
+
+fn convert_material(material: &gltf::Material) -> GltfMaterial {
+
+    let pbr = material.pbr_metallic_roughness();
+
+    return GltfMaterial {
+        name: material.name().map(String::from),
+        base_color_factor: glm::Vec4::from(pbr.base_color_factor()),
+        base_color_texture: pbr.base_color_texture().map(|t| t.texture().index()),
+        metallic_factor: pbr.metallic_factor(),
+        roughness_factor: pbr.roughness_factor(),
+        metallic_roughness_texture: pbr.metallic_roughness_texture().map(|t| t.texture().index()),
+        normal_texture: material.normal_texture().map(|t| t.texture().index()),
+        normal_scale: material.normal_texture().map(|t| t.scale()).unwrap_or(1.0),
+        occlusion_texture: material.occlusion_texture().map(|t| t.texture().index()),
+        occlusion_strength: material.occlusion_texture().map(|t| t.strength()).unwrap_or(1.0),
+        emissive_factor: glm::Vec3::from(material.emissive_factor()),
+        emissive_texture: material.emissive_texture().map(|t| t.texture().index()),
+        alpha_mode: match material.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => GltfAlphaMode::Opaque,
+            gltf::material::AlphaMode::Mask => GltfAlphaMode::Mask,
+            gltf::material::AlphaMode::Blend => GltfAlphaMode::Blend
+        },
+        alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+        double_sided: material.double_sided()
+    };
+}
+
+
+fn convert_image(image: &gltf::image::Data) -> GltfTexture {
+    use gltf::image::Format;
+
+    // 16-bit and float channels are native endian, they're narrowed to 8 bits.
+    let narrow_u16 = |pixels: &[u8]| pixels.chunks_exact(2)
+        .map(|c| (u16::from_ne_bytes([c[0], c[1]]) >> 8) as u8)
+        .collect::<Vec<_>>();
+    let narrow_f32 = |pixels: &[u8]| pixels.chunks_exact(4)
+        .map(|c| (f32::from_ne_bytes([c[0], c[1], c[2], c[3]]).clamp(0.0, 1.0) * 255.0).round() as u8)
+        .collect::<Vec<_>>();
+
+    let (channels, samples) = match image.format {
+        Format::R8 => (1, image.pixels.clone()),
+        Format::R8G8 => (2, image.pixels.clone()),
+        Format::R8G8B8 => (3, image.pixels.clone()),
+        Format::R8G8B8A8 => (4, image.pixels.clone()),
+        Format::R16 => (1, narrow_u16(&image.pixels)),
+        Format::R16G16 => (2, narrow_u16(&image.pixels)),
+        Format::R16G16B16 => (3, narrow_u16(&image.pixels)),
+        Format::R16G16B16A16 => (4, narrow_u16(&image.pixels)),
+        Format::R32G32B32FLOAT => (3, narrow_f32(&image.pixels)),
+        Format::R32G32B32A32FLOAT => (4, narrow_f32(&image.pixels))
+    };
+
+    let pixels = samples.chunks_exact(channels)
+        .flat_map(|p| match channels {
+            1 => [p[0], p[0], p[0], 255],
+            2 => [p[0], p[0], p[0], p[1]],
+            3 => [p[0], p[1], p[2], 255],
+            _ => [p[0], p[1], p[2], p[3]]
+        })
+        .collect::<Vec<_>>();
+
+    return GltfTexture { pixels, width: image.width, height: image.height };
+}
+
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A single triangle without normals, instanced by a child node translated along X under a root translated along Y.
+    pub(crate) const TRIANGLE_GLTF: &str = r#"{
+        "asset": { "version": "2.0" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [
+            { "name": "root", "translation": [0.0, 2.0, 0.0], "children": [1] },
+            { "name": "child", "translation": [3.0, 0.0, 0.0], "mesh": 0 }
+        ],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1, "material": 0 }] }],
+        "materials": [{ "name": "red", "pbrMetallicRoughness": { "baseColorFactor": [1.0, 0.0, 0.0, 1.0], "metallicFactor": 0.0 } }],
+        "buffers": [{ "byteLength": 44, "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAAABAAIAAAA=" }],
+        "bufferViews": [
+            { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 36, "byteLength": 6 }
+        ],
+        "accessors": [
+            { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0] },
+            { "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }
+        ]
+    }"#;
+
+
+    fn load_triangle() -> GltfScene {
+        let path = std::env::temp_dir().join(format!("rustbbbb_triangle_{}.gltf", std::process::id()));
+        std::fs::write(&path, TRIANGLE_GLTF).unwrap();
+        let scene = load_gltf(&path);
+        std::fs::remove_file(&path).unwrap();
+        scene.unwrap()
+    }
+
+
+    #[test]
+    fn loads_meshes_and_materials() {
+        let scene = load_triangle();
+
+        let primitive = &scene.meshes[0].primitives[0];
+        assert_eq!(primitive.indices, vec![0, 1, 2]);
+        assert_eq!(primitive.vertices[1].pos, glm::vec3(1.0, 0.0, 0.0));
+        assert_eq!(primitive.vertices[0].normal, glm::vec3(0.0, 0.0, 1.0));
+        assert_eq!(primitive.material, Some(0));
+
+        let material = &scene.materials[0];
+        assert_eq!(material.name.as_deref(), Some("red"));
+        assert_eq!(material.base_color_factor, glm::vec4(1.0, 0.0, 0.0, 1.0));
+        assert_eq!(material.metallic_factor, 0.0);
+        assert_eq!(material.alpha_mode, GltfAlphaMode::Opaque);
+    }
+
+    #[test]
+    fn visits_nodes_with_world_transforms() {
+        let scene = load_triangle();
+
+        let mut visited = vec![];
+        scene.visit_nodes(|node, world| visited.push((node.name.clone().unwrap(), world.column(3).xyz())));
+
+        assert_eq!(scene.roots, vec![0]);
+        assert_eq!(visited, vec![
+            ("root".to_string(), glm::vec3(0.0, 2.0, 0.0)),
+            ("child".to_string(), glm::vec3(3.0, 2.0, 0.0))
+        ]);
+    }
+
+    #[test]
+    fn visits_siblings_in_file_order() {
+        let node = |name: &str, children: Vec<usize>| GltfNode { name: Some(name.to_string()), transform: glm::Mat4::identity(), mesh: None, children };
+        let scene = GltfScene {
+            meshes: vec![],
+            materials: vec![],
+            textures: vec![],
+            nodes: vec![node("a", vec![2, 3]), node("b", vec![]), node("a1", vec![]), node("a2", vec![])],
+            roots: vec![0, 1]
+        };
+
+        let mut visited = vec![];
+        scene.visit_nodes(|node, _| visited.push(node.name.clone().unwrap()));
+
+        assert_eq!(visited, vec!["a", "a1", "a2", "b"]);
+    }
+
+    #[test]
+    fn narrows_16_bit_and_float_textures() {
+        let pixels = [0xffffu16, 0x8000, 0x0000].iter().flat_map(|c| c.to_ne_bytes()).collect();
+        let texture = convert_image(&gltf::image::Data { pixels, format: gltf::image::Format::R16G16B16, width: 1, height: 1 });
+        assert_eq!(texture.pixels, vec![255, 128, 0, 255]);
+
+        let pixels = [2.0f32, 0.5, -1.0, 1.0].iter().flat_map(|c| c.to_ne_bytes()).collect();
+        let texture = convert_image(&gltf::image::Data { pixels, format: gltf::image::Format::R32G32B32A32FLOAT, width: 1, height: 1 });
+        assert_eq!(texture.pixels, vec![255, 128, 0, 255]);
+    }
+}
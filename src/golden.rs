@@ -0,0 +1,191 @@
+use anyhow::{Result, anyhow};
+use log::*;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::{headless::write_png, images::load_png_rgba};
+
+
+/// Directory with the checked-in reference renders.
+const REFERENCE_DIR: &str = "tests/golden";
+
+/// Directory the actual and diff images of failing comparisons are written to.
+const OUTPUT_DIR: &str = "target/golden";
+
+/// Setting this environment variable overwrites the reference images with the current renders.
+const BLESS_VAR: &str = "GOLDEN_BLESS";
+
+
+#[derive(Debug)]
+pub struct ImageComparison {
+    pub mismatched_pixels: usize,
+    pub max_difference: u8,
+    /// RGBA8 image with mismatched pixels in red and matching pixels as a faded copy of the expected image.
+    pub diff: Vec<u8>
+}
+
+
+/// Compares two RGBA8 images of the same size, a pixel mismatches when any channel differs by more than `tolerance`.
+pub fn compare_images(actual: &[u8], expected: &[u8], tolerance: u8) -> ImageComparison {
+
+    let mut mismatched_pixels = 0;
+    let mut max_difference = 0;
+    let mut diff = Vec::with_capacity(expected.len());
+
+    for (a, e) in actual.chunks_exact(4).zip(expected.chunks_exact(4)) {
+        let difference = a.iter()
+            .zip(e.iter())
+            .map(|(a, e)| a.abs_diff(*e))
+            .max()
+            .unwrap_or(0);
+
+        max_difference = max_difference.max(difference);
+
+        if difference > tolerance {
+            mismatched_pixels += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            let luma = (e[0] as u32 + e[1] as u32 + e[2] as u32) / 3;
+            let faded = (luma / 4) as u8;
+            diff.extend_from_slice(&[faded, faded, faded, 255]);
+        }
+    }
+
+    return ImageComparison { mismatched_pixels, max_difference, diff };
+}
+
+
+/// Checks a render against `tests/golden/<name>.png`, allowing `max_mismatched_ratio` of the pixels to be off by more than `tolerance`.
+/// On failure the render and a diff image are written to `target/golden`.
+pub fn check_golden(name: &str, pixels: &[u8], width: u32, height: u32, tolerance: u8, max_mismatched_ratio: f32) -> Result<()> {
+
+    let reference_path = PathBuf::from(REFERENCE_DIR).join(format!("{}.png", name));
+
+    if std::env::var_os(BLESS_VAR).is_some() {
+        fs::create_dir_all(REFERENCE_DIR)?;
+        write_png(&reference_path, width, height, pixels)?;
+        warn!("Blessed new reference image {}", reference_path.display());
+        return Ok(());
+    }
+
+    if !reference_path.exists() {
+        return Err(anyhow!("Reference image {} doesn't exist, run the test with {}=1 to create it.", reference_path.display(), BLESS_VAR));
+    }
+
+    let (expected, expected_width, expected_height) = load_png_rgba(&reference_path)?;
+
+    if (expected_width, expected_height) != (width, height) {
+        return Err(anyhow!("Reference image is {}x{} but the render is {}x{}.", expected_width, expected_height, width, height));
+    }
+
+    let comparison = compare_images(pixels, &expected, tolerance);
+    let mismatched_ratio = comparison.mismatched_pixels as f32 / (width * height) as f32;
+
+    if mismatched_ratio <= max_mismatched_ratio {
+        debug!("{}: {} pixels mismatched, max difference {}", name, comparison.mismatched_pixels, comparison.max_difference);
+        return Ok(());
+    }
+
+
+    fs::create_dir_all(OUTPUT_DIR)?;
+    let actual_path = PathBuf::from(OUTPUT_DIR).join(format!("{}.actual.png", name));
+    let diff_path = PathBuf::from(OUTPUT_DIR).join(format!("{}.diff.png", name));
+
+    write_png(&actual_path, width, height, pixels)?;
+    write_png(&diff_path, width, height, &comparison.diff)?;
+
+    return Err(anyhow!(
+        "{} doesn't match its reference: {} pixels ({:.2}%) differ by more than {}, max difference {}. See {} and {}.",
+        name,
+        comparison.mismatched_pixels,
+        mismatched_ratio * 100.0,
+        tolerance,
+        comparison.max_difference,
+        actual_path.display(),
+        diff_path.display()));
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vulkanalia::loader::{LibloadingLoader, LIBRARY};
+    use nalgebra_glm as glm;
+    use std::path::Path;
+    use crate::{config::RendererConfig, material::{Material, MaterialTextures}, renderer::{ColorSpace, DrawItem, Renderer}};
+
+    const WIDTH: u32 = 320;
+    const HEIGHT: u32 = 240;
+
+
+    #[test]
+    fn identical_images_match() {
+        let image = vec![10, 20, 30, 255, 40, 50, 60, 255];
+        let comparison = compare_images(&image, &image, 0);
+
+        assert_eq!(comparison.mismatched_pixels, 0);
+        assert_eq!(comparison.max_difference, 0);
+        assert_eq!(comparison.diff.len(), image.len());
+    }
+
+    #[test]
+    fn differences_within_tolerance_match() {
+        let expected = vec![100, 100, 100, 255];
+        let actual = vec![103, 98, 100, 255];
+        let comparison = compare_images(&actual, &expected, 3);
+
+        assert_eq!(comparison.mismatched_pixels, 0);
+        assert_eq!(comparison.max_difference, 3);
+    }
+
+    #[test]
+    fn differences_above_tolerance_are_marked() {
+        let expected = vec![100, 100, 100, 255, 0, 0, 0, 255];
+        let actual = vec![100, 100, 100, 255, 0, 50, 0, 255];
+        let comparison = compare_images(&actual, &expected, 8);
+
+        assert_eq!(comparison.mismatched_pixels, 1);
+        assert_eq!(comparison.max_difference, 50);
+        assert_eq!(&comparison.diff[4..], &[255, 0, 0, 255]);
+    }
+
+
+    /// The first frame of the viking room example, a single unrotated model.
+    unsafe fn draw_viking_room(renderer: &mut Renderer) -> Result<Vec<u8>> {
+        let texture = renderer.load_texture(Path::new("resources/texture.png"), ColorSpace::Srgb)?;
+        let material = renderer.create_material(Material::default(), MaterialTextures::base_color(texture))?;
+        let mesh = renderer.load_mesh(Path::new("resources/viking_room.obj"), Some(material))?;
+
+        let transform = glm::translate(&glm::identity(), &glm::vec3(0.0, -1.25, 1.0));
+        renderer.submit(DrawItem { opacity: 0.25, ..DrawItem::new(mesh, transform) });
+
+        return renderer.render_offscreen();
+    }
+
+
+    /// Renders the viking room at the start of its animation and compares it to the reference render.
+    /// Meant to run on a software rasterizer such as lavapipe, so it only runs with `cargo test -- --ignored`, see .github/workflows/golden.yml.
+    /// The reference is blessed on lavapipe as well, by running that workflow with `bless` set.
+    #[test]
+    #[ignore = "needs a Vulkan implementation such as lavapipe"]
+    fn viking_room_matches_reference() {
+        if let Err(error) = unsafe { LibloadingLoader::new(LIBRARY) } {
+            panic!("The golden image test needs a Vulkan loader and driver: {}", error);
+        }
+
+        let config = RendererConfig { width: WIDTH, height: HEIGHT, ..Default::default() };
+
+        let mut renderer = match unsafe { Renderer::new_headless(config) } {
+            Ok(renderer) => renderer,
+            Err(error) => panic!("Couldn't create a headless renderer for the golden image test: {:?}", error)
+        };
+
+        let pixels = unsafe { draw_viking_room(&mut renderer) };
+        unsafe { renderer.destroy() };
+
+        if let Err(error) = check_golden("viking_room", &pixels.unwrap(), WIDTH, HEIGHT, 8, 0.001) {
+            panic!("{:?}", error);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use vulkanalia::prelude::v1_0::*;
+use anyhow::Result;
+use log::*;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::{app::AppData, buffers::{create_buffer, begin_single_time_commands, end_single_time_commands}, images::{create_image, create_image_view}};
+
+
+pub const OFFSCREEN_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+
+/// Creates the image the render pass resolves into when there is no swapchain.
+/// It takes the place of the swapchain images, so command pools, the render graph etc. work the same way.
+pub unsafe fn create_offscreen_target(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    width: u32,
+    height: u32
+) -> Result<()> {
+
+    data.swapchain_extent = vk::Extent2D { width, height };
+    data.swapchain_image_format = OFFSCREEN_FORMAT;
+
+    let image = create_image(
+        instance,
+        device,
+        data,
+        width,
+        height,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+        OFFSCREEN_FORMAT,
+        1,
+        1,
+        vk::SampleCountFlags::_1,
+        vk::ImageCreateFlags::empty())?;
+
+
+    let subresource = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1).build();
+
+    data.swapchain_images = vec![*image];
+    data.swapchain_image_views = vec![create_image_view(&image, device, data, OFFSCREEN_FORMAT, subresource, vk::ImageViewType::_2D)?];
+    data.offscreen_image = image;
+
+    info!("Created offscreen render target ({}x{})", width, height);
+
+    return Ok(());
+}
+
+
+/// Copies the resolved offscreen image back to the host as tightly packed RGBA8 pixels.
+pub unsafe fn read_offscreen_image(instance: &Instance, device: &Device, data: &mut AppData) -> Result<Vec<u8>> {
+
+    let vk::Extent2D { width, height } = data.swapchain_extent;
+    let size = (width * height * 4) as u64;
+
+    let buffer = create_buffer(
+        size,
+        vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        device,
+        instance,
+        data)?;
+
+
+    let command_buffer = begin_single_time_commands(device, data)?;
+
+    let subresource_range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    // The render graph already left the image in TRANSFER_SRC_OPTIMAL, this only makes the resolve visible to the copy.
+    let image_barrier = vk::ImageMemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+        .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(data.swapchain_images[0])
+        .subresource_range(subresource_range);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[image_barrier]);
+
+
+    let subresource = vk::ImageSubresourceLayers::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .mip_level(0)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    let region = vk::BufferImageCopy::builder()
+        .buffer_offset(0)
+        .buffer_row_length(0)
+        .buffer_image_height(0)
+        .image_subresource(subresource)
+        .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+        .image_extent(vk::Extent3D { width, height, depth: 1 });
+
+    device.cmd_copy_image_to_buffer(
+        command_buffer,
+        data.swapchain_images[0],
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        *buffer,
+        &[region]);
+
+
+    let buffer_barrier = vk::BufferMemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::HOST_READ)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .buffer(*buffer)
+        .offset(0)
+        .size(size);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::HOST,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[buffer_barrier],
+        &[] as &[vk::ImageMemoryBarrier]);
+
+    end_single_time_commands(device, data, command_buffer)?;
+
+
+    let pixels = std::slice::from_raw_parts(buffer.allocation.mapped, size as usize).to_vec();
+
+    debug!("Read back {} bytes from the offscreen image", pixels.len());
+
+    return Ok(pixels);
+}
+
+
+pub fn write_png(path: &Path, width: u32, height: u32, pixels: &[u8]) -> Result<()> {
+
+    let file = BufWriter::new(File::create(path)?);
+
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels)?;
+
+    info!("Wrote {}x{} image to {}", width, height, path.display());
+
+    return Ok(());
+}
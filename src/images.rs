@@ -2,68 +2,53 @@ use vulkanalia::prelude::v1_0::*;
 use anyhow::{anyhow, Result};
 use log::*;
 use std::fs::File;
+use std::path::Path;
 use png::ColorType;
 
-use crate::{app::AppData, buffers::{create_buffer, fill_buffer, get_memory_type_index, begin_single_time_commands, end_single_time_commands}};
+use crate::{allocator::{ResourceKind, allocate_memory}, app::AppData, resources::{Image, ImageView, Sampler}, buffers::{create_buffer, fill_buffer, begin_single_time_commands, end_single_time_commands}};
+use crate::render_graph::Access;
 
 
 pub unsafe fn create_image_view(image: &vk::Image,
     device: &Device,
+    data: &AppData,
     format: vk::Format, 
     subresource: vk::ImageSubresourceRange,
-    
-    ) -> Result<vk::ImageView> {
+    view_type: vk::ImageViewType
+    ) -> Result<ImageView> {
 
     let info = vk::ImageViewCreateInfo::builder()
         .image(*image)
         .subresource_range(subresource)
-        .view_type(vk::ImageViewType::_2D)
+        .view_type(view_type)
         .format(format);
         
     debug!("Image view has been created");
-    return Ok(device.create_image_view(&info, None)?);
+    return Ok(ImageView::new(device.create_image_view(&info, None)?, &data.deletion_queue));
 }
 
 
 
-pub unsafe fn create_texture_image(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
-
-    let image = File::open("resources/texture.png")?;
-
-    let decorder = png::Decoder::new(image);
-    let mut reader = decorder.read_info()?;
-
-
-    debug!("Color type is {:?}", reader.info().color_type);
-
-
-    let mut buffer = vec![0; reader.info().raw_bytes()];
-    reader.next_frame(&mut buffer)?;
-
-    let pixels: Vec<u8>;
-
+/// A sampled RGBA8 texture with a full mip chain, sampled with `AppData::texture_image_sampler`.
+#[derive(Debug, Default)]
+pub struct Texture {
+    /// Only sampled through `view`, but owned here so it lives as long as the view.
+    #[allow(dead_code)]
+    pub image: Image,
+    pub view: ImageView
+}
 
-    if reader.info().color_type == ColorType::Rgb {
-        // convert to RGBA
-        pixels = buffer.chunks_exact(3)
-        .flat_map(|rgb| {
-            vec!(rgb[0], rgb[1], rgb[2], 255)
-        })
-        .collect::<Vec<_>>();
-        debug!("Image converted to RGBA")
-    } else {
-        pixels = buffer;
-    }
 
+/// Uploads tightly packed RGBA8 `pixels` into a new texture of `format` (R8G8B8A8 SRGB or UNORM) and generates its mipmaps.
+pub unsafe fn create_texture(instance: &Instance, device: &Device, data: &mut AppData, pixels: &[u8], width: u32, height: u32, format: vk::Format) -> Result<Texture> {
 
     let size = pixels.len() as u64;
-    let (width, height) = reader.info().size();
 
 
-    data.mip_levels = (width.max(height) as f32).log2().floor() as u32 + 1;
+    let mip_levels = (width.max(height) as f32).log2().floor() as u32 + 1;
 
 
-    let (staging_buffer, staging_buffer_memory) = create_buffer(
+    let staging_buffer = create_buffer(
         size, 
         vk::BufferUsageFlags::TRANSFER_SRC, 
         vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT, 
@@ -72,15 +57,10 @@ pub unsafe fn create_texture_image(instance: &Instance, device: &Device, data: &
         data)?;
 
     
-    fill_buffer(&staging_buffer, 
-        &staging_buffer_memory, 
-        &size, 
-        pixels.as_ptr(), 
-        pixels.len(), 
-        device)?;
+    fill_buffer(&staging_buffer.allocation, pixels.as_ptr(), pixels.len())?;
 
 
-    let (image, image_memory) = create_image(
+    let image = create_image(
         instance, 
         device, 
         data, 
@@ -88,47 +68,84 @@ pub unsafe fn create_texture_image(instance: &Instance, device: &Device, data: &
         height, 
         vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST | 
         vk::ImageUsageFlags::TRANSFER_SRC,
-    vk::Format::R8G8B8A8_SRGB,
-        data.mip_levels,
-        vk::SampleCountFlags::_1
+        format,
+        mip_levels,
+        1,
+        vk::SampleCountFlags::_1,
+        vk::ImageCreateFlags::empty()
         )?;
-    
-    device.bind_image_memory(image, image_memory, 0)?;
 
     transition_image_layout(
         device, 
         data, 
-        image, 
+        *image, 
         vk::ImageLayout::UNDEFINED, 
         vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-        data.mip_levels
+        mip_levels
     )?;
 
 
-    copy_buffer_to_image(device, data, staging_buffer, image, width, height)?;
-
-    device.destroy_buffer(staging_buffer, None);
-    device.free_memory(staging_buffer_memory, None);
+    copy_buffer_to_image(device, data, *staging_buffer, *image, width, height)?;
 
 
     generate_mipmaps(
         instance, 
         device, 
         data, 
-        image, 
-        vk::Format::R8G8B8A8_SRGB,
+        *image, 
+        format,
         width, 
         height, 
-        data.mip_levels)?;
+        mip_levels)?;
 
-    
 
+    let subresource = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(mip_levels)
+        .base_array_layer(0)
+        .layer_count(1).build();
 
-    data.texture_image = image;
-    data.texture_image_memory = image_memory;
+    let view = create_image_view(&image, device, data, format, subresource, vk::ImageViewType::_2D)?;
 
 
-    return Ok(());
+    return Ok(Texture { image, view });
+}
+
+
+/// Decodes a PNG file into RGBA8 pixels, adding an opaque alpha channel to RGB images.
+pub fn load_png_rgba<P: AsRef<Path>>(path: P) -> Result<(Vec<u8>, u32, u32)> {
+
+    let image = File::open(path)?;
+
+    let decorder = png::Decoder::new(image);
+    let mut reader = decorder.read_info()?;
+
+
+    debug!("Color type is {:?}", reader.info().color_type);
+
+
+    let mut buffer = vec![0; reader.info().raw_bytes()];
+    reader.next_frame(&mut buffer)?;
+
+    let pixels: Vec<u8>;
+
+
+    if reader.info().color_type == ColorType::Rgb {
+        // convert to RGBA
+        pixels = buffer.chunks_exact(3)
+        .flat_map(|rgb| {
+            vec!(rgb[0], rgb[1], rgb[2], 255)
+        })
+        .collect::<Vec<_>>();
+        debug!("Image converted to RGBA")
+    } else {
+        pixels = buffer;
+    }
+
+    let (width, height) = reader.info().size();
+
+    return Ok((pixels, width, height));
 }
 
 
@@ -281,24 +298,7 @@ pub unsafe fn generate_mipmaps(
 }
 
 
-pub unsafe fn create_texture_image_view(device: &Device, data: &mut AppData) -> Result<()> {
-
-
-    let subresource = vk::ImageSubresourceRange::builder()
-        .aspect_mask(vk::ImageAspectFlags::COLOR)
-        .base_mip_level(0)
-        .level_count(data.mip_levels)
-        .base_array_layer(0)
-        .layer_count(1).build();
-
-
-    data.texture_image_view = create_image_view(&data.texture_image, device, vk::Format::R8G8B8A8_SRGB, subresource)?;
-
-
-
-    return Ok(());
-}
-
+/// Creates the sampler shared by all textures, `max_lod` is unclamped so it works for every mip chain length.
 pub unsafe fn create_texture_sampler(device: &Device, data: &mut AppData) -> Result<()> {
 
     let create_info = vk::SamplerCreateInfo::builder()
@@ -316,10 +316,10 @@ pub unsafe fn create_texture_sampler(device: &Device, data: &mut AppData) -> Res
         .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
         .mip_lod_bias(0.0)
         .min_lod(0.0)
-        .max_lod(data.mip_levels as f32);
+        .max_lod(vk::LOD_CLAMP_NONE);
 
 
-    data.texture_image_sampler = device.create_sampler(&create_info, None)?;
+    data.texture_image_sampler = Sampler::new(device.create_sampler(&create_info, None)?, &data.deletion_queue);
 
     
 
@@ -336,25 +336,26 @@ pub unsafe fn transition_image_layout(
     mip_levels: u32
 ) -> Result<()> {
     
-    let (src_access_mask, dst_access_mask, src_stage_mask, dst_stage_mask) = match (old_layout, new_layout) {
-        (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL) => (
-            vk::AccessFlags::empty(),
-            vk::AccessFlags::TRANSFER_WRITE,
-            vk::PipelineStageFlags::TOP_OF_PIPE,
-            vk::PipelineStageFlags::TRANSFER
-        ),
-        (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
-            vk::AccessFlags::TRANSFER_WRITE,
-            vk::AccessFlags::SHADER_READ,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::PipelineStageFlags::FRAGMENT_SHADER
-        ),
-        _ => return Err(anyhow!("Not a supported image transition."))
+    // The stages and accesses of the layouts are the ones the render graph synchronizes with.
+    let (src_stage_mask, src_access_mask) = match old_layout {
+        vk::ImageLayout::UNDEFINED => (vk::PipelineStageFlags::TOP_OF_PIPE, vk::AccessFlags::empty()),
+        layout => Access::from_layout(layout)
+            .map(|access| (access.stages(), access.write_access()))
+            .ok_or_else(|| anyhow!("Not a supported image transition."))?
+    };
+
+    let (dst_stage_mask, dst_access_mask) = Access::from_layout(new_layout)
+        .map(|access| (access.stages(), access.access()))
+        .ok_or_else(|| anyhow!("Not a supported image transition."))?;
+
+    let aspect_mask = match new_layout {
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL | vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL => vk::ImageAspectFlags::DEPTH,
+        _ => vk::ImageAspectFlags::COLOR
     };
 
 
     let subresource = vk::ImageSubresourceRange::builder()
-        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .aspect_mask(aspect_mask)
         .base_mip_level(0)
         .level_count(mip_levels)
         .base_array_layer(0)
@@ -442,19 +443,22 @@ pub unsafe fn create_image(instance: &Instance,
     usage: vk::ImageUsageFlags, 
     format: vk::Format,
     mip_levels: u32,
-    samples: vk::SampleCountFlags
-) -> Result<(vk::Image, vk::DeviceMemory)> {
+    array_layers: u32,
+    samples: vk::SampleCountFlags,
+    flags: vk::ImageCreateFlags
+) -> Result<Image> {
 
 
 
     let indicies = &[data.queue_family_indicies.graphics];
 
     let info = vk::ImageCreateInfo::builder()
+    .flags(flags)
     .image_type(vk::ImageType::_2D)
     .format(format)
     .extent(vk::Extent3D {width, height, depth: 1})
     .mip_levels(mip_levels)
-    .array_layers(1)
+    .array_layers(array_layers)
     .samples(samples)
     .tiling(vk::ImageTiling::OPTIMAL)
     .usage(usage)
@@ -468,21 +472,19 @@ pub unsafe fn create_image(instance: &Instance,
     let requirements = device.get_image_memory_requirements(image);
 
 
-    let allocate_info = vk::MemoryAllocateInfo::builder()
-        .allocation_size(requirements.size)
-        .memory_type_index(
-            get_memory_type_index(
-            instance, 
-            data, 
-            vk::MemoryPropertyFlags::DEVICE_LOCAL, 
-            requirements)?);
-    
+    let allocation = allocate_memory(
+        instance, 
+        device, 
+        data, 
+        requirements, 
+        vk::MemoryPropertyFlags::DEVICE_LOCAL, 
+        ResourceKind::Optimal)?;
 
-    let memory = device.allocate_memory(&allocate_info, None)?;
+    device.bind_image_memory(image, allocation.memory, allocation.offset)?;
 
     debug!("Image has been created!");
 
-    return Ok((image, memory));
+    return Ok(Image::new(image, allocation, &data.deletion_queue));
 }
 
 
@@ -527,90 +529,3 @@ pub unsafe fn get_depth_format(instance: &Instance, data: &AppData) -> Result<vk
         vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
     )
 }
-
-
-
-pub unsafe fn create_depth_buffer(
-    instance: &Instance,
-    device: &Device, 
-    data: &mut AppData
-) -> Result<()> {
-
-
-    let format = get_depth_format(instance, data)?;
-
-    let (depth_image, depth_image_memory) = create_image(
-        instance, 
-        device, 
-        data, 
-        data.swapchain_extent.width, 
-        data.swapchain_extent.height, 
-        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT, 
-        format,
-        1,
-        data.msaa_samples
-    )?;
-
-    device.bind_image_memory(depth_image, depth_image_memory, 0)?;
-
-
-    data.depth_image = depth_image;
-    data.depth_image_memory = depth_image_memory;
-
-
-    let subresource = vk::ImageSubresourceRange::builder()
-        .aspect_mask(vk::ImageAspectFlags::DEPTH)
-        .base_mip_level(0)
-        .level_count(1)
-        .base_array_layer(0)
-        .layer_count(1).build();
-
-    data.depth_image_view = create_image_view(&data.depth_image, device, format, subresource)?;
-
-    return Ok(());
-}
-
-
-
-pub unsafe fn create_color_buffer(
-    instance: &Instance,
-    device: &Device,
-    data: &mut AppData
-) -> Result<()> {
-    
-    let (color_image, color_image_memory) = create_image(
-        instance, 
-        device, 
-        data, 
-        data.swapchain_extent.width, 
-        data.swapchain_extent.height, 
-        vk::ImageUsageFlags::COLOR_ATTACHMENT |
-        vk::ImageUsageFlags::TRANSIENT_ATTACHMENT, 
-        data.swapchain_image_format, 
-        1, 
-        data.msaa_samples)?;
-    
-
-    data.color_image = color_image;
-    data.color_image_memory = color_image_memory;
-
-    device.bind_image_memory(data.color_image, data.color_image_memory, 0)?;
-
-
-
-    let subresource_range = vk::ImageSubresourceRange::builder()
-        .aspect_mask(vk::ImageAspectFlags::COLOR)
-        .base_mip_level(0)
-        .level_count(1)
-        .base_array_layer(0)
-        .layer_count(1).build();
-
-
-    data.color_image_view = create_image_view(
-        &color_image, 
-        device, 
-        data.swapchain_image_format, 
-        subresource_range)?;
-    
-    return Ok(());
-}
\ No newline at end of file
@@ -0,0 +1,305 @@
+use vulkanalia::prelude::v1_0::*;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::mem::size_of;
+
+use crate::app::AppData;
+use crate::buffers::{DynamicBuffer, write_dynamic_buffer};
+use crate::instancing::Batch;
+use crate::vertex::Mesh;
+
+
+
+/// Consecutive commands of the indirect buffer that are drawn with the same material, in a single indirect draw.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct IndirectDraw {
+    /// Indexes `AppData::material_descriptor_sets`, `None` when the draws don't need a material (the opaque shadow draws).
+    pub material: Option<usize>,
+    pub first_command: u32,
+    pub command_count: u32
+}
+
+
+/// Which pipeline draws a command, see `AppData::pipeline` and `AppData::shadow_cutout_pipeline`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum DrawPass {
+    Opaque,
+    Cutout,
+    Transparent
+}
+
+
+/// The draws of a pass by pipeline, in the order they are drawn.
+/// Only the main pass has transparent draws, the shadow passes leave them empty.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct DrawLists {
+    pub opaque: Vec<IndirectDraw>,
+    pub cutout: Vec<IndirectDraw>,
+    /// Back to front, like their batches.
+    pub transparent: Vec<IndirectDraw>
+}
+
+
+impl DrawLists {
+    pub fn len(&self) -> usize {
+        self.opaque.len() + self.cutout.len() + self.transparent.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+
+/// Appends a command per draw range of every batch to `commands` for the shadow passes, split by the pipeline `pass` picks for the material of a range.
+/// The opaque commands are a single draw without a material, the cutout ones are grouped by material and the transparent ones don't cast shadows.
+pub(crate) fn shadow_draws(meshes: &[Mesh], batches: &[Batch], pass: impl Fn(usize) -> DrawPass, commands: &mut Vec<vk::DrawIndexedIndirectCommand>) -> DrawLists {
+    let mut opaque = vec![];
+    let mut cutout = vec![];
+
+    for (_, material, command) in batch_commands(meshes, batches) {
+        match pass(material) {
+            DrawPass::Opaque => opaque.push((None, command)),
+            DrawPass::Cutout => cutout.push((Some(material), command)),
+            DrawPass::Transparent => {}
+        }
+    }
+
+    let opaque = append_by_material(opaque, commands);
+    let cutout = append_by_material(cutout, commands);
+
+    return DrawLists { opaque, cutout, transparent: vec![] };
+}
+
+
+/// Appends a command per draw range of every batch to `commands` for the main pass, split into the pipelines `pass` picks by a batch and the material of a range.
+/// Opaque and cutout commands are grouped by material, in the order a material is first used, transparent ones keep the order of `batches`
+/// and only consecutive commands with the same material share a draw.
+pub(crate) fn main_draws(meshes: &[Mesh], batches: &[Batch], pass: impl Fn(&Batch, usize) -> DrawPass, commands: &mut Vec<vk::DrawIndexedIndirectCommand>) -> DrawLists {
+    let mut opaque = vec![];
+    let mut cutout = vec![];
+    let mut transparent = vec![];
+
+    for (batch, material, command) in batch_commands(meshes, batches) {
+        match pass(batch, material) {
+            DrawPass::Opaque => opaque.push((Some(material), command)),
+            DrawPass::Cutout => cutout.push((Some(material), command)),
+            DrawPass::Transparent => transparent.push((material, command))
+        }
+    }
+
+    let opaque = append_by_material(opaque, commands);
+    let cutout = append_by_material(cutout, commands);
+
+    let mut draws: Vec<IndirectDraw> = vec![];
+
+    for (material, command) in transparent {
+        match draws.last_mut() {
+            Some(draw) if draw.material == Some(material) => draw.command_count += 1,
+            _ => draws.push(IndirectDraw { material: Some(material), first_command: commands.len() as u32, command_count: 1 })
+        }
+
+        commands.push(command);
+    }
+
+    return DrawLists { opaque, cutout, transparent: draws };
+}
+
+
+/// The command of every draw range of every batch, with its batch and the material it's drawn with.
+fn batch_commands<'a>(meshes: &'a [Mesh], batches: &'a [Batch]) -> impl Iterator<Item = (&'a Batch, usize, vk::DrawIndexedIndirectCommand)> + 'a {
+    batches.iter().flat_map(move |batch| {
+        let mesh = &meshes[batch.mesh.0];
+
+        mesh.draw_ranges.iter().map(move |range| (batch, batch.material.map_or(range.material, |m| m.0), vk::DrawIndexedIndirectCommand {
+            index_count: range.index_count,
+            instance_count: batch.instance_count,
+            first_index: mesh.first_index + range.first_index,
+            vertex_offset: mesh.vertex_offset as i32,
+            first_instance: batch.first_instance
+        }))
+    })
+}
+
+
+/// Appends the commands grouped by material, in the order a material is first used, with a draw per group.
+fn append_by_material(ranges: impl IntoIterator<Item = (Option<usize>, vk::DrawIndexedIndirectCommand)>, commands: &mut Vec<vk::DrawIndexedIndirectCommand>) -> Vec<IndirectDraw> {
+    let mut groups: Vec<(Option<usize>, Vec<vk::DrawIndexedIndirectCommand>)> = vec![];
+    let mut group_indices = HashMap::new();
+
+    for (material, command) in ranges {
+        let group = *group_indices.entry(material).or_insert_with(|| {
+            groups.push((material, vec![]));
+            groups.len() - 1
+        });
+
+        groups[group].1.push(command);
+    }
+
+    let mut draws = Vec::with_capacity(groups.len());
+
+    for (material, group) in groups {
+        draws.push(IndirectDraw { material, first_command: commands.len() as u32, command_count: group.len() as u32 });
+        commands.extend(group);
+    }
+
+    return draws;
+}
+
+
+/// Writes the draw commands of a frame into the indirect buffer of the swapchain image.
+pub(crate) unsafe fn update_indirect_buffer(instance: &Instance, device: &Device, data: &mut AppData, image_index: usize, commands: &[vk::DrawIndexedIndirectCommand]) -> Result<()> {
+
+    if data.indirect_buffers.len() <= image_index {
+        data.indirect_buffers.resize_with(image_index + 1, DynamicBuffer::default);
+    }
+
+    let mut buffer = std::mem::take(&mut data.indirect_buffers[image_index]);
+    let result = write_dynamic_buffer(instance, device, data, &mut buffer, vk::BufferUsageFlags::INDIRECT_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER, commands);
+    data.indirect_buffers[image_index] = buffer;
+
+    return result;
+}
+
+
+/// Issues the commands of `draw` from the indirect buffer of the swapchain image, one call per command when the device can't draw several at once.
+pub(crate) unsafe fn cmd_draw_indirect(device: &Device, data: &AppData, command_buffer: vk::CommandBuffer, image_index: usize, draw: &IndirectDraw) {
+
+    let buffer = *data.indirect_buffers[image_index].buffer;
+    let stride = size_of::<vk::DrawIndexedIndirectCommand>() as u64;
+    let offset = draw.first_command as u64 * stride;
+
+    if data.multi_draw_indirect {
+        device.cmd_draw_indexed_indirect(command_buffer, buffer, offset, draw.command_count, stride as u32);
+    } else {
+        for i in 0..draw.command_count as u64 {
+            device.cmd_draw_indexed_indirect(command_buffer, buffer, offset + i * stride, 1, stride as u32);
+        }
+    }
+}
+
+
+/// Draws the main pass draws of one pipeline, binding their material sets.
+/// Everything is bound again, since the skybox in between the opaque and the transparent draws has its own pipeline layout.
+pub(crate) unsafe fn cmd_draw_main(device: &Device, data: &AppData, command_buffer: vk::CommandBuffer, image_index: usize, pipeline: vk::Pipeline, draws: &[IndirectDraw]) {
+    if draws.is_empty() {
+        return;
+    }
+
+    cmd_bind_geometry(device, data, command_buffer, image_index);
+
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+    device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, *data.pipeline_layout, 0, &[data.descriptor_sets[image_index]], &[]);
+
+    for draw in draws {
+        device.cmd_bind_descriptor_sets(
+            command_buffer, 
+            vk::PipelineBindPoint::GRAPHICS, 
+            *data.pipeline_layout, 
+            1, 
+            &[data.material_descriptor_sets[draw.material.unwrap()]], 
+            &[]);
+
+        cmd_draw_indirect(device, data, command_buffer, image_index, draw);
+    }
+}
+
+
+/// Binds the geometry buffers and the instances of the swapchain image, which every indirect draw reads.
+pub(crate) unsafe fn cmd_bind_geometry(device: &Device, data: &AppData, command_buffer: vk::CommandBuffer, image_index: usize) {
+
+    device.cmd_bind_vertex_buffers(command_buffer, 0, &[*data.geometry.vertex_buffer, *data.instance_buffers[image_index].buffer], &[0, 0]);
+    device.cmd_bind_index_buffer(command_buffer, *data.geometry.index_buffer, 0, vk::IndexType::UINT32);
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::DrawRange;
+    use crate::renderer::{MaterialHandle, MeshHandle};
+
+    fn meshes() -> Vec<Mesh> {
+        vec![
+            Mesh { vertex_offset: 0, first_index: 0, draw_ranges: vec![DrawRange { material: 0, first_index: 0, index_count: 6 }], ..Default::default() },
+            Mesh {
+                vertex_offset: 4,
+                first_index: 6,
+                draw_ranges: vec![DrawRange { material: 1, first_index: 0, index_count: 3 }, DrawRange { material: 0, first_index: 3, index_count: 9 }],
+                ..Default::default()
+            }
+        ]
+    }
+
+    fn batches() -> Vec<Batch> {
+        vec![
+            Batch { mesh: MeshHandle(1), material: None, first_instance: 0, instance_count: 2 },
+            Batch { mesh: MeshHandle(0), material: None, first_instance: 2, instance_count: 5 },
+            Batch { mesh: MeshHandle(0), material: Some(MaterialHandle(1)), first_instance: 7, instance_count: 1 }
+        ]
+    }
+
+
+    #[test]
+    fn commands_are_grouped_by_material() {
+        let mut commands = vec![];
+        let draws = main_draws(&meshes(), &batches(), |_, _| DrawPass::Opaque, &mut commands);
+
+        assert_eq!(draws.opaque, vec![
+            IndirectDraw { material: Some(1), first_command: 0, command_count: 2 },
+            IndirectDraw { material: Some(0), first_command: 2, command_count: 2 }
+        ]);
+
+        let summary: Vec<_> = commands.iter().map(|c| (c.first_index, c.vertex_offset, c.index_count, c.first_instance, c.instance_count)).collect();
+        assert_eq!(summary, vec![(6, 4, 3, 0, 2), (0, 0, 6, 7, 1), (9, 4, 9, 0, 2), (0, 0, 6, 2, 5)]);
+    }
+
+    #[test]
+    fn opaque_shadow_draws_are_one_draw() {
+        let mut commands = vec![vk::DrawIndexedIndirectCommand::default()];
+        let draws = shadow_draws(&meshes(), &batches(), |_| DrawPass::Opaque, &mut commands);
+
+        assert_eq!(draws.opaque, vec![IndirectDraw { material: None, first_command: 1, command_count: 4 }]);
+        assert_eq!(commands.len(), 5);
+    }
+
+    #[test]
+    fn shadow_draws_keep_the_cutout_materials_and_skip_the_transparent_ones() {
+        let mut commands = vec![];
+
+        // Material 1 is masked, material 0 blended.
+        let draws = shadow_draws(&meshes(), &batches(), |material| match material {
+            1 => DrawPass::Cutout,
+            _ => DrawPass::Transparent
+        }, &mut commands);
+
+        assert!(draws.opaque.is_empty());
+        assert_eq!(draws.cutout, vec![IndirectDraw { material: Some(1), first_command: 0, command_count: 2 }]);
+        assert!(draws.transparent.is_empty());
+        assert_eq!(commands.len(), 2);
+    }
+
+    #[test]
+    fn transparent_commands_keep_their_order() {
+        let mut commands = vec![];
+
+        // Material 1 is blended, the second batch is transparent as a whole.
+        let draws = main_draws(&meshes(), &batches(), |batch, material| match (material, batch.first_instance) {
+            (1, _) | (_, 2) => DrawPass::Transparent,
+            _ => DrawPass::Opaque
+        }, &mut commands);
+
+        assert_eq!(draws.opaque, vec![IndirectDraw { material: Some(0), first_command: 0, command_count: 1 }]);
+        assert!(draws.cutout.is_empty());
+        assert_eq!(draws.transparent, vec![
+            IndirectDraw { material: Some(1), first_command: 1, command_count: 1 },
+            IndirectDraw { material: Some(0), first_command: 2, command_count: 1 },
+            IndirectDraw { material: Some(1), first_command: 3, command_count: 1 }
+        ]);
+        assert_eq!(draws.len(), 4);
+
+        let summary: Vec<_> = commands.iter().map(|c| (c.first_index, c.first_instance)).collect();
+        assert_eq!(summary, vec![(9, 0), (6, 0), (0, 2), (0, 7)]);
+    }
+}
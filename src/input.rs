@@ -0,0 +1,304 @@
+use anyhow::{Result, anyhow};
+use nalgebra_glm as glm;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+use winit::event::{DeviceEvent, ElementState, Event, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent};
+
+
+
+/// A physical key or mouse button.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton)
+}
+
+
+/// Named actions and the bindings that trigger them, loaded from a RON file like
+/// `(actions: { "move_forward": [Key(W), Key(Up)], "rotate": [Mouse(Left)] })`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InputMap {
+    pub actions: BTreeMap<String, Vec<Binding>>
+}
+
+
+impl Default for InputMap {
+    /// The controls of the viking room example, see `CameraInput::from_input` for the camera actions.
+    fn default() -> Self {
+        use Binding::*;
+        use VirtualKeyCode as K;
+
+        let actions = [
+            ("move_forward", vec![Key(K::W)]),
+            ("move_back", vec![Key(K::S)]),
+            ("move_left", vec![Key(K::A)]),
+            ("move_right", vec![Key(K::D)]),
+            ("move_up", vec![Key(K::Space)]),
+            ("move_down", vec![Key(K::LShift)]),
+            ("look", vec![Mouse(MouseButton::Right)]),
+            ("rotate", vec![Mouse(MouseButton::Left)]),
+            ("pan", vec![Mouse(MouseButton::Right), Mouse(MouseButton::Middle)]),
+            ("toggle_camera", vec![Key(K::Tab)]),
+            ("cycle_projection", vec![Key(K::P)]),
+            ("more_models", vec![Key(K::Right)]),
+            ("fewer_models", vec![Key(K::Left)]),
+            ("save_scene", vec![Key(K::F5)]),
+            ("cycle_tone_mapping", vec![Key(K::T)]),
+            ("toggle_auto_exposure", vec![Key(K::X)]),
+            ("reload_post_chain", vec![Key(K::F6)])
+        ];
+
+        Self { actions: actions.into_iter().map(|(name, bindings)| (name.to_string(), bindings)).collect() }
+    }
+}
+
+
+impl InputMap {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Couldn't read input map {}: {}", path.display(), e))?;
+
+        return ron::from_str(&text).map_err(|e| anyhow!("Couldn't parse input map {}: {}", path.display(), e));
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?)?;
+
+        return Ok(());
+    }
+
+    fn bindings(&self, action: &str) -> &[Binding] {
+        self.actions.get(action).map(|b| b.as_slice()).unwrap_or(&[])
+    }
+}
+
+
+
+/// Keyboard, mouse button, cursor and scroll state, fed with every winit event through `handle_event`.
+///
+/// Pressed and released are true for the frame the change happened in, call `end_frame` after the frame's input was used.
+#[derive(Clone, Debug, Default)]
+pub struct Input {
+    map: InputMap,
+    held: HashSet<Binding>,
+    pressed: HashSet<Binding>,
+    released: HashSet<Binding>,
+    /// Position in physical pixels from the top left of the window.
+    cursor: glm::Vec2,
+    /// Raw mouse movement, not limited by the window border.
+    mouse_delta: glm::Vec2,
+    /// Lines scrolled, positive is away from the user.
+    scroll: f32
+}
+
+
+/// Pixels per line for touchpads that scroll in pixels.
+const PIXELS_PER_LINE: f32 = 40.0;
+
+
+impl Input {
+    pub fn new(map: InputMap) -> Self {
+        Self { map, ..Default::default() }
+    }
+
+    pub fn map(&self) -> &InputMap {
+        &self.map
+    }
+
+    pub fn set_map(&mut self, map: InputMap) {
+        self.map = map;
+    }
+
+
+    pub fn handle_event<T>(&mut self, event: &Event<T>) {
+        match event {
+            Event::WindowEvent { event: WindowEvent::KeyboardInput { input, .. }, .. } => {
+                if let Some(key) = input.virtual_keycode {
+                    self.set_state(Binding::Key(key), input.state);
+                }
+            },
+
+            Event::WindowEvent { event: WindowEvent::MouseInput { state, button, .. }, .. } => {
+                self.set_state(Binding::Mouse(*button), *state);
+            },
+
+            Event::WindowEvent { event: WindowEvent::CursorMoved { position, .. }, .. } => {
+                self.cursor = glm::vec2(position.x as f32, position.y as f32);
+            },
+
+            Event::WindowEvent { event: WindowEvent::MouseWheel { delta, .. }, .. } => {
+                self.scroll += match delta {
+                    MouseScrollDelta::LineDelta(_, lines) => *lines,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE
+                };
+            },
+
+            // Everything is released when the window loses focus, it won't see the key up events.
+            Event::WindowEvent { event: WindowEvent::Focused(false), .. } => {
+                self.released.extend(self.held.drain());
+            },
+
+            Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. } => {
+                self.mouse_delta += glm::vec2(delta.0 as f32, delta.1 as f32);
+            },
+
+            _ => {}
+        }
+    }
+
+    fn set_state(&mut self, binding: Binding, state: ElementState) {
+        match state {
+            // Key repeats aren't presses.
+            ElementState::Pressed => if self.held.insert(binding) {
+                self.pressed.insert(binding);
+            },
+            ElementState::Released => if self.held.remove(&binding) {
+                self.released.insert(binding);
+            }
+        }
+    }
+
+    /// Forgets the presses, releases, mouse movement and scrolling of the frame that just ended.
+    pub fn end_frame(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+        self.mouse_delta = glm::Vec2::zeros();
+        self.scroll = 0.0;
+    }
+
+
+    /// Whether a binding of the action went down this frame.
+    pub fn pressed(&self, action: &str) -> bool {
+        self.map.bindings(action).iter().any(|b| self.pressed.contains(b))
+    }
+
+    /// Whether a binding of the action is down.
+    pub fn held(&self, action: &str) -> bool {
+        self.map.bindings(action).iter().any(|b| self.held.contains(b))
+    }
+
+    /// Whether a binding of the action went up this frame.
+    pub fn released(&self, action: &str) -> bool {
+        self.map.bindings(action).iter().any(|b| self.released.contains(b))
+    }
+
+    /// 1 when only `positive` is held, -1 when only `negative` is and 0 otherwise.
+    pub fn axis(&self, positive: &str, negative: &str) -> f32 {
+        self.held(positive) as i32 as f32 - self.held(negative) as i32 as f32
+    }
+
+    pub fn cursor(&self) -> glm::Vec2 {
+        self.cursor
+    }
+
+    pub fn mouse_delta(&self) -> glm::Vec2 {
+        self.mouse_delta
+    }
+
+    pub fn scroll(&self) -> f32 {
+        self.scroll
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::event::{DeviceId, KeyboardInput, ModifiersState};
+    use winit::window::WindowId;
+
+    #[allow(deprecated)]
+    fn key_event(key: VirtualKeyCode, state: ElementState) -> Event<'static, ()> {
+        Event::WindowEvent {
+            window_id: unsafe { WindowId::dummy() },
+            event: WindowEvent::KeyboardInput {
+                device_id: unsafe { DeviceId::dummy() },
+                input: KeyboardInput { scancode: 0, state, virtual_keycode: Some(key), modifiers: ModifiersState::empty() },
+                is_synthetic: false
+            }
+        }
+    }
+
+    fn input() -> Input {
+        Input::new(InputMap::default())
+    }
+
+
+    #[test]
+    fn presses_last_one_frame_and_holds_until_released() {
+        let mut input = input();
+
+        input.handle_event(&key_event(VirtualKeyCode::W, ElementState::Pressed));
+        assert!(input.pressed("move_forward") && input.held("move_forward"));
+
+        input.end_frame();
+        // A key repeat.
+        input.handle_event(&key_event(VirtualKeyCode::W, ElementState::Pressed));
+        assert!(!input.pressed("move_forward") && input.held("move_forward"));
+
+        input.end_frame();
+        input.handle_event(&key_event(VirtualKeyCode::W, ElementState::Released));
+        assert!(input.released("move_forward") && !input.held("move_forward"));
+
+        input.end_frame();
+        assert!(!input.released("move_forward"));
+    }
+
+    #[test]
+    fn taps_within_a_frame_are_still_pressed() {
+        let mut input = input();
+
+        input.handle_event(&key_event(VirtualKeyCode::Tab, ElementState::Pressed));
+        input.handle_event(&key_event(VirtualKeyCode::Tab, ElementState::Released));
+
+        assert!(input.pressed("toggle_camera") && input.released("toggle_camera"));
+        assert!(!input.held("toggle_camera"));
+    }
+
+    #[test]
+    fn axes_cancel_out() {
+        let mut input = input();
+
+        input.handle_event(&key_event(VirtualKeyCode::D, ElementState::Pressed));
+        assert_eq!(input.axis("move_right", "move_left"), 1.0);
+
+        input.handle_event(&key_event(VirtualKeyCode::A, ElementState::Pressed));
+        assert_eq!(input.axis("move_right", "move_left"), 0.0);
+    }
+
+    #[test]
+    fn rebinding_changes_the_action() {
+        let mut map = InputMap::default();
+        map.actions.insert("move_forward".to_string(), vec![Binding::Key(VirtualKeyCode::Up)]);
+        let mut input = Input::new(map);
+
+        input.handle_event(&key_event(VirtualKeyCode::W, ElementState::Pressed));
+        assert!(!input.held("move_forward"));
+
+        input.handle_event(&key_event(VirtualKeyCode::Up, ElementState::Pressed));
+        assert!(input.held("move_forward"));
+        assert!(!input.held("unknown action"));
+    }
+
+    #[test]
+    fn maps_load_from_ron() {
+        let map: InputMap = ron::from_str(r#"(actions: { "jump": [Key(Space), Mouse(Other(4))] })"#).unwrap();
+
+        assert_eq!(map.bindings("jump"), &[Binding::Key(VirtualKeyCode::Space), Binding::Mouse(MouseButton::Other(4))]);
+
+        let text = ron::to_string(&InputMap::default()).unwrap();
+        assert_eq!(ron::from_str::<InputMap>(&text).unwrap(), InputMap::default());
+    }
+
+    #[test]
+    fn example_map_binds_every_default_action() {
+        let map = InputMap::load(Path::new("resources/input.ron")).unwrap();
+
+        for action in InputMap::default().actions.keys() {
+            assert!(!map.bindings(action).is_empty(), "{} isn't bound", action);
+        }
+    }
+}
@@ -17,7 +17,7 @@ use crate::app::AppData;
  */
 
 
-pub unsafe fn create_instance(window: &Window, entry: &Entry, data: &mut AppData) -> Result<Instance> {
+pub unsafe fn create_instance(window: Option<&Window>, entry: &Entry, data: &mut AppData) -> Result<Instance> {
 
     let app_info = vk::ApplicationInfo::builder()
         .application_version(0)
@@ -25,7 +25,11 @@ pub unsafe fn create_instance(window: &Window, entry: &Entry, data: &mut AppData
 
 
     
-    let mut extentions = vulkanalia::window::get_required_instance_extensions(window).iter().map(|e| e.as_ptr()).collect::<Vec<_>>();
+    // Headless rendering has no surface, so it doesn't need any of the window system extensions.
+    let mut extentions = match window {
+        Some(window) => vulkanalia::window::get_required_instance_extensions(window).iter().map(|e| e.as_ptr()).collect::<Vec<_>>(),
+        None => vec![]
+    };
 
     extentions.push(vk::EXT_DEBUG_UTILS_EXTENSION.name.as_ptr());
 
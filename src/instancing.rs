@@ -0,0 +1,191 @@
+use vulkanalia::prelude::v1_0::*;
+use nalgebra_glm as glm;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::mem::size_of;
+
+use crate::app::AppData;
+use crate::buffers::{DynamicBuffer, write_dynamic_buffer};
+use crate::renderer::{DrawItem, MaterialHandle, MeshHandle};
+use crate::vertex::Mesh;
+
+
+
+/// Per-instance vertex data, the vertex shaders read it from binding 1 next to the `Vertex` data of binding 0.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct InstanceData {
+    pub model: glm::Mat4,
+    /// rgb multiplies the base color, a the opacity.
+    pub tint: glm::Vec4
+}
+
+
+impl InstanceData {
+    pub fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(1)
+            .stride(size_of::<InstanceData>() as u32)
+            .input_rate(vk::VertexInputRate::INSTANCE).build()
+    }
+
+    /// A mat4 attribute takes four locations, one per column, starting after the locations of `Vertex`.
+    pub fn attribute_description() -> [vk::VertexInputAttributeDescription; 5] {
+        let column = |i: u32| vk::VertexInputAttributeDescription::builder()
+            .location(4 + i)
+            .binding(1)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset(i * size_of::<glm::Vec4>() as u32).build();
+
+        let tint = vk::VertexInputAttributeDescription::builder()
+            .location(8)
+            .binding(1)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset(size_of::<glm::Mat4>() as u32).build();
+
+        [column(0), column(1), column(2), column(3), tint]
+    }
+}
+
+
+impl From<&DrawItem> for InstanceData {
+    fn from(item: &DrawItem) -> Self {
+        Self { model: item.transform, tint: item.tint.push(item.opacity) }
+    }
+}
+
+
+/// Draw items with the same mesh and material, drawn together with one instanced draw per draw range.
+/// Their instances are `instance_count` consecutive elements of the instance buffer, from `first_instance`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Batch {
+    pub mesh: MeshHandle,
+    pub material: Option<MaterialHandle>,
+    pub first_instance: u32,
+    pub instance_count: u32
+}
+
+
+/// Groups the draw items by mesh and material, in the order their first item was submitted,
+/// and appends their instances to `instances`.
+pub(crate) fn batch_draw_items<'a>(items: impl IntoIterator<Item = &'a DrawItem>, instances: &mut Vec<InstanceData>) -> Vec<Batch> {
+    let mut groups: Vec<Vec<&DrawItem>> = vec![];
+    let mut group_indices = HashMap::new();
+
+    for item in items {
+        let group = *group_indices.entry((item.mesh, item.material)).or_insert_with(|| {
+            groups.push(vec![]);
+            groups.len() - 1
+        });
+
+        groups[group].push(item);
+    }
+
+    let mut batches = Vec::with_capacity(groups.len());
+
+    for group in groups {
+        batches.push(Batch {
+            mesh: group[0].mesh,
+            material: group[0].material,
+            first_instance: instances.len() as u32,
+            instance_count: group.len() as u32
+        });
+
+        instances.extend(group.into_iter().map(InstanceData::from));
+    }
+
+    return batches;
+}
+
+
+/// A batch per draw item, ordered from the farthest to the nearest by the view depth of their bounds' centers,
+/// for the blended draws that have to be drawn back to front. Their instances are appended to `instances`.
+pub(crate) fn back_to_front_batches<'a>(items: impl IntoIterator<Item = &'a DrawItem>, meshes: &[Mesh], view: &glm::Mat4, instances: &mut Vec<InstanceData>) -> Vec<Batch> {
+    // The camera looks down -Z in view space, the farthest item has the lowest Z.
+    let depth = |item: &DrawItem| (view * item.transform * meshes[item.mesh.0].bounds.sphere.center.push(1.0)).z;
+
+    let mut items = items.into_iter().map(|item| (depth(item), item)).collect::<Vec<_>>();
+    items.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    return items.into_iter().map(|(_, item)| {
+        instances.push(InstanceData::from(item));
+
+        Batch { mesh: item.mesh, material: item.material, first_instance: instances.len() as u32 - 1, instance_count: 1 }
+    }).collect();
+}
+
+
+/// Writes the instances of a frame into the instance buffer of the swapchain image.
+pub(crate) unsafe fn update_instance_buffer(instance: &Instance, device: &Device, data: &mut AppData, image_index: usize, instances: &[InstanceData]) -> Result<()> {
+
+    if data.instance_buffers.len() <= image_index {
+        data.instance_buffers.resize_with(image_index + 1, DynamicBuffer::default);
+    }
+
+    let mut buffer = std::mem::take(&mut data.instance_buffers[image_index]);
+    let result = write_dynamic_buffer(instance, device, data, &mut buffer, vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER, instances);
+    data.instance_buffers[image_index] = buffer;
+
+    return result;
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(mesh: usize, material: Option<usize>, x: f32) -> DrawItem {
+        DrawItem {
+            material: material.map(MaterialHandle),
+            ..DrawItem::new(MeshHandle(mesh), glm::translation(&glm::vec3(x, 0.0, 0.0)))
+        }
+    }
+
+
+    #[test]
+    fn instance_data_matches_the_attributes() {
+        assert_eq!(size_of::<InstanceData>(), 80);
+        assert_eq!(InstanceData::attribute_description()[4].offset, 64);
+        assert_eq!(InstanceData::binding_description().input_rate, vk::VertexInputRate::INSTANCE);
+    }
+
+    #[test]
+    fn items_with_the_same_mesh_and_material_share_a_batch() {
+        let items = [item(0, None, 0.0), item(1, None, 1.0), item(0, None, 2.0), item(0, Some(3), 3.0), item(1, None, 4.0)];
+
+        let mut instances = vec![InstanceData::from(&item(2, None, -1.0))];
+        let batches = batch_draw_items(&items, &mut instances);
+
+        assert_eq!(batches, vec![
+            Batch { mesh: MeshHandle(0), material: None, first_instance: 1, instance_count: 2 },
+            Batch { mesh: MeshHandle(1), material: None, first_instance: 3, instance_count: 2 },
+            Batch { mesh: MeshHandle(0), material: Some(MaterialHandle(3)), first_instance: 5, instance_count: 1 }
+        ]);
+
+        let x: Vec<f32> = instances.iter().map(|i| i.model[(0, 3)]).collect();
+        assert_eq!(x, vec![-1.0, 0.0, 2.0, 1.0, 4.0, 3.0]);
+    }
+
+    #[test]
+    fn blended_items_are_sorted_back_to_front() {
+        let meshes = vec![Mesh::default()];
+        let at = |z: f32| DrawItem::new(MeshHandle(0), glm::translation(&glm::vec3(0.0, 0.0, z)));
+        let items = [at(-2.0), at(-10.0), at(1.0), at(-5.0)];
+
+        let mut instances = vec![];
+        let batches = back_to_front_batches(&items, &meshes, &glm::Mat4::identity(), &mut instances);
+
+        assert_eq!(batches.iter().map(|b| (b.first_instance, b.instance_count)).collect::<Vec<_>>(), vec![(0, 1), (1, 1), (2, 1), (3, 1)]);
+
+        let z: Vec<f32> = instances.iter().map(|i| i.model[(2, 3)]).collect();
+        assert_eq!(z, vec![-10.0, -5.0, -2.0, 1.0]);
+    }
+
+    #[test]
+    fn tint_alpha_is_the_opacity() {
+        let item = DrawItem { tint: glm::vec3(1.0, 0.5, 0.25), opacity: 0.5, ..item(0, None, 0.0) };
+
+        assert_eq!(InstanceData::from(&item).tint, glm::vec4(1.0, 0.5, 0.25, 0.5));
+    }
+}
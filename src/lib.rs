@@ -0,0 +1,49 @@
+//! A small Vulkan renderer.
+//!
+//! Create a `Renderer` for a window (or headless for offscreen rendering), upload meshes, textures and materials,
+//! then `submit` the meshes to draw every frame and `present` them.
+
+mod app;
+mod instance;
+mod device;
+mod swapchain;
+mod images;
+mod pipeline;
+mod render_pass;
+mod buffers;
+mod sync;
+mod ubo;
+mod descriptors;
+mod headless;
+mod allocator;
+mod resources;
+mod shadows;
+mod instancing;
+mod indirect;
+mod culling;
+mod render_graph;
+mod environment;
+pub mod config;
+pub mod material;
+pub mod vertex;
+pub mod gltf_loader;
+pub mod camera;
+pub mod bounds;
+pub mod input;
+pub mod lights;
+pub mod renderer;
+pub mod scene;
+pub mod scene_file;
+pub mod tone_mapping;
+pub mod post_processing;
+#[cfg(test)]
+mod golden;
+
+pub use renderer::{Renderer, DrawItem, FrameStats, ColorSpace, MeshHandle, TextureHandle, MaterialHandle};
+pub use allocator::AllocatorStats;
+pub use scene::{Scene, Node, NodeId, Transform};
+pub use scene_file::{SceneFile, LoadedScene};
+pub use lights::{Light, LightKind};
+pub use input::{Input, InputMap, Binding};
+pub use tone_mapping::{ToneMapping, ToneMapOperator, Exposure};
+pub use post_processing::{PostChain, PostEffect, PostStage};
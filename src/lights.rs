@@ -0,0 +1,266 @@
+use std::mem::size_of;
+
+use vulkanalia::prelude::v1_0::*;
+use nalgebra_glm as glm;
+use anyhow::Result;
+
+use crate::{app::AppData, buffers::create_buffer, shadows::{ShadowLayout, CASCADE_COUNT, MAX_SHADOW_LAYERS}};
+
+
+
+/// Lights beyond this many are ignored, the light buffers are allocated at this size.
+pub const MAX_LIGHTS: usize = 256;
+
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LightKind {
+    /// Infinitely far away like the sun, `direction` is the direction the light travels in.
+    Directional { direction: glm::Vec3 },
+    /// Shines in all directions, fading out to nothing at `range`.
+    Point { position: glm::Vec3, range: f32 },
+    /// Shines along `direction`, at full strength within `inner_angle` of it and fading out towards `outer_angle` (radians).
+    Spot { position: glm::Vec3, direction: glm::Vec3, range: f32, inner_angle: f32, outer_angle: f32 }
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: glm::Vec3,
+    pub intensity: f32,
+    /// Only directional and spot lights cast shadows.
+    pub cast_shadows: bool
+}
+
+
+impl Default for Light {
+    /// The sun the renderer starts with, shining down at the scene from the front left.
+    /// At an intensity of π it lights a white surface facing it fully white.
+    fn default() -> Self {
+        Light::directional(glm::vec3(-1.0, 3.0, 1.0)).with_color(glm::vec3(1.0, 1.0, 1.0), std::f32::consts::PI).with_shadows()
+    }
+}
+
+
+impl Light {
+    /// A white light of intensity 1 without shadows.
+    pub fn new(kind: LightKind) -> Self {
+        Self { kind, color: glm::vec3(1.0, 1.0, 1.0), intensity: 1.0, cast_shadows: false }
+    }
+
+    pub fn directional(direction: glm::Vec3) -> Self {
+        Self::new(LightKind::Directional { direction })
+    }
+
+    pub fn point(position: glm::Vec3, range: f32) -> Self {
+        Self::new(LightKind::Point { position, range })
+    }
+
+    pub fn spot(position: glm::Vec3, direction: glm::Vec3, range: f32, inner_angle: f32, outer_angle: f32) -> Self {
+        Self::new(LightKind::Spot { position, direction, range, inner_angle, outer_angle })
+    }
+
+    pub fn with_color(self, color: glm::Vec3, intensity: f32) -> Self {
+        Self { color, intensity, ..self }
+    }
+
+    pub fn with_shadows(self) -> Self {
+        Self { cast_shadows: true, ..self }
+    }
+
+
+    fn to_gpu(&self, shadow_layers: Option<(u32, u32)>) -> GpuLight {
+        let color = self.color.push(self.intensity);
+        let shadow = match shadow_layers {
+            Some((first, count)) => glm::vec4(first as f32, count as f32, 0.0, 0.0),
+            None => glm::vec4(-1.0, 0.0, 0.0, 0.0)
+        };
+
+        match self.kind {
+            LightKind::Directional { direction } => GpuLight {
+                position: glm::Vec4::zeros(),
+                direction: glm::normalize(&direction).push(LIGHT_DIRECTIONAL),
+                color,
+                cone: glm::Vec4::zeros(),
+                shadow
+            },
+            LightKind::Point { position, range } => GpuLight {
+                position: position.push(range),
+                direction: glm::vec4(0.0, 0.0, 0.0, LIGHT_POINT),
+                color,
+                cone: glm::Vec4::zeros(),
+                shadow
+            },
+            LightKind::Spot { position, direction, range, inner_angle, outer_angle } => GpuLight {
+                position: position.push(range),
+                direction: glm::normalize(&direction).push(LIGHT_SPOT),
+                color,
+                cone: glm::vec4(inner_angle.cos(), outer_angle.cos(), 0.0, 0.0),
+                shadow
+            }
+        }
+    }
+}
+
+
+
+// Light types in the W component of `GpuLight::direction`, must match shader.frag.
+const LIGHT_DIRECTIONAL: f32 = 0.0;
+const LIGHT_POINT: f32 = 1.0;
+const LIGHT_SPOT: f32 = 2.0;
+
+
+/// `Light` as the fragment shader reads it from the storage buffer, std430.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct GpuLight {
+    /// XYZ is the position, W the range.
+    position: glm::Vec4,
+    /// XYZ is the normalized direction, W the type.
+    direction: glm::Vec4,
+    /// RGB is the color, A the intensity.
+    color: glm::Vec4,
+    /// Cosines of the inner and outer angle of spot lights.
+    cone: glm::Vec4,
+    /// X is the first shadow map layer (-1 without shadows), Y the number of layers.
+    shadow: glm::Vec4
+}
+
+
+/// Start of the light buffer, followed by `count` `GpuLight`s.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct LightsHeader {
+    /// RGB is the ambient light, A the intensity of the environment light, 0 without one.
+    ambient: glm::Vec4,
+    count: u32,
+    _padding: [u32; 3],
+    /// See `ShadowLayout`.
+    cascade_splits: [f32; CASCADE_COUNT],
+    shadow_matrices: [glm::Mat4; MAX_SHADOW_LAYERS]
+}
+
+
+const LIGHT_BUFFER_SIZE: usize = size_of::<LightsHeader>() + MAX_LIGHTS * size_of::<GpuLight>();
+
+
+
+/// One light buffer per swapchain image, like the uniform buffers, bound at set 0 binding 1.
+pub(crate) unsafe fn create_light_buffers(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+
+    data.light_buffers.clear();
+
+    for _ in 0..data.swapchain_images.len() {
+        let buffer = create_buffer(
+            LIGHT_BUFFER_SIZE as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            device,
+            instance,
+            data)?;
+
+        data.light_buffers.push(buffer);
+    }
+
+    return Ok(());
+}
+
+
+/// Writes the lights and their shadow matrices into the persistently mapped light buffer of a swapchain image.
+pub(crate) unsafe fn update_light_buffer(data: &AppData, image_index: usize, lights: &[Light], ambient: glm::Vec3, environment_intensity: f32, shadows: &ShadowLayout) {
+
+    let bytes = light_buffer_contents(lights, ambient, environment_intensity, shadows);
+
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), data.light_buffers[image_index].allocation.mapped, bytes.len());
+}
+
+
+fn light_buffer_contents(lights: &[Light], ambient: glm::Vec3, environment_intensity: f32, shadows: &ShadowLayout) -> Vec<u8> {
+    let lights = lights.iter()
+        .take(MAX_LIGHTS)
+        .enumerate()
+        .map(|(i, l)| l.to_gpu(shadows.light_layers.get(i).copied().flatten()))
+        .collect::<Vec<_>>();
+
+    let mut shadow_matrices = [glm::Mat4::identity(); MAX_SHADOW_LAYERS];
+    shadow_matrices[..shadows.matrices.len()].copy_from_slice(&shadows.matrices);
+
+    let header = LightsHeader {
+        ambient: ambient.push(environment_intensity),
+        count: lights.len() as u32,
+        _padding: [0; 3],
+        cascade_splits: shadows.cascade_splits,
+        shadow_matrices
+    };
+
+    let mut bytes = Vec::with_capacity(size_of::<LightsHeader>() + lights.len() * size_of::<GpuLight>());
+
+    unsafe {
+        bytes.extend_from_slice(std::slice::from_raw_parts((&header as *const LightsHeader).cast::<u8>(), size_of::<LightsHeader>()));
+        bytes.extend_from_slice(lights.align_to::<u8>().1);
+    }
+
+    return bytes;
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn floats(bytes: &[u8]) -> Vec<f32> {
+        bytes.chunks_exact(4).map(|c| f32::from_ne_bytes(c.try_into().unwrap())).collect()
+    }
+
+
+    #[test]
+    fn layout_matches_std430() {
+        assert_eq!(size_of::<LightsHeader>(), 48 + 64 * MAX_SHADOW_LAYERS);
+        assert_eq!(size_of::<GpuLight>(), 80);
+    }
+
+    #[test]
+    fn lights_follow_the_header() {
+        let lights = [
+            Light::default(),
+            Light::point(glm::vec3(1.0, 2.0, 3.0), 5.0).with_color(glm::vec3(1.0, 0.5, 0.0), 2.0)
+        ];
+
+        let shadows = ShadowLayout { light_layers: vec![Some((0, 4)), None], matrices: vec![glm::Mat4::identity() * 2.0; 4], ..Default::default() };
+
+        let bytes = light_buffer_contents(&lights, glm::vec3(0.1, 0.1, 0.1), 0.5, &shadows);
+
+        let header = size_of::<LightsHeader>();
+        assert_eq!(bytes.len(), header + 2 * 80);
+        assert_eq!(floats(&bytes[..16]), vec![0.1, 0.1, 0.1, 0.5]);
+        assert_eq!(u32::from_ne_bytes(bytes[16..20].try_into().unwrap()), 2);
+        assert_eq!(floats(&bytes[48..52])[0], 2.0);
+
+        let sun = floats(&bytes[header..]);
+        assert_eq!(&sun[16..18], &[0.0, 4.0]);
+
+        let point = floats(&bytes[header + 80..]);
+        assert_eq!(&point[0..4], &[1.0, 2.0, 3.0, 5.0]);
+        assert_eq!(point[7], LIGHT_POINT);
+        assert_eq!(&point[8..12], &[1.0, 0.5, 0.0, 2.0]);
+        assert_eq!(point[16], -1.0);
+    }
+
+    #[test]
+    fn spot_cones_are_cosines() {
+        let spot = Light::spot(glm::Vec3::zeros(), glm::vec3(0.0, 0.0, -2.0), 10.0, 0.0, std::f32::consts::FRAC_PI_2).to_gpu(None);
+
+        assert_eq!(spot.direction, glm::vec4(0.0, 0.0, -1.0, LIGHT_SPOT));
+        assert!((spot.cone.x - 1.0).abs() < 1e-6 && spot.cone.y.abs() < 1e-6);
+    }
+
+    #[test]
+    fn lights_beyond_the_maximum_are_dropped() {
+        let lights = vec![Light::default(); MAX_LIGHTS + 10];
+
+        let bytes = light_buffer_contents(&lights, glm::Vec3::zeros(), 0.0, &ShadowLayout::default());
+
+        assert_eq!(bytes.len(), LIGHT_BUFFER_SIZE);
+    }
+}
@@ -0,0 +1,343 @@
+use vulkanalia::prelude::v1_0::*;
+use nalgebra_glm as glm;
+use anyhow::Result;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+
+use crate::{app::AppData, buffers::{create_buffer, fill_buffer}, descriptors::create_material_descriptor_set, gltf_loader::{GltfAlphaMode, GltfMaterial}, renderer::{ColorSpace, TextureHandle}};
+
+
+
+/// How the opacity of a material (its `opacity` times the alpha of its base color texture) is used, like glTF's alpha modes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AlphaMode {
+    /// The opacity is ignored.
+    #[default]
+    Opaque,
+    /// Cut out where the opacity is below `Material::alpha_cutoff`, like leaves. The edges are smoothed with alpha-to-coverage under MSAA.
+    Mask,
+    /// Blended over what's behind it, like glass. Blended draws are sorted back to front and don't write depth.
+    Blend
+}
+
+
+
+/// A metallic-roughness material like glTF's, uploaded with `Renderer::create_material`.
+///
+/// Every factor is multiplied with its texture, materials without a texture use the factor as is.
+#[derive(Clone, Debug)]
+pub struct Material {
+    pub name: String,
+    pub base_color: glm::Vec3,
+    pub opacity: f32,
+    pub alpha_mode: AlphaMode,
+    /// Opacity below which a `AlphaMode::Mask` material is cut out.
+    pub alpha_cutoff: f32,
+    /// 0 is a dielectric like plastic or wood, 1 a bare metal.
+    pub metallic: f32,
+    /// 0 is a perfect mirror, 1 completely diffuse.
+    pub roughness: f32,
+    pub emissive_color: glm::Vec3,
+    /// Scales the X and Y of the tangent space normals of `normal_texture`.
+    pub normal_scale: f32,
+    /// How much `occlusion_texture` darkens the ambient light, 0 ignores it.
+    pub occlusion_strength: f32,
+    /// sRGB, `map_Kd` of MTL files.
+    pub base_color_texture: Option<PathBuf>,
+    /// Linear, roughness in G and metallic in B.
+    pub metallic_roughness_texture: Option<PathBuf>,
+    /// Linear tangent space normals, `map_Bump`/`bump` of MTL files.
+    pub normal_texture: Option<PathBuf>,
+    /// Linear, occlusion in R.
+    pub occlusion_texture: Option<PathBuf>,
+    /// sRGB, `map_Ke` of MTL files.
+    pub emissive_texture: Option<PathBuf>
+}
+
+
+impl Default for Material {
+    /// Plain white and fully rough, unlike glTF's default it isn't metallic.
+    fn default() -> Self {
+        Self {
+            name: String::from("default"),
+            base_color: glm::vec3(1.0, 1.0, 1.0),
+            opacity: 1.0,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            metallic: 0.0,
+            roughness: 1.0,
+            emissive_color: glm::vec3(0.0, 0.0, 0.0),
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            base_color_texture: None,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            occlusion_texture: None,
+            emissive_texture: None
+        }
+    }
+}
+
+
+impl Material {
+    /// Converts a tobj material, texture paths in MTL files are relative to the directory of the OBJ file.
+    ///
+    /// The PBR extension's `Pr` and `Pm` are used when present, otherwise the roughness is derived from the shininess `Ns`.
+    /// Materials that dissolve (`d` below 1) are blended.
+    pub fn from_mtl(material: &tobj::Material, directory: &Path) -> Self {
+        let texture = |name: &str| if name.is_empty() { None } else { Some(directory.join(name)) };
+
+        let floats = |key: &str| material.unknown_param.get(key)
+            .and_then(|value| value.split_whitespace().map(|v| v.parse::<f32>()).collect::<Result<Vec<_>, _>>().ok());
+
+        let emissive_color = match floats("Ke").as_deref() {
+            Some([r, g, b]) => glm::vec3(*r, *g, *b),
+            _ => glm::Vec3::zeros()
+        };
+
+        let roughness = match floats("Pr").as_deref() {
+            Some([roughness]) => *roughness,
+            _ => (2.0 / (material.shininess.max(0.0) + 2.0)).sqrt()
+        };
+
+        let metallic = match floats("Pm").as_deref() {
+            Some([metallic]) => *metallic,
+            _ => 0.0
+        };
+
+        return Self {
+            name: material.name.clone(),
+            base_color: glm::Vec3::from(material.diffuse),
+            opacity: material.dissolve,
+            alpha_mode: if material.dissolve < 1.0 { AlphaMode::Blend } else { AlphaMode::Opaque },
+            metallic,
+            roughness,
+            emissive_color,
+            base_color_texture: texture(&material.diffuse_texture),
+            normal_texture: texture(&material.normal_texture),
+            emissive_texture: material.unknown_param.get("map_Ke").and_then(|t| texture(t)),
+            ..Default::default()
+        };
+    }
+
+    /// Copies the factors of a glTF material, its textures are uploaded separately by `Renderer::load_gltf`.
+    pub fn from_gltf(material: &GltfMaterial) -> Self {
+        Self {
+            name: material.name.clone().unwrap_or_default(),
+            base_color: material.base_color_factor.xyz(),
+            opacity: material.base_color_factor.w,
+            alpha_mode: match material.alpha_mode {
+                GltfAlphaMode::Opaque => AlphaMode::Opaque,
+                GltfAlphaMode::Mask => AlphaMode::Mask,
+                GltfAlphaMode::Blend => AlphaMode::Blend
+            },
+            alpha_cutoff: material.alpha_cutoff,
+            metallic: material.metallic_factor,
+            roughness: material.roughness_factor,
+            emissive_color: material.emissive_factor,
+            normal_scale: material.normal_scale,
+            occlusion_strength: material.occlusion_strength,
+            ..Default::default()
+        }
+    }
+
+    /// The texture paths in binding order, see `MaterialTextures`.
+    pub(crate) fn texture_paths(&self) -> [Option<&Path>; TEXTURES_PER_MATERIAL] {
+        [
+            self.base_color_texture.as_deref(),
+            self.metallic_roughness_texture.as_deref(),
+            self.normal_texture.as_deref(),
+            self.occlusion_texture.as_deref(),
+            self.emissive_texture.as_deref()
+        ]
+    }
+
+    fn uniform(&self) -> MaterialUniform {
+        MaterialUniform {
+            base_color: self.base_color.push(self.opacity),
+            emissive: self.emissive_color.push(if self.alpha_mode == AlphaMode::Mask { self.alpha_cutoff } else { 0.0 }),
+            parameters: glm::vec4(self.metallic, self.roughness, self.normal_scale, self.occlusion_strength)
+        }
+    }
+}
+
+
+
+/// Textures of a material, each replaces the texture path of the same name in `Material`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MaterialTextures {
+    pub base_color: Option<TextureHandle>,
+    pub metallic_roughness: Option<TextureHandle>,
+    pub normal: Option<TextureHandle>,
+    pub occlusion: Option<TextureHandle>,
+    pub emissive: Option<TextureHandle>
+}
+
+
+/// Number of textures of a material, bound at set 1, bindings 0 and 2 to 5.
+pub(crate) const TEXTURES_PER_MATERIAL: usize = 5;
+
+
+impl MaterialTextures {
+    /// Only a base color texture, the other textures come from the material.
+    pub fn base_color(texture: TextureHandle) -> Self {
+        Self { base_color: Some(texture), ..Default::default() }
+    }
+
+    pub(crate) fn into_array(self) -> [Option<TextureHandle>; TEXTURES_PER_MATERIAL] {
+        [self.base_color, self.metallic_roughness, self.normal, self.occlusion, self.emissive]
+    }
+
+    pub(crate) fn from_array([base_color, metallic_roughness, normal, occlusion, emissive]: [Option<TextureHandle>; TEXTURES_PER_MATERIAL]) -> Self {
+        Self { base_color, metallic_roughness, normal, occlusion, emissive }
+    }
+
+    /// How the texture in each slot is encoded, colors are sRGB and everything else is linear data.
+    pub(crate) const COLOR_SPACES: [ColorSpace; TEXTURES_PER_MATERIAL] =
+        [ColorSpace::Srgb, ColorSpace::Linear, ColorSpace::Linear, ColorSpace::Linear, ColorSpace::Srgb];
+
+    /// RGBA8 pixel used for a missing texture in each slot, it leaves the factor unchanged.
+    pub(crate) const FALLBACK_PIXELS: [[u8; 4]; TEXTURES_PER_MATERIAL] =
+        [[255; 4], [255; 4], [128, 128, 255, 255], [255; 4], [255; 4]];
+}
+
+
+/// Layout of the per-material uniform buffer at set 1, binding 1 of the fragment shader.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct MaterialUniform {
+    /// rgb = base color, a = opacity
+    pub base_color: glm::Vec4,
+    /// rgb = emissive color, a = alpha cutoff of masked materials, 0 for the others
+    pub emissive: glm::Vec4,
+    /// x = metallic, y = roughness, z = normal scale, w = occlusion strength
+    pub parameters: glm::Vec4
+}
+
+
+/// A range of a mesh's indices that is drawn with a single material.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DrawRange {
+    pub material: usize,
+    pub first_index: u32,
+    pub index_count: u32
+}
+
+
+
+/// Uploads `material`, drawn with the `AppData::textures` in `textures`, and returns its index into `AppData::materials`.
+pub(crate) unsafe fn create_material(instance: &Instance, device: &Device, data: &mut AppData, material: Material, textures: [usize; TEXTURES_PER_MATERIAL]) -> Result<usize> {
+
+    let size = size_of::<MaterialUniform>() as u64;
+
+    let buffer = create_buffer(
+        size,
+        vk::BufferUsageFlags::UNIFORM_BUFFER,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        device,
+        instance,
+        data)?;
+
+    let uniform = material.uniform();
+
+    fill_buffer(&buffer.allocation, &uniform, 1)?;
+
+    let views = textures.map(|t| *data.textures[t].view);
+    let descriptor_set = create_material_descriptor_set(device, data, views, *buffer)?;
+
+    debug!("Created material {} with textures {:?}", material.name, textures);
+
+    data.materials.push(material);
+    data.material_textures.push(textures);
+    data.material_uniform_buffers.push(buffer);
+    data.material_descriptor_sets.push(descriptor_set);
+
+    return Ok(data.materials.len() - 1);
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MTL: &str = "
+newmtl Glass
+Kd 0.2 0.4 0.6
+Ks 1.0 1.0 1.0
+Ns 250.0
+Ke 0.5 0.0 0.0
+d 0.25
+map_Kd textures/glass.png
+map_Bump textures/glass_normal.png
+";
+
+    const PBR_MTL: &str = "
+newmtl Brass
+Kd 0.9 0.7 0.3
+Pr 0.3
+Pm 1.0
+map_Ke textures/glow.png
+";
+
+
+    #[test]
+    fn converts_mtl_materials() {
+        let (materials, _) = tobj::load_mtl_buf(&mut MTL.as_bytes()).unwrap();
+        let material = Material::from_mtl(&materials[0], Path::new("resources"));
+
+        assert_eq!(material.name, "Glass");
+        assert_eq!(material.base_color, glm::vec3(0.2, 0.4, 0.6));
+        assert!((material.roughness - (2.0f32 / 252.0).sqrt()).abs() < 1e-6);
+        assert_eq!(material.metallic, 0.0);
+        assert_eq!(material.emissive_color, glm::vec3(0.5, 0.0, 0.0));
+        assert_eq!(material.opacity, 0.25);
+        assert_eq!(material.alpha_mode, AlphaMode::Blend);
+        assert_eq!(material.base_color_texture, Some(PathBuf::from("resources/textures/glass.png")));
+        assert_eq!(material.normal_texture, Some(PathBuf::from("resources/textures/glass_normal.png")));
+    }
+
+    #[test]
+    fn missing_values_fall_back_to_mtl_defaults() {
+        let (materials, _) = tobj::load_mtl_buf(&mut "newmtl Plain\nKd 1 1 1\n".as_bytes()).unwrap();
+        let material = Material::from_mtl(&materials[0], Path::new(""));
+
+        assert_eq!(material.emissive_color, glm::Vec3::zeros());
+        assert_eq!(material.opacity, 1.0);
+        assert_eq!(material.alpha_mode, AlphaMode::Opaque);
+        assert_eq!(material.base_color_texture, None);
+        assert_eq!(material.normal_texture, None);
+        assert_eq!(material.roughness, 1.0);
+    }
+
+    #[test]
+    fn uses_pbr_extension_parameters() {
+        let (materials, _) = tobj::load_mtl_buf(&mut PBR_MTL.as_bytes()).unwrap();
+        let material = Material::from_mtl(&materials[0], Path::new(""));
+
+        assert_eq!(material.roughness, 0.3);
+        assert_eq!(material.metallic, 1.0);
+        assert_eq!(material.emissive_texture, Some(PathBuf::from("textures/glow.png")));
+    }
+
+    #[test]
+    fn uniform_packs_the_factors() {
+        let material = Material { metallic: 0.5, roughness: 0.25, normal_scale: 2.0, opacity: 0.75, ..Default::default() };
+        let uniform = material.uniform();
+
+        assert_eq!(uniform.base_color, glm::vec4(1.0, 1.0, 1.0, 0.75));
+        assert_eq!(uniform.parameters, glm::vec4(0.5, 0.25, 2.0, 1.0));
+        assert_eq!(size_of::<MaterialUniform>(), 48);
+    }
+
+    #[test]
+    fn only_masked_materials_have_a_cutoff() {
+        let mask = Material { alpha_mode: AlphaMode::Mask, alpha_cutoff: 0.3, ..Default::default() };
+        let blend = Material { alpha_mode: AlphaMode::Blend, alpha_cutoff: 0.3, ..Default::default() };
+
+        assert_eq!(mask.uniform().emissive.w, 0.3);
+        assert_eq!(blend.uniform().emissive.w, 0.0);
+    }
+}
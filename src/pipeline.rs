@@ -2,16 +2,18 @@ use vulkanalia::{prelude::v1_0::*};
 use anyhow::{Result, anyhow};
 use log::*;
 use crate::vertex::Vertex;
+use crate::instancing::InstanceData;
+use crate::environment::create_skybox_pipeline;
 
-use crate::{app::AppData, render_pass::create_render_pass};
+use crate::{app::AppData, render_pass::create_render_pass, resources::{Pipeline, PipelineLayout}};
 
 
 
 pub unsafe fn create_pipeline(instance: &Instance, data: &mut AppData, device: &Device) -> Result<()> {
 
 
-    let binding_descriptions = [Vertex::binding_description()];
-    let attribute_descriptions = Vertex::attribute_description();
+    let binding_descriptions = [Vertex::binding_description(), InstanceData::binding_description()];
+    let attribute_descriptions = [&Vertex::attribute_description()[..], &InstanceData::attribute_description()[..]].concat();
 
     let vertex_input_stage = vk::PipelineVertexInputStateCreateInfo::builder()
         .vertex_binding_descriptions(&binding_descriptions)
@@ -74,7 +76,17 @@ pub unsafe fn create_pipeline(instance: &Instance, data: &mut AppData, device: &
         .sample_shading_enable(false)
         .rasterization_samples(data.msaa_samples);
 
-    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+    // Cutouts turn their opacity into the coverage of the samples, which only smooths their edges with more than one.
+    let cutout_multi_sample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(data.msaa_samples)
+        .alpha_to_coverage_enable(data.msaa_samples != vk::SampleCountFlags::_1);
+
+    let opaque_attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(false);
+
+    let transparent_attachment = vk::PipelineColorBlendAttachmentState::builder()
         .color_write_mask(vk::ColorComponentFlags::all())
         .blend_enable(true)
         .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
@@ -85,50 +97,46 @@ pub unsafe fn create_pipeline(instance: &Instance, data: &mut AppData, device: &
         .alpha_blend_op(vk::BlendOp::ADD);
 
 
-    let attachments = &[attachment];
+    let opaque_attachments = &[opaque_attachment];
+    let transparent_attachments = &[transparent_attachment];
 
-    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
-        .attachments(attachments)
+    let opaque_color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .attachments(opaque_attachments)
         .logic_op_enable(false)
         .blend_constants([0.0, 0.0, 0.0, 0.0]);
 
-    let vert_push_constant_range = vk::PushConstantRange::builder()
-        .stage_flags(vk::ShaderStageFlags::VERTEX)
-        .offset(0)
-        .size(64);
-
-    let frag_push_constant_range = vk::PushConstantRange::builder()
-        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
-        .offset(64)
-        .size(16);
-
-    
-
+    let transparent_color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .attachments(transparent_attachments)
+        .logic_op_enable(false)
+        .blend_constants([0.0, 0.0, 0.0, 0.0]);
 
-    let set_layouts = &[data.descriptor_set_layout];
-    let push_constant_ranges = &[vert_push_constant_range, frag_push_constant_range];
+    // The model matrix and tint come from the instance buffer, not push constants.
+    let set_layouts = &[*data.descriptor_set_layout, *data.material_descriptor_set_layout];
 
     let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
-        .set_layouts(set_layouts)
-        .push_constant_ranges(push_constant_ranges);
-
-    data.pipeline_layout = device.create_pipeline_layout(&pipeline_layout_info, None)?;
+        .set_layouts(set_layouts);
 
+    data.pipeline_layout = PipelineLayout::new(device.create_pipeline_layout(&pipeline_layout_info, None)?, &data.deletion_queue);
 
-    let render_pass = create_render_pass(instance, device, data)?;
 
-    data.render_pass = render_pass;
+    data.render_pass = create_render_pass(instance, device, data)?;
 
     let stages = &[vertex_stage_info, fragment_stage_info];
 
+    let depth_compare_op = if data.reverse_z { vk::CompareOp::GREATER } else { vk::CompareOp::LESS };
+
     let depth_stencil_stage = vk::PipelineDepthStencilStateCreateInfo::builder()
         .depth_test_enable(true)
         .depth_write_enable(true)
-        .depth_compare_op(vk::CompareOp::LESS)
+        .depth_compare_op(depth_compare_op)
         .stencil_test_enable(false);
 
-
-
+    // Blended surfaces are tested against the opaque ones but don't hide each other, they are drawn back to front instead.
+    let transparent_depth_stencil_stage = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(false)
+        .depth_compare_op(depth_compare_op)
+        .stencil_test_enable(false);
 
 
     let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
@@ -138,18 +146,32 @@ pub unsafe fn create_pipeline(instance: &Instance, data: &mut AppData, device: &
         .viewport_state(&viewport_state)
         .rasterization_state(&rasterization_state)
         .multisample_state(&multi_sample_state)
-        .color_blend_state(&color_blend_state)
+        .color_blend_state(&opaque_color_blend_state)
         .depth_stencil_state(&depth_stencil_stage)
-        .layout(data.pipeline_layout)
-        .render_pass(render_pass)
+        .layout(*data.pipeline_layout)
+        .render_pass(*data.render_pass)
         .subpass(0);
 
-    data.pipeline = device.create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)?.0;
+    let cutout_pipeline_info = pipeline_info
+        .multisample_state(&cutout_multi_sample_state);
+
+    let transparent_pipeline_info = pipeline_info
+        .color_blend_state(&transparent_color_blend_state)
+        .depth_stencil_state(&transparent_depth_stencil_stage);
+
+    let create = |info| Ok::<_, anyhow::Error>(Pipeline::new(device.create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?.0, &data.deletion_queue));
+
+    data.pipeline = create(pipeline_info)?;
+    data.cutout_pipeline = create(cutout_pipeline_info)?;
+    data.transparent_pipeline = create(transparent_pipeline_info)?;
 
 
     device.destroy_shader_module(vertex_shader_module, None);
     device.destroy_shader_module(fragment_shader_module, None);
 
+    // Drawn in the same render pass with the same layout, so it's replaced along with the main pipeline.
+    create_skybox_pipeline(device, data)?;
+
     info!("Created pipeline!");
 
     return Ok(());
@@ -158,7 +180,129 @@ pub unsafe fn create_pipeline(instance: &Instance, data: &mut AppData, device: &
 
 
 
-unsafe fn create_shader_module(device: &Device, bytecode: &[u8]) -> Result<vk::ShaderModule> {
+/// Creates a compute pipeline running the `main` function of a compiled compute shader.
+pub(crate) unsafe fn create_compute_pipeline(device: &Device, data: &AppData, layout: vk::PipelineLayout, bytecode: &[u8]) -> Result<Pipeline> {
+
+    let shader_module = create_shader_module(device, bytecode)?;
+
+    let stage_info = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(shader_module)
+        .name(b"main\0");
+
+    let pipeline_info = vk::ComputePipelineCreateInfo::builder()
+        .stage(stage_info)
+        .layout(layout);
+
+    let pipeline = device.create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info], None);
+
+    device.destroy_shader_module(shader_module, None);
+
+    return Ok(Pipeline::new(pipeline?.0, &data.deletion_queue));
+}
+
+
+/// Creates a pipeline that draws a full-screen triangle with `fullscreen.vert` and the given fragment shader, for passes that read images
+/// and write every pixel of the target. The viewport is dynamic, see `cmd_set_viewport`, as these passes run at different sizes.
+pub(crate) unsafe fn create_fullscreen_pipeline(device: &Device, data: &AppData, layout: vk::PipelineLayout, render_pass: vk::RenderPass, fragment_bytecode: &[u8]) -> Result<Pipeline> {
+
+    let vertex_shader_module = create_shader_module(device, include_bytes!("shaders/fullscreen.spv"))?;
+    let fragment_shader_module = create_shader_module(device, fragment_bytecode)?;
+
+    let vertex_stage_info = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vertex_shader_module)
+        .name(b"main\0");
+
+    let fragment_stage_info = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(fragment_shader_module)
+        .name(b"main\0");
+
+    // The full-screen triangle comes from the vertex index alone.
+    let vertex_input_stage = vk::PipelineVertexInputStateCreateInfo::builder();
+
+    let input_assembly_stage = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewport_count(1)
+        .scissor_count(1);
+
+    let dynamic_states = &[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+
+    let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+        .dynamic_states(dynamic_states);
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false)
+        .line_width(1.0);
+
+    let multi_sample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(vk::SampleCountFlags::_1);
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(false);
+
+    let blend_attachments = &[attachment];
+
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .attachments(blend_attachments);
+
+    let stages = &[vertex_stage_info, fragment_stage_info];
+
+    let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_stage)
+        .input_assembly_state(&input_assembly_stage)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multi_sample_state)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state)
+        .layout(layout)
+        .render_pass(render_pass)
+        .subpass(0);
+
+    let pipeline = device.create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None);
+
+    device.destroy_shader_module(vertex_shader_module, None);
+    device.destroy_shader_module(fragment_shader_module, None);
+
+    return Ok(Pipeline::new(pipeline?.0, &data.deletion_queue));
+}
+
+
+/// Sets the dynamic viewport and scissor of a full-screen pipeline to cover `extent`.
+pub(crate) unsafe fn cmd_set_viewport(device: &Device, command_buffer: vk::CommandBuffer, extent: vk::Extent2D) {
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(extent.width as f32)
+        .height(extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(extent);
+
+    device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+    device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+}
+
+
+pub(crate) unsafe fn create_shader_module(device: &Device, bytecode: &[u8]) -> Result<vk::ShaderModule> {
 
     let (prefix, aligned_bytes, suffix) = bytecode.align_to::<u32>();
 
@@ -0,0 +1,622 @@
+use vulkanalia::prelude::v1_0::*;
+use nalgebra_glm as glm;
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use log::*;
+use std::fs;
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+
+use crate::allocator::{ResourceKind, allocate_memory};
+use crate::app::AppData;
+use crate::buffers::{create_buffer, fill_buffer, begin_single_time_commands, end_single_time_commands};
+use crate::descriptors::update_post_descriptor_set;
+use crate::images::{create_image_view, transition_image_layout};
+use crate::pipeline::{create_fullscreen_pipeline, cmd_set_viewport};
+use crate::render_graph::{Access, ImageId, RenderGraph, TransientDesc};
+use crate::resources::{Image, ImageView, Pipeline, PipelineLayout, Sampler};
+
+
+
+/// Passes a frame's post-processing chain can have, each takes a descriptor set of the swapchain image's post pool.
+pub(crate) const MAX_POST_PASSES: usize = 32;
+
+
+
+/// A full-screen effect applied to the tone mapped image, see `PostChain`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PostEffect {
+    /// Makes the parts brighter than `threshold` glow, blurred at half resolution over about `radius` texels of it.
+    /// The colors are tone mapped already, so the threshold is between 0 and 1. `knee` softens the threshold by that much either side.
+    Bloom { threshold: f32, knee: f32, intensity: f32, radius: f32 },
+    /// Fast approximate anti-aliasing. Edges need a contrast of `edge_threshold` times the brightest pixel around them,
+    /// and at least `edge_threshold_min`. `subpixel` from 0 to 1 is how much aliasing within a pixel is smoothed.
+    Fxaa { edge_threshold: f32, edge_threshold_min: f32, subpixel: f32 },
+    /// Darkens the corners by up to `intensity`, from `radius` (0 at the center, 1 at the corners) over `smoothness`.
+    Vignette { intensity: f32, radius: f32, smoothness: f32 },
+    /// Pushes the red and blue channels `strength` texels apart at the corners, like the fringes of a cheap lens.
+    ChromaticAberration { strength: f32 },
+    /// Grades the colors with the 3D LUT of a .cube file, blended with the ungraded colors by `strength`.
+    ColorGrading { lut: PathBuf, strength: f32 }
+}
+
+
+impl PostEffect {
+    pub fn bloom() -> Self {
+        PostEffect::Bloom { threshold: 0.8, knee: 0.2, intensity: 0.6, radius: 1.5 }
+    }
+
+    pub fn fxaa() -> Self {
+        PostEffect::Fxaa { edge_threshold: 0.166, edge_threshold_min: 0.0833, subpixel: 0.75 }
+    }
+
+    pub fn vignette() -> Self {
+        PostEffect::Vignette { intensity: 0.4, radius: 0.5, smoothness: 0.5 }
+    }
+
+    pub fn chromatic_aberration() -> Self {
+        PostEffect::ChromaticAberration { strength: 3.0 }
+    }
+
+    pub fn color_grading(lut: impl Into<PathBuf>) -> Self {
+        PostEffect::ColorGrading { lut: lut.into(), strength: 1.0 }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PostEffect::Bloom { .. } => "bloom",
+            PostEffect::Fxaa { .. } => "fxaa",
+            PostEffect::Vignette { .. } => "vignette",
+            PostEffect::ChromaticAberration { .. } => "chromatic aberration",
+            PostEffect::ColorGrading { .. } => "color grading"
+        }
+    }
+
+    /// The full-screen passes the effect takes.
+    fn pass_count(&self) -> usize {
+        match self {
+            PostEffect::Bloom { .. } => 4,
+            _ => 1
+        }
+    }
+}
+
+
+/// An effect of a `PostChain`, disabled effects keep their settings and place in the chain.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PostStage {
+    pub effect: PostEffect,
+    #[serde(default = "enabled")]
+    pub enabled: bool
+}
+
+
+fn enabled() -> bool {
+    true
+}
+
+
+/// The post-processing effects applied to the tone mapped image before it is presented, in order. Set it with `Renderer::set_post_chain`.
+///
+/// Chains can be loaded from RON files like
+/// `(stages: [(effect: Bloom(threshold: 0.8, knee: 0.2, intensity: 0.6, radius: 1.5)), (effect: Fxaa(edge_threshold: 0.166, edge_threshold_min: 0.0833, subpixel: 0.75), enabled: false)])`,
+/// see resources/post.ron.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PostChain {
+    pub stages: Vec<PostStage>
+}
+
+
+impl PostChain {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Couldn't read post chain {}: {}", path.display(), e))?;
+
+        return ron::from_str(&text).map_err(|e| anyhow!("Couldn't parse post chain {}: {}", path.display(), e));
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?)?;
+
+        return Ok(());
+    }
+
+    /// Adds an enabled effect at the end of the chain.
+    pub fn push(&mut self, effect: PostEffect) -> &mut Self {
+        self.stages.push(PostStage { effect, enabled: true });
+        self
+    }
+
+    /// Moves the stage at `from` to `to`, shifting the stages in between.
+    pub fn move_stage(&mut self, from: usize, to: usize) {
+        let stage = self.stages.remove(from);
+        self.stages.insert(to.min(self.stages.len()), stage);
+    }
+
+    /// The first stage with an effect of that name, see `PostEffect::name`.
+    pub fn find_mut(&mut self, name: &str) -> Option<&mut PostStage> {
+        self.stages.iter_mut().find(|stage| stage.effect.name() == name)
+    }
+
+    pub fn enabled_effects(&self) -> impl Iterator<Item = &PostEffect> {
+        self.stages.iter().filter(|stage| stage.enabled).map(|stage| &stage.effect)
+    }
+
+    /// The LUT files of the color grading stages, enabled or not.
+    pub fn luts(&self) -> impl Iterator<Item = &Path> {
+        self.stages.iter().filter_map(|stage| match &stage.effect {
+            PostEffect::ColorGrading { lut, .. } => Some(lut.as_path()),
+            _ => None
+        })
+    }
+}
+
+
+
+/// The push constants of the post-processing pipelines, must match post.glsl.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct PostConstants {
+    texel_size: glm::Vec2,
+    _padding: glm::Vec2,
+    params: glm::Vec4
+}
+
+
+impl PostConstants {
+    fn new(input_extent: vk::Extent2D, params: glm::Vec4) -> Self {
+        let texel_size = glm::vec2(1.0 / input_extent.width as f32, 1.0 / input_extent.height as f32);
+
+        Self { texel_size, _padding: glm::Vec2::zeros(), params }
+    }
+}
+
+
+/// The pipelines of the post-processing passes, they render into images of the swapchain format.
+#[derive(Debug, Default)]
+pub(crate) struct PostPipelines {
+    pub bloom_threshold: Pipeline,
+    pub blur: Pipeline,
+    pub bloom_composite: Pipeline,
+    pub fxaa: Pipeline,
+    pub vignette: Pipeline,
+    pub chromatic_aberration: Pipeline,
+    pub color_grading: Pipeline
+}
+
+
+/// A 3D color grading LUT, sampled with `AppData::post_sampler`.
+#[derive(Debug)]
+pub(crate) struct Lut {
+    /// Only sampled through `view`, but owned here so it lives as long as the view.
+    #[allow(dead_code)]
+    pub image: Image,
+    pub view: ImageView,
+    pub size: u32
+}
+
+
+/// The entries of a .cube file, red changes fastest, then green, then blue.
+#[derive(Clone, Debug, PartialEq)]
+struct CubeLut {
+    size: u32,
+    entries: Vec<glm::Vec3>
+}
+
+
+/// Parses an Adobe / Resolve .cube 3D LUT, only LUTs with the default domain of 0 to 1 are supported.
+fn parse_cube(text: &str) -> Result<CubeLut> {
+
+    let mut size = None;
+    let mut entries = vec![];
+
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+        let mut words = line.split_whitespace();
+        let keyword = words.next().unwrap_or_default();
+
+        match keyword {
+            "TITLE" => {},
+            "LUT_3D_SIZE" => size = Some(words.next().and_then(|s| s.parse::<u32>().ok()).ok_or_else(|| anyhow!("Invalid LUT size: {}", line))?),
+            "LUT_1D_SIZE" => return Err(anyhow!("1D LUTs aren't supported.")),
+            "DOMAIN_MIN" | "DOMAIN_MAX" => {
+                let expected = if keyword == "DOMAIN_MIN" { 0.0 } else { 1.0 };
+
+                if words.any(|w| w.parse::<f32>().ok() != Some(expected)) {
+                    return Err(anyhow!("Only LUTs with a domain of 0 to 1 are supported: {}", line));
+                }
+            },
+            _ => {
+                let values = line.split_whitespace().map(|w| w.parse::<f32>()).collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| anyhow!("Invalid LUT line: {}", line))?;
+
+                match values[..] {
+                    [r, g, b] => entries.push(glm::vec3(r, g, b)),
+                    _ => return Err(anyhow!("Invalid LUT line: {}", line))
+                }
+            }
+        }
+    }
+
+    let size = size.ok_or_else(|| anyhow!("The LUT has no LUT_3D_SIZE."))?;
+
+    if size < 2 || entries.len() != (size * size * size) as usize {
+        return Err(anyhow!("A LUT of size {} needs {} entries, not {}.", size, size * size * size, entries.len()));
+    }
+
+    return Ok(CubeLut { size, entries });
+}
+
+
+/// The sampler the post-processing passes read their inputs and LUTs with, and the layout of their pipelines.
+pub(crate) unsafe fn create_post_resources(device: &Device, data: &mut AppData) -> Result<()> {
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .offset(0)
+        .size(size_of::<PostConstants>() as u32);
+
+    let set_layouts = &[*data.post_descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+
+    let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.post_pipeline_layout = PipelineLayout::new(device.create_pipeline_layout(&pipeline_layout_info, None)?, &data.deletion_queue);
+
+
+    // Bilinear, the bloom downsample and blur take several texels per tap.
+    let sampler_info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .min_lod(0.0)
+        .max_lod(0.0);
+
+    data.post_sampler = Sampler::new(device.create_sampler(&sampler_info, None)?, &data.deletion_queue);
+
+    return Ok(());
+}
+
+
+/// The post-processing pipelines share the tone mapping render pass, they write the same kind of image.
+pub(crate) unsafe fn create_post_pipelines(device: &Device, data: &mut AppData) -> Result<()> {
+
+    let layout = *data.post_pipeline_layout;
+    let render_pass = *data.tone_mapping_render_pass;
+
+    data.post_pipelines = PostPipelines {
+        bloom_threshold: create_fullscreen_pipeline(device, data, layout, render_pass, include_bytes!("shaders/bloom_threshold.spv"))?,
+        blur: create_fullscreen_pipeline(device, data, layout, render_pass, include_bytes!("shaders/blur.spv"))?,
+        bloom_composite: create_fullscreen_pipeline(device, data, layout, render_pass, include_bytes!("shaders/bloom_composite.spv"))?,
+        fxaa: create_fullscreen_pipeline(device, data, layout, render_pass, include_bytes!("shaders/fxaa.spv"))?,
+        vignette: create_fullscreen_pipeline(device, data, layout, render_pass, include_bytes!("shaders/vignette.spv"))?,
+        chromatic_aberration: create_fullscreen_pipeline(device, data, layout, render_pass, include_bytes!("shaders/chromatic_aberration.spv"))?,
+        color_grading: create_fullscreen_pipeline(device, data, layout, render_pass, include_bytes!("shaders/color_grading.spv"))?
+    };
+
+    info!("Created post-processing pipelines!");
+
+    return Ok(());
+}
+
+
+/// Loads a .cube file into a 3D texture of RGBA8 texels and keeps it in `AppData::luts` under its path.
+pub(crate) unsafe fn load_lut(instance: &Instance, device: &Device, data: &mut AppData, path: &Path) -> Result<()> {
+
+    let text = fs::read_to_string(path).map_err(|e| anyhow!("Couldn't read LUT {}: {}", path.display(), e))?;
+    let cube = parse_cube(&text).map_err(|e| anyhow!("Couldn't parse LUT {}: {}", path.display(), e))?;
+
+    let texels = cube.entries.iter()
+        .flat_map(|entry| [entry.x, entry.y, entry.z, 1.0])
+        .map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8)
+        .collect::<Vec<_>>();
+
+    let format = vk::Format::R8G8B8A8_UNORM;
+    let size = cube.size;
+
+    let staging_buffer = create_buffer(
+        texels.len() as u64,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        device,
+        instance,
+        data)?;
+
+    fill_buffer(&staging_buffer.allocation, texels.as_ptr(), texels.len())?;
+
+
+    let indicies = &[data.queue_family_indicies.graphics];
+
+    let info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::_3D)
+        .format(format)
+        .extent(vk::Extent3D { width: size, height: size, depth: size })
+        .mip_levels(1)
+        .array_layers(1)
+        .samples(vk::SampleCountFlags::_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .queue_family_indices(indicies)
+        .initial_layout(vk::ImageLayout::UNDEFINED);
+
+    let image = device.create_image(&info, None)?;
+    let requirements = device.get_image_memory_requirements(image);
+    let allocation = allocate_memory(instance, device, data, requirements, vk::MemoryPropertyFlags::DEVICE_LOCAL, ResourceKind::Optimal)?;
+
+    device.bind_image_memory(image, allocation.memory, allocation.offset)?;
+
+    let image = Image::new(image, allocation, &data.deletion_queue);
+
+
+    transition_image_layout(device, data, *image, vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL, 1)?;
+
+    let subresource = vk::ImageSubresourceLayers::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .mip_level(0)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    let region = vk::BufferImageCopy::builder()
+        .buffer_offset(0)
+        .buffer_row_length(0)
+        .buffer_image_height(0)
+        .image_subresource(subresource)
+        .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+        .image_extent(vk::Extent3D { width: size, height: size, depth: size });
+
+    let command_buffer = begin_single_time_commands(device, data)?;
+    device.cmd_copy_buffer_to_image(command_buffer, *staging_buffer, *image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &[region]);
+    end_single_time_commands(device, data, command_buffer)?;
+
+    transition_image_layout(device, data, *image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, 1)?;
+
+
+    let subresource_range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1)
+        .build();
+
+    let view = create_image_view(&image, device, data, format, subresource_range, vk::ImageViewType::_3D)?;
+
+    info!("Loaded the {}^3 LUT {}", size, path.display());
+
+    data.luts.insert(path.to_path_buf(), Lut { image, view, size });
+
+    return Ok(());
+}
+
+
+/// A full-screen pass of the chain. `second` is bound next to the input, the bloom of the composite or the LUT of the grading.
+struct PostPass {
+    name: &'static str,
+    pipeline: vk::Pipeline,
+    input: ImageId,
+    input_extent: vk::Extent2D,
+    second: Option<Second>,
+    output: ImageId,
+    output_extent: vk::Extent2D,
+    params: glm::Vec4
+}
+
+
+#[derive(Copy, Clone)]
+enum Second {
+    Image(ImageId),
+    Lut(vk::ImageView)
+}
+
+
+impl PostPass {
+    /// A pass that reads and writes images of the same size.
+    fn new(name: &'static str, pipeline: &Pipeline, input: ImageId, output: ImageId, extent: vk::Extent2D, params: glm::Vec4) -> Self {
+        Self { name, pipeline: **pipeline, input, input_extent: extent, second: None, output, output_extent: extent, params }
+    }
+}
+
+
+/// Adds the passes of the enabled effects of `chain`, the last one writes `target`. Returns the image the first one reads,
+/// which the tone mapping pass has to write, `target` itself when no effect is enabled.
+pub(crate) unsafe fn add_post_passes(graph: &mut RenderGraph, device: &Device, data: &AppData, image_index: usize, chain: &PostChain, target: ImageId) -> Result<ImageId> {
+
+    let effects = chain.enabled_effects().collect::<Vec<_>>();
+
+    if effects.is_empty() {
+        return Ok(target);
+    }
+
+    let pass_count = effects.iter().map(|effect| effect.pass_count()).sum::<usize>();
+
+    if pass_count > MAX_POST_PASSES {
+        return Err(anyhow!("The post chain takes {} passes, at most {} are supported.", pass_count, MAX_POST_PASSES));
+    }
+
+    // The frame that last used the pool's sets is done, the swapchain image was waited for.
+    let pool = *data.post_descriptor_pools[image_index];
+    device.reset_descriptor_pool(pool, vk::DescriptorPoolResetFlags::empty())?;
+
+    let set_layouts = vec![*data.post_descriptor_set_layout; pass_count];
+
+    let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(pool)
+        .set_layouts(&set_layouts);
+
+    let mut descriptor_sets = device.allocate_descriptor_sets(&allocate_info)?.into_iter();
+
+
+    let extent = data.swapchain_extent;
+    let half_extent = vk::Extent2D { width: (extent.width / 2).max(1), height: (extent.height / 2).max(1) };
+
+    let image = |extent| TransientDesc {
+        format: data.swapchain_image_format,
+        extent,
+        samples: vk::SampleCountFlags::_1,
+        usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        aspect: vk::ImageAspectFlags::COLOR
+    };
+
+    let first = graph.create_image("tone mapped", image(extent));
+    let mut input = first;
+
+    let pipelines = &data.post_pipelines;
+
+    for (i, effect) in effects.iter().enumerate() {
+        let output = if i + 1 == effects.len() { target } else { graph.create_image("post", image(extent)) };
+
+        let passes = match effect {
+            PostEffect::Bloom { threshold, knee, intensity, radius } => {
+                let bright = graph.create_image("bloom bright", image(half_extent));
+                let blurred = graph.create_image("bloom blurred", image(half_extent));
+                let bloom = graph.create_image("bloom", image(half_extent));
+
+                vec![
+                    PostPass { input_extent: extent, ..PostPass::new("bloom threshold", &pipelines.bloom_threshold, input, bright, half_extent, glm::vec4(*threshold, *knee, 0.0, 0.0)) },
+                    PostPass::new("bloom blur", &pipelines.blur, bright, blurred, half_extent, glm::vec4(*radius, 0.0, 0.0, 0.0)),
+                    PostPass::new("bloom blur", &pipelines.blur, blurred, bloom, half_extent, glm::vec4(0.0, *radius, 0.0, 0.0)),
+                    PostPass { second: Some(Second::Image(bloom)), ..PostPass::new("bloom", &pipelines.bloom_composite, input, output, extent, glm::vec4(*intensity, 0.0, 0.0, 0.0)) }
+                ]
+            },
+            PostEffect::Fxaa { edge_threshold, edge_threshold_min, subpixel } =>
+                vec![PostPass::new("fxaa", &pipelines.fxaa, input, output, extent, glm::vec4(*edge_threshold, *edge_threshold_min, *subpixel, 0.0))],
+            PostEffect::Vignette { intensity, radius, smoothness } =>
+                vec![PostPass::new("vignette", &pipelines.vignette, input, output, extent, glm::vec4(*intensity, *radius, *smoothness, 0.0))],
+            PostEffect::ChromaticAberration { strength } =>
+                vec![PostPass::new("chromatic aberration", &pipelines.chromatic_aberration, input, output, extent, glm::vec4(*strength, 0.0, 0.0, 0.0))],
+            PostEffect::ColorGrading { lut, strength } => {
+                let lut = data.luts.get(lut).ok_or_else(|| anyhow!("The LUT {} isn't loaded.", lut.display()))?;
+
+                vec![PostPass {
+                    second: Some(Second::Lut(*lut.view)),
+                    ..PostPass::new("color grading", &pipelines.color_grading, input, output, extent, glm::vec4(*strength, lut.size as f32, 0.0, 0.0))
+                }]
+            }
+        };
+
+        for pass in passes {
+            add_post_pass(graph, *data.tone_mapping_render_pass, pass, descriptor_sets.next().unwrap());
+        }
+
+        input = output;
+    }
+
+    return Ok(first);
+}
+
+
+/// The post passes share the tone mapping render pass, see `create_post_pipelines`.
+unsafe fn add_post_pass(graph: &mut RenderGraph, render_pass: vk::RenderPass, pass: PostPass, descriptor_set: vk::DescriptorSet) {
+
+    let constants = PostConstants::new(pass.input_extent, pass.params);
+    let PostPass { pipeline, input, second, output_extent, .. } = pass;
+
+    let graph_pass = graph.add_pass(pass.name)
+        .render_pass(render_pass, output_extent, &[(pass.output, Access::ColorAttachment)], &[])
+        .image(input, Access::Sampled);
+
+    if let Some(Second::Image(image)) = second {
+        graph_pass.image(image, Access::Sampled);
+    }
+
+    graph_pass.record(move |device, data, images, command_buffer| {
+        let second_view = match second {
+            Some(Second::Image(image)) => Some(images.view(image)),
+            Some(Second::Lut(view)) => Some(view),
+            None => None
+        };
+
+        update_post_descriptor_set(device, data, descriptor_set, images.view(input), second_view);
+
+        cmd_set_viewport(device, command_buffer, output_extent);
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+        device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, *data.post_pipeline_layout, 0, &[descriptor_set], &[]);
+
+        let constant_bytes = std::slice::from_raw_parts((&constants as *const PostConstants).cast::<u8>(), size_of::<PostConstants>());
+        device.cmd_push_constants(command_buffer, *data.post_pipeline_layout, vk::ShaderStageFlags::FRAGMENT, 0, constant_bytes);
+
+        device.cmd_draw(command_buffer, 3, 1, 0, 0);
+    });
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain() -> PostChain {
+        let mut chain = PostChain::default();
+        chain.push(PostEffect::bloom()).push(PostEffect::fxaa()).push(PostEffect::color_grading("resources/warm.cube"));
+        chain
+    }
+
+
+    #[test]
+    fn constants_match_the_shader_layout() {
+        assert_eq!(size_of::<PostConstants>(), 32);
+
+        let constants = PostConstants::new(vk::Extent2D { width: 800, height: 400 }, glm::vec4(1.0, 2.0, 3.0, 4.0));
+        assert_eq!(constants.texel_size, glm::vec2(1.0 / 800.0, 1.0 / 400.0));
+    }
+
+    #[test]
+    fn stages_can_be_reordered_and_disabled() {
+        let mut chain = chain();
+        chain.move_stage(2, 0);
+        chain.find_mut("bloom").unwrap().enabled = false;
+
+        let names: Vec<_> = chain.enabled_effects().map(|effect| effect.name()).collect();
+        assert_eq!(names, vec!["color grading", "fxaa"]);
+
+        // Disabled stages keep their LUTs loaded.
+        chain.find_mut("color grading").unwrap().enabled = false;
+        assert_eq!(chain.luts().collect::<Vec<_>>(), vec![Path::new("resources/warm.cube")]);
+    }
+
+    #[test]
+    fn chains_round_trip_through_ron() {
+        let text = ron::to_string(&chain()).unwrap();
+        assert_eq!(ron::from_str::<PostChain>(&text).unwrap(), chain());
+
+        let chain: PostChain = ron::from_str("(stages: [(effect: Vignette(intensity: 0.5, radius: 0.2, smoothness: 0.6)), (effect: ChromaticAberration(strength: 2.0), enabled: false)])").unwrap();
+        assert_eq!(chain.enabled_effects().collect::<Vec<_>>(), vec![&PostEffect::Vignette { intensity: 0.5, radius: 0.2, smoothness: 0.6 }]);
+    }
+
+    #[test]
+    fn the_example_files_load() {
+        let chain = PostChain::load(Path::new("resources/post.ron")).unwrap();
+
+        for lut in chain.luts() {
+            assert_eq!(parse_cube(&fs::read_to_string(lut).unwrap()).unwrap().entries.len(), 8 * 8 * 8);
+        }
+    }
+
+    #[test]
+    fn cube_entries_are_in_file_order() {
+        let mut text = String::from("# comment\nTITLE \"test\"\nLUT_3D_SIZE 2\nDOMAIN_MIN 0 0 0\nDOMAIN_MAX 1 1 1\n\n");
+
+        for i in 0..8 {
+            text += &format!("{} {} {}\n", i as f32 / 10.0, 0.5, 1.0);
+        }
+
+        let cube = parse_cube(&text).unwrap();
+        assert_eq!(cube.size, 2);
+        assert_eq!(cube.entries[1], glm::vec3(0.1, 0.5, 1.0));
+        assert_eq!(cube.entries[7], glm::vec3(0.7, 0.5, 1.0));
+    }
+
+    #[test]
+    fn invalid_cubes_are_rejected() {
+        assert!(parse_cube("LUT_3D_SIZE 2\n0 0 0\n").is_err());
+        assert!(parse_cube("0 0 0\n").is_err());
+        assert!(parse_cube("LUT_1D_SIZE 2\n0 0 0\n1 1 1\n").is_err());
+        assert!(parse_cube("LUT_3D_SIZE 2\nDOMAIN_MAX 2 2 2\n").is_err());
+        assert!(parse_cube("LUT_3D_SIZE 2\n0 0\n").is_err());
+    }
+}
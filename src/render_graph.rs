@@ -0,0 +1,967 @@
+use vulkanalia::prelude::v1_0::*;
+use anyhow::{Result, anyhow};
+use log::*;
+use std::collections::HashMap;
+
+use crate::app::AppData;
+use crate::images::{create_image, create_image_view};
+use crate::resources::{Framebuffer, Image, ImageView};
+
+
+
+/// How a pass uses an image or buffer, which decides the stages, memory accesses and image layout the barriers around it synchronize.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Access {
+    ColorAttachment,
+    DepthAttachment,
+    /// A depth image read by fragment shaders, like the shadow map.
+    DepthSampled,
+    Sampled,
+    StorageRead,
+    /// Storage writes can also read, like the atomic counters of the culling shaders.
+    StorageWrite,
+    VertexBuffer,
+    IndirectBuffer,
+    TransferSrc,
+    TransferDst,
+    /// Read by the host once the frame is done, like the culled counts.
+    HostRead,
+    /// Handed to the presentation engine once the graph is done.
+    Present
+}
+
+
+impl Access {
+    /// The access that leaves an image in `layout`, for the transitions made outside of a graph.
+    pub fn from_layout(layout: vk::ImageLayout) -> Option<Self> {
+        match layout {
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => Some(Access::ColorAttachment),
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => Some(Access::DepthAttachment),
+            vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL => Some(Access::DepthSampled),
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => Some(Access::Sampled),
+            vk::ImageLayout::GENERAL => Some(Access::StorageWrite),
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL => Some(Access::TransferSrc),
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL => Some(Access::TransferDst),
+            vk::ImageLayout::PRESENT_SRC_KHR => Some(Access::Present),
+            _ => None
+        }
+    }
+
+    pub fn stages(self) -> vk::PipelineStageFlags {
+        match self {
+            Access::ColorAttachment => vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            Access::DepthAttachment => vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            Access::DepthSampled => vk::PipelineStageFlags::FRAGMENT_SHADER,
+            Access::Sampled => vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+            Access::StorageRead | Access::StorageWrite => vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+            Access::VertexBuffer => vk::PipelineStageFlags::VERTEX_INPUT,
+            Access::IndirectBuffer => vk::PipelineStageFlags::DRAW_INDIRECT,
+            Access::TransferSrc | Access::TransferDst => vk::PipelineStageFlags::TRANSFER,
+            Access::HostRead => vk::PipelineStageFlags::HOST,
+            Access::Present => vk::PipelineStageFlags::BOTTOM_OF_PIPE
+        }
+    }
+
+    pub fn access(self) -> vk::AccessFlags {
+        match self {
+            Access::ColorAttachment => vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            Access::DepthAttachment => vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            Access::DepthSampled | Access::Sampled | Access::StorageRead => vk::AccessFlags::SHADER_READ,
+            Access::StorageWrite => vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            Access::VertexBuffer => vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+            Access::IndirectBuffer => vk::AccessFlags::INDIRECT_COMMAND_READ,
+            Access::TransferSrc => vk::AccessFlags::TRANSFER_READ,
+            Access::TransferDst => vk::AccessFlags::TRANSFER_WRITE,
+            Access::HostRead => vk::AccessFlags::HOST_READ,
+            Access::Present => vk::AccessFlags::empty()
+        }
+    }
+
+    /// The part of `access` that writes, which later accesses have to wait for.
+    pub fn write_access(self) -> vk::AccessFlags {
+        match self {
+            Access::ColorAttachment => vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            Access::DepthAttachment => vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            Access::StorageWrite => vk::AccessFlags::SHADER_WRITE,
+            Access::TransferDst => vk::AccessFlags::TRANSFER_WRITE,
+            _ => vk::AccessFlags::empty()
+        }
+    }
+
+    pub fn layout(self) -> vk::ImageLayout {
+        match self {
+            Access::ColorAttachment => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            Access::DepthAttachment => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            Access::DepthSampled => vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            Access::Sampled => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            Access::StorageRead | Access::StorageWrite => vk::ImageLayout::GENERAL,
+            Access::VertexBuffer | Access::IndirectBuffer | Access::HostRead => vk::ImageLayout::UNDEFINED,
+            Access::TransferSrc => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            Access::TransferDst => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            Access::Present => vk::ImageLayout::PRESENT_SRC_KHR
+        }
+    }
+}
+
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct ImageId(usize);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct BufferId(usize);
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum Resource {
+    Image(usize),
+    Buffer(usize)
+}
+
+
+/// An image that only lives for the frame, the graph creates it and gives it to other transient images with the same description
+/// once the passes using it are done.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct TransientDesc {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub samples: vk::SampleCountFlags,
+    pub usage: vk::ImageUsageFlags,
+    pub aspect: vk::ImageAspectFlags
+}
+
+
+/// An image that outlives the frame, like a swapchain image or a layer of the shadow map.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct ImportedImage {
+    pub image: vk::Image,
+    pub view: vk::ImageView,
+    pub subresource: vk::ImageSubresourceRange,
+    /// How the previous frame left it, `None` when its contents can be discarded.
+    pub initial: Option<Access>,
+    /// How it has to be left for what comes after the graph, `None` to leave it as the last pass used it.
+    pub last: Option<Access>
+}
+
+
+#[derive(Copy, Clone, Debug)]
+enum ImageSource {
+    Imported(ImportedImage),
+    Transient(TransientDesc)
+}
+
+
+struct Raster {
+    render_pass: vk::RenderPass,
+    extent: vk::Extent2D,
+    attachments: Vec<ImageId>,
+    clear_values: Vec<vk::ClearValue>
+}
+
+
+type Record<'a> = Box<dyn FnOnce(&Device, &AppData, &Images, vk::CommandBuffer) + 'a>;
+
+
+/// The images of the graph while it is recorded, for passes that have to point descriptors at transient images.
+pub(crate) struct Images<'b>(&'b [(vk::Image, vk::ImageView, vk::ImageSubresourceRange)]);
+
+
+impl Images<'_> {
+    pub fn view(&self, image: ImageId) -> vk::ImageView {
+        self.0[image.0].1
+    }
+}
+
+
+/// A node of the graph, the images and buffers it declares decide what it waits for and what waits for it.
+pub(crate) struct Pass<'a> {
+    name: &'static str,
+    uses: Vec<(Resource, Access)>,
+    raster: Option<Raster>,
+    record: Option<Record<'a>>
+}
+
+
+impl<'a> Pass<'a> {
+    pub fn image(&mut self, image: ImageId, access: Access) -> &mut Self {
+        self.uses.push((Resource::Image(image.0), access));
+        self
+    }
+
+    /// Buffers are written by the host before the frame is submitted, only what passes do with them is synchronized.
+    pub fn buffer(&mut self, buffer: BufferId, access: Access) -> &mut Self {
+        self.uses.push((Resource::Buffer(buffer.0), access));
+        self
+    }
+
+    /// The graph begins `render_pass` before the recorded commands and ends it after them.
+    /// `attachments` are in the order of the render pass' attachment descriptions, whose layouts have to be the ones of their access.
+    pub fn render_pass(&mut self, render_pass: vk::RenderPass, extent: vk::Extent2D, attachments: &[(ImageId, Access)], clear_values: &[vk::ClearValue]) -> &mut Self {
+        attachments.iter().for_each(|(image, access)| { self.image(*image, *access); });
+
+        self.raster = Some(Raster {
+            render_pass,
+            extent,
+            attachments: attachments.iter().map(|(image, _)| *image).collect(),
+            clear_values: clear_values.to_vec()
+        });
+        self
+    }
+
+    /// The commands of the pass, recorded once its barriers are.
+    pub fn record(&mut self, record: impl FnOnce(&Device, &AppData, &Images, vk::CommandBuffer) + 'a) -> &mut Self {
+        self.record = Some(Box::new(record));
+        self
+    }
+}
+
+
+
+/// The passes of a frame and the images and buffers they use. Passes that only read a resource run after every pass that writes it,
+/// passes that write the same resource in the order they were added.
+#[derive(Default)]
+pub(crate) struct RenderGraph<'a> {
+    images: Vec<(&'static str, ImageSource)>,
+    /// With how the previous frame left them, `None` for buffers the host writes.
+    buffers: Vec<(&'static str, vk::Buffer, Option<Access>)>,
+    passes: Vec<Pass<'a>>
+}
+
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self { images: vec![], buffers: vec![], passes: vec![] }
+    }
+
+    pub fn import_image(&mut self, name: &'static str, image: ImportedImage) -> ImageId {
+        self.images.push((name, ImageSource::Imported(image)));
+        ImageId(self.images.len() - 1)
+    }
+
+    pub fn create_image(&mut self, name: &'static str, desc: TransientDesc) -> ImageId {
+        self.images.push((name, ImageSource::Transient(desc)));
+        ImageId(self.images.len() - 1)
+    }
+
+    pub fn import_buffer(&mut self, name: &'static str, buffer: vk::Buffer) -> BufferId {
+        self.buffers.push((name, buffer, None));
+        BufferId(self.buffers.len() - 1)
+    }
+
+    /// A buffer that only the GPU writes and that carries its contents from one frame to the next,
+    /// the first pass using it waits for `initial` in the previous frames.
+    pub fn import_persistent_buffer(&mut self, name: &'static str, buffer: vk::Buffer, initial: Access) -> BufferId {
+        self.buffers.push((name, buffer, Some(initial)));
+        BufferId(self.buffers.len() - 1)
+    }
+
+    pub fn add_pass(&mut self, name: &'static str) -> &mut Pass<'a> {
+        self.passes.push(Pass { name, uses: vec![], raster: None, record: None });
+        self.passes.last_mut().unwrap()
+    }
+
+
+    /// Records the passes with the barriers between them into `command_buffer`, creating the transient images and framebuffers
+    /// the previous frames didn't need.
+    pub unsafe fn execute(self, instance: &Instance, device: &Device, data: &mut AppData, command_buffer: vk::CommandBuffer) -> Result<()> {
+
+        let schedule = self.compile()?;
+
+        let transients = claim_transients(instance, device, data, &schedule.transients)?;
+
+        let images = self.images.iter().enumerate().map(|(i, (_, source))| match source {
+            ImageSource::Imported(imported) => (imported.image, imported.view, imported.subresource),
+            ImageSource::Transient(desc) => {
+                let (image, view) = transients[schedule.slots[i].unwrap_or_default()];
+                (image, view, subresource(desc.aspect))
+            }
+        }).collect::<Vec<_>>();
+
+        let buffers = self.buffers.iter().map(|(_, buffer, _)| *buffer).collect::<Vec<_>>();
+
+        let mut passes = self.passes.into_iter().map(Some).collect::<Vec<_>>();
+
+        for (pass, barriers) in schedule.order.iter().zip(&schedule.barriers) {
+            let pass = passes[*pass].take().unwrap();
+
+            cmd_barriers(device, command_buffer, barriers, &images, &buffers);
+
+            if let Some(raster) = &pass.raster {
+                let attachments = raster.attachments.iter().map(|image| images[image.0].1).collect();
+                let framebuffer = framebuffer(device, data, raster.render_pass, attachments, raster.extent)?;
+
+                let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+                    .render_pass(raster.render_pass)
+                    .framebuffer(framebuffer)
+                    .render_area(vk::Rect2D { offset: vk::Offset2D { x: 0, y: 0 }, extent: raster.extent })
+                    .clear_values(&raster.clear_values);
+
+                device.cmd_begin_render_pass(command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
+            }
+
+            if let Some(record) = pass.record {
+                record(device, data, &Images(&images), command_buffer);
+            }
+
+            if pass.raster.is_some() {
+                device.cmd_end_render_pass(command_buffer);
+            }
+        }
+
+        cmd_barriers(device, command_buffer, &schedule.final_barriers, &images, &buffers);
+
+        return Ok(());
+    }
+
+
+    /// Orders the passes, aliases the transient images and works out the barriers, without touching the device.
+    fn compile(&self) -> Result<Schedule> {
+
+        let uses = self.passes.iter().map(|pass| merge_uses(pass, &self.images)).collect::<Result<Vec<_>>>()?;
+
+        let order = self.order(&uses)?;
+        let (slots, transients) = self.alias_transients(&order, &uses);
+
+        let mut states: HashMap<Physical, State> = HashMap::new();
+        let mut used = vec![false; self.images.len()];
+
+        let mut barriers = Vec::with_capacity(order.len());
+
+        for pass in &order {
+            let mut pass_barriers = Barriers::default();
+
+            for (resource, usage) in &uses[*pass] {
+                let (physical, discard, is_image) = match *resource {
+                    Resource::Image(i) => {
+                        let discard = !used[i] && !matches!(self.images[i].1, ImageSource::Imported(ImportedImage { initial: Some(_), .. }));
+                        used[i] = true;
+                        (self.physical(i, &slots), discard, true)
+                    },
+                    Resource::Buffer(i) => (Physical::Buffer(i), false, false)
+                };
+
+                let state = states.entry(physical).or_insert_with(|| self.initial_state(physical));
+
+                if let Some(dependency) = state.access(usage, discard, is_image) {
+                    pass_barriers.push(*resource, dependency);
+                }
+            }
+
+            barriers.push(pass_barriers);
+        }
+
+        let mut final_barriers = Barriers::default();
+
+        for (i, (_, source)) in self.images.iter().enumerate() {
+            if let ImageSource::Imported(ImportedImage { initial, last: Some(last), .. }) = source {
+                let physical = Physical::Imported(i);
+                let state = states.entry(physical).or_insert_with(|| self.initial_state(physical));
+
+                if let Some(dependency) = state.access(&Usage::from(*last), !used[i] && initial.is_none(), true) {
+                    final_barriers.push(Resource::Image(i), dependency);
+                }
+            }
+        }
+
+        return Ok(Schedule { order, barriers, final_barriers, slots, transients });
+    }
+
+
+    /// Sorts the passes so that each runs after the passes it depends on, keeping the order they were added in where it can.
+    fn order(&self, uses: &[Vec<(Resource, Usage)>]) -> Result<Vec<usize>> {
+
+        let mut writers: HashMap<Resource, Vec<usize>> = HashMap::new();
+        let mut readers: HashMap<Resource, Vec<usize>> = HashMap::new();
+
+        for (pass, pass_uses) in uses.iter().enumerate() {
+            for (resource, usage) in pass_uses {
+                let passes = if usage.writes() { &mut writers } else { &mut readers };
+                passes.entry(*resource).or_default().push(pass);
+            }
+        }
+
+        let mut dependents = vec![vec![]; self.passes.len()];
+        let mut dependencies = vec![0; self.passes.len()];
+
+        let mut depend = |before: usize, after: usize| {
+            dependents[before].push(after);
+            dependencies[after] += 1;
+        };
+
+        for (resource, writers) in &writers {
+            writers.windows(2).for_each(|w| depend(w[0], w[1]));
+
+            for reader in readers.get(resource).into_iter().flatten() {
+                depend(*writers.last().unwrap(), *reader);
+            }
+        }
+
+        let mut order = Vec::with_capacity(self.passes.len());
+        let mut done = vec![false; self.passes.len()];
+
+        while let Some(pass) = (0..self.passes.len()).find(|p| !done[*p] && dependencies[*p] == 0) {
+            done[pass] = true;
+            order.push(pass);
+            dependents[pass].iter().for_each(|d| dependencies[*d] -= 1);
+        }
+
+        if order.len() < self.passes.len() {
+            let cycle = (0..self.passes.len()).filter(|p| !done[*p]).map(|p| self.passes[p].name).collect::<Vec<_>>();
+            return Err(anyhow!("The render graph passes {:?} depend on each other.", cycle));
+        }
+
+        return Ok(order);
+    }
+
+
+    /// Gives every used transient image an index into the transient images of the frame, images with the same description share one
+    /// when no pass uses both, from the first pass using one to the last.
+    fn alias_transients(&self, order: &[usize], uses: &[Vec<(Resource, Usage)>]) -> (Vec<Option<usize>>, Vec<TransientDesc>) {
+
+        let mut lifetimes: Vec<Option<(usize, usize)>> = vec![None; self.images.len()];
+
+        for (position, pass) in order.iter().enumerate() {
+            for (resource, _) in &uses[*pass] {
+                if let Resource::Image(i) = resource {
+                    lifetimes[*i] = Some(lifetimes[*i].map_or((position, position), |(first, _)| (first, position)));
+                }
+            }
+        }
+
+        let mut images = (0..self.images.len())
+            .filter_map(|i| match (self.images[i].1, lifetimes[i]) {
+                (ImageSource::Transient(desc), Some(lifetime)) => Some((i, desc, lifetime)),
+                _ => None
+            })
+            .collect::<Vec<_>>();
+
+        images.sort_by_key(|(_, _, (first, _))| *first);
+
+        let mut slots = vec![None; self.images.len()];
+        let mut transients: Vec<(TransientDesc, usize)> = vec![];
+
+        for (image, desc, (first, last)) in images {
+            let slot = match transients.iter().position(|(d, end)| *d == desc && *end < first) {
+                Some(slot) => {
+                    transients[slot].1 = last;
+                    slot
+                },
+                None => {
+                    transients.push((desc, last));
+                    transients.len() - 1
+                }
+            };
+
+            slots[image] = Some(slot);
+        }
+
+        return (slots, transients.into_iter().map(|(desc, _)| desc).collect());
+    }
+
+
+    fn physical(&self, image: usize, slots: &[Option<usize>]) -> Physical {
+        match self.images[image].1 {
+            ImageSource::Imported(_) => Physical::Imported(image),
+            ImageSource::Transient(_) => Physical::Transient(slots[image].unwrap_or_default())
+        }
+    }
+
+    fn initial_state(&self, physical: Physical) -> State {
+        match physical {
+            Physical::Imported(i) => match self.images[i].1 {
+                ImageSource::Imported(ImportedImage { initial: Some(initial), .. }) => State::after(initial),
+                _ => State::default()
+            },
+            Physical::Buffer(i) => self.buffers[i].2.map(State::after).unwrap_or_default(),
+            // The cached transient images are shared by the frames in flight, which may still use them in any pass of theirs.
+            Physical::Transient(_) => State {
+                write_stages: vk::PipelineStageFlags::ALL_COMMANDS,
+                write_access: vk::AccessFlags::MEMORY_WRITE,
+                ..Default::default()
+            }
+        }
+    }
+}
+
+
+
+/// The memory a resource is backed by, aliased transient images share one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum Physical {
+    Imported(usize),
+    Transient(usize),
+    Buffer(usize)
+}
+
+
+/// The accesses of a pass to one resource, combined.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Usage {
+    stages: vk::PipelineStageFlags,
+    access: vk::AccessFlags,
+    write_access: vk::AccessFlags,
+    layout: vk::ImageLayout
+}
+
+
+impl Usage {
+    fn writes(&self) -> bool {
+        !self.write_access.is_empty()
+    }
+}
+
+
+impl From<Access> for Usage {
+    fn from(access: Access) -> Self {
+        Self { stages: access.stages(), access: access.access(), write_access: access.write_access(), layout: access.layout() }
+    }
+}
+
+
+/// Combines the accesses of a pass to the same resource, an image can only be in one layout at a time.
+fn merge_uses(pass: &Pass, images: &[(&'static str, ImageSource)]) -> Result<Vec<(Resource, Usage)>> {
+    let mut merged: Vec<(Resource, Usage)> = vec![];
+
+    for (resource, access) in &pass.uses {
+        let usage = Usage::from(*access);
+
+        match merged.iter_mut().find(|(r, _)| r == resource) {
+            Some((Resource::Image(i), existing)) if existing.layout != usage.layout => {
+                return Err(anyhow!("The {} pass uses the {} image in both {:?} and {:?}.", pass.name, images[*i].0, existing.layout, usage.layout));
+            },
+            Some((_, existing)) => {
+                existing.stages |= usage.stages;
+                existing.access |= usage.access;
+                existing.write_access |= usage.write_access;
+            },
+            None => merged.push((*resource, usage))
+        }
+    }
+
+    return Ok(merged);
+}
+
+
+
+/// What happened to a resource so far, which the next access has to wait for.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+struct State {
+    layout: vk::ImageLayout,
+    /// The last write or layout transition.
+    write_stages: vk::PipelineStageFlags,
+    write_access: vk::AccessFlags,
+    /// The reads that waited for it since, the next write waits for them to be done.
+    read_stages: vk::PipelineStageFlags,
+    read_access: vk::AccessFlags
+}
+
+
+/// A barrier for one resource.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Dependency {
+    src_stages: vk::PipelineStageFlags,
+    src_access: vk::AccessFlags,
+    dst_stages: vk::PipelineStageFlags,
+    dst_access: vk::AccessFlags,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout
+}
+
+
+impl State {
+    /// The state a resource is in after `access`.
+    fn after(access: Access) -> Self {
+        let mut state = Self { layout: access.layout(), ..Default::default() };
+
+        match access.write_access().is_empty() {
+            true => (state.read_stages, state.read_access) = (access.stages(), access.access()),
+            false => (state.write_stages, state.write_access) = (access.stages(), access.write_access())
+        }
+
+        return state;
+    }
+
+    /// Moves on to `usage` and returns the barrier it needs first, if any. `discard` throws away what is in the image.
+    fn access(&mut self, usage: &Usage, discard: bool, is_image: bool) -> Option<Dependency> {
+
+        let old_layout = if discard { vk::ImageLayout::UNDEFINED } else { self.layout };
+        let new_layout = if is_image { usage.layout } else { vk::ImageLayout::UNDEFINED };
+
+        let dependency = |src_stages, src_access| Dependency {
+            src_stages,
+            src_access,
+            dst_stages: usage.stages,
+            dst_access: usage.access,
+            old_layout,
+            new_layout
+        };
+
+        if usage.writes() || old_layout != new_layout {
+            let mut src = (self.write_stages | self.read_stages, self.write_access);
+
+            // Nothing happened to it this frame, but the same pass may still be using it in the previous frame.
+            // For a swapchain image that also waits for it to be acquired, which the submit waits for at the color attachment stage.
+            if src.0.is_empty() {
+                if !is_image {
+                    *self = Self { write_stages: usage.stages, write_access: usage.write_access, ..Default::default() };
+                    return None;
+                }
+
+                src = (usage.stages, usage.write_access);
+            }
+
+            // A transition writes the image too, even when the access itself only reads.
+            *self = match usage.writes() {
+                true => Self { layout: new_layout, write_stages: usage.stages, write_access: usage.write_access, ..Default::default() },
+                false => Self { layout: new_layout, write_stages: usage.stages, read_stages: usage.stages, read_access: usage.access, ..Default::default() }
+            };
+
+            return Some(dependency(src.0, src.1));
+        }
+
+        let visible = self.read_stages.contains(usage.stages) && self.read_access.contains(usage.access);
+
+        let result = match self.write_stages.is_empty() || visible {
+            true => None,
+            false => Some(dependency(self.write_stages, self.write_access))
+        };
+
+        self.read_stages |= usage.stages;
+        self.read_access |= usage.access;
+
+        return result;
+    }
+}
+
+
+
+/// The barriers recorded before a pass, as a single `cmd_pipeline_barrier`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Barriers {
+    src_stages: vk::PipelineStageFlags,
+    dst_stages: vk::PipelineStageFlags,
+    dependencies: Vec<(Resource, Dependency)>
+}
+
+
+impl Barriers {
+    fn push(&mut self, resource: Resource, dependency: Dependency) {
+        self.src_stages |= dependency.src_stages;
+        self.dst_stages |= dependency.dst_stages;
+        self.dependencies.push((resource, dependency));
+    }
+}
+
+
+#[derive(Debug)]
+struct Schedule {
+    /// Indices of the passes in the order they are recorded.
+    order: Vec<usize>,
+    /// Recorded before the pass at the same position in `order`.
+    barriers: Vec<Barriers>,
+    /// Leave the imported images the way what comes after the graph expects them.
+    final_barriers: Barriers,
+    /// For every image, the transient image it is backed by, an index into `transients`.
+    slots: Vec<Option<usize>>,
+    transients: Vec<TransientDesc>
+}
+
+
+unsafe fn cmd_barriers(device: &Device, command_buffer: vk::CommandBuffer, barriers: &Barriers, images: &[(vk::Image, vk::ImageView, vk::ImageSubresourceRange)], buffers: &[vk::Buffer]) {
+
+    if barriers.dependencies.is_empty() {
+        return;
+    }
+
+    let mut image_barriers = vec![];
+    let mut buffer_barriers = vec![];
+
+    for (resource, dependency) in &barriers.dependencies {
+        match *resource {
+            Resource::Image(i) => image_barriers.push(vk::ImageMemoryBarrier::builder()
+                .src_access_mask(dependency.src_access)
+                .dst_access_mask(dependency.dst_access)
+                .old_layout(dependency.old_layout)
+                .new_layout(dependency.new_layout)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(images[i].0)
+                .subresource_range(images[i].2)
+                .build()),
+            Resource::Buffer(i) => buffer_barriers.push(vk::BufferMemoryBarrier::builder()
+                .src_access_mask(dependency.src_access)
+                .dst_access_mask(dependency.dst_access)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(buffers[i])
+                .offset(0)
+                .size(vk::WHOLE_SIZE as u64)
+                .build())
+        }
+    }
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        barriers.src_stages,
+        barriers.dst_stages,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &buffer_barriers,
+        &image_barriers);
+}
+
+
+
+/// The transient images and framebuffers of the render graph, kept from one frame to the next so a frame only creates what the previous ones didn't have.
+#[derive(Debug, Default)]
+pub(crate) struct RenderGraphCache {
+    images: Vec<(TransientDesc, Image, ImageView)>,
+    framebuffers: HashMap<(vk::RenderPass, Vec<vk::ImageView>, vk::Extent2D), Framebuffer>
+}
+
+
+impl RenderGraphCache {
+    /// Drops everything, for when the render passes or imported images of the framebuffers are replaced.
+    pub fn clear(&mut self) {
+        self.images.clear();
+        self.framebuffers.clear();
+    }
+}
+
+
+fn subresource(aspect: vk::ImageAspectFlags) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange::builder()
+        .aspect_mask(aspect)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1)
+        .build()
+}
+
+
+/// Finds an image for every description in `transients`, creating the ones that are missing and dropping those no longer needed,
+/// together with their framebuffers.
+unsafe fn claim_transients(instance: &Instance, device: &Device, data: &mut AppData, transients: &[TransientDesc]) -> Result<Vec<(vk::Image, vk::ImageView)>> {
+
+    let mut cached = std::mem::take(&mut data.render_graph.images);
+    let mut claimed = Vec::with_capacity(transients.len());
+
+    for desc in transients {
+        match cached.iter().position(|(d, _, _)| d == desc) {
+            Some(i) => claimed.push(cached.swap_remove(i)),
+            None => {
+                let image = create_image(instance, device, data, desc.extent.width, desc.extent.height, desc.usage, desc.format, 1, 1, desc.samples, vk::ImageCreateFlags::empty())?;
+                let view = create_image_view(&image, device, data, desc.format, subresource(desc.aspect), vk::ImageViewType::_2D)?;
+
+                debug!("Created a transient {:?} image of {}x{}", desc.format, desc.extent.width, desc.extent.height);
+
+                claimed.push((*desc, image, view));
+            }
+        }
+    }
+
+    let unused = cached.iter().map(|(_, _, view)| **view).collect::<Vec<_>>();
+    data.render_graph.framebuffers.retain(|(_, views, _), _| !views.iter().any(|v| unused.contains(v)));
+
+    let handles = claimed.iter().map(|(_, image, view)| (**image, **view)).collect();
+    data.render_graph.images = claimed;
+
+    return Ok(handles);
+}
+
+
+unsafe fn framebuffer(device: &Device, data: &mut AppData, render_pass: vk::RenderPass, attachments: Vec<vk::ImageView>, extent: vk::Extent2D) -> Result<vk::Framebuffer> {
+
+    let key = (render_pass, attachments, extent);
+
+    if let Some(framebuffer) = data.render_graph.framebuffers.get(&key) {
+        return Ok(**framebuffer);
+    }
+
+    let framebuffer_info = vk::FramebufferCreateInfo::builder()
+        .render_pass(render_pass)
+        .attachments(&key.1)
+        .width(extent.width)
+        .height(extent.height)
+        .layers(1);
+
+    let framebuffer = Framebuffer::new(device.create_framebuffer(&framebuffer_info, None)?, &data.deletion_queue);
+    let handle = *framebuffer;
+
+    data.render_graph.framebuffers.insert(key, framebuffer);
+
+    return Ok(handle);
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn imported(initial: Option<Access>, last: Option<Access>) -> ImportedImage {
+        ImportedImage { image: vk::Image::null(), view: vk::ImageView::null(), subresource: subresource(vk::ImageAspectFlags::COLOR), initial, last }
+    }
+
+    fn transient(width: u32) -> TransientDesc {
+        TransientDesc {
+            format: vk::Format::R8G8B8A8_SRGB,
+            extent: vk::Extent2D { width, height: width },
+            samples: vk::SampleCountFlags::_1,
+            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            aspect: vk::ImageAspectFlags::COLOR
+        }
+    }
+
+    fn names(graph: &RenderGraph, schedule: &Schedule) -> Vec<&'static str> {
+        schedule.order.iter().map(|p| graph.passes[*p].name).collect()
+    }
+
+
+    #[test]
+    fn readers_run_after_the_writers() {
+        let mut graph = RenderGraph::new();
+        let shadow = graph.import_image("shadow", imported(Some(Access::DepthSampled), Some(Access::DepthSampled)));
+        let target = graph.import_image("target", imported(None, Some(Access::Present)));
+        let buffer = graph.import_buffer("instances", vk::Buffer::null());
+
+        graph.add_pass("main").image(shadow, Access::DepthSampled).image(target, Access::ColorAttachment).buffer(buffer, Access::VertexBuffer);
+        graph.add_pass("shadow").image(shadow, Access::DepthAttachment).buffer(buffer, Access::VertexBuffer);
+        graph.add_pass("cull").buffer(buffer, Access::StorageWrite);
+
+        let schedule = graph.compile().unwrap();
+
+        assert_eq!(names(&graph, &schedule), vec!["cull", "shadow", "main"]);
+    }
+
+    #[test]
+    fn passes_depending_on_each_other_are_an_error() {
+        let mut graph = RenderGraph::new();
+        let a = graph.create_image("a", transient(4));
+        let b = graph.create_image("b", transient(4));
+
+        graph.add_pass("first").image(a, Access::ColorAttachment).image(b, Access::Sampled);
+        graph.add_pass("second").image(b, Access::ColorAttachment).image(a, Access::Sampled);
+
+        assert!(graph.compile().is_err());
+    }
+
+    #[test]
+    fn layouts_are_transitioned_between_passes() {
+        let mut graph = RenderGraph::new();
+        let shadow = graph.import_image("shadow", imported(Some(Access::DepthSampled), Some(Access::DepthSampled)));
+        let target = graph.import_image("target", imported(None, Some(Access::Present)));
+
+        graph.add_pass("shadow").image(shadow, Access::DepthAttachment);
+        graph.add_pass("main").image(shadow, Access::DepthSampled).image(target, Access::ColorAttachment);
+
+        let schedule = graph.compile().unwrap();
+
+        // The previous frame sampled the shadow map, the write only has to wait for that.
+        let (_, before_shadow) = schedule.barriers[0].dependencies[0];
+        assert_eq!(before_shadow.src_stages, vk::PipelineStageFlags::FRAGMENT_SHADER);
+        assert_eq!(before_shadow.src_access, vk::AccessFlags::empty());
+        assert_eq!((before_shadow.old_layout, before_shadow.new_layout), (vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL));
+
+        let main = &schedule.barriers[1].dependencies;
+        assert_eq!(main.len(), 2);
+        assert_eq!(main[0].1.src_access, vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE);
+        assert_eq!(main[0].1.new_layout, vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL);
+        assert_eq!((main[1].1.old_layout, main[1].1.src_stages), (vk::ImageLayout::UNDEFINED, vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT));
+
+        let present = &schedule.final_barriers.dependencies;
+        assert_eq!(present.len(), 1);
+        assert_eq!(present[0].0, Resource::Image(target.0));
+        assert_eq!((present[0].1.old_layout, present[0].1.new_layout), (vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, vk::ImageLayout::PRESENT_SRC_KHR));
+    }
+
+    #[test]
+    fn reads_wait_for_a_write_once() {
+        let mut graph = RenderGraph::new();
+        let buffer = graph.import_buffer("commands", vk::Buffer::null());
+
+        graph.add_pass("counts").buffer(buffer, Access::StorageWrite);
+        graph.add_pass("shadow").buffer(buffer, Access::IndirectBuffer);
+        graph.add_pass("main").buffer(buffer, Access::IndirectBuffer);
+
+        let schedule = graph.compile().unwrap();
+
+        // The host wrote the buffer before the frame was submitted.
+        assert!(schedule.barriers[0].dependencies.is_empty());
+        assert!(schedule.barriers[1].src_stages.contains(vk::PipelineStageFlags::COMPUTE_SHADER));
+        assert_eq!(schedule.barriers[1].dst_stages, vk::PipelineStageFlags::DRAW_INDIRECT);
+        assert!(schedule.barriers[2].dependencies.is_empty());
+    }
+
+    #[test]
+    fn host_reads_wait_for_the_shader_writes() {
+        let mut graph = RenderGraph::new();
+        let buffer = graph.import_buffer("batch counts", vk::Buffer::null());
+
+        graph.add_pass("cull").buffer(buffer, Access::StorageWrite);
+        graph.add_pass("draw counts").buffer(buffer, Access::StorageRead);
+        graph.add_pass("readback").buffer(buffer, Access::HostRead);
+
+        let schedule = graph.compile().unwrap();
+        let (_, dependency) = schedule.barriers[2].dependencies[0];
+
+        assert!(dependency.src_stages.contains(vk::PipelineStageFlags::COMPUTE_SHADER));
+        assert_eq!((dependency.dst_stages, dependency.dst_access), (vk::PipelineStageFlags::HOST, vk::AccessFlags::HOST_READ));
+    }
+
+    #[test]
+    fn persistent_buffers_wait_for_the_previous_frame() {
+        let mut graph = RenderGraph::new();
+        let buffer = graph.import_persistent_buffer("exposure", vk::Buffer::null(), Access::StorageWrite);
+
+        graph.add_pass("tone mapping").buffer(buffer, Access::StorageRead);
+
+        let schedule = graph.compile().unwrap();
+        let (_, dependency) = schedule.barriers[0].dependencies[0];
+
+        assert_eq!((dependency.src_access, dependency.dst_access), (vk::AccessFlags::SHADER_WRITE, vk::AccessFlags::SHADER_READ));
+    }
+
+    #[test]
+    fn transients_that_are_never_used_together_share_an_image() {
+        let mut graph = RenderGraph::new();
+        let a = graph.create_image("a", transient(4));
+        let b = graph.create_image("b", transient(4));
+        let c = graph.create_image("c", transient(4));
+        let other = graph.create_image("other", transient(8));
+        let unused = graph.create_image("unused", transient(4));
+
+        graph.add_pass("write a").image(a, Access::ColorAttachment);
+        graph.add_pass("a to b").image(a, Access::Sampled).image(b, Access::ColorAttachment).image(other, Access::ColorAttachment);
+        graph.add_pass("b to c").image(b, Access::Sampled).image(c, Access::ColorAttachment);
+
+        let schedule = graph.compile().unwrap();
+
+        assert_eq!(schedule.slots[a.0], schedule.slots[c.0]);
+        assert_ne!(schedule.slots[a.0], schedule.slots[b.0]);
+        assert_eq!(schedule.slots[unused.0], None);
+        assert_eq!(schedule.transients.len(), 3);
+
+        // c discards what a left, but only once a is no longer read.
+        let (_, before_c) = schedule.barriers[2].dependencies.iter().find(|(r, _)| *r == Resource::Image(c.0)).unwrap();
+        assert_eq!(before_c.old_layout, vk::ImageLayout::UNDEFINED);
+        assert!(before_c.src_stages.contains(vk::PipelineStageFlags::FRAGMENT_SHADER));
+    }
+
+    #[test]
+    fn transients_wait_for_the_previous_frames() {
+        let mut graph = RenderGraph::new();
+        let hdr = graph.create_image("hdr", transient(4));
+
+        graph.add_pass("main").image(hdr, Access::ColorAttachment);
+        graph.add_pass("histogram").image(hdr, Access::Sampled);
+
+        let schedule = graph.compile().unwrap();
+
+        // The previous frame may still be reading the same image in a compute pass.
+        let (_, before_main) = schedule.barriers[0].dependencies[0];
+        assert_eq!(before_main.src_stages, vk::PipelineStageFlags::ALL_COMMANDS);
+        assert_eq!(before_main.old_layout, vk::ImageLayout::UNDEFINED);
+    }
+}
@@ -2,18 +2,20 @@ use vulkanalia::prelude::v1_0::*;
 use log::*;
 use anyhow::Result;
 
-use crate::{app::AppData, images::get_depth_format};
+use crate::{app::AppData, images::get_depth_format, resources::RenderPass, tone_mapping::HDR_FORMAT};
 
-pub unsafe fn create_render_pass(instance: &Instance, device: &Device, data: &mut AppData) -> Result<vk::RenderPass> {
+/// The attachments stay in the layouts the subpass uses them in, the render graph transitions them before the render pass
+/// and synchronizes it with the passes around it.
+pub unsafe fn create_render_pass(instance: &Instance, device: &Device, data: &mut AppData) -> Result<RenderPass> {
 
     let color_attachment = vk::AttachmentDescription::builder()
-        .format(data.swapchain_image_format)
+        .format(HDR_FORMAT)
         .samples(data.msaa_samples)
         .load_op(vk::AttachmentLoadOp::CLEAR)
         .store_op(vk::AttachmentStoreOp::STORE)
         .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
         .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
         .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
 
 
@@ -26,24 +28,26 @@ pub unsafe fn create_render_pass(instance: &Instance, device: &Device, data: &mu
 
 
     let color_resolve_attachment = vk::AttachmentDescription::builder()
-        .format(data.swapchain_image_format)
+        .format(HDR_FORMAT)
         .samples(vk::SampleCountFlags::_1)
         .load_op(vk::AttachmentLoadOp::DONT_CARE)
         .store_op(vk::AttachmentStoreOp::STORE)
         .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
         .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-        .initial_layout(vk::ImageLayout::UNDEFINED)
-        .final_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+        .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
 
+    data.depth_format = get_depth_format(instance, data)?;
 
     let depth_attachment = vk::AttachmentDescription::builder()
-        .format(get_depth_format(instance, data)?)
+        .format(data.depth_format)
         .samples(data.msaa_samples)
         .load_op(vk::AttachmentLoadOp::CLEAR)
         .store_op(vk::AttachmentStoreOp::DONT_CARE)
         .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
         .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .initial_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
         .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
 
 
@@ -71,17 +75,6 @@ pub unsafe fn create_render_pass(instance: &Instance, device: &Device, data: &mu
         .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
 
 
-    let dependency = vk::SubpassDependency::builder()
-        .src_subpass(vk::SUBPASS_EXTERNAL)
-        .dst_subpass(0)
-        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
-        .src_access_mask(vk::AccessFlags::empty())
-        .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
-        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE);
-
-
-
-
     let subpasses = &[subpass];
 
     let attachments = &[
@@ -90,18 +83,15 @@ pub unsafe fn create_render_pass(instance: &Instance, device: &Device, data: &mu
         color_resolve_attachment
         ];
 
-    let dependencies = &[dependency];
-
     
     let info = vk::RenderPassCreateInfo::builder()
         .attachments(attachments)
-        .subpasses(subpasses)
-        .dependencies(dependencies);
+        .subpasses(subpasses);
 
 
     let render_pass = device.create_render_pass(&info, None)?;
 
     info!("Created render pass: {:?}", render_pass);
 
-    return Ok(render_pass);
+    return Ok(RenderPass::new(render_pass, &data.deletion_queue));
 }
\ No newline at end of file
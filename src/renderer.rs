@@ -0,0 +1,1048 @@
+use vulkanalia::{loader::{LibloadingLoader, LIBRARY}, vk::{ExtDebugUtilsExtension, KhrSurfaceExtension, KhrSwapchainExtension}};
+use winit::window::{Window};
+use anyhow::{Result, anyhow};
+use vulkanalia::prelude::v1_0::*;
+use crate::{instance::create_instance, device::{pick_physical_device, create_logical_device, QueueFamilyIndices}, swapchain::{create_swapchain, create_swapchain_image_views}, pipeline::create_pipeline, buffers::{create_command_pools, create_command_buffers}, sync::{create_semaphore, create_fence}, vertex::{upload_geometry, load_model, Mesh, Vertex}, ubo::{ create_uniform_buffers, MVP_UBO}, images::{create_texture, load_png_rgba}};
+use log::*;
+use vulkanalia::window as vkWindow;
+use nalgebra_glm as glm;
+use std::collections::HashMap;
+use std::ptr::copy_nonoverlapping as memcpy;
+use crate::app::{AppData, MAX_FRAMES_IN_FLIGHT, create_render_resources};
+use crate::camera::Camera;
+use crate::descriptors::{create_descriptor_pool, create_descriptor_sets};
+use crate::headless::{create_offscreen_target, read_offscreen_image, write_png};
+use crate::config::RendererConfig;
+use crate::allocator::{Allocator, AllocatorStats};
+use crate::resources::Image;
+use crate::material::{AlphaMode, Material, MaterialTextures, DrawRange, TEXTURES_PER_MATERIAL, create_material};
+use crate::scene::{NodeId, Scene, Transform};
+use crate::gltf_loader::{GltfScene, load_gltf};
+use crate::lights::{Light, MAX_LIGHTS, create_light_buffers, update_light_buffer};
+use crate::shadows::{ShadowLayout, shadow_layout, add_shadow_passes};
+use crate::instancing::{Batch, back_to_front_batches, batch_draw_items, update_instance_buffer};
+use crate::indirect::{DrawPass, main_draws, shadow_draws, update_indirect_buffer, cmd_draw_main};
+use crate::bounds::{Bounds, Frustum};
+use crate::culling::{cull_instances, command_batches, culled_instances, update_cull_buffers, add_cull_passes};
+use crate::render_graph::{Access, ImportedImage, RenderGraph, TransientDesc};
+use crate::tone_mapping::{ToneMapping, HDR_FORMAT, add_tone_mapping_passes, create_tone_mapping_pipeline};
+use crate::post_processing::{PostChain, add_post_passes, create_post_pipelines, load_lut};
+use crate::environment::{cmd_draw_skybox, load_environment};
+use std::time::Instant;
+use std::path::{Path, PathBuf};
+
+
+
+/// A mesh uploaded with `Renderer::load_mesh` or `Renderer::create_mesh`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MeshHandle(pub(crate) usize);
+
+/// A texture uploaded with `Renderer::load_texture` or `Renderer::create_texture`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextureHandle(pub(crate) usize);
+
+/// How the pixels of a texture are encoded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    /// Colors, like base color and emissive textures.
+    Srgb,
+    /// Data that is used as is, like normal, metallic-roughness and occlusion textures.
+    Linear
+}
+
+/// A material uploaded with `Renderer::create_material`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MaterialHandle(pub(crate) usize);
+
+
+/// A mesh to draw in the next frame, see `Renderer::submit`.
+#[derive(Copy, Clone, Debug)]
+pub struct DrawItem {
+    pub mesh: MeshHandle,
+    pub transform: glm::Mat4,
+    /// Replaces the materials of every draw range of the mesh when set.
+    pub material: Option<MaterialHandle>,
+    /// Multiplies the base color, white keeps it.
+    pub tint: glm::Vec3,
+    /// 1 is fully opaque.
+    pub opacity: f32
+}
+
+
+impl DrawItem {
+    pub fn new(mesh: MeshHandle, transform: glm::Mat4) -> Self {
+        Self { mesh, transform, material: None, tint: glm::vec3(1.0, 1.0, 1.0), opacity: 1.0 }
+    }
+}
+
+
+/// Counts of the last frame that was drawn, see `Renderer::stats`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Draw items that were submitted.
+    pub draw_items: usize,
+    /// Submitted draw items outside of the camera's frustum, they weren't drawn but still cast shadows.
+    /// They are counted on the GPU, so with a window this is the count of the last frame that was drawn into the same swapchain image,
+    /// two or three frames before the others (and 0 until every image was drawn once). `render_offscreen` reads back the count of its own frame.
+    pub culled: usize,
+    /// Groups of visible draw items with the same mesh and material, each is drawn instanced.
+    pub batches: usize,
+    /// Indirect draws of the main pass, one per material (or one per draw range of a batch without multi-draw indirect support).
+    pub draw_calls: usize
+}
+
+
+/// Draws meshes into a window, or into an offscreen image when created with `new_headless`.
+///
+/// Meshes, textures and materials can be uploaded at any time and live as long as the renderer.
+/// Every frame, `submit` the meshes to draw and call `present` (or `render_offscreen`).
+#[derive(Debug)]
+pub struct Renderer {
+    /// Keeps the Vulkan library loaded, the functions of `instance` and `device` point into it.
+    _entry: Entry,
+    instance: Instance,
+    data: AppData,
+    device: Device,
+    frame: usize,
+    camera: Camera,
+    lights: Vec<Light>,
+    ambient: glm::Vec3,
+    environment_intensity: f32,
+    /// Shadow map layers of the frame being recorded.
+    shadows: ShadowLayout,
+    /// Of the camera in the frame being recorded, draw items outside of it are culled.
+    frustum: Frustum,
+    draw_items: Vec<DrawItem>,
+    stats: FrameStats,
+    tone_mapping: ToneMapping,
+    post_chain: PostChain,
+    /// When the previous frame was recorded, the automatic exposure adapts by the time since.
+    last_frame: Option<Instant>,
+    loaded_textures: HashMap<(PathBuf, ColorSpace), TextureHandle>,
+    /// 1x1 textures standing in for missing material textures.
+    fallback_textures: HashMap<([u8; 4], ColorSpace), TextureHandle>
+}
+
+
+impl Renderer {
+    /// Creates a renderer that draws into `window`.
+    ///
+    /// # Safety
+    /// `window` has to outlive the renderer, and `destroy` has to be called before either of them is dropped.
+    pub unsafe fn new(window: &Window, config: RendererConfig) -> Result<Self> {
+        let loader = LibloadingLoader::new(LIBRARY)?;
+        let entry = Entry::new(loader).map_err(|e| anyhow!(e))?;
+        let mut data = AppData { config, ..Default::default() };
+        let instance = create_instance(Some(window), &entry, &mut data)?;
+        data.surface = vkWindow::create_surface(&instance, window)?;
+        pick_physical_device(&instance, &mut data)?;
+        data.allocator = Allocator::new(&instance, data.physical_device);
+        let device = create_logical_device(&instance, &mut data)?;
+
+
+        data.queue_family_indicies = QueueFamilyIndices::get(&instance, &mut data, None)?;
+
+
+        create_swapchain(&instance, &mut data, &device, window)?;
+        create_swapchain_image_views(&mut data, &device)?;
+
+
+        create_render_resources(&instance, &device, &mut data)?;
+
+        for _ in 0..data.swapchain_images.len() {
+            data.render_finished_semaphores.push(create_semaphore(&device)?);
+            data.image_available_semaphores.push(create_semaphore(&device)?);
+            data.in_flight_fences.push(create_fence(&device, true)?);
+        }
+        
+        data.images_in_flight = data.swapchain_images.iter().map(|_| vk::Fence::null()).collect();
+
+
+
+        return Ok(Self::from_parts(entry, instance, data, device));
+    }
+
+    /// Creates a renderer that draws into an offscreen image of `config.width` by `config.height` instead of a window, see `render_to_png`.
+    ///
+    /// # Safety
+    /// `destroy` has to be called before the renderer is dropped.
+    pub unsafe fn new_headless(config: RendererConfig) -> Result<Self> {
+        let loader = LibloadingLoader::new(LIBRARY)?;
+        let entry = Entry::new(loader).map_err(|e| anyhow!(e))?;
+        let (width, height) = (config.width, config.height);
+        let mut data = AppData { config, headless: true, ..Default::default() };
+        let instance = create_instance(None, &entry, &mut data)?;
+        pick_physical_device(&instance, &mut data)?;
+        data.allocator = Allocator::new(&instance, data.physical_device);
+        let device = create_logical_device(&instance, &mut data)?;
+
+
+        data.queue_family_indicies = QueueFamilyIndices::get(&instance, &mut data, None)?;
+
+
+        create_offscreen_target(&instance, &device, &mut data, width, height)?;
+
+        create_render_resources(&instance, &device, &mut data)?;
+
+
+        data.in_flight_fences.push(create_fence(&device, true)?);
+        data.images_in_flight = vec![vk::Fence::null()];
+
+        return Ok(Self::from_parts(entry, instance, data, device));
+    }
+
+    fn from_parts(entry: Entry, instance: Instance, data: AppData, device: Device) -> Self {
+        Self {
+            _entry: entry,
+            instance,
+            data,
+            device,
+            frame: 0,
+            camera: Camera::default(),
+            lights: vec![Light::default()],
+            ambient: glm::vec3(0.02, 0.02, 0.02),
+            environment_intensity: 1.0,
+            shadows: ShadowLayout::default(),
+            frustum: Frustum::default(),
+            draw_items: vec![],
+            stats: FrameStats::default(),
+            tone_mapping: ToneMapping::default(),
+            post_chain: PostChain::default(),
+            last_frame: None,
+            loaded_textures: HashMap::new(),
+            fallback_textures: HashMap::new()
+        }
+    }
+
+
+    /// Loads a PNG texture, loading the same file twice in the same color space returns the same texture.
+    ///
+    /// # Safety
+    /// The renderer must not have been destroyed.
+    pub unsafe fn load_texture(&mut self, path: &Path, color_space: ColorSpace) -> Result<TextureHandle> {
+        if let Some(texture) = self.loaded_textures.get(&(path.to_path_buf(), color_space)) {
+            return Ok(*texture);
+        }
+
+        let (pixels, width, height) = load_png_rgba(path)
+            .map_err(|e| anyhow!("Couldn't load texture {}: {}", path.display(), e))?;
+
+        let texture = self.create_texture(&pixels, width, height, color_space)?;
+        self.loaded_textures.insert((path.to_path_buf(), color_space), texture);
+
+        return Ok(texture);
+    }
+
+    /// Uploads tightly packed RGBA8 pixels as a texture with mipmaps.
+    ///
+    /// # Safety
+    /// The renderer must not have been destroyed.
+    pub unsafe fn create_texture(&mut self, pixels: &[u8], width: u32, height: u32, color_space: ColorSpace) -> Result<TextureHandle> {
+        if pixels.len() != (width * height * 4) as usize {
+            return Err(anyhow!("Expected {} bytes of RGBA8 pixels for a {}x{} texture, got {}.", width * height * 4, width, height, pixels.len()));
+        }
+
+        let format = match color_space {
+            ColorSpace::Srgb => vk::Format::R8G8B8A8_SRGB,
+            ColorSpace::Linear => vk::Format::R8G8B8A8_UNORM
+        };
+
+        let texture = create_texture(&self.instance, &self.device, &mut self.data, pixels, width, height, format)?;
+        self.data.textures.push(texture);
+
+        return Ok(TextureHandle(self.data.textures.len() - 1));
+    }
+
+    /// Creates a material drawn with `textures`, the textures that are `None` are loaded from the paths in `material`.
+    /// Textures that are missing (or can't be loaded) are replaced by a plain one that leaves the material's factor as is.
+    ///
+    /// # Safety
+    /// The renderer must not have been destroyed. The texture handles have to come from this renderer.
+    pub unsafe fn create_material(&mut self, material: Material, textures: MaterialTextures) -> Result<MaterialHandle> {
+        let paths = material.texture_paths();
+        let mut indices = [0; TEXTURES_PER_MATERIAL];
+
+        for (slot, texture) in textures.into_array().into_iter().enumerate() {
+            let color_space = MaterialTextures::COLOR_SPACES[slot];
+
+            let texture = match (texture, paths[slot]) {
+                (Some(texture), _) => texture,
+                (None, Some(path)) => match self.load_texture(path, color_space) {
+                    Ok(texture) => texture,
+                    Err(e) => {
+                        warn!("Using a plain texture instead for material {}: {}", material.name, e);
+                        self.fallback_texture(slot)?
+                    }
+                },
+                (None, None) => self.fallback_texture(slot)?
+            };
+
+            indices[slot] = texture.0;
+        }
+
+        let material = create_material(&self.instance, &self.device, &mut self.data, material, indices)?;
+
+        return Ok(MaterialHandle(material));
+    }
+
+    unsafe fn fallback_texture(&mut self, slot: usize) -> Result<TextureHandle> {
+        let key = (MaterialTextures::FALLBACK_PIXELS[slot], MaterialTextures::COLOR_SPACES[slot]);
+
+        if let Some(texture) = self.fallback_textures.get(&key) {
+            return Ok(*texture);
+        }
+
+        let texture = self.create_texture(&key.0, 1, 1, key.1)?;
+        self.fallback_textures.insert(key, texture);
+
+        return Ok(texture);
+    }
+
+    /// Loads an OBJ or glTF model together with its materials and textures, a glTF scene is flattened into a single mesh.
+    /// Faces the file doesn't give a material are drawn with `default_material`, or plain white when that's `None`.
+    /// Use `load_gltf` to keep the node hierarchy of a glTF scene.
+    ///
+    /// # Safety
+    /// The renderer must not have been destroyed. `default_material` has to come from this renderer.
+    pub unsafe fn load_mesh(&mut self, path: &Path, default_material: Option<MaterialHandle>) -> Result<MeshHandle> {
+        let model = load_model(path)?;
+
+        // The glTF materials come first in `model.materials`, followed by the default one.
+        let mut materials: Vec<Option<MaterialHandle>> = match &model.gltf_scene {
+            Some(gltf) => self.create_gltf_materials(gltf)?.into_iter().map(Some).collect(),
+            None => vec![]
+        };
+
+        materials.resize(model.materials.len(), None);
+
+        for range in &model.draw_ranges {
+            if materials[range.material].is_some() {
+                continue;
+            }
+
+            materials[range.material] = Some(match default_material {
+                Some(material) if model.default_material == Some(range.material) => material,
+                _ => self.create_material(model.materials[range.material].clone(), MaterialTextures::default())?
+            });
+        }
+
+        let draw_ranges = model.draw_ranges.iter()
+            .map(|r| DrawRange { material: materials[r.material].unwrap().0, ..*r })
+            .collect();
+
+        return self.upload_mesh(&model.vertices, &model.indices, draw_ranges, model.bounds);
+    }
+
+    /// Loads a glTF file into `scene` under `parent`, keeping its node hierarchy, and returns the node that holds it.
+    /// Every glTF mesh is uploaded once, with a draw range per primitive, no matter how many nodes use it.
+    ///
+    /// # Safety
+    /// The renderer must not have been destroyed.
+    pub unsafe fn load_gltf(&mut self, path: &Path, scene: &mut Scene, parent: Option<NodeId>) -> Result<NodeId> {
+        let gltf = load_gltf(path)?;
+
+        let mut materials = self.create_gltf_materials(&gltf)?;
+
+        // Primitives without a material use the glTF default material, which is plain white.
+        let default_material = materials.len();
+        materials.push(self.create_material(Material::default(), MaterialTextures::default())?);
+
+        let mut meshes = vec![];
+
+        for mesh in &gltf.meshes {
+            let mut vertices = vec![];
+            let mut indices = vec![];
+            let mut draw_ranges = vec![];
+
+            for primitive in &mesh.primitives {
+                let offset = vertices.len() as u32;
+
+                draw_ranges.push(DrawRange {
+                    material: materials[primitive.material.unwrap_or(default_material)].0,
+                    first_index: indices.len() as u32,
+                    index_count: primitive.indices.len() as u32
+                });
+
+                vertices.extend_from_slice(&primitive.vertices);
+                indices.extend(primitive.indices.iter().map(|i| i + offset));
+            }
+
+            meshes.push(match draw_ranges.is_empty() {
+                true => None,
+                false => Some(self.upload_mesh(&vertices, &indices, draw_ranges, Bounds::from_vertices(&vertices))?)
+            });
+        }
+
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let root = scene.add_node(&name, parent);
+
+        // glTF is Y-up while the camera expects Z-up.
+        scene.set_transform(root, Transform::from_rotation(glm::radians(&glm::vec1(90.0))[0], &glm::vec3(1.0, 0.0, 0.0)));
+
+        let mut stack = gltf.roots.iter().map(|r| (*r, root)).collect::<Vec<_>>();
+
+        while let Some((index, parent)) = stack.pop() {
+            let gltf_node = &gltf.nodes[index];
+            let node = scene.add_node(gltf_node.name.as_deref().unwrap_or(""), Some(parent));
+
+            scene.set_transform(node, Transform::from_matrix(&gltf_node.transform));
+            scene.node_mut(node).mesh = gltf_node.mesh.and_then(|m| meshes[m]);
+
+            stack.extend(gltf_node.children.iter().rev().map(|c| (*c, node)));
+        }
+
+        return Ok(root);
+    }
+
+    /// Creates the materials of a glTF scene with the textures embedded in it, in the order of `gltf.materials`.
+    unsafe fn create_gltf_materials(&mut self, gltf: &GltfScene) -> Result<Vec<MaterialHandle>> {
+        // A glTF texture is uploaded once per color space it is used in.
+        let mut textures: HashMap<(usize, ColorSpace), TextureHandle> = HashMap::new();
+        let mut materials = vec![];
+
+        for gltf_material in &gltf.materials {
+            let mut handles = [None; TEXTURES_PER_MATERIAL];
+
+            for (slot, index) in gltf_material.texture_indices().into_iter().enumerate() {
+                let Some(index) = index else { continue };
+                let color_space = MaterialTextures::COLOR_SPACES[slot];
+
+                let handle = match textures.get(&(index, color_space)) {
+                    Some(handle) => *handle,
+                    None => {
+                        let texture = &gltf.textures[index];
+                        let handle = self.create_texture(&texture.pixels, texture.width, texture.height, color_space)?;
+                        textures.insert((index, color_space), handle);
+                        handle
+                    }
+                };
+
+                handles[slot] = Some(handle);
+            }
+
+            materials.push(self.create_material(Material::from_gltf(gltf_material), MaterialTextures::from_array(handles))?);
+        }
+
+        return Ok(materials);
+    }
+
+    /// Uploads a mesh that is drawn with a single material.
+    ///
+    /// # Safety
+    /// The renderer must not have been destroyed. `material` has to come from this renderer.
+    pub unsafe fn create_mesh(&mut self, vertices: &[Vertex], indices: &[u32], material: MaterialHandle) -> Result<MeshHandle> {
+        let draw_ranges = vec![DrawRange { material: material.0, first_index: 0, index_count: indices.len() as u32 }];
+
+        return self.upload_mesh(vertices, indices, draw_ranges, Bounds::from_vertices(vertices));
+    }
+
+    unsafe fn upload_mesh(&mut self, vertices: &[Vertex], indices: &[u32], draw_ranges: Vec<DrawRange>, bounds: Bounds) -> Result<MeshHandle> {
+        if vertices.is_empty() || indices.is_empty() {
+            return Err(anyhow!("Can't create a mesh without vertices or indices."));
+        }
+
+        let (vertex_offset, first_index) = upload_geometry(&self.instance, &self.device, &mut self.data, vertices, indices)?;
+
+        self.data.meshes.push(Mesh { vertex_offset, first_index, draw_ranges, bounds });
+
+        return Ok(MeshHandle(self.data.meshes.len() - 1));
+    }
+
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.camera = camera;
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    /// Replaces the lights that shade every mesh, only the first `MAX_LIGHTS` are used.
+    pub fn set_lights(&mut self, lights: Vec<Light>) {
+        if lights.len() > MAX_LIGHTS {
+            warn!("{} lights, only the first {} are used", lights.len(), MAX_LIGHTS);
+        }
+
+        self.lights = lights;
+    }
+
+    /// Light added to every surface regardless of the lights, so unlit sides aren't black.
+    pub fn ambient(&self) -> glm::Vec3 {
+        self.ambient
+    }
+
+    pub fn set_ambient(&mut self, ambient: glm::Vec3) {
+        self.ambient = ambient;
+    }
+
+    /// Loads an equirectangular Radiance .hdr image as the environment, shown behind the meshes and lighting them
+    /// by its diffuse irradiance and prefiltered reflections. Replaces the previous environment, the ambient light still adds to it.
+    ///
+    /// # Safety
+    /// The renderer must not have been destroyed.
+    pub unsafe fn load_environment<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        load_environment(&self.instance, &self.device, &mut self.data, path.as_ref())
+    }
+
+    /// Scales the skybox and the light of the environment, 1 by default.
+    pub fn environment_intensity(&self) -> f32 {
+        self.environment_intensity
+    }
+
+    pub fn set_environment_intensity(&mut self, intensity: f32) {
+        self.environment_intensity = intensity;
+    }
+
+    /// How the HDR colors of the scene are mapped to the window.
+    pub fn tone_mapping(&self) -> ToneMapping {
+        self.tone_mapping
+    }
+
+    pub fn set_tone_mapping(&mut self, tone_mapping: ToneMapping) {
+        self.tone_mapping = tone_mapping;
+    }
+
+    /// The effects applied to the tone mapped image.
+    pub fn post_chain(&self) -> &PostChain {
+        &self.post_chain
+    }
+
+    /// Replaces the post-processing chain, loading the LUTs of its color grading stages the renderer doesn't have yet.
+    /// A LUT is loaded once per path, change the path to load an edited file.
+    ///
+    /// # Safety
+    /// The renderer must not have been destroyed.
+    pub unsafe fn set_post_chain(&mut self, chain: PostChain) -> Result<()> {
+        for lut in chain.luts() {
+            if !self.data.luts.contains_key(lut) {
+                load_lut(&self.instance, &self.device, &mut self.data, lut)?;
+            }
+        }
+
+        self.post_chain = chain;
+
+        return Ok(());
+    }
+
+    /// What was drawn in the last frame, see `FrameStats::culled` for when the culled count is from.
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
+
+    /// GPU memory the renderer allocated so far, for meshes, textures, render targets and everything else.
+    pub fn memory_stats(&self) -> AllocatorStats {
+        self.data.allocator.stats()
+    }
+
+    /// Size of the swapchain (or offscreen) images.
+    pub fn extent(&self) -> (u32, u32) {
+        (self.data.swapchain_extent.width, self.data.swapchain_extent.height)
+    }
+
+    /// Queues a mesh for the next `present` or `render_offscreen`, which clear the queue.
+    /// Items with the same mesh and material are drawn together, instanced, so submitting thousands of copies is cheap.
+    pub fn submit(&mut self, item: DrawItem) {
+        self.draw_items.push(item);
+    }
+
+    /// Updates the world transforms of the scene and queues every visible node with a mesh, like `submit`.
+    pub fn submit_scene(&mut self, scene: &mut Scene) {
+        scene.update_world_transforms();
+
+        scene.visit_visible(|_, node| self.draw_items.push(DrawItem {
+            mesh: node.mesh.unwrap(),
+            transform: *node.world_transform(),
+            material: node.material,
+            tint: glm::vec3(1.0, 1.0, 1.0),
+            opacity: node.opacity
+        }));
+    }
+
+
+    /// Draws everything that was submitted since the last frame and presents it to the window.
+    ///
+    /// # Safety
+    /// The renderer must not have been destroyed. `window` has to be the window it was created with, and the submitted draw items can only use handles from this renderer.
+    pub unsafe fn present(&mut self, window: &Window) -> Result<()> {
+
+
+
+        let in_flight_fence = self.data.in_flight_fences[self.frame];
+
+
+
+        self.device
+            .wait_for_fences(&[in_flight_fence], true, u64::MAX)?;
+
+        self.data.deletion_queue.next_frame(&self.device, &mut self.data.allocator);
+
+
+
+        let image_index = self
+            .device
+            .acquire_next_image_khr(
+                self.data.swapchain,
+                u64::MAX,
+                self.data.image_available_semaphores[self.frame],
+                vk::Fence::null(),
+            )?
+            .0 as usize;
+
+        
+
+        let image_in_flight = self.data.images_in_flight[image_index];
+        if !image_in_flight.is_null() {
+            self.device
+                .wait_for_fences(&[image_in_flight], true, u64::MAX)?;
+        }
+
+        self.data.images_in_flight[image_index] = in_flight_fence;
+
+
+        self.update_depth_mode()?;
+        self.update_uniform_buffers(image_index)?;
+        self.update_command_buffer(image_index)?;
+
+        let wait_semaphores = &[self.data.image_available_semaphores[self.frame]];
+        let wait_stages = &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        let command_buffers = &[self.data.command_buffers[image_index]];
+        let signal_semaphores = &[self.data.render_finished_semaphores[self.frame]];
+        let submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(wait_semaphores)
+            .wait_dst_stage_mask(wait_stages)
+            .command_buffers(command_buffers)
+            .signal_semaphores(signal_semaphores);
+
+        self.device.reset_fences(&[in_flight_fence])?;
+
+        self.device
+            .queue_submit(self.data.graphics_queue, &[submit_info], in_flight_fence)?;
+
+        let swapchains = &[self.data.swapchain];
+        let image_indices = &[image_index as u32];
+        let present_info = vk::PresentInfoKHR::builder()
+            .wait_semaphores(signal_semaphores)
+            .swapchains(swapchains)
+            .image_indices(image_indices);
+
+        let result = self.device.queue_present_khr(self.data.present_queue, &present_info);
+
+
+        let changed = result == Ok(vk::SuccessCode::SUBOPTIMAL_KHR)
+    || result == Err(vk::ErrorCode::OUT_OF_DATE_KHR);
+
+
+        if changed {
+            self.recreate_swapchain(window)?;
+        } else if let Err(e) = result {
+            return Err(anyhow!(e));
+        }
+
+
+
+        self.frame = (self.frame + 1) % MAX_FRAMES_IN_FLIGHT;
+
+        Ok(())
+    }
+
+
+    /// Draws everything that was submitted since the last frame into the offscreen image of a headless renderer and reads it back as RGBA8 pixels.
+    ///
+    /// # Safety
+    /// The renderer must not have been destroyed. The submitted draw items can only use handles from this renderer.
+    pub unsafe fn render_offscreen(&mut self) -> Result<Vec<u8>> {
+        if !self.data.headless {
+            return Err(anyhow!("Only headless renderers can render offscreen."));
+        }
+
+        let fence = self.data.in_flight_fences[0];
+
+        self.device.wait_for_fences(&[fence], true, u64::MAX)?;
+
+        // Every earlier submission has finished, there is only one frame in flight.
+        self.data.deletion_queue.flush(&self.device, &mut self.data.allocator);
+
+        self.update_depth_mode()?;
+        self.update_uniform_buffers(0)?;
+        self.update_command_buffer(0)?;
+
+        let command_buffers = &[self.data.command_buffers[0]];
+        let submit_info = vk::SubmitInfo::builder()
+            .command_buffers(command_buffers);
+
+        self.device.reset_fences(&[fence])?;
+
+        self.device
+            .queue_submit(self.data.graphics_queue, &[submit_info], fence)?;
+
+        self.device.wait_for_fences(&[fence], true, u64::MAX)?;
+
+        self.stats.culled = culled_instances(&self.data, 0);
+
+        return read_offscreen_image(&self.instance, &self.device, &mut self.data);
+    }
+
+    /// Renders a single frame like `render_offscreen` and writes it to `path`.
+    ///
+    /// # Safety
+    /// Same as `render_offscreen`.
+    pub unsafe fn render_to_png(&mut self, path: &Path) -> Result<()> {
+        let pixels = self.render_offscreen()?;
+
+        write_png(path, self.data.swapchain_extent.width, self.data.swapchain_extent.height, &pixels)?;
+
+        return Ok(());
+    }
+
+
+    /// Rebuilds the pipeline when the camera switched between a reverse-Z projection and a regular one.
+    /// The old pipeline and framebuffers stay alive in the deletion queue until the frames using them are done.
+    unsafe fn update_depth_mode(&mut self) -> Result<()> {
+        if self.camera.projection.reverse_z() == self.data.reverse_z {
+            return Ok(());
+        }
+
+        self.data.reverse_z = self.camera.projection.reverse_z();
+
+        create_pipeline(&self.instance, &mut self.data, &self.device)?;
+
+        // The framebuffers were created for the replaced render pass.
+        self.data.render_graph.clear();
+
+        info!("Switched to {} depth", if self.data.reverse_z { "reverse-Z" } else { "regular" });
+
+        return Ok(());
+    }
+
+
+    unsafe fn update_command_buffer(&mut self, image_index: usize) -> Result<()> {
+
+        let command_pool = *self.data.command_pools[image_index];
+        self.device.reset_command_pool(command_pool, vk::CommandPoolResetFlags::empty())?;
+
+        let command_buffer = self.data.command_buffers[image_index];
+            
+        let command_buffer_begin_info = vk::CommandBufferBeginInfo::builder();
+
+        self.device.begin_command_buffer(command_buffer, &command_buffer_begin_info)?;
+
+        // The main pass instances are culled on the GPU, which packs the visible ones over the submitted ones and writes their counts into the commands.
+        // Draw items outside of the view can still cast shadows into it, the shadow passes draw all of them but the blended ones.
+        let meshes = &self.data.meshes;
+        let materials = &self.data.materials;
+
+        let mut instances = vec![];
+
+        let shadow_batches = match self.shadows.matrices.is_empty() {
+            true => vec![],
+            false => batch_draw_items(self.draw_items.iter().filter(|item| item.opacity >= 1.0), &mut instances)
+        };
+
+        let shadow_pass = |material: usize| match materials[material].alpha_mode {
+            AlphaMode::Opaque => DrawPass::Opaque,
+            AlphaMode::Mask => DrawPass::Cutout,
+            AlphaMode::Blend => DrawPass::Transparent
+        };
+
+        // Blended draw items are drawn one at a time after the others, from the farthest to the nearest, so they aren't instanced.
+
+        let blended = |item: &DrawItem| item.opacity < 1.0 || meshes[item.mesh.0].draw_ranges.iter()
+            .any(|range| materials[item.material.map_or(range.material, |m| m.0)].alpha_mode == AlphaMode::Blend);
+
+        let (blended_items, other_items): (Vec<_>, Vec<_>) = self.draw_items.iter().partition(|item| blended(item));
+
+        let mut batches = batch_draw_items(other_items, &mut instances);
+        batches.extend(back_to_front_batches(blended_items, meshes, &self.camera.view(), &mut instances));
+
+        // An instance that isn't fully opaque is blended with whatever material it's drawn, the batches of those have that instance only.
+        let pass = |batch: &Batch, material: usize| match materials[material].alpha_mode {
+            AlphaMode::Blend => DrawPass::Transparent,
+            _ if instances[batch.first_instance as usize].tint.w < 1.0 => DrawPass::Transparent,
+            AlphaMode::Mask => DrawPass::Cutout,
+            AlphaMode::Opaque => DrawPass::Opaque
+        };
+
+        let mut commands = vec![];
+        let shadow_draws = shadow_draws(meshes, &shadow_batches, shadow_pass, &mut commands);
+        let first_command = commands.len();
+        let draws = main_draws(meshes, &batches, pass, &mut commands);
+
+        let cull_instances = cull_instances(&self.data.meshes, &batches, &instances);
+        let command_batches = command_batches(&batches, &commands[first_command..]);
+
+        // Read before the counts are reset for this frame.
+        let culled = culled_instances(&self.data, image_index);
+
+        update_instance_buffer(&self.instance, &self.device, &mut self.data, image_index, &instances)?;
+        update_indirect_buffer(&self.instance, &self.device, &mut self.data, image_index, &commands)?;
+        update_cull_buffers(&self.instance, &self.device, &mut self.data, image_index, &cull_instances, batches.len(), &command_batches)?;
+
+        self.stats = FrameStats {
+            draw_items: self.draw_items.len(),
+            culled,
+            batches: batches.len(),
+            draw_calls: draws.len()
+        };
+
+        let extent = self.data.swapchain_extent;
+
+        let mut graph = RenderGraph::new();
+
+        let target = graph.import_image("swapchain image", ImportedImage {
+            image: self.data.swapchain_images[image_index],
+            view: *self.data.swapchain_image_views[image_index],
+            subresource: vk::ImageSubresourceRange::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .base_mip_level(0)
+                .level_count(1)
+                .base_array_layer(0)
+                .layer_count(1)
+                .build(),
+            initial: None,
+            last: Some(if self.data.headless { Access::TransferSrc } else { Access::Present })
+        });
+
+        let color = graph.create_image("color", TransientDesc {
+            format: HDR_FORMAT,
+            extent,
+            samples: self.data.msaa_samples,
+            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+            aspect: vk::ImageAspectFlags::COLOR
+        });
+
+        let hdr = graph.create_image("hdr", TransientDesc {
+            format: HDR_FORMAT,
+            extent,
+            samples: vk::SampleCountFlags::_1,
+            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            aspect: vk::ImageAspectFlags::COLOR
+        });
+
+        let depth = graph.create_image("depth", TransientDesc {
+            format: self.data.depth_format,
+            extent,
+            samples: self.data.msaa_samples,
+            usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            aspect: vk::ImageAspectFlags::DEPTH
+        });
+
+        // The buffers only exist once something has been drawn.
+        let geometry = match commands.is_empty() {
+            true => None,
+            false => Some((
+                graph.import_buffer("instances", *self.data.instance_buffers[image_index].buffer),
+                graph.import_buffer("draw commands", *self.data.indirect_buffers[image_index].buffer)
+            ))
+        };
+
+        if let Some((instances, commands)) = geometry {
+            add_cull_passes(&mut graph, &self.data, image_index, &self.frustum, first_command, command_batches.len(), instances, commands);
+        }
+
+        let shadow_layers = add_shadow_passes(&mut graph, &self.data, image_index, &self.shadows, &shadow_draws, geometry);
+
+        let clear_value = vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: [0.0, 0.0, 0.0, 1.0]
+            }
+        };
+
+        let depth_clear_value = vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: if self.data.reverse_z { 0.0 } else { 1.0 },
+                stencil: 0
+            }
+        };
+
+        let clear_values = &[clear_value, depth_clear_value];
+
+        // The color attachment is resolved into the HDR image at the end of the render pass, which is then tone mapped into the swapchain image.
+        let attachments = &[(color, Access::ColorAttachment), (depth, Access::DepthAttachment), (hdr, Access::ColorAttachment)];
+
+        let main_pass = graph.add_pass("main").render_pass(*self.data.render_pass, extent, attachments, clear_values);
+
+        shadow_layers.into_iter().for_each(|layer| { main_pass.image(layer, Access::DepthSampled); });
+
+        if let Some((instances, commands)) = geometry {
+            main_pass.buffer(instances, Access::VertexBuffer).buffer(commands, Access::IndirectBuffer);
+        }
+
+        let draws = &draws;
+
+        // Every draw comes from the indirect buffer, so recording doesn't depend on the number of draw items, only on the number of materials.
+        // The skybox fills in what the opaque and cutout draws left uncovered, the blended draws go over it.
+        main_pass.record(move |device, data, _, command_buffer| {
+            cmd_draw_main(device, data, command_buffer, image_index, *data.pipeline, &draws.opaque);
+            cmd_draw_main(device, data, command_buffer, image_index, *data.cutout_pipeline, &draws.cutout);
+
+            cmd_draw_skybox(device, data, command_buffer, image_index);
+
+            cmd_draw_main(device, data, command_buffer, image_index, *data.transparent_pipeline, &draws.transparent);
+        });
+
+        let now = Instant::now();
+        let delta_time = self.last_frame.map_or(0.0, |last| (now - last).as_secs_f32());
+        self.last_frame = Some(now);
+
+        let tone_mapped = add_post_passes(&mut graph, &self.device, &self.data, image_index, &self.post_chain, target)?;
+
+        add_tone_mapping_passes(&mut graph, &self.data, image_index, &self.tone_mapping, delta_time, hdr, tone_mapped);
+
+        graph.execute(&self.instance, &self.device, &mut self.data, command_buffer)?;
+
+        self.device.end_command_buffer(command_buffer)?;
+
+        self.draw_items.clear();
+
+
+        return Ok(());
+    }
+
+
+    unsafe fn update_uniform_buffers(&mut self, image_index: usize) -> Result<()> {
+
+        let aspect_ratio = self.data.swapchain_extent.width as f32 / self.data.swapchain_extent.height as f32;
+
+        let view = self.camera.view();
+
+        let proj = self.camera.projection_matrix(aspect_ratio);
+
+        let ubo = MVP_UBO { view, proj, eye: self.camera.position.push(1.0) };
+
+        self.frustum = Frustum::from_matrix(&(proj * view));
+
+        // Copy
+
+        memcpy(&ubo, self.data.uniform_buffers[image_index].allocation.mapped.cast(), 1);
+
+        self.shadows = shadow_layout(&self.lights, &self.camera, aspect_ratio);
+
+        // Without an environment the shaders skip its lookups.
+        let environment_intensity = if self.data.environment.loaded { self.environment_intensity } else { 0.0 };
+
+        update_light_buffer(&self.data, image_index, &self.lights, self.ambient, environment_intensity, &self.shadows);
+
+
+        Ok(())
+
+    }
+
+    /// Drops the swapchain and everything that is created per swapchain image, the device has to be idle.
+    /// Resources that only depend on the swapchain extent are replaced, and with that dropped, by `recreate_swapchain`.
+    unsafe fn destroy_swapchain(&mut self) {
+
+        self.data.render_graph.clear();
+        self.data.swapchain_image_views.clear();
+
+        // Command buffers are freed together with their pools.
+        self.data.command_pools.clear();
+        self.data.command_buffers.clear();
+
+        if self.data.headless {
+            self.data.offscreen_image = Image::default();
+        }
+
+        self.data.deletion_queue.flush(&self.device, &mut self.data.allocator);
+        debug!("Destroyed framebuffers, image views & command pools");
+
+
+        if !self.data.headless {
+            self.device.destroy_swapchain_khr(self.data.swapchain, None);
+            debug!("Destroyed swapchain");
+        }
+
+        self.data.swapchain_images.clear();
+    }
+
+    unsafe fn recreate_swapchain(&mut self, window: &Window) -> Result<()> {
+        self.device.device_wait_idle()?;
+
+        self.destroy_swapchain();
+
+        create_swapchain(&self.instance, &mut self.data, &self.device, window)?;
+        create_swapchain_image_views(&mut self.data, &self.device)?;
+
+        create_command_pools(&self.device, &mut self.data)?;
+        create_command_buffers(&self.device, &mut self.data)?;
+
+        // There is a uniform and light buffer per swapchain image, they only have to change when the number of images does.
+        if self.data.uniform_buffers.len() != self.data.swapchain_images.len() {
+            create_uniform_buffers(&self.instance, &self.device, &mut self.data)?;
+            create_light_buffers(&self.instance, &self.device, &mut self.data)?;
+        }
+
+        create_pipeline(&self.instance, &mut self.data, &self.device)?;
+        create_tone_mapping_pipeline(&self.device, &mut self.data)?;
+        create_post_pipelines(&self.device, &mut self.data)?;
+
+
+        create_descriptor_pool(&self.device, &mut self.data)?;
+
+        create_descriptor_sets(&self.device, &mut self.data)?;
+
+
+
+        
+        info!("Swapchain & related objects have been re-created!");
+        
+        return Ok(());
+    }
+
+    /// Waits for the device to be idle and destroys every Vulkan object of the renderer.
+    ///
+    /// # Safety
+    /// The renderer can't be used afterwards, and this must be called exactly once.
+    pub unsafe fn destroy(&mut self) {
+        self.device.device_wait_idle().unwrap();
+
+        
+        self.data.image_available_semaphores.iter().for_each(|s| self.device.destroy_semaphore(*s, None));
+        self.data.render_finished_semaphores.iter().for_each(|s| self.device.destroy_semaphore(*s, None));
+        self.data.in_flight_fences.iter().for_each(|f| self.device.destroy_fence(*f, None));
+        debug!("Destroyed fences & semaphores");
+        
+        self.destroy_swapchain();
+
+
+        // Replacing the data drops every owning wrapper into the deletion queue, only the handles that outlive the device are kept.
+        let queue = self.data.deletion_queue.clone();
+        let mut allocator = std::mem::take(&mut self.data.allocator);
+
+        self.data = AppData {
+            headless: self.data.headless,
+            surface: self.data.surface,
+            messenger: self.data.messenger,
+            ..Default::default()
+        };
+
+        queue.flush(&self.device, &mut allocator);
+        debug!("Destroyed resources");
+
+        allocator.destroy(&self.device);
+        debug!("Destroyed allocator");
+
+
+        self.device.destroy_device(None);
+        debug!("Destroyed device");
+
+
+        if !self.data.headless {
+            self.instance.destroy_surface_khr(self.data.surface, None);
+            debug!("Destroyed surface");
+        }
+
+
+        self.instance.destroy_debug_utils_messenger_ext(self.data.messenger, None);
+        debug!("Destroyed debug messenger");
+
+        self.instance.destroy_instance(None);
+        debug!("destroyed instance");
+    }
+
+
+
+}
@@ -0,0 +1,297 @@
+use vulkanalia::prelude::v1_0::*;
+use log::*;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ops::Deref;
+use std::rc::Rc;
+
+use crate::allocator::{Allocation, Allocator};
+use crate::app::MAX_FRAMES_IN_FLIGHT;
+
+
+
+/// A resource whose owner has been dropped, waiting in the `DeletionQueue` until the GPU is done with it.
+#[derive(Debug)]
+enum Garbage {
+    Buffer(vk::Buffer, Allocation),
+    Image(vk::Image, Allocation),
+    ImageView(vk::ImageView),
+    Sampler(vk::Sampler),
+    Framebuffer(vk::Framebuffer),
+    RenderPass(vk::RenderPass),
+    Pipeline(vk::Pipeline),
+    PipelineLayout(vk::PipelineLayout),
+    DescriptorSetLayout(vk::DescriptorSetLayout),
+    DescriptorPool(vk::DescriptorPool),
+    CommandPool(vk::CommandPool)
+}
+
+
+impl Garbage {
+    unsafe fn destroy(self, device: &Device, allocator: &mut Allocator) {
+        match self {
+            Garbage::Buffer(buffer, allocation) => {
+                device.destroy_buffer(buffer, None);
+                allocator.free(device, allocation);
+            },
+            Garbage::Image(image, allocation) => {
+                device.destroy_image(image, None);
+                allocator.free(device, allocation);
+            },
+            Garbage::ImageView(view) => device.destroy_image_view(view, None),
+            Garbage::Sampler(sampler) => device.destroy_sampler(sampler, None),
+            Garbage::Framebuffer(framebuffer) => device.destroy_framebuffer(framebuffer, None),
+            Garbage::RenderPass(render_pass) => device.destroy_render_pass(render_pass, None),
+            Garbage::Pipeline(pipeline) => device.destroy_pipeline(pipeline, None),
+            Garbage::PipelineLayout(layout) => device.destroy_pipeline_layout(layout, None),
+            Garbage::DescriptorSetLayout(layout) => device.destroy_descriptor_set_layout(layout, None),
+            Garbage::DescriptorPool(pool) => device.destroy_descriptor_pool(pool, None),
+            Garbage::CommandPool(pool) => device.destroy_command_pool(pool, None)
+        }
+    }
+}
+
+
+
+/// Resources are destroyed `MAX_FRAMES_IN_FLIGHT` frames after their owner is dropped, when every frame that could still use them has finished.
+/// Every owning wrapper holds a clone of the queue, so dropping one is always safe, even in the middle of recording a frame.
+#[derive(Clone, Debug, Default)]
+pub struct DeletionQueue(Rc<RefCell<Queue>>);
+
+
+#[derive(Debug, Default)]
+struct Queue {
+    frame: u64,
+    /// Garbage together with the frame it can be destroyed at, in the order it was dropped.
+    garbage: VecDeque<(u64, Garbage)>
+}
+
+
+impl DeletionQueue {
+    fn push(&self, garbage: Garbage) {
+        let mut queue = self.0.borrow_mut();
+        let frame = queue.frame + MAX_FRAMES_IN_FLIGHT as u64;
+        queue.garbage.push_back((frame, garbage));
+    }
+
+
+    /// Starts a new frame, call after waiting for the fence of the frame that's about to be reused.
+    /// Destroys everything that was dropped `MAX_FRAMES_IN_FLIGHT` frames ago.
+    pub unsafe fn next_frame(&self, device: &Device, allocator: &mut Allocator) {
+        let retired = self.0.borrow_mut().next_frame();
+
+        retired.into_iter().for_each(|g| g.destroy(device, allocator));
+    }
+
+
+    /// Destroys everything right away, only call once the device is idle.
+    pub unsafe fn flush(&self, device: &Device, allocator: &mut Allocator) {
+        let garbage = self.0.borrow_mut().garbage.drain(..).map(|(_, g)| g).collect::<Vec<_>>();
+
+        if !garbage.is_empty() {
+            debug!("Destroying {} dropped resources", garbage.len());
+        }
+
+        garbage.into_iter().for_each(|g| g.destroy(device, allocator));
+    }
+}
+
+
+impl Queue {
+    fn next_frame(&mut self) -> Vec<Garbage> {
+        self.frame += 1;
+
+        let retired = self.garbage.iter().take_while(|(frame, _)| *frame <= self.frame).count();
+
+        return self.garbage.drain(..retired).map(|(_, g)| g).collect();
+    }
+}
+
+
+impl Drop for Queue {
+    fn drop(&mut self) {
+        if !self.garbage.is_empty() {
+            warn!("{} resources were dropped but never destroyed", self.garbage.len());
+        }
+    }
+}
+
+
+
+macro_rules! owned_handle {
+    ($(#[$meta:meta])* $name:ident($handle:ty)) => {
+        $(#[$meta])*
+        #[derive(Debug, Default)]
+        pub struct $name {
+            handle: $handle,
+            queue: DeletionQueue
+        }
+
+        impl $name {
+            pub fn new(handle: $handle, queue: &DeletionQueue) -> Self {
+                Self { handle, queue: queue.clone() }
+            }
+        }
+
+        impl Deref for $name {
+            type Target = $handle;
+
+            fn deref(&self) -> &$handle {
+                &self.handle
+            }
+        }
+
+        impl Drop for $name {
+            fn drop(&mut self) {
+                if !self.handle.is_null() {
+                    self.queue.push(Garbage::$name(self.handle));
+                }
+            }
+        }
+    };
+}
+
+
+owned_handle!(ImageView(vk::ImageView));
+owned_handle!(Sampler(vk::Sampler));
+owned_handle!(Framebuffer(vk::Framebuffer));
+owned_handle!(RenderPass(vk::RenderPass));
+owned_handle!(Pipeline(vk::Pipeline));
+owned_handle!(PipelineLayout(vk::PipelineLayout));
+owned_handle!(DescriptorSetLayout(vk::DescriptorSetLayout));
+owned_handle!(
+    /// Descriptor sets allocated from the pool are freed together with it.
+    DescriptorPool(vk::DescriptorPool)
+);
+owned_handle!(
+    /// Command buffers allocated from the pool are freed together with it.
+    CommandPool(vk::CommandPool)
+);
+
+
+
+/// A buffer that owns the memory it's bound to.
+#[derive(Debug, Default)]
+pub struct Buffer {
+    handle: vk::Buffer,
+    pub allocation: Allocation,
+    queue: DeletionQueue
+}
+
+
+impl Buffer {
+    pub fn new(handle: vk::Buffer, allocation: Allocation, queue: &DeletionQueue) -> Self {
+        Self { handle, allocation, queue: queue.clone() }
+    }
+}
+
+
+impl Deref for Buffer {
+    type Target = vk::Buffer;
+
+    fn deref(&self) -> &vk::Buffer {
+        &self.handle
+    }
+}
+
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        if !self.handle.is_null() {
+            self.queue.push(Garbage::Buffer(self.handle, self.allocation));
+        }
+    }
+}
+
+
+
+/// An image that owns the memory it's bound to, swapchain images are owned by the swapchain instead.
+#[derive(Debug, Default)]
+pub struct Image {
+    handle: vk::Image,
+    pub allocation: Allocation,
+    queue: DeletionQueue
+}
+
+
+impl Image {
+    pub fn new(handle: vk::Image, allocation: Allocation, queue: &DeletionQueue) -> Self {
+        Self { handle, allocation, queue: queue.clone() }
+    }
+}
+
+
+impl Deref for Image {
+    type Target = vk::Image;
+
+    fn deref(&self) -> &vk::Image {
+        &self.handle
+    }
+}
+
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        if !self.handle.is_null() {
+            self.queue.push(Garbage::Image(self.handle, self.allocation));
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(queue: &DeletionQueue) -> usize {
+        queue.0.borrow().garbage.len()
+    }
+
+    fn retire(queue: &DeletionQueue) -> usize {
+        queue.0.borrow_mut().next_frame().len()
+    }
+
+
+    #[test]
+    fn dropped_resources_wait_for_frames_in_flight() {
+        let queue = DeletionQueue::default();
+
+        drop(Sampler::new(vk::Sampler::from_raw(1), &queue));
+        assert_eq!(pending(&queue), 1);
+
+        for _ in 1..MAX_FRAMES_IN_FLIGHT {
+            assert_eq!(retire(&queue), 0);
+        }
+
+        assert_eq!(retire(&queue), 1);
+        assert_eq!(pending(&queue), 0);
+    }
+
+    #[test]
+    fn resources_are_retired_in_drop_order() {
+        let queue = DeletionQueue::default();
+
+        drop(Pipeline::new(vk::Pipeline::from_raw(1), &queue));
+        assert_eq!(retire(&queue), 0);
+        drop(Pipeline::new(vk::Pipeline::from_raw(2), &queue));
+
+        for _ in 1..MAX_FRAMES_IN_FLIGHT {
+            retire(&queue);
+        }
+
+        assert_eq!(pending(&queue), 1);
+        assert_eq!(retire(&queue), 1);
+        assert_eq!(pending(&queue), 0);
+    }
+
+    #[test]
+    fn null_handles_are_not_queued() {
+        let queue = DeletionQueue::default();
+
+        drop(Buffer::new(vk::Buffer::null(), Allocation::default(), &queue));
+        drop(Image { queue: queue.clone(), ..Default::default() });
+
+        assert_eq!(pending(&queue), 0);
+    }
+}
@@ -0,0 +1,430 @@
+use anyhow::{Result, anyhow};
+use nalgebra_glm as glm;
+
+use crate::renderer::{MaterialHandle, MeshHandle};
+
+
+
+/// A node of a `Scene`, only valid for the scene that created it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+
+/// Translation, rotation and scale, applied in reverse order.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: glm::Vec3,
+    pub rotation: glm::Quat,
+    pub scale: glm::Vec3
+}
+
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: glm::Vec3::zeros(),
+            rotation: glm::Quat::identity(),
+            scale: glm::vec3(1.0, 1.0, 1.0)
+        }
+    }
+}
+
+
+impl Transform {
+    pub fn from_translation(translation: glm::Vec3) -> Self {
+        Self { translation, ..Default::default() }
+    }
+
+    /// Rotation of `angle` radians around `axis`.
+    pub fn from_rotation(angle: f32, axis: &glm::Vec3) -> Self {
+        Self { rotation: glm::quat_angle_axis(angle, axis), ..Default::default() }
+    }
+
+    /// Splits an affine matrix into translation, rotation and scale, shear is lost.
+    pub fn from_matrix(matrix: &glm::Mat4) -> Self {
+        let translation = matrix.column(3).xyz();
+        let scale = glm::vec3(matrix.column(0).xyz().norm(), matrix.column(1).xyz().norm(), matrix.column(2).xyz().norm());
+
+        let mut rotation = glm::mat4_to_mat3(matrix);
+
+        for i in 0..3 {
+            if scale[i] != 0.0 {
+                rotation.set_column(i, &(rotation.column(i) / scale[i]));
+            }
+        }
+
+        return Self { translation, rotation: glm::mat3_to_quat(&rotation), scale };
+    }
+
+    pub fn matrix(&self) -> glm::Mat4 {
+        glm::translation(&self.translation) * glm::quat_to_mat4(&self.rotation) * glm::scaling(&self.scale)
+    }
+}
+
+
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub name: String,
+    /// Transform relative to the parent, change it with `Scene::set_transform`.
+    transform: Transform,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    /// Cached by `Scene::update_world_transforms`.
+    world: glm::Mat4,
+    /// Set when `transform` or `parent` changed since `world` was computed.
+    dirty: bool,
+    pub mesh: Option<MeshHandle>,
+    /// Replaces the materials of every draw range of `mesh` when set.
+    pub material: Option<MaterialHandle>,
+    /// 1 is fully opaque.
+    pub opacity: f32,
+    /// Hidden nodes aren't drawn, and neither are their children.
+    pub visible: bool
+}
+
+
+impl Node {
+    fn new(name: &str, parent: Option<NodeId>) -> Self {
+        Self {
+            name: name.to_string(),
+            transform: Transform::default(),
+            parent,
+            children: vec![],
+            world: glm::Mat4::identity(),
+            dirty: true,
+            mesh: None,
+            material: None,
+            opacity: 1.0,
+            visible: true
+        }
+    }
+
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    /// Transform relative to the scene, as of the last `Scene::update_world_transforms`.
+    pub fn world_transform(&self) -> &glm::Mat4 {
+        &self.world
+    }
+}
+
+
+
+/// A hierarchy of nodes with transforms relative to their parents, drawn with `Renderer::submit_scene`.
+///
+/// World transforms are cached, changing a node only recomputes it and its descendants on the next `update_world_transforms`.
+#[derive(Clone, Debug, Default)]
+pub struct Scene {
+    /// Removed nodes leave a `None` behind so that the ids of the others stay valid.
+    nodes: Vec<Option<Node>>,
+    roots: Vec<NodeId>
+}
+
+
+impl Scene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an empty node with an identity transform, at the root of the scene when `parent` is `None`.
+    pub fn add_node(&mut self, name: &str, parent: Option<NodeId>) -> NodeId {
+        let id = NodeId(self.nodes.len());
+
+        match parent {
+            Some(parent) => self.node_mut(parent).children.push(id),
+            None => self.roots.push(id)
+        }
+
+        self.nodes.push(Some(Node::new(name, parent)));
+
+        return id;
+    }
+
+    /// Removes a node together with all of its descendants.
+    pub fn remove_node(&mut self, id: NodeId) {
+        self.detach(id);
+
+        let mut stack = vec![id];
+
+        while let Some(id) = stack.pop() {
+            if let Some(node) = self.nodes[id.0].take() {
+                stack.extend(node.children);
+            }
+        }
+    }
+
+    /// Copies a node and all of its descendants under `parent`, meshes and materials are shared.
+    pub fn duplicate(&mut self, id: NodeId, parent: Option<NodeId>) -> NodeId {
+        let source = self.node(id).clone();
+        let copy = self.add_node(&source.name, parent);
+
+        let node = self.node_mut(copy);
+        node.transform = source.transform;
+        node.mesh = source.mesh;
+        node.material = source.material;
+        node.opacity = source.opacity;
+        node.visible = source.visible;
+
+        for child in source.children {
+            self.duplicate(child, Some(copy));
+        }
+
+        return copy;
+    }
+
+    /// Moves a node (and its descendants) under `parent`, keeping its local transform.
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> Result<()> {
+        let mut ancestor = parent;
+
+        while let Some(a) = ancestor {
+            if a == id {
+                return Err(anyhow!("Node {} can't become a descendant of itself.", self.node(id).name));
+            }
+
+            ancestor = self.node(a).parent;
+        }
+
+        self.detach(id);
+
+        match parent {
+            Some(parent) => self.node_mut(parent).children.push(id),
+            None => self.roots.push(id)
+        }
+
+        let node = self.node_mut(id);
+        node.parent = parent;
+        node.dirty = true;
+
+        return Ok(());
+    }
+
+    fn detach(&mut self, id: NodeId) {
+        let siblings = match self.node(id).parent {
+            Some(parent) => &mut self.node_mut(parent).children,
+            None => &mut self.roots
+        };
+
+        siblings.retain(|s| *s != id);
+    }
+
+
+    /// Panics when the node was removed.
+    pub fn node(&self, id: NodeId) -> &Node {
+        self.nodes[id.0].as_ref().expect("Node was removed from the scene")
+    }
+
+    /// Panics when the node was removed, use `set_transform` to move it.
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        self.nodes[id.0].as_mut().expect("Node was removed from the scene")
+    }
+
+    pub fn set_transform(&mut self, id: NodeId, transform: Transform) {
+        let node = self.node_mut(id);
+        node.transform = transform;
+        node.dirty = true;
+    }
+
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    /// Finds the first node called `name`.
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.nodes.iter()
+            .position(|n| n.as_ref().map_or(false, |n| n.name == name))
+            .map(NodeId)
+    }
+
+
+    /// Recomputes the world transforms of changed nodes and their descendants, returns how many were recomputed.
+    pub fn update_world_transforms(&mut self) -> usize {
+        let mut updated = 0;
+
+        let mut stack = self.roots.iter()
+            .map(|r| (*r, glm::Mat4::identity(), false))
+            .collect::<Vec<_>>();
+
+        while let Some((id, parent_world, parent_changed)) = stack.pop() {
+            let node = self.node_mut(id);
+            let changed = node.dirty || parent_changed;
+
+            if changed {
+                node.world = parent_world * node.transform.matrix();
+                node.dirty = false;
+                updated += 1;
+            }
+
+            let world = node.world;
+            stack.extend(node.children.iter().map(|c| (*c, world, changed)));
+        }
+
+        return updated;
+    }
+
+    /// Calls `f` with every visible node that has a mesh, parents before children.
+    /// World transforms are the cached ones, call `update_world_transforms` first.
+    pub fn visit_visible<F: FnMut(NodeId, &Node)>(&self, mut f: F) {
+        let mut stack = self.roots.iter().rev().copied().collect::<Vec<_>>();
+
+        while let Some(id) = stack.pop() {
+            let node = self.node(id);
+
+            if !node.visible {
+                continue;
+            }
+
+            if node.mesh.is_some() {
+                f(id, node);
+            }
+
+            stack.extend(node.children.iter().rev());
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translation(node: &Node) -> glm::Vec3 {
+        node.world_transform().column(3).xyz()
+    }
+
+
+    #[test]
+    fn world_transforms_include_parents() {
+        let mut scene = Scene::new();
+        let root = scene.add_node("root", None);
+        let child = scene.add_node("child", Some(root));
+
+        scene.set_transform(root, Transform::from_translation(glm::vec3(0.0, 2.0, 0.0)));
+        scene.set_transform(child, Transform::from_translation(glm::vec3(3.0, 0.0, 0.0)));
+
+        assert_eq!(scene.update_world_transforms(), 2);
+        assert_eq!(translation(scene.node(child)), glm::vec3(3.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn only_changed_subtrees_are_recomputed() {
+        let mut scene = Scene::new();
+        let a = scene.add_node("a", None);
+        let a_child = scene.add_node("a child", Some(a));
+        scene.add_node("b", None);
+
+        scene.update_world_transforms();
+        assert_eq!(scene.update_world_transforms(), 0);
+
+        scene.set_transform(a, Transform::from_translation(glm::vec3(1.0, 0.0, 0.0)));
+
+        assert_eq!(scene.update_world_transforms(), 2);
+        assert_eq!(translation(scene.node(a_child)), glm::vec3(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn reparenting_keeps_the_local_transform() {
+        let mut scene = Scene::new();
+        let a = scene.add_node("a", None);
+        let b = scene.add_node("b", None);
+        let child = scene.add_node("child", Some(a));
+
+        scene.set_transform(b, Transform::from_translation(glm::vec3(0.0, 0.0, 5.0)));
+        scene.set_transform(child, Transform::from_translation(glm::vec3(1.0, 0.0, 0.0)));
+        scene.update_world_transforms();
+
+        scene.set_parent(child, Some(b)).unwrap();
+        scene.update_world_transforms();
+
+        assert!(scene.node(a).children().is_empty());
+        assert_eq!(scene.node(child).parent(), Some(b));
+        assert_eq!(translation(scene.node(child)), glm::vec3(1.0, 0.0, 5.0));
+    }
+
+    #[test]
+    fn nodes_cant_become_their_own_descendants() {
+        let mut scene = Scene::new();
+        let root = scene.add_node("root", None);
+        let child = scene.add_node("child", Some(root));
+
+        assert!(scene.set_parent(root, Some(child)).is_err());
+        assert_eq!(scene.roots(), &[root]);
+    }
+
+    #[test]
+    fn removing_a_node_removes_its_descendants() {
+        let mut scene = Scene::new();
+        let root = scene.add_node("root", None);
+        let child = scene.add_node("child", Some(root));
+        scene.add_node("grandchild", Some(child));
+
+        scene.remove_node(child);
+
+        assert!(scene.node(root).children().is_empty());
+        assert_eq!(scene.find("grandchild"), None);
+        assert_eq!(scene.find("root"), Some(root));
+    }
+
+    #[test]
+    fn duplicates_copy_the_whole_subtree() {
+        let mut scene = Scene::new();
+        let root = scene.add_node("root", None);
+        let child = scene.add_node("child", Some(root));
+        scene.set_transform(child, Transform::from_translation(glm::vec3(1.0, 0.0, 0.0)));
+
+        let copy = scene.duplicate(root, None);
+        scene.set_transform(copy, Transform::from_translation(glm::vec3(0.0, 4.0, 0.0)));
+        scene.update_world_transforms();
+
+        let copied_child = scene.node(copy).children()[0];
+        assert_ne!(copied_child, child);
+        assert_eq!(translation(scene.node(copied_child)), glm::vec3(1.0, 4.0, 0.0));
+        assert_eq!(translation(scene.node(child)), glm::vec3(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn hidden_nodes_hide_their_children() {
+        let mut scene = Scene::new();
+        let root = scene.add_node("root", None);
+        let child = scene.add_node("child", Some(root));
+
+        for id in [root, child] {
+            scene.node_mut(id).mesh = Some(MeshHandle(0));
+        }
+
+        let mut visited = vec![];
+        scene.visit_visible(|id, _| visited.push(id));
+        assert_eq!(visited, vec![root, child]);
+
+        scene.node_mut(root).visible = false;
+
+        let mut visited = vec![];
+        scene.visit_visible(|id, _| visited.push(id));
+        assert!(visited.is_empty());
+    }
+
+    #[test]
+    fn matrices_split_into_translation_rotation_and_scale() {
+        let transform = Transform {
+            translation: glm::vec3(1.0, 2.0, 3.0),
+            rotation: glm::quat_angle_axis(0.5, &glm::vec3(0.0, 0.0, 1.0)),
+            scale: glm::vec3(2.0, 2.0, 2.0)
+        };
+
+        let split = Transform::from_matrix(&transform.matrix());
+
+        assert!((split.translation - transform.translation).norm() < 1e-5);
+        assert!((split.scale - transform.scale).norm() < 1e-5);
+        assert!((split.matrix() - transform.matrix()).norm() < 1e-5);
+    }
+}
@@ -0,0 +1,599 @@
+use anyhow::{Result, anyhow};
+use log::*;
+use nalgebra_glm as glm;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::camera::{Camera, Projection};
+use crate::material::{AlphaMode, Material, MaterialTextures, TEXTURES_PER_MATERIAL};
+use crate::lights::{Light, LightKind};
+use crate::renderer::{MaterialHandle, MeshHandle, Renderer};
+use crate::scene::{NodeId, Scene, Transform};
+
+
+
+/// A scene as written in a RON file: the assets to load, the node hierarchy that uses them, the camera and the lights.
+/// Assets are referenced by name, paths are relative to the scene file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SceneFile {
+    pub textures: Vec<TextureDesc>,
+    pub materials: Vec<MaterialDesc>,
+    pub meshes: Vec<MeshDesc>,
+    pub nodes: Vec<NodeDesc>,
+    pub camera: CameraDesc,
+    pub lights: Vec<LightDesc>,
+    pub ambient: [f32; 3]
+}
+
+
+impl Default for SceneFile {
+    fn default() -> Self {
+        Self {
+            textures: vec![],
+            materials: vec![],
+            meshes: vec![],
+            nodes: vec![],
+            camera: CameraDesc::default(),
+            lights: vec![],
+            ambient: [0.02; 3]
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TextureDesc {
+    pub name: String,
+    /// A PNG file.
+    pub path: PathBuf
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MaterialDesc {
+    pub name: String,
+    pub base_color: [f32; 3],
+    pub opacity: f32,
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emissive_color: [f32; 3],
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    /// Names of textures, see `Material` for what each holds. Missing textures leave the factors as they are.
+    pub base_color_texture: Option<String>,
+    pub metallic_roughness_texture: Option<String>,
+    pub normal_texture: Option<String>,
+    pub occlusion_texture: Option<String>,
+    pub emissive_texture: Option<String>
+}
+
+
+impl Default for MaterialDesc {
+    fn default() -> Self {
+        let material = Material::default();
+
+        Self {
+            name: String::new(),
+            base_color: material.base_color.into(),
+            opacity: material.opacity,
+            alpha_mode: material.alpha_mode,
+            alpha_cutoff: material.alpha_cutoff,
+            metallic: material.metallic,
+            roughness: material.roughness,
+            emissive_color: material.emissive_color.into(),
+            normal_scale: material.normal_scale,
+            occlusion_strength: material.occlusion_strength,
+            base_color_texture: None,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            occlusion_texture: None,
+            emissive_texture: None
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MeshDesc {
+    pub name: String,
+    /// An OBJ or glTF file, glTF hierarchies are flattened into a single mesh.
+    pub path: PathBuf,
+    /// Name of the material for the faces the file doesn't give one.
+    #[serde(default)]
+    pub material: Option<String>
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeDesc {
+    pub name: String,
+    pub translation: [f32; 3],
+    /// Euler angles in degrees, applied around X, then Y, then Z.
+    pub rotation: [f32; 3],
+    pub scale: [f32; 3],
+    /// Name of a mesh.
+    pub mesh: Option<String>,
+    /// Name of a material that replaces all materials of the mesh.
+    pub material: Option<String>,
+    pub opacity: f32,
+    pub visible: bool,
+    pub children: Vec<NodeDesc>
+}
+
+
+impl Default for NodeDesc {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            translation: [0.0; 3],
+            rotation: [0.0; 3],
+            scale: [1.0; 3],
+            mesh: None,
+            material: None,
+            opacity: 1.0,
+            visible: true,
+            children: vec![]
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraDesc {
+    pub position: [f32; 3],
+    pub target: [f32; 3],
+    pub up: [f32; 3],
+    pub projection: ProjectionDesc
+}
+
+
+/// `Projection` with angles in degrees.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ProjectionDesc {
+    Perspective { fov_y: f32, near: f32, far: f32 },
+    Orthographic { height: f32, near: f32, far: f32 },
+    InfiniteReverseZ { fov_y: f32, near: f32 }
+}
+
+
+impl Default for CameraDesc {
+    fn default() -> Self {
+        CameraDesc::from(&Camera::default())
+    }
+}
+
+
+/// `Light` with angles in degrees, the color defaults to white, the intensity to 1 and lights don't cast shadows unless asked to.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LightDesc {
+    Directional {
+        direction: [f32; 3],
+        #[serde(default = "white")]
+        color: [f32; 3],
+        #[serde(default = "one")]
+        intensity: f32,
+        #[serde(default)]
+        cast_shadows: bool
+    },
+    Point {
+        position: [f32; 3],
+        range: f32,
+        #[serde(default = "white")]
+        color: [f32; 3],
+        #[serde(default = "one")]
+        intensity: f32
+    },
+    Spot {
+        position: [f32; 3],
+        direction: [f32; 3],
+        range: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+        #[serde(default = "white")]
+        color: [f32; 3],
+        #[serde(default = "one")]
+        intensity: f32,
+        #[serde(default)]
+        cast_shadows: bool
+    }
+}
+
+
+fn white() -> [f32; 3] {
+    [1.0; 3]
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+
+
+impl SceneFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Couldn't read scene {}: {}", path.display(), e))?;
+
+        return ron::from_str(&text).map_err(|e| anyhow!("Couldn't parse scene {}: {}", path.display(), e));
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(path, text)?;
+
+        info!("Saved scene to {}", path.display());
+
+        return Ok(());
+    }
+
+
+    /// Loads every asset of the file, builds its node hierarchy and sets the camera and lights of the renderer.
+    /// Relative asset paths are resolved against `directory`, usually the directory of the scene file.
+    ///
+    /// # Safety
+    /// `renderer` must not have been destroyed, like for the `Renderer` methods that load the assets.
+    pub unsafe fn instantiate(&self, renderer: &mut Renderer, directory: &Path) -> Result<LoadedScene> {
+        let textures: HashMap<&str, PathBuf> = self.textures.iter()
+            .map(|t| (t.name.as_str(), directory.join(&t.path)))
+            .collect();
+
+        let mut materials: HashMap<&str, MaterialHandle> = HashMap::new();
+        let mut meshes: HashMap<&str, MeshHandle> = HashMap::new();
+
+        // Textures are loaded when a material uses them, as they are encoded differently depending on what they hold.
+        for desc in &self.materials {
+            let mut handles = [None; TEXTURES_PER_MATERIAL];
+
+            for (slot, name) in desc.texture_names().into_iter().enumerate() {
+                let Some(name) = name else { continue };
+
+                let path = textures.get(name).ok_or_else(|| anyhow!("Material {} uses unknown texture {}.", desc.name, name))?;
+                handles[slot] = Some(renderer.load_texture(path, MaterialTextures::COLOR_SPACES[slot])?);
+            }
+
+            materials.insert(&desc.name, renderer.create_material(desc.to_material(), MaterialTextures::from_array(handles))?);
+        }
+
+        for desc in &self.meshes {
+            let material = match &desc.material {
+                Some(name) => Some(*materials.get(name.as_str()).ok_or_else(|| anyhow!("Mesh {} uses unknown material {}.", desc.name, name))?),
+                None => None
+            };
+
+            meshes.insert(&desc.name, renderer.load_mesh(&directory.join(&desc.path), material)?);
+        }
+
+        let mut scene = Scene::new();
+
+        let mut stack = self.nodes.iter().rev().map(|n| (n, None)).collect::<Vec<_>>();
+
+        while let Some((desc, parent)) = stack.pop() {
+            let node = scene.add_node(&desc.name, parent);
+            scene.set_transform(node, desc.transform());
+
+            let node_mut = scene.node_mut(node);
+            node_mut.opacity = desc.opacity;
+            node_mut.visible = desc.visible;
+
+            node_mut.mesh = match &desc.mesh {
+                Some(name) => Some(*meshes.get(name.as_str()).ok_or_else(|| anyhow!("Node {} uses unknown mesh {}.", desc.name, name))?),
+                None => None
+            };
+
+            node_mut.material = match &desc.material {
+                Some(name) => Some(*materials.get(name.as_str()).ok_or_else(|| anyhow!("Node {} uses unknown material {}.", desc.name, name))?),
+                None => None
+            };
+
+            stack.extend(desc.children.iter().rev().map(|c| (c, Some(node))));
+        }
+
+        renderer.set_camera(self.camera.to_camera());
+
+        renderer.set_lights(self.lights.iter().map(|l| l.to_light()).collect());
+        renderer.set_ambient(glm::Vec3::from(self.ambient));
+
+        info!("Instantiated scene with {} textures, {} materials, {} meshes", textures.len(), materials.len(), meshes.len());
+
+        return Ok(LoadedScene {
+            mesh_names: meshes.into_iter().map(|(name, mesh)| (mesh, name.to_string())).collect(),
+            material_names: materials.into_iter().map(|(name, material)| (material, name.to_string())).collect(),
+            file: self.clone(),
+            scene
+        });
+    }
+}
+
+
+impl MaterialDesc {
+    fn to_material(&self) -> Material {
+        Material {
+            name: self.name.clone(),
+            base_color: glm::Vec3::from(self.base_color),
+            opacity: self.opacity,
+            alpha_mode: self.alpha_mode,
+            alpha_cutoff: self.alpha_cutoff,
+            metallic: self.metallic,
+            roughness: self.roughness,
+            emissive_color: glm::Vec3::from(self.emissive_color),
+            normal_scale: self.normal_scale,
+            occlusion_strength: self.occlusion_strength,
+            ..Default::default()
+        }
+    }
+
+    fn texture_names(&self) -> [Option<&str>; TEXTURES_PER_MATERIAL] {
+        [
+            self.base_color_texture.as_deref(),
+            self.metallic_roughness_texture.as_deref(),
+            self.normal_texture.as_deref(),
+            self.occlusion_texture.as_deref(),
+            self.emissive_texture.as_deref()
+        ]
+    }
+}
+
+
+impl NodeDesc {
+    fn transform(&self) -> Transform {
+        let [x, y, z] = self.rotation.map(|r| glm::radians(&glm::vec1(r))[0]);
+
+        let rotation = glm::quat_angle_axis(z, &glm::vec3(0.0, 0.0, 1.0))
+            * glm::quat_angle_axis(y, &glm::vec3(0.0, 1.0, 0.0))
+            * glm::quat_angle_axis(x, &glm::vec3(1.0, 0.0, 0.0));
+
+        Transform {
+            translation: glm::Vec3::from(self.translation),
+            rotation,
+            scale: glm::Vec3::from(self.scale)
+        }
+    }
+
+    fn set_transform(&mut self, transform: &Transform) {
+        // Returns the angles around Z, Y and X.
+        let angles = glm::quat_euler_angles(&transform.rotation);
+
+        self.translation = transform.translation.into();
+        self.rotation = [angles.z, angles.y, angles.x].map(|a| glm::degrees(&glm::vec1(a))[0]);
+        self.scale = transform.scale.into();
+    }
+}
+
+
+impl CameraDesc {
+    fn to_camera(&self) -> Camera {
+        Camera {
+            position: glm::Vec3::from(self.position),
+            target: glm::Vec3::from(self.target),
+            up: glm::Vec3::from(self.up),
+            projection: match self.projection {
+                ProjectionDesc::Perspective { fov_y, near, far } => Projection::Perspective { fov_y: fov_y.to_radians(), near, far },
+                ProjectionDesc::Orthographic { height, near, far } => Projection::Orthographic { height, near, far },
+                ProjectionDesc::InfiniteReverseZ { fov_y, near } => Projection::InfiniteReverseZ { fov_y: fov_y.to_radians(), near }
+            }
+        }
+    }
+}
+
+
+impl From<&Camera> for CameraDesc {
+    fn from(camera: &Camera) -> Self {
+        Self {
+            position: camera.position.into(),
+            target: camera.target.into(),
+            up: camera.up.into(),
+            projection: match camera.projection {
+                Projection::Perspective { fov_y, near, far } => ProjectionDesc::Perspective { fov_y: fov_y.to_degrees(), near, far },
+                Projection::Orthographic { height, near, far } => ProjectionDesc::Orthographic { height, near, far },
+                Projection::InfiniteReverseZ { fov_y, near } => ProjectionDesc::InfiniteReverseZ { fov_y: fov_y.to_degrees(), near }
+            }
+        }
+    }
+}
+
+
+
+impl LightDesc {
+    fn to_light(&self) -> Light {
+        match *self {
+            LightDesc::Directional { direction, color, intensity, cast_shadows } =>
+                Light { cast_shadows, ..Light::directional(direction.into()).with_color(color.into(), intensity) },
+            LightDesc::Point { position, range, color, intensity } =>
+                Light::point(position.into(), range).with_color(color.into(), intensity),
+            LightDesc::Spot { position, direction, range, inner_angle, outer_angle, color, intensity, cast_shadows } => Light {
+                cast_shadows,
+                ..Light::spot(position.into(), direction.into(), range, inner_angle.to_radians(), outer_angle.to_radians()).with_color(color.into(), intensity)
+            }
+        }
+    }
+}
+
+
+impl From<&Light> for LightDesc {
+    fn from(light: &Light) -> Self {
+        let color = light.color.into();
+        let intensity = light.intensity;
+        let cast_shadows = light.cast_shadows;
+
+        match light.kind {
+            LightKind::Directional { direction } => LightDesc::Directional { direction: direction.into(), color, intensity, cast_shadows },
+            LightKind::Point { position, range } => LightDesc::Point { position: position.into(), range, color, intensity },
+            LightKind::Spot { position, direction, range, inner_angle, outer_angle } => LightDesc::Spot {
+                position: position.into(),
+                direction: direction.into(),
+                range,
+                inner_angle: inner_angle.to_degrees(),
+                outer_angle: outer_angle.to_degrees(),
+                color,
+                intensity,
+                cast_shadows
+            }
+        }
+    }
+}
+
+
+
+/// A scene built from a `SceneFile`, remembers the names of its assets so that it can be saved again.
+#[derive(Debug)]
+pub struct LoadedScene {
+    pub scene: Scene,
+    /// The file the scene was built from, its assets are written back as they were.
+    file: SceneFile,
+    mesh_names: HashMap<MeshHandle, String>,
+    material_names: HashMap<MaterialHandle, String>
+}
+
+
+impl LoadedScene {
+    /// Describes the current nodes, camera and lights of the scene. Nodes using assets that aren't part of the file lose them.
+    pub fn to_file(&self, renderer: &Renderer) -> SceneFile {
+        let nodes = self.scene.roots().iter()
+            .map(|r| self.describe_node(*r))
+            .collect();
+
+        SceneFile {
+            nodes,
+            camera: CameraDesc::from(renderer.camera()),
+            lights: renderer.lights().iter().map(LightDesc::from).collect(),
+            ambient: renderer.ambient().into(),
+            ..self.file.clone()
+        }
+    }
+
+    /// Saves the current state of the scene, see `to_file`.
+    pub fn save(&self, renderer: &Renderer, path: &Path) -> Result<()> {
+        self.to_file(renderer).save(path)
+    }
+
+    fn describe_node(&self, id: NodeId) -> NodeDesc {
+        let node = self.scene.node(id);
+
+        let mut desc = NodeDesc {
+            name: node.name.clone(),
+            mesh: node.mesh.and_then(|m| self.mesh_names.get(&m).cloned()),
+            material: node.material.and_then(|m| self.material_names.get(&m).cloned()),
+            opacity: node.opacity,
+            visible: node.visible,
+            children: node.children().iter().map(|c| self.describe_node(*c)).collect(),
+            ..Default::default()
+        };
+
+        desc.set_transform(node.transform());
+
+        return desc;
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENE: &str = r#"(
+        textures: [(name: "wood", path: "texture.png")],
+        materials: [(name: "wood", base_color_texture: Some("wood"), roughness: 0.8)],
+        meshes: [(name: "room", path: "viking_room.obj", material: Some("wood"))],
+        nodes: [
+            (
+                name: "room",
+                translation: (0.0, 1.0, 0.0),
+                rotation: (0.0, 0.0, 90.0),
+                mesh: Some("room"),
+                children: [(name: "child", opacity: 0.5)],
+            ),
+        ],
+        lights: [
+            Directional(direction: (-1.0, 3.0, 1.0), cast_shadows: true),
+            Spot(position: (0.0, 0.0, 3.0), direction: (0.0, 0.0, -1.0), range: 10.0, inner_angle: 20.0, outer_angle: 30.0, color: (1.0, 0.8, 0.6)),
+        ],
+    )"#;
+
+
+    #[test]
+    fn missing_fields_use_defaults() {
+        let file: SceneFile = ron::from_str(SCENE).unwrap();
+
+        assert_eq!(file.materials[0].opacity, 1.0);
+        assert_eq!(file.materials[0].alpha_mode, AlphaMode::Opaque);
+        assert_eq!(file.materials[0].base_color, [1.0, 1.0, 1.0]);
+        assert_eq!(file.materials[0].metallic, 0.0);
+        assert_eq!(file.materials[0].normal_texture, None);
+        assert_eq!(file.nodes[0].scale, [1.0, 1.0, 1.0]);
+        assert_eq!(file.nodes[0].children[0].opacity, 0.5);
+        assert!(file.nodes[0].children[0].visible);
+        assert_eq!(file.camera, CameraDesc::default());
+    }
+
+    #[test]
+    fn projections_use_degrees() {
+        let desc: CameraDesc = ron::from_str("(projection: InfiniteReverseZ(fov_y: 90.0, near: 0.5))").unwrap();
+
+        let camera = desc.to_camera();
+        assert_eq!(camera.projection, Projection::InfiniteReverseZ { fov_y: std::f32::consts::FRAC_PI_2, near: 0.5 });
+        assert_eq!(CameraDesc::from(&camera), desc.clone());
+    }
+
+    #[test]
+    fn light_angles_use_degrees() {
+        let file: SceneFile = ron::from_str(SCENE).unwrap();
+
+        let spot = file.lights[1].to_light();
+        assert_eq!(spot.intensity, 1.0);
+        assert_eq!(spot.color, glm::vec3(1.0, 0.8, 0.6));
+        assert!(matches!(spot.kind, LightKind::Spot { outer_angle, .. } if (outer_angle - std::f32::consts::FRAC_PI_6).abs() < 1e-6));
+
+        let desc = LightDesc::from(&spot);
+        assert!(matches!(desc, LightDesc::Spot { inner_angle, .. } if (inner_angle - 20.0).abs() < 1e-4));
+        assert_eq!(file.ambient, [0.02; 3]);
+        assert!(file.lights[0].to_light().cast_shadows && !spot.cast_shadows);
+    }
+
+    #[test]
+    fn example_scene_loads() {
+        let file = SceneFile::load(Path::new("resources/viking_room.ron")).unwrap();
+
+        assert_eq!(file.meshes[0].path, PathBuf::from("viking_room.obj"));
+        assert_eq!(file.nodes[0].children[0].scale, [0.5, 0.5, 0.5]);
+        assert_eq!(file.lights.len(), 2);
+    }
+
+    #[test]
+    fn saved_files_load_the_same() {
+        let file: SceneFile = ron::from_str(SCENE).unwrap();
+
+        let text = ron::ser::to_string_pretty(&file, ron::ser::PrettyConfig::default()).unwrap();
+
+        assert_eq!(ron::from_str::<SceneFile>(&text).unwrap(), file);
+    }
+
+    #[test]
+    fn rotations_survive_a_round_trip() {
+        let desc = NodeDesc { rotation: [30.0, -20.0, 90.0], ..Default::default() };
+
+        let mut copy = NodeDesc::default();
+        copy.set_transform(&desc.transform());
+
+        for (a, b) in desc.rotation.iter().zip(copy.rotation.iter()) {
+            assert!((a - b).abs() < 1e-3, "{:?} != {:?}", desc.rotation, copy.rotation);
+        }
+    }
+
+    #[test]
+    fn z_rotations_turn_x_into_y() {
+        let desc = NodeDesc { rotation: [0.0, 0.0, 90.0], ..Default::default() };
+
+        let x = desc.transform().matrix() * glm::vec4(1.0, 0.0, 0.0, 0.0);
+
+        assert!((x.xyz() - glm::vec3(0.0, 1.0, 0.0)).norm() < 1e-5);
+    }
+}
@@ -0,0 +1,16 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "post.glsl"
+
+// params: x the intensity.
+
+// The blurred bright parts, at half the size of the input.
+layout(binding = 1) uniform sampler2D bloomImage;
+
+
+void main() {
+    vec3 color = texture(inputImage, fragTexCoord).rgb + texture(bloomImage, fragTexCoord).rgb * constants.params.x;
+
+    outColor = vec4(color, 1.0);
+}
@@ -0,0 +1,29 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "post.glsl"
+
+// params: x the threshold, y the width of the soft knee around it.
+
+
+void main() {
+    // Four bilinear taps average 4x4 texels, the output is half the size of the input.
+    vec2 offset = constants.texel_size;
+    vec3 color = 0.25 * (
+        texture(inputImage, fragTexCoord + vec2(-offset.x, -offset.y)).rgb +
+        texture(inputImage, fragTexCoord + vec2(offset.x, -offset.y)).rgb +
+        texture(inputImage, fragTexCoord + vec2(-offset.x, offset.y)).rgb +
+        texture(inputImage, fragTexCoord + vec2(offset.x, offset.y)).rgb);
+
+    float threshold = constants.params.x;
+    float knee = max(constants.params.y, 1e-4);
+
+    // A quadratic curve from threshold - knee to threshold + knee, so colors don't pop in at the threshold.
+    float brightness = max(color.r, max(color.g, color.b));
+    float soft = clamp(brightness - threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee);
+
+    float contribution = max(soft, brightness - threshold) / max(brightness, 1e-4);
+
+    outColor = vec4(color * contribution, 1.0);
+}
@@ -0,0 +1,24 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "post.glsl"
+
+// params: xy the direction of the blur, in texels.
+
+
+// A 9 tap Gaussian in 5 bilinear taps, the offsets fall between two texels and their weights are combined.
+const float offsets[3] = float[](0.0, 1.3846153846, 3.2307692308);
+const float weights[3] = float[](0.2270270270, 0.3162162162, 0.0702702703);
+
+
+void main() {
+    vec2 step = constants.params.xy * constants.texel_size;
+    vec3 color = texture(inputImage, fragTexCoord).rgb * weights[0];
+
+    for (int i = 1; i < 3; i++) {
+        color += texture(inputImage, fragTexCoord + step * offsets[i]).rgb * weights[i];
+        color += texture(inputImage, fragTexCoord - step * offsets[i]).rgb * weights[i];
+    }
+
+    outColor = vec4(color, 1.0);
+}
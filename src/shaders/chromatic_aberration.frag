@@ -0,0 +1,19 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "post.glsl"
+
+// params: x how far red and blue are pushed apart at the corners, in texels.
+
+
+void main() {
+    // The fringes grow towards the edges like those of a lens, the center stays sharp.
+    vec2 from_center = fragTexCoord - 0.5;
+    vec2 offset = from_center * 2.0 * constants.params.x * constants.texel_size;
+
+    float r = texture(inputImage, fragTexCoord + offset).r;
+    float g = texture(inputImage, fragTexCoord).g;
+    float b = texture(inputImage, fragTexCoord - offset).b;
+
+    outColor = vec4(r, g, b, 1.0);
+}
@@ -0,0 +1,30 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "post.glsl"
+
+// params: x how much of the graded color is used, y the size of the LUT.
+
+// Red along x, green along y and blue along z, like the entries of a .cube file.
+layout(binding = 1) uniform sampler3D lut;
+
+
+vec3 srgb_encode(vec3 color) {
+    return mix(color * 12.92, 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055, step(vec3(0.0031308), color));
+}
+
+vec3 srgb_decode(vec3 color) {
+    return mix(color / 12.92, pow((color + 0.055) / 1.055, vec3(2.4)), step(vec3(0.04045), color));
+}
+
+
+void main() {
+    vec3 color = clamp(texture(inputImage, fragTexCoord).rgb, 0.0, 1.0);
+
+    // LUTs are made for the colors as they are displayed, sampled at the centers of the first and last texels.
+    float size = constants.params.y;
+    vec3 coordinate = srgb_encode(color) * ((size - 1.0) / size) + 0.5 / size;
+    vec3 graded = srgb_decode(texture(lut, coordinate).rgb);
+
+    outColor = vec4(mix(color, graded, constants.params.x), 1.0);
+}
@@ -1,2 +1,24 @@
 C:\VulkanSDK\1.3.236.0\Bin\glslc.exe shader.vert -o vertex.spv
-C:\VulkanSDK\1.3.236.0\Bin\glslc.exe shader.frag -o fragment.spv
\ No newline at end of file
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe shader.frag -o fragment.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe shadow.vert -o shadow.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe cull.comp -o cull.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe draw_counts.comp -o draw_counts.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe fullscreen.vert -o fullscreen.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe tone_mapping.frag -o tone_mapping.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe histogram.comp -o histogram.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe exposure.comp -o exposure.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe bloom_threshold.frag -o bloom_threshold.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe blur.frag -o blur.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe bloom_composite.frag -o bloom_composite.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe fxaa.frag -o fxaa.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe vignette.frag -o vignette.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe chromatic_aberration.frag -o chromatic_aberration.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe color_grading.frag -o color_grading.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe equirect_to_cube.comp -o equirect_to_cube.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe irradiance.comp -o irradiance.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe prefilter.comp -o prefilter.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe brdf_lut.comp -o brdf_lut.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe skybox.vert -o skybox_vert.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe skybox.frag -o skybox_frag.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe shadow_cutout.vert -o shadow_cutout_vert.spv
+C:\VulkanSDK\1.3.236.0\Bin\glslc.exe shadow_cutout.frag -o shadow_cutout_frag.spv
\ No newline at end of file
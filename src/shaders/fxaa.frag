@@ -0,0 +1,117 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "post.glsl"
+
+// params: x the contrast an edge needs relative to the brightest neighbour, y the contrast it needs at least,
+// z how much sub-pixel aliasing is removed.
+
+
+// The luma of a gamma encoded color, which is how FXAA expects contrast.
+float perceived(vec2 uv) {
+    return sqrt(luma(texture(inputImage, uv).rgb));
+}
+
+
+// A variant of Timothy Lottes' FXAA 3.11 quality preset, searching along the edge in a few fixed steps.
+void main() {
+    vec2 texel = constants.texel_size;
+    vec3 center_color = texture(inputImage, fragTexCoord).rgb;
+
+    float m = sqrt(luma(center_color));
+    float n = perceived(fragTexCoord + vec2(0.0, -texel.y));
+    float s = perceived(fragTexCoord + vec2(0.0, texel.y));
+    float e = perceived(fragTexCoord + vec2(texel.x, 0.0));
+    float w = perceived(fragTexCoord + vec2(-texel.x, 0.0));
+
+    float highest = max(max(max(n, s), max(e, w)), m);
+    float lowest = min(min(min(n, s), min(e, w)), m);
+    float contrast = highest - lowest;
+
+    if (contrast < max(constants.params.y, highest * constants.params.x)) {
+        outColor = vec4(center_color, 1.0);
+        return;
+    }
+
+    float ne = perceived(fragTexCoord + vec2(texel.x, -texel.y));
+    float nw = perceived(fragTexCoord + vec2(-texel.x, -texel.y));
+    float se = perceived(fragTexCoord + vec2(texel.x, texel.y));
+    float sw = perceived(fragTexCoord + vec2(-texel.x, texel.y));
+
+    // How far the center differs from its neighbourhood decides the sub-pixel blend.
+    float average = (2.0 * (n + s + e + w) + ne + nw + se + sw) / 12.0;
+    float subpixel = smoothstep(0.0, 1.0, clamp(abs(average - m) / contrast, 0.0, 1.0));
+    subpixel = subpixel * subpixel * constants.params.z;
+
+    float horizontal = abs(n + s - 2.0 * m) * 2.0 + abs(ne + se - 2.0 * e) + abs(nw + sw - 2.0 * w);
+    float vertical = abs(e + w - 2.0 * m) * 2.0 + abs(ne + nw - 2.0 * n) + abs(se + sw - 2.0 * s);
+    bool is_horizontal = horizontal >= vertical;
+
+    // Step towards the neighbour on the other side of the edge.
+    float positive = is_horizontal ? s : e;
+    float negative = is_horizontal ? n : w;
+    float step_length = is_horizontal ? texel.y : texel.x;
+
+    float gradient = abs(positive - m);
+    float opposite = negative;
+
+    if (abs(negative - m) > gradient) {
+        step_length = -step_length;
+        gradient = abs(negative - m);
+    } else {
+        opposite = positive;
+    }
+
+    vec2 edge_uv = fragTexCoord;
+    vec2 along = is_horizontal ? vec2(texel.x, 0.0) : vec2(0.0, texel.y);
+
+    if (is_horizontal) {
+        edge_uv.y += step_length * 0.5;
+    } else {
+        edge_uv.x += step_length * 0.5;
+    }
+
+    float edge_luma = (m + opposite) * 0.5;
+    float gradient_threshold = gradient * 0.25;
+
+    // Walk both ways along the edge until the luma leaves it.
+    const float steps[8] = float[](1.0, 1.5, 2.0, 2.0, 2.0, 2.0, 4.0, 8.0);
+
+    vec2 uv_p = edge_uv + along;
+    vec2 uv_n = edge_uv - along;
+    float delta_p = perceived(uv_p) - edge_luma;
+    float delta_n = perceived(uv_n) - edge_luma;
+
+    for (int i = 1; i < 8 && (abs(delta_p) < gradient_threshold || abs(delta_n) < gradient_threshold); i++) {
+        if (abs(delta_p) < gradient_threshold) {
+            uv_p += along * steps[i];
+            delta_p = perceived(uv_p) - edge_luma;
+        }
+
+        if (abs(delta_n) < gradient_threshold) {
+            uv_n -= along * steps[i];
+            delta_n = perceived(uv_n) - edge_luma;
+        }
+    }
+
+    float distance_p = is_horizontal ? uv_p.x - fragTexCoord.x : uv_p.y - fragTexCoord.y;
+    float distance_n = is_horizontal ? fragTexCoord.x - uv_n.x : fragTexCoord.y - uv_n.y;
+
+    // Only the end of the edge closer to the pixel decides, and only when the luma changes the other way than at the pixel.
+    bool closer_p = distance_p <= distance_n;
+    float closest = min(distance_p, distance_n);
+    float delta = closer_p ? delta_p : delta_n;
+
+    float edge_blend = ((m - edge_luma < 0.0) != (delta < 0.0)) ? 0.5 - closest / (distance_p + distance_n) : 0.0;
+    float blend = max(edge_blend, subpixel);
+
+    vec2 uv = fragTexCoord;
+
+    if (is_horizontal) {
+        uv.y += blend * step_length;
+    } else {
+        uv.x += blend * step_length;
+    }
+
+    outColor = vec4(texture(inputImage, uv).rgb, 1.0);
+}
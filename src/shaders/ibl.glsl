@@ -0,0 +1,64 @@
+// Shared by the compute shaders that precompute the image-based lighting, must match `IblConstants` in environment.rs.
+layout(push_constant) uniform Constants {
+    // Of the prefiltered mip being written.
+    float roughness;
+    uint sample_count;
+    // Width of a face of the mip 0 of the environment cube.
+    float source_size;
+    float padding;
+} constants;
+
+const float PI = 3.14159265359;
+
+
+// The world direction through the center of a texel of a cube face, the faces are the layers in the order +X, -X, +Y, -Y, +Z, -Z.
+vec3 cube_direction(uvec3 id, float size) {
+    vec2 uv = (vec2(id.xy) + 0.5) / size * 2.0 - 1.0;
+
+    switch (id.z) {
+        case 0: return normalize(vec3(1.0, -uv.y, -uv.x));
+        case 1: return normalize(vec3(-1.0, -uv.y, uv.x));
+        case 2: return normalize(vec3(uv.x, 1.0, uv.y));
+        case 3: return normalize(vec3(uv.x, -1.0, -uv.y));
+        case 4: return normalize(vec3(uv.x, -uv.y, 1.0));
+        default: return normalize(vec3(-uv.x, -uv.y, -1.0));
+    }
+}
+
+
+// An orthonormal basis around N, for the hemisphere samples.
+mat3 tangent_frame(vec3 N) {
+    vec3 up = abs(N.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, N));
+    vec3 bitangent = cross(N, tangent);
+
+    return mat3(tangent, bitangent, N);
+}
+
+
+// The i-th of n points of a low discrepancy sequence on the unit square.
+vec2 hammersley(uint i, uint n) {
+    uint bits = bitfieldReverse(i);
+
+    return vec2(float(i) / float(n), float(bits) * 2.3283064365386963e-10);
+}
+
+
+// A half vector around N distributed like the GGX distribution of `roughness`.
+vec3 importance_sample_ggx(vec2 xi, vec3 N, float roughness) {
+    float a = roughness * roughness;
+
+    float phi = 2.0 * PI * xi.x;
+    float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+
+    return tangent_frame(N) * vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+}
+
+
+float distribution_ggx(float NdotH, float roughness) {
+    float a2 = roughness * roughness * roughness * roughness;
+    float d = NdotH * NdotH * (a2 - 1.0) + 1.0;
+
+    return a2 / (PI * d * d);
+}
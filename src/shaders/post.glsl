@@ -0,0 +1,19 @@
+// Shared by the full-screen passes of the post-processing chain, must match `PostConstants` in post_processing.rs.
+layout(push_constant) uniform Constants {
+    // The size of a texel of the input image.
+    vec2 texel_size;
+    // What the parameters mean depends on the effect.
+    vec4 params;
+} constants;
+
+// The output of the previous pass.
+layout(binding = 0) uniform sampler2D inputImage;
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+
+float luma(vec3 color) {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
@@ -6,23 +6,244 @@ layout(location=0) in vec3 fragColor;
 layout(location=1) in vec2 texCoord;
 layout(location=2) in vec3 normal;
 layout(location=3) in vec3 pos;
+layout(location=4) in vec4 tint; // rgb tint, a opacity
 
-layout(binding=1) uniform sampler2D texSampler;
+layout(set=0, binding=0) uniform UniformBufferObject {
+    mat4 view;
+    mat4 proj;
+    vec4 eye;
+} ubo;
 
-layout(push_constant) uniform PushConstants {
-    layout(offset=64) vec3 light_dir;
-    layout(offset=76) float opacity;
-} pcs;
+layout(set=1, binding=0) uniform sampler2D baseColorTexture;
 
+layout(set=1, binding=1) uniform MaterialUniform {
+    vec4 base_color; // rgb base color, a opacity
+    vec4 emissive;  // rgb emissive color, a alpha cutoff of masked materials or 0
+    vec4 parameters; // x metallic, y roughness, z normal scale, w occlusion strength
+} material;
 
-void main() {
+layout(set=1, binding=2) uniform sampler2D metallicRoughnessTexture;
+layout(set=1, binding=3) uniform sampler2D normalTexture;
+layout(set=1, binding=4) uniform sampler2D occlusionTexture;
+layout(set=1, binding=5) uniform sampler2D emissiveTexture;
+
+layout(set=0, binding=2) uniform sampler2DArrayShadow shadowMap;
+
+// The image-based lighting of the environment, see environment.rs.
+layout(set=0, binding=3) uniform samplerCube irradianceMap;
+layout(set=0, binding=4) uniform samplerCube prefilteredMap;
+layout(set=0, binding=5) uniform sampler2D brdfLut;
+
+// Must match GpuLight in lights.rs.
+const float LIGHT_DIRECTIONAL = 0;
+const float LIGHT_POINT = 1;
+const float LIGHT_SPOT = 2;
+
+struct Light {
+    vec4 position;  // xyz position, w range
+    vec4 direction; // xyz direction, w type
+    vec4 color;     // rgb color, a intensity
+    vec4 cone;      // x cos inner angle, y cos outer angle
+    vec4 shadow;    // x first shadow map layer or -1, y layer count
+};
+
+// Must match MAX_SHADOW_LAYERS in shadows.rs.
+const int MAX_SHADOW_LAYERS = 8;
+
+layout(std430, set=0, binding=1) readonly buffer Lights {
+    vec4 ambient; // rgb ambient light, a environment intensity
+    uint count;
+    vec4 cascade_splits;
+    mat4 shadow_matrices[MAX_SHADOW_LAYERS];
+    Light lights[];
+} lights;
+
+const float PI = 3.14159265359;
 
+
+// The meshes have no tangents, the tangent frame is derived from the screen space derivatives of the position and texture coordinates instead.
+vec3 perturbed_normal() {
     vec3 N = normalize(normal);
 
-    float diffuse = max(dot(N, pcs.light_dir), 0);
+    vec3 uv_dx = dFdx(vec3(texCoord, 0));
+    vec3 uv_dy = dFdy(vec3(texCoord, 0));
+    vec3 t = (uv_dy.t * dFdx(pos) - uv_dx.t * dFdy(pos)) / (uv_dx.s * uv_dy.t - uv_dy.s * uv_dx.t);
+
+    vec3 map = texture(normalTexture, texCoord).rgb * 2 - 1;
+    map.xy *= material.parameters.z;
+
+    // Without usable texture coordinates there's no frame, and nothing to perturb.
+    if (any(isnan(t)) || any(isinf(t)) || dot(t, t) < 1e-12) {
+        return N;
+    }
+
+    vec3 T = normalize(t - N * dot(N, t));
+    vec3 B = cross(N, T);
+
+    return normalize(mat3(T, B, N) * map);
+}
+
+
+// GGX normal distribution.
+float distribution(float NdotH, float alpha) {
+    float a2 = alpha * alpha;
+    float d = NdotH * NdotH * (a2 - 1) + 1;
+    return a2 / (PI * d * d);
+}
+
+// Smith-Schlick geometry term for both the light and view direction.
+float geometry(float NdotV, float NdotL, float roughness) {
+    float k = (roughness + 1) * (roughness + 1) / 8;
+    return NdotV / (NdotV * (1 - k) + k) * NdotL / (NdotL * (1 - k) + k);
+}
+
+vec3 fresnel(float cos_theta, vec3 F0) {
+    return F0 + (1 - F0) * pow(clamp(1 - cos_theta, 0, 1), 5);
+}
+
+// Fresnel averaged over the microfacets of a rough surface, for the environment light which has no single half vector.
+vec3 fresnel_roughness(float cos_theta, vec3 F0, float roughness) {
+    return F0 + (max(vec3(1 - roughness), F0) - F0) * pow(clamp(1 - cos_theta, 0, 1), 5);
+}
+
+
+// How much of the light reaches the fragment, 0 in full shadow. Averages 3x3 shadow map texels (PCF) to soften the edges.
+float shadow(Light light, vec3 N, vec3 L) {
+    if (light.shadow.x < 0) {
+        return 1;
+    }
+
+    int layer = int(light.shadow.x);
+
+    // Directional lights have a cascade per distance from the camera, nothing beyond the last one is shadowed.
+    if (light.shadow.y > 1) {
+        float depth = -(ubo.view * vec4(pos, 1)).z;
+        int cascade = 0;
+
+        while (cascade < int(light.shadow.y) && depth > lights.cascade_splits[cascade]) {
+            cascade++;
+        }
+
+        if (cascade == int(light.shadow.y)) {
+            return 1;
+        }
+
+        layer += cascade;
+    }
+
+    // Moving the point along the normal, more so at grazing angles, keeps surfaces from shadowing themselves.
+    vec3 offset = N * 0.02 * (1 - max(dot(N, L), 0));
+    vec4 coord = lights.shadow_matrices[layer] * vec4(pos + offset, 1);
+    coord.xyz /= coord.w;
+
+    if (coord.z >= 1) {
+        return 1;
+    }
+
+    vec2 uv = coord.xy * 0.5 + 0.5;
+    vec2 texel = 1.0 / textureSize(shadowMap, 0).xy;
+    float lit = 0;
+
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            lit += texture(shadowMap, vec4(uv + vec2(x, y) * texel, layer, coord.z));
+        }
+    }
+
+    return lit / 9;
+}
+
+
+// Radiance arriving from the light and the direction towards it.
+vec3 incoming(Light light, out vec3 L) {
+    float attenuation = 1;
+
+    if (light.direction.w == LIGHT_DIRECTIONAL) {
+        L = -light.direction.xyz;
+    } else {
+        vec3 to_light = light.position.xyz - pos;
+        float d = length(to_light);
+        L = to_light / max(d, 0.0001);
+
+        // Inverse square falloff, windowed to reach zero at the range.
+        float window = clamp(1 - pow(d / light.position.w, 4), 0, 1);
+        attenuation = window * window / (d * d + 1);
+
+        if (light.direction.w == LIGHT_SPOT) {
+            attenuation *= smoothstep(light.cone.y, light.cone.x, dot(-L, light.direction.xyz));
+        }
+    }
+
+    return light.color.rgb * light.color.a * attenuation;
+}
+
+
+void main() {
+
+    vec4 base = texture(baseColorTexture, texCoord) * material.base_color;
+
+    // Masked materials are cut out below the cutoff, before the opacity of the instance. Their opacity is sharpened to about a pixel around it,
+    // which alpha-to-coverage under MSAA turns into smooth edges. Without MSAA only the discard matters.
+    float cutoff = material.emissive.a;
+    if (cutoff > 0) {
+        base.a = (base.a - cutoff) / max(fwidth(base.a), 1e-4) + 0.5;
+
+        if (base.a <= 0) {
+            discard;
+        }
+
+        base.a = clamp(base.a, 0, 1);
+    }
+
+    base *= tint;
+
+    vec4 metallic_roughness = texture(metallicRoughnessTexture, texCoord);
+    float metallic = clamp(material.parameters.x * metallic_roughness.b, 0, 1);
+    float roughness = clamp(material.parameters.y * metallic_roughness.g, 0.04, 1);
+    float occlusion = 1 + material.parameters.w * (texture(occlusionTexture, texCoord).r - 1);
+
+    vec3 N = perturbed_normal();
+    vec3 V = normalize(ubo.eye.xyz - pos);
+
+    float NdotV = max(dot(N, V), 1e-4);
+    vec3 F0 = mix(vec3(0.04), base.rgb, metallic);
+
+    vec3 color = lights.ambient.rgb * base.rgb * occlusion;
+
+    // Diffuse from the irradiance map and specular from the split-sum approximation, the prefiltered mips go from smooth to rough.
+    if (lights.ambient.a > 0) {
+        vec3 F = fresnel_roughness(NdotV, F0, roughness);
+        vec3 diffuse = (1 - F) * (1 - metallic) * base.rgb * texture(irradianceMap, N).rgb;
+
+        float lod = roughness * float(textureQueryLevels(prefilteredMap) - 1);
+        vec3 prefiltered = textureLod(prefilteredMap, reflect(-V, N), lod).rgb;
+        vec2 brdf = texture(brdfLut, vec2(NdotV, roughness)).rg;
+        vec3 specular = prefiltered * (F0 * brdf.x + brdf.y);
+
+        color += (diffuse + specular) * occlusion * lights.ambient.a;
+    }
+
+    for (uint i = 0; i < lights.count; i++) {
+        vec3 L;
+        vec3 radiance = incoming(lights.lights[i], L);
+
+        float NdotL = dot(N, L);
+        if (NdotL <= 0) {
+            continue;
+        }
+
+        radiance *= shadow(lights.lights[i], N, L);
+
+        vec3 H = normalize(L + V);
+        vec3 F = fresnel(max(dot(H, V), 0), F0);
+
+        vec3 specular = distribution(max(dot(N, H), 0), roughness * roughness) * geometry(NdotV, NdotL, roughness) * F / (4 * NdotV * NdotL);
+        vec3 diffuse = (1 - F) * (1 - metallic) * base.rgb / PI;
+
+        color += (diffuse + specular) * radiance * NdotL;
+    }
 
-    float ambientStrength = 0.1;
-    vec4 textureColor = texture(texSampler, texCoord);
+    color += material.emissive.rgb * texture(emissiveTexture, texCoord).rgb;
 
-    outColor = vec4(textureColor.rgb  * (diffuse + 0.02), 1);
-}
\ No newline at end of file
+    outColor = vec4(color, base.a);
+}
@@ -4,29 +4,32 @@
 layout(binding=0) uniform UniformBufferObject {
     mat4 view;
     mat4 proj;
+    vec4 eye;
 } ubo;
 
-layout(push_constant) uniform PushConstants {
-    mat4 model;
-} pcs;
-
 layout(location=0) in vec3 inPos;
 layout(location=1) in vec3 inColor;
 layout(location=2) in vec2 texCoord;
 layout(location=3) in vec3 normal;
 
+// Per instance, see InstanceData in instancing.rs.
+layout(location=4) in mat4 model;
+layout(location=8) in vec4 tint;
+
 
 layout(location=0) out vec3 fragColor;
 layout(location=1) out vec2 fragTexCoord;
 layout(location=2) out vec3 fragNormal;
 layout(location=3) out vec3 fragPos;
+layout(location=4) out vec4 fragTint;
 
 
 void main() {
-    gl_Position = ubo.proj * ubo.view * pcs.model * vec4(inPos, 1.0);
+    gl_Position = ubo.proj * ubo.view * model * vec4(inPos, 1.0);
     fragColor = inColor;
     fragTexCoord = texCoord;
-    fragNormal = normalize(mat3(pcs.model) * normal);
-    fragPos = vec3(pcs.model * vec4(inPos, 1.0));
+    fragNormal = normalize(mat3(model) * normal);
+    fragPos = vec3(model * vec4(inPos, 1.0));
+    fragTint = tint;
 
 }
\ No newline at end of file
@@ -0,0 +1,14 @@
+#version 450
+
+
+layout(push_constant) uniform PushConstants {
+    mat4 light;
+} pcs;
+
+layout(location=0) in vec3 inPos;
+layout(location=4) in mat4 model;
+
+
+void main() {
+    gl_Position = pcs.light * model * vec4(inPos, 1.0);
+}
@@ -0,0 +1,21 @@
+#version 450
+
+// The material set of the main pass, see shader.frag, only its base color alpha matters here.
+layout(set=0, binding=0) uniform sampler2D baseColorTexture;
+
+layout(set=0, binding=1) uniform MaterialUniform {
+    vec4 base_color; // rgb base color, a opacity
+    vec4 emissive;  // rgb emissive color, a alpha cutoff
+    vec4 parameters;
+} material;
+
+layout(location=0) in vec2 texCoord;
+
+
+void main() {
+    float alpha = texture(baseColorTexture, texCoord).a * material.base_color.a;
+
+    if (alpha < material.emissive.a) {
+        discard;
+    }
+}
@@ -0,0 +1,31 @@
+#version 450
+
+layout(location = 0) in vec2 ndc;
+
+layout(location = 0) out vec4 outColor;
+
+layout(set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 view;
+    mat4 proj;
+    vec4 eye;
+} ubo;
+
+// Only the start of the light buffer, the environment intensity is the w of the ambient light.
+layout(std430, set = 0, binding = 1) readonly buffer Lights {
+    vec4 ambient;
+} lights;
+
+layout(set = 0, binding = 6) uniform samplerCube environment;
+
+
+// The view direction of the pixel comes from unprojecting two depths, which works for any projection, reverse-Z and infinite ones included.
+void main() {
+    mat4 inverse_view_proj = inverse(ubo.proj * mat4(mat3(ubo.view)));
+
+    vec4 near = inverse_view_proj * vec4(ndc, 0.25, 1.0);
+    vec4 far = inverse_view_proj * vec4(ndc, 0.75, 1.0);
+
+    vec3 direction = normalize(far.xyz / far.w - near.xyz / near.w);
+
+    outColor = vec4(textureLod(environment, direction, 0.0).rgb * lights.ambient.w, 1.0);
+}
@@ -0,0 +1,12 @@
+#version 450
+
+// Whether the depth buffer is cleared to 0 and compared with GREATER, must match `AppData::reverse_z`.
+layout(constant_id = 0) const bool REVERSE_Z = false;
+
+// A triangle that covers the screen at the far plane, drawn without vertex buffers after the opaque geometry.
+layout(location = 0) out vec2 ndc;
+
+void main() {
+    ndc = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2) * 2.0 - 1.0;
+    gl_Position = vec4(ndc, REVERSE_Z ? 0.0 : 1.0, 1.0);
+}
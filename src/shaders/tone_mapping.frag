@@ -0,0 +1,47 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#define EXPOSURE_ACCESS readonly
+#include "tone_mapping.glsl"
+
+layout(binding = 0) uniform sampler2D hdrImage;
+
+layout(location = 0) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+
+// Krzysztof Narkowicz's fit of the ACES reference rendering and output transforms.
+vec3 aces(vec3 x) {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+vec3 reinhard(vec3 x) {
+    return x / (1.0 + x);
+}
+
+// John Hable's Uncharted 2 curve, with white at 11.2.
+vec3 hable(vec3 x) {
+    const float A = 0.15, B = 0.50, C = 0.10, D = 0.20, E = 0.02, F = 0.30;
+    return ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F;
+}
+
+vec3 filmic(vec3 x) {
+    return hable(2.0 * x) / hable(vec3(11.2));
+}
+
+
+void main() {
+    vec3 color = texture(hdrImage, fragTexCoord).rgb;
+
+    color *= constants.automatic != 0 ? state.exposure : constants.exposure;
+
+    switch (constants.operator) {
+        case 1: color = reinhard(color); break;
+        case 2: color = filmic(color); break;
+        default: color = aces(color); break;
+    }
+
+    // The swapchain image is sRGB, the hardware encodes the linear color.
+    outColor = vec4(color, 1.0);
+}
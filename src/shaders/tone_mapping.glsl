@@ -0,0 +1,33 @@
+// Shared by tone_mapping.frag, histogram.comp and exposure.comp.
+
+// Must match HISTOGRAM_BINS in tone_mapping.rs.
+#define HISTOGRAM_BINS 256
+
+// Must match ToneMappingConstants in tone_mapping.rs.
+layout(push_constant) uniform Constants {
+    uint operator; // 0 ACES, 1 Reinhard, 2 filmic
+    uint automatic;
+    // The manual exposure, or the exposure compensation when automatic.
+    float exposure;
+    float min_log_luminance;
+    float log_luminance_range;
+    // How far the exposure moves towards the average luminance of this frame.
+    float adaptation;
+    uint pixel_count;
+} constants;
+
+// Fragment shaders can only read storage buffers.
+#ifndef EXPOSURE_ACCESS
+#define EXPOSURE_ACCESS
+#endif
+
+// Carries the exposure from one frame to the next, the bins are cleared once they are averaged.
+layout(std430, binding = 1) EXPOSURE_ACCESS buffer Exposure {
+    uint bins[HISTOGRAM_BINS];
+    float average_luminance;
+    float exposure;
+} state;
+
+float luminance(vec3 color) {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
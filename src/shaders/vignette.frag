@@ -0,0 +1,20 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "post.glsl"
+
+// params: x how dark the corners get, y where the darkening starts, as a distance from the center, z how gradual it is.
+
+
+void main() {
+    vec3 color = texture(inputImage, fragTexCoord).rgb;
+
+    // 0 at the center and 1 at the corners, round on screens that aren't square.
+    float aspect = constants.texel_size.y / constants.texel_size.x;
+    vec2 offset = (fragTexCoord - 0.5) * vec2(aspect, 1.0);
+    float distance = length(offset) / length(vec2(aspect, 1.0) * 0.5);
+
+    float darkening = smoothstep(constants.params.y, constants.params.y + max(constants.params.z, 1e-4), distance);
+
+    outColor = vec4(color * (1.0 - darkening * constants.params.x), 1.0);
+}
@@ -0,0 +1,545 @@
+use vulkanalia::prelude::v1_0::*;
+use nalgebra_glm as glm;
+use anyhow::Result;
+use log::*;
+
+use crate::app::AppData;
+use crate::buffers::{begin_single_time_commands, end_single_time_commands};
+use crate::camera::Camera;
+use crate::images::{create_image, create_image_view, get_supported_format};
+use crate::lights::{Light, LightKind, MAX_LIGHTS};
+use crate::pipeline::create_shader_module;
+use crate::instancing::InstanceData;
+use crate::indirect::{DrawLists, cmd_bind_geometry, cmd_draw_indirect};
+use crate::render_graph::{Access, BufferId, ImageId, ImportedImage, RenderGraph};
+use crate::resources::{Pipeline, PipelineLayout, RenderPass, Sampler};
+use crate::vertex::Vertex;
+
+
+
+/// Width and height of every layer of the shadow map.
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+
+/// Layers of the shadow map, a directional light takes `CASCADE_COUNT` of them and a spot light one.
+pub const MAX_SHADOW_LAYERS: usize = 8;
+
+/// Cascades of a directional light's shadow, each covers a farther and larger part of the view.
+pub const CASCADE_COUNT: usize = 4;
+
+/// Shadows end at this distance from the camera, or at its far plane when that is closer.
+const MAX_SHADOW_DISTANCE: f32 = 50.0;
+
+/// Blend between uniform (0) and logarithmic (1) cascade splits, logarithmic gives close cascades more resolution.
+const CASCADE_SPLIT_LAMBDA: f32 = 0.75;
+
+/// How far behind a cascade, towards the light, objects still cast shadows into it.
+const CASTER_DISTANCE: f32 = 50.0;
+
+/// Spot light shadows start this far from the light.
+const SPOT_NEAR: f32 = 0.05;
+
+
+
+/// Which lights cast shadows this frame and the shadow map layers they render to.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShadowLayout {
+    /// Per light, the first layer and the number of layers, `None` for lights without shadows.
+    pub light_layers: Vec<Option<(u32, u32)>>,
+    /// World to light clip space of every used layer.
+    pub matrices: Vec<glm::Mat4>,
+    /// Distance along the view direction where each cascade ends.
+    pub cascade_splits: [f32; CASCADE_COUNT]
+}
+
+
+/// Assigns shadow map layers to the shadow casting lights, in order, until the layers run out.
+pub fn shadow_layout(lights: &[Light], camera: &Camera, aspect_ratio: f32) -> ShadowLayout {
+    let (near, far) = camera.projection.clip_distances();
+    let cascade_splits = cascade_splits(near, far.min(MAX_SHADOW_DISTANCE));
+
+    let mut layout = ShadowLayout { cascade_splits, ..Default::default() };
+
+    for light in lights.iter().take(MAX_LIGHTS) {
+        let matrices = match light.kind {
+            _ if !light.cast_shadows => vec![],
+            LightKind::Directional { direction } => (0..CASCADE_COUNT)
+                .map(|c| {
+                    let start = if c == 0 { near } else { cascade_splits[c - 1] };
+                    directional_matrix(direction, &camera.frustum_corners(aspect_ratio, start, cascade_splits[c]))
+                })
+                .collect(),
+            LightKind::Spot { position, direction, range, outer_angle, .. } => vec![spot_matrix(position, direction, range, outer_angle)],
+            LightKind::Point { .. } => vec![]
+        };
+
+        if matrices.is_empty() || layout.matrices.len() + matrices.len() > MAX_SHADOW_LAYERS {
+            if !matrices.is_empty() {
+                debug!("Out of shadow map layers, a light doesn't cast shadows");
+            }
+
+            layout.light_layers.push(None);
+            continue;
+        }
+
+        layout.light_layers.push(Some((layout.matrices.len() as u32, matrices.len() as u32)));
+        layout.matrices.extend(matrices);
+    }
+
+    return layout;
+}
+
+
+/// Where each cascade ends, between `near` and `far`.
+fn cascade_splits(near: f32, far: f32) -> [f32; CASCADE_COUNT] {
+    let mut splits = [far; CASCADE_COUNT];
+
+    for (i, split) in splits.iter_mut().enumerate() {
+        let p = (i + 1) as f32 / CASCADE_COUNT as f32;
+        let logarithmic = near * (far / near).powf(p);
+        let uniform = near + (far - near) * p;
+
+        *split = CASCADE_SPLIT_LAMBDA * logarithmic + (1.0 - CASCADE_SPLIT_LAMBDA) * uniform;
+    }
+
+    return splits;
+}
+
+
+/// An orthographic projection along `direction` that covers the bounding sphere of `corners`.
+/// The sphere keeps the size constant while the camera turns, and snapping to texels keeps the edges from crawling while it moves.
+fn directional_matrix(direction: glm::Vec3, corners: &[glm::Vec3; 8]) -> glm::Mat4 {
+    let direction = glm::normalize(&direction);
+    let center = corners.iter().sum::<glm::Vec3>() / 8.0;
+    let radius = corners.iter().map(|c| glm::distance(c, &center)).fold(0.0, f32::max);
+    let radius = (radius * 16.0).ceil() / 16.0;
+
+    let eye = center - direction * (radius + CASTER_DISTANCE);
+    let view = glm::look_at(&eye, &center, &up_for(direction));
+    let mut proj = glm::ortho_rh_zo(-radius, radius, -radius, radius, 0.0, 2.0 * radius + CASTER_DISTANCE);
+
+    let origin = (proj * view * glm::vec4(0.0, 0.0, 0.0, 1.0)).xy() * (SHADOW_MAP_SIZE as f32 * 0.5);
+    let offset = (origin.map(f32::round) - origin) * (2.0 / SHADOW_MAP_SIZE as f32);
+    proj[(0, 3)] += offset.x;
+    proj[(1, 3)] += offset.y;
+
+    return proj * view;
+}
+
+
+fn spot_matrix(position: glm::Vec3, direction: glm::Vec3, range: f32, outer_angle: f32) -> glm::Mat4 {
+    let direction = glm::normalize(&direction);
+    let fov = (2.0 * outer_angle).clamp(0.01, std::f32::consts::PI - 0.01);
+
+    let view = glm::look_at(&position, &(position + direction), &up_for(direction));
+    let proj = glm::perspective_rh_zo(1.0, fov, SPOT_NEAR, range.max(SPOT_NEAR * 2.0));
+
+    return proj * view;
+}
+
+
+/// Z, unless that is (almost) the direction itself.
+fn up_for(direction: glm::Vec3) -> glm::Vec3 {
+    if direction.z.abs() > 0.99 { glm::vec3(0.0, 1.0, 0.0) } else { glm::vec3(0.0, 0.0, 1.0) }
+}
+
+
+
+/// The shadow map with a view per layer, the depth only pipeline that renders into it and the comparison sampler
+/// the fragment shader reads it with. None of it depends on the swapchain, so it is created once.
+pub unsafe fn create_shadow_resources(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+
+    let format = get_supported_format(
+        instance,
+        data,
+        &[vk::Format::D32_SFLOAT, vk::Format::D16_UNORM],
+        vk::ImageTiling::OPTIMAL,
+        vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT | vk::FormatFeatureFlags::SAMPLED_IMAGE)?;
+
+    data.shadow_image = create_image(
+        instance,
+        device,
+        data,
+        SHADOW_MAP_SIZE,
+        SHADOW_MAP_SIZE,
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        format,
+        1,
+        MAX_SHADOW_LAYERS as u32,
+        vk::SampleCountFlags::_1,
+        vk::ImageCreateFlags::empty())?;
+
+    let subresource = |base_array_layer, layer_count| vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::DEPTH)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(base_array_layer)
+        .layer_count(layer_count)
+        .build();
+
+    data.shadow_image_view = create_image_view(&data.shadow_image, device, data, format, subresource(0, MAX_SHADOW_LAYERS as u32), vk::ImageViewType::_2D_ARRAY)?;
+
+    data.shadow_layer_views = (0..MAX_SHADOW_LAYERS as u32)
+        .map(|layer| create_image_view(&data.shadow_image, device, data, format, subresource(layer, 1), vk::ImageViewType::_2D))
+        .collect::<Result<Vec<_>>>()?;
+
+
+    // Layers no light renders to are still sampled, they must be in the layout the fragment shader expects.
+    let barrier = vk::ImageMemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::empty())
+        .dst_access_mask(vk::AccessFlags::SHADER_READ)
+        .old_layout(vk::ImageLayout::UNDEFINED)
+        .new_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(*data.shadow_image)
+        .subresource_range(subresource(0, MAX_SHADOW_LAYERS as u32));
+
+    let command_buffer = begin_single_time_commands(device, data)?;
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TOP_OF_PIPE,
+        vk::PipelineStageFlags::FRAGMENT_SHADER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[barrier]);
+
+    end_single_time_commands(device, data, command_buffer)?;
+
+
+    data.shadow_render_pass = create_shadow_render_pass(device, data, format)?;
+
+    create_shadow_pipeline(device, data)?;
+
+
+    // Comparing in the sampler gives 1 where the fragment is lit and 0 where it is in shadow, outside the map is lit.
+    let sampler_info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::NEAREST)
+        .min_filter(vk::Filter::NEAREST)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+        .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
+        .compare_enable(true)
+        .compare_op(vk::CompareOp::LESS_OR_EQUAL)
+        .min_lod(0.0)
+        .max_lod(0.0);
+
+    data.shadow_sampler = Sampler::new(device.create_sampler(&sampler_info, None)?, &data.deletion_queue);
+
+    info!("Created a {}x{} shadow map with {} layers", SHADOW_MAP_SIZE, SHADOW_MAP_SIZE, MAX_SHADOW_LAYERS);
+
+    return Ok(());
+}
+
+
+unsafe fn create_shadow_render_pass(device: &Device, data: &AppData, format: vk::Format) -> Result<RenderPass> {
+
+    let depth_attachment = vk::AttachmentDescription::builder()
+        .format(format)
+        .samples(vk::SampleCountFlags::_1)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+        .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+    let depth_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(0)
+        .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+    let subpass = vk::SubpassDescription::builder()
+        .depth_stencil_attachment(&depth_attachment_ref)
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+
+
+    let attachments = &[depth_attachment];
+    let subpasses = &[subpass];
+    let info = vk::RenderPassCreateInfo::builder()
+        .attachments(attachments)
+        .subpasses(subpasses);
+
+    return Ok(RenderPass::new(device.create_render_pass(&info, None)?, &data.deletion_queue));
+}
+
+
+/// Renders positions only, with the model matrices of the instances and the light matrix as a push constant.
+/// The cutout pipeline also discards what the alpha cutoff of a masked material cuts away, with the material's descriptor set as set 0.
+unsafe fn create_shadow_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+
+    let binding_descriptions = [Vertex::binding_description(), InstanceData::binding_description()];
+    let vertex_attributes = Vertex::attribute_description();
+    let instance_attributes = InstanceData::attribute_description();
+    let attribute_descriptions = [vertex_attributes[0], instance_attributes[0], instance_attributes[1], instance_attributes[2], instance_attributes[3]];
+    let cutout_attribute_descriptions = [vertex_attributes[0], vertex_attributes[2], instance_attributes[0], instance_attributes[1], instance_attributes[2], instance_attributes[3]];
+
+    let vertex_input_stage = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_binding_descriptions(&binding_descriptions)
+        .vertex_attribute_descriptions(&attribute_descriptions);
+
+    let cutout_vertex_input_stage = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_binding_descriptions(&binding_descriptions)
+        .vertex_attribute_descriptions(&cutout_attribute_descriptions);
+
+    let input_assembly_stage = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    let vertex_shader_module = create_shader_module(device, include_bytes!("shaders/shadow.spv"))?;
+
+    let cutout_vertex_shader_module = create_shader_module(device, include_bytes!("shaders/shadow_cutout_vert.spv"))?;
+    let cutout_fragment_shader_module = create_shader_module(device, include_bytes!("shaders/shadow_cutout_frag.spv"))?;
+
+    let vertex_stage_info = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vertex_shader_module)
+        .name(b"main\0");
+
+    let cutout_vertex_stage_info = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(cutout_vertex_shader_module)
+        .name(b"main\0");
+
+    let cutout_fragment_stage_info = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(cutout_fragment_shader_module)
+        .name(b"main\0");
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(SHADOW_MAP_SIZE as f32)
+        .height(SHADOW_MAP_SIZE as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(vk::Extent2D { width: SHADOW_MAP_SIZE, height: SHADOW_MAP_SIZE });
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    // The bias pushes the stored depth away from the light, so that surfaces don't shadow themselves (shadow acne).
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(true)
+        .depth_bias_constant_factor(1.25)
+        .depth_bias_slope_factor(1.75)
+        .line_width(1.0);
+
+    let multi_sample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(vk::SampleCountFlags::_1);
+
+    let depth_stencil_stage = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(true)
+        .depth_compare_op(vk::CompareOp::LESS)
+        .stencil_test_enable(false);
+
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false);
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::VERTEX)
+        .offset(0)
+        .size(64);
+
+    let push_constant_ranges = &[push_constant_range];
+    let set_layouts = &[*data.material_descriptor_set_layout];
+
+    let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.shadow_pipeline_layout = PipelineLayout::new(device.create_pipeline_layout(&pipeline_layout_info, None)?, &data.deletion_queue);
+
+    let stages = &[vertex_stage_info];
+
+    let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_stage)
+        .input_assembly_state(&input_assembly_stage)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multi_sample_state)
+        .color_blend_state(&color_blend_state)
+        .depth_stencil_state(&depth_stencil_stage)
+        .layout(*data.shadow_pipeline_layout)
+        .render_pass(*data.shadow_render_pass)
+        .subpass(0);
+
+    let cutout_stages = &[cutout_vertex_stage_info, cutout_fragment_stage_info];
+
+    let cutout_pipeline_info = pipeline_info
+        .stages(cutout_stages)
+        .vertex_input_state(&cutout_vertex_input_stage);
+
+    let pipeline = device.create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)?.0;
+    data.shadow_pipeline = Pipeline::new(pipeline, &data.deletion_queue);
+
+    let cutout_pipeline = device.create_graphics_pipelines(vk::PipelineCache::null(), &[cutout_pipeline_info], None)?.0;
+    data.shadow_cutout_pipeline = Pipeline::new(cutout_pipeline, &data.deletion_queue);
+
+    device.destroy_shader_module(vertex_shader_module, None);
+    device.destroy_shader_module(cutout_vertex_shader_module, None);
+    device.destroy_shader_module(cutout_fragment_shader_module, None);
+
+    return Ok(());
+}
+
+
+
+/// Adds a depth pass per used layer of `layout` to `graph`, which draw `draws` from the indirect buffer of the swapchain image,
+/// and returns the layers for the passes that sample them. `geometry` are the instance and indirect buffers, `None` when nothing is drawn.
+pub(crate) unsafe fn add_shadow_passes<'a>(
+    graph: &mut RenderGraph<'a>,
+    data: &AppData,
+    image_index: usize,
+    layout: &ShadowLayout,
+    draws: &'a DrawLists,
+    geometry: Option<(BufferId, BufferId)>
+) -> Vec<ImageId> {
+
+    let clear_values = &[vk::ClearValue {
+        depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 }
+    }];
+
+    let extent = vk::Extent2D { width: SHADOW_MAP_SIZE, height: SHADOW_MAP_SIZE };
+
+    let mut layers = vec![];
+
+    for (layer, matrix) in layout.matrices.iter().enumerate() {
+        let subresource = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::DEPTH)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(layer as u32)
+            .layer_count(1)
+            .build();
+
+        // Between frames every layer is ready to be sampled, like after the transition in `create_shadow_resources`.
+        let image = graph.import_image("shadow layer", ImportedImage {
+            image: *data.shadow_image,
+            view: *data.shadow_layer_views[layer],
+            subresource,
+            initial: Some(Access::DepthSampled),
+            last: Some(Access::DepthSampled)
+        });
+
+        let pass = graph.add_pass("shadow")
+            .render_pass(*data.shadow_render_pass, extent, &[(image, Access::DepthAttachment)], clear_values);
+
+        if let Some((instances, commands)) = geometry {
+            pass.buffer(instances, Access::VertexBuffer).buffer(commands, Access::IndirectBuffer);
+        }
+
+        let matrix = *matrix;
+
+        pass.record(move |device, data, _, command_buffer| {
+            if draws.is_empty() {
+                return;
+            }
+
+            let (_, matrix_bytes, _) = matrix.as_slice().align_to::<u8>();
+            device.cmd_push_constants(command_buffer, *data.shadow_pipeline_layout, vk::ShaderStageFlags::VERTEX, 0, matrix_bytes);
+
+            cmd_bind_geometry(device, data, command_buffer, image_index);
+
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, *data.shadow_pipeline);
+
+            for draw in &draws.opaque {
+                cmd_draw_indirect(device, data, command_buffer, image_index, draw);
+            }
+
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, *data.shadow_cutout_pipeline);
+
+            for draw in &draws.cutout {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    *data.shadow_pipeline_layout,
+                    0,
+                    &[data.material_descriptor_sets[draw.material.unwrap()]],
+                    &[]);
+
+                cmd_draw_indirect(device, data, command_buffer, image_index, draw);
+            }
+        });
+
+        layers.push(image);
+    }
+
+    return layers;
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn light_space(matrix: &glm::Mat4, point: glm::Vec3) -> glm::Vec3 {
+        let clip = matrix * point.push(1.0);
+        clip.xyz() / clip.w
+    }
+
+
+    #[test]
+    fn cascades_split_the_shadow_distance() {
+        let splits = cascade_splits(0.1, 50.0);
+
+        assert_eq!(splits[CASCADE_COUNT - 1], 50.0);
+        assert!(splits.windows(2).all(|w| w[0] < w[1]));
+        // Closer cascades are smaller than with uniform splits.
+        assert!(splits[0] < 50.0 / CASCADE_COUNT as f32);
+    }
+
+    #[test]
+    fn cascades_cover_their_part_of_the_view() {
+        let camera = Camera::default();
+        let corners = camera.frustum_corners(1.5, 1.0, 4.0);
+        let matrix = directional_matrix(glm::vec3(-1.0, 3.0, -2.0), &corners);
+
+        for corner in corners {
+            let p = light_space(&matrix, corner);
+            assert!(p.x.abs() <= 1.0 && p.y.abs() <= 1.0 && p.z > 0.0 && p.z < 1.0, "{:?} is outside", p);
+        }
+    }
+
+    #[test]
+    fn spot_shadows_look_along_the_light() {
+        let matrix = spot_matrix(glm::vec3(0.0, 0.0, 5.0), glm::vec3(0.0, 0.0, -1.0), 10.0, 0.5);
+
+        let below = light_space(&matrix, glm::vec3(0.0, 0.0, 0.0));
+        assert!(below.x.abs() < 1e-5 && below.y.abs() < 1e-5 && below.z > 0.0 && below.z < 1.0);
+
+        let beyond_range = light_space(&matrix, glm::vec3(0.0, 0.0, -6.0));
+        assert!(beyond_range.z > 1.0);
+    }
+
+    #[test]
+    fn layers_go_to_shadow_casters_until_they_run_out() {
+        let sun = Light::default().with_shadows();
+        let spot = Light::spot(glm::vec3(0.0, 0.0, 3.0), glm::vec3(0.0, 0.0, -1.0), 10.0, 0.3, 0.5).with_shadows();
+        let lights = [sun, Light::point(glm::Vec3::zeros(), 5.0), spot, sun, spot];
+
+        let layout = shadow_layout(&lights, &Camera::default(), 1.0);
+
+        let cascades = CASCADE_COUNT as u32;
+        assert_eq!(layout.light_layers, vec![Some((0, cascades)), None, Some((cascades, 1)), None, Some((cascades + 1, 1))]);
+        assert_eq!(layout.matrices.len(), CASCADE_COUNT + 2);
+    }
+}
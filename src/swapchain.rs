@@ -1,9 +1,9 @@
 use vulkanalia::{prelude::v1_0::{*, vk::KhrSurfaceExtension}, vk::{KhrSwapchainExtension}};
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use log::*;
 use winit::window::Window;
 
-use crate::{app::AppData, images::create_image_view};
+use crate::{app::AppData, images::create_image_view, resources::ImageView};
 use crate::device::QueueFamilyIndices;
 
 
@@ -36,11 +36,15 @@ pub unsafe fn create_swapchain(instance: &Instance, data: &mut AppData, device:
     let present_modes = instance.get_physical_device_surface_present_modes_khr(data.physical_device, data.surface)?;
 
     
-    let present_mode = *present_modes.iter()
-    .find(|p| **p == vk::PresentModeKHR::MAILBOX)
-    .unwrap_or_else(|| {
-        debug!("Mailbox isn't supported, so using FIFO");
-        &vk::PresentModeKHR::FIFO});
+    let present_mode = match data.config.present_mode {
+        Some(mode) if present_modes.contains(&mode) => mode,
+        Some(mode) => return Err(anyhow!("Present mode {:?} isn't supported, the supported modes are {:?}.", mode, present_modes)),
+        None => *present_modes.iter()
+            .find(|p| **p == vk::PresentModeKHR::MAILBOX)
+            .unwrap_or_else(|| {
+                debug!("Mailbox isn't supported, so using FIFO");
+                &vk::PresentModeKHR::FIFO})
+    };
 
 
     let info = vk::SwapchainCreateInfoKHR::builder()
@@ -127,11 +131,11 @@ pub unsafe fn create_swapchain_image_views(data: &mut AppData, device: &Device)
         .base_array_layer(0)
         .layer_count(1).build();
 
-    let mut image_views: Vec<vk::ImageView> = vec![];
+    let mut image_views: Vec<ImageView> = vec![];
 
 
     for image in &data.swapchain_images {
-        image_views.push(create_image_view(image, device, data.swapchain_image_format, subresource)?);
+        image_views.push(create_image_view(image, device, data, data.swapchain_image_format, subresource, vk::ImageViewType::_2D)?);
         debug!("Created swapchain image view");
     }
 
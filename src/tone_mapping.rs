@@ -0,0 +1,371 @@
+use vulkanalia::prelude::v1_0::*;
+use anyhow::Result;
+use log::*;
+use std::mem::size_of;
+
+use crate::app::AppData;
+use crate::buffers::{create_buffer, begin_single_time_commands, end_single_time_commands};
+use crate::descriptors::update_tone_mapping_descriptor_set;
+use crate::pipeline::{create_compute_pipeline, create_fullscreen_pipeline, cmd_set_viewport};
+use crate::render_graph::{Access, BufferId, ImageId, RenderGraph};
+use crate::resources::{PipelineLayout, RenderPass, Sampler};
+
+
+
+/// The main pass renders into an image of this format, the tone mapping pass maps it to the swapchain image.
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// Bins of the luminance histogram the automatic exposure averages, must match tone_mapping.glsl.
+const HISTOGRAM_BINS: usize = 256;
+
+/// Width and height of the pixels a histogram workgroup counts, must match histogram.comp.
+const HISTOGRAM_TILE: u32 = 16;
+
+
+
+/// The curve that maps the HDR colors of the scene into the range of the display.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ToneMapOperator {
+    /// A fit of the ACES filmic curve, with a slight contrast boost and desaturated highlights.
+    #[default]
+    Aces,
+    /// `c / (1 + c)`, which keeps the colors but never quite reaches white.
+    Reinhard,
+    /// John Hable's Uncharted 2 curve.
+    Filmic
+}
+
+
+impl ToneMapOperator {
+    /// ACES, then Reinhard, then filmic.
+    pub fn next(self) -> Self {
+        match self {
+            ToneMapOperator::Aces => ToneMapOperator::Reinhard,
+            ToneMapOperator::Reinhard => ToneMapOperator::Filmic,
+            ToneMapOperator::Filmic => ToneMapOperator::Aces
+        }
+    }
+}
+
+
+/// What the HDR colors are multiplied with before they are tone mapped.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Exposure {
+    /// Scales the colors by 2^`stops`.
+    Manual { stops: f32 },
+    /// Maps the average luminance of the frame to middle gray, from a histogram of its log luminance.
+    /// Luminance outside of `min_luminance..max_luminance` is clamped, which also bounds the exposure.
+    Automatic {
+        /// Stops added on top of the automatic exposure.
+        compensation: f32,
+        min_luminance: f32,
+        max_luminance: f32,
+        /// How fast the exposure follows a change in brightness, the larger the faster. About a third of the way is left after 1 / `speed` seconds.
+        speed: f32
+    }
+}
+
+
+impl Exposure {
+    /// Automatic exposure for scenes lit from a thousandth to a few thousand.
+    pub fn automatic() -> Self {
+        Exposure::Automatic { compensation: 0.0, min_luminance: 1.0 / 1024.0, max_luminance: 4096.0, speed: 1.5 }
+    }
+}
+
+
+impl Default for Exposure {
+    fn default() -> Self {
+        Exposure::Manual { stops: 0.0 }
+    }
+}
+
+
+/// How the renderer maps the HDR scene to the swapchain image, see `Renderer::set_tone_mapping`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    pub exposure: Exposure
+}
+
+
+
+/// The push constants of the tone mapping pipelines, must match tone_mapping.glsl.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct ToneMappingConstants {
+    operator: u32,
+    automatic: u32,
+    /// The manual exposure, or the exposure compensation when automatic.
+    exposure: f32,
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    /// How far the exposure moves towards the average luminance of this frame.
+    adaptation: f32,
+    pixel_count: u32,
+    _padding: u32
+}
+
+
+impl ToneMappingConstants {
+    /// For a frame of `extent`, `delta_time` seconds after the previous one.
+    fn new(tone_mapping: &ToneMapping, extent: vk::Extent2D, delta_time: f32) -> Self {
+        let operator = match tone_mapping.operator {
+            ToneMapOperator::Aces => 0,
+            ToneMapOperator::Reinhard => 1,
+            ToneMapOperator::Filmic => 2
+        };
+
+        let pixel_count = extent.width * extent.height;
+
+        match tone_mapping.exposure {
+            Exposure::Manual { stops } => Self { operator, exposure: stops.exp2(), pixel_count, ..Default::default() },
+            Exposure::Automatic { compensation, min_luminance, max_luminance, speed } => Self {
+                operator,
+                automatic: 1,
+                exposure: compensation.exp2(),
+                min_log_luminance: min_luminance.log2(),
+                log_luminance_range: (max_luminance.log2() - min_luminance.log2()).max(1e-3),
+                adaptation: 1.0 - (-delta_time * speed).exp(),
+                pixel_count,
+                _padding: 0
+            }
+        }
+    }
+}
+
+
+
+/// The histogram bins and the exposure carried from one frame to the next, must match tone_mapping.glsl.
+#[repr(C)]
+struct ExposureState {
+    bins: [u32; HISTOGRAM_BINS],
+    average_luminance: f32,
+    exposure: f32
+}
+
+
+/// The pipelines of the tone mapping pass and of the automatic exposure, the sampler they read the HDR image with
+/// and the buffer with the exposure. The tone mapping pipeline renders into the swapchain image, see `create_tone_mapping_pipeline`.
+pub(crate) unsafe fn create_tone_mapping_resources(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE)
+        .offset(0)
+        .size(size_of::<ToneMappingConstants>() as u32);
+
+    let set_layouts = &[*data.tone_mapping_descriptor_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+
+    let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.tone_mapping_pipeline_layout = PipelineLayout::new(device.create_pipeline_layout(&pipeline_layout_info, None)?, &data.deletion_queue);
+
+    data.histogram_pipeline = create_compute_pipeline(device, data, *data.tone_mapping_pipeline_layout, include_bytes!("shaders/histogram.spv"))?;
+    data.exposure_pipeline = create_compute_pipeline(device, data, *data.tone_mapping_pipeline_layout, include_bytes!("shaders/exposure.spv"))?;
+
+
+    let sampler_info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::NEAREST)
+        .min_filter(vk::Filter::NEAREST)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .min_lod(0.0)
+        .max_lod(0.0);
+
+    data.hdr_sampler = Sampler::new(device.create_sampler(&sampler_info, None)?, &data.deletion_queue);
+
+
+    // Zero is no average luminance yet, the first automatic exposure doesn't adapt from it.
+    let size = size_of::<ExposureState>() as u64;
+
+    data.exposure_buffer = create_buffer(
+        size,
+        vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        device,
+        instance,
+        data)?;
+
+    let command_buffer = begin_single_time_commands(device, data)?;
+    device.cmd_fill_buffer(command_buffer, *data.exposure_buffer, 0, size, 0);
+    end_single_time_commands(device, data, command_buffer)?;
+
+    info!("Created tone mapping resources!");
+
+    return Ok(());
+}
+
+
+/// The render pass and pipeline that tone map the HDR image into the swapchain image, they depend on its format.
+pub(crate) unsafe fn create_tone_mapping_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+
+    // Every pixel is written, what was in the image doesn't matter.
+    let color_attachment = vk::AttachmentDescription::builder()
+        .format(data.swapchain_image_format)
+        .samples(vk::SampleCountFlags::_1)
+        .load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+    let color_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(0)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+    let color_attachment_refs = &[color_attachment_ref];
+
+    let subpass = vk::SubpassDescription::builder()
+        .color_attachments(color_attachment_refs)
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+
+    let attachments = &[color_attachment];
+    let subpasses = &[subpass];
+
+    let render_pass_info = vk::RenderPassCreateInfo::builder()
+        .attachments(attachments)
+        .subpasses(subpasses);
+
+    data.tone_mapping_render_pass = RenderPass::new(device.create_render_pass(&render_pass_info, None)?, &data.deletion_queue);
+
+
+    data.tone_mapping_pipeline = create_fullscreen_pipeline(device, data, *data.tone_mapping_pipeline_layout, *data.tone_mapping_render_pass, include_bytes!("shaders/tone_mapping.spv"))?;
+
+    return Ok(());
+}
+
+
+/// Adds the passes that tone map `hdr` into `target`, `delta_time` seconds after the previous frame.
+/// With automatic exposure a histogram of `hdr` is counted first and averaged into the exposure, on the GPU.
+pub(crate) unsafe fn add_tone_mapping_passes(
+    graph: &mut RenderGraph,
+    data: &AppData,
+    image_index: usize,
+    tone_mapping: &ToneMapping,
+    delta_time: f32,
+    hdr: ImageId,
+    target: ImageId
+) {
+
+    let extent = data.swapchain_extent;
+    let constants = ToneMappingConstants::new(tone_mapping, extent, delta_time);
+
+    let exposure = graph.import_persistent_buffer("exposure", *data.exposure_buffer, Access::StorageWrite);
+
+    let push_constants = move |device: &Device, data: &AppData, command_buffer: vk::CommandBuffer| {
+        let constant_bytes = std::slice::from_raw_parts((&constants as *const ToneMappingConstants).cast::<u8>(), size_of::<ToneMappingConstants>());
+
+        device.cmd_push_constants(command_buffer, *data.tone_mapping_pipeline_layout, vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE, 0, constant_bytes);
+    };
+
+    if constants.automatic != 0 {
+        add_exposure_passes(graph, image_index, extent, hdr, exposure, push_constants);
+    }
+
+    graph.add_pass("tone mapping")
+        .render_pass(*data.tone_mapping_render_pass, extent, &[(target, Access::ColorAttachment)], &[])
+        .image(hdr, Access::Sampled)
+        .buffer(exposure, Access::StorageRead)
+        .record(move |device, data, images, command_buffer| {
+            let descriptor_set = data.tone_mapping_descriptor_sets[image_index];
+            update_tone_mapping_descriptor_set(device, data, descriptor_set, images.view(hdr));
+
+            cmd_set_viewport(device, command_buffer, extent);
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, *data.tone_mapping_pipeline);
+            device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, *data.tone_mapping_pipeline_layout, 0, &[descriptor_set], &[]);
+            push_constants(device, data, command_buffer);
+
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        });
+}
+
+
+/// Counts the log luminance of `hdr` into the histogram of the exposure buffer, then averages it into the exposure.
+/// Each pass binds a descriptor set of its own, as a set can't be updated once a command buffer bound it.
+unsafe fn add_exposure_passes(
+    graph: &mut RenderGraph,
+    image_index: usize,
+    extent: vk::Extent2D,
+    hdr: ImageId,
+    exposure: BufferId,
+    push_constants: impl Fn(&Device, &AppData, vk::CommandBuffer) + Copy + 'static
+) {
+
+    graph.add_pass("histogram")
+        .image(hdr, Access::Sampled)
+        .buffer(exposure, Access::StorageWrite)
+        .record(move |device, data, images, command_buffer| {
+            let descriptor_set = data.histogram_descriptor_sets[image_index];
+            update_tone_mapping_descriptor_set(device, data, descriptor_set, images.view(hdr));
+
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, *data.histogram_pipeline);
+            device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::COMPUTE, *data.tone_mapping_pipeline_layout, 0, &[descriptor_set], &[]);
+            push_constants(device, data, command_buffer);
+
+            let groups = |size: u32| size.div_ceil(HISTOGRAM_TILE);
+            device.cmd_dispatch(command_buffer, groups(extent.width), groups(extent.height), 1);
+        });
+
+    // The exposure shader only uses the buffer, which the histogram pass already pointed the set at.
+    graph.add_pass("exposure")
+        .buffer(exposure, Access::StorageWrite)
+        .record(move |device, data, _, command_buffer| {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, *data.exposure_pipeline);
+            device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::COMPUTE, *data.tone_mapping_pipeline_layout, 0, &[data.histogram_descriptor_sets[image_index]], &[]);
+            push_constants(device, data, command_buffer);
+
+            device.cmd_dispatch(command_buffer, 1, 1, 1);
+        });
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXTENT: vk::Extent2D = vk::Extent2D { width: 800, height: 600 };
+
+
+    #[test]
+    fn constants_match_the_shader_layout() {
+        assert_eq!(size_of::<ToneMappingConstants>(), 32);
+        assert_eq!(size_of::<ExposureState>(), HISTOGRAM_BINS * 4 + 8);
+    }
+
+    #[test]
+    fn manual_exposure_is_in_stops() {
+        let tone_mapping = ToneMapping { operator: ToneMapOperator::Filmic, exposure: Exposure::Manual { stops: -2.0 } };
+        let constants = ToneMappingConstants::new(&tone_mapping, EXTENT, 0.1);
+
+        assert_eq!((constants.operator, constants.automatic), (2, 0));
+        assert_eq!(constants.exposure, 0.25);
+    }
+
+    #[test]
+    fn automatic_exposure_adapts_over_time() {
+        let tone_mapping = ToneMapping { exposure: Exposure::automatic(), ..Default::default() };
+
+        let constants = ToneMappingConstants::new(&tone_mapping, EXTENT, 0.0);
+        assert_eq!((constants.automatic, constants.pixel_count), (1, 800 * 600));
+        assert_eq!((constants.min_log_luminance, constants.log_luminance_range), (-10.0, 22.0));
+        assert_eq!(constants.adaptation, 0.0);
+
+        let later = ToneMappingConstants::new(&tone_mapping, EXTENT, 0.5).adaptation;
+        let much_later = ToneMappingConstants::new(&tone_mapping, EXTENT, 10.0).adaptation;
+        assert!(later > 0.0 && later < much_later && much_later <= 1.0);
+    }
+
+    #[test]
+    fn operators_cycle() {
+        let operators: Vec<_> = std::iter::successors(Some(ToneMapOperator::Aces), |o| Some(o.next())).take(4).collect();
+
+        assert_eq!(operators, vec![ToneMapOperator::Aces, ToneMapOperator::Reinhard, ToneMapOperator::Filmic, ToneMapOperator::Aces]);
+    }
+}
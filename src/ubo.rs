@@ -13,7 +13,9 @@ use crate::{app::AppData, buffers::create_buffer};
 #[derive(Copy, Clone, Debug)]
 pub struct MVP_UBO {
     pub view: glm::Mat4,
-    pub proj: glm::Mat4
+    pub proj: glm::Mat4,
+    /// xyz = camera position, for the specular highlights of the fragment shader.
+    pub eye: glm::Vec4
 }
 
 
@@ -22,12 +24,11 @@ pub struct MVP_UBO {
 pub unsafe fn create_uniform_buffers(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
 
     data.uniform_buffers.clear();
-    data.uniform_buffer_memory.clear();
 
 
 
     for _ in 0..data.swapchain_images.len() {
-        let (buffer, buffer_memory) = create_buffer(
+        let buffer = create_buffer(
             size_of::<MVP_UBO>() as u64, 
             vk::BufferUsageFlags::UNIFORM_BUFFER, 
             vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT, 
@@ -36,8 +37,6 @@ pub unsafe fn create_uniform_buffers(instance: &Instance, device: &Device, data:
             data)?;
         
             data.uniform_buffers.push(buffer);
-            data.uniform_buffer_memory.push(buffer_memory);
-            device.bind_buffer_memory(buffer, buffer_memory, 0)?;
         
     }
 
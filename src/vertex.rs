@@ -3,14 +3,16 @@ use vulkanalia::prelude::v1_0::*;
 use nalgebra_glm as glm;
 use nalgebra_glm::{Vec3, Vec2};
 use std::mem::size_of;
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use log::*;
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
 use std::io::BufReader;
 use std::fs::File;
+use std::path::Path;
 
-use crate::{app::AppData, buffers::{create_buffer, fill_buffer, copy_buffer}};
+use crate::bounds::Bounds;
+use crate::{app::AppData, buffers::{create_buffer, fill_buffer, copy_buffer}, gltf_loader::{load_gltf, compute_smooth_normals, GltfScene}, material::{Material, DrawRange}, resources::Buffer};
 
 
 
@@ -52,25 +54,90 @@ impl Hash for Vertex {
 
 
 
-pub unsafe fn load_model(data: &mut AppData) -> Result<()> {
+/// Everything `load_model` reads from a model file, ready to be uploaded by `Renderer::load_mesh`.
+#[derive(Debug, Default)]
+pub struct ModelData {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    pub materials: Vec<Material>,
+    /// Ranges of `indices` per material, `DrawRange::material` indexes `materials`.
+    pub draw_ranges: Vec<DrawRange>,
+    /// The material of faces that the file doesn't give one, it has no texture.
+    pub default_material: Option<usize>,
+    /// Bounds of `vertices`, in the model's space.
+    pub bounds: Bounds,
+    pub gltf_scene: Option<GltfScene>
+}
+
+
+
+/// Loads an OBJ (with its MTL materials) or glTF model.
+pub fn load_model(path: &Path) -> Result<ModelData> {
 
-    let mut reader = BufReader::new(File::open("resources/viking_room.obj")?);
+    let mut data = match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+        Some("obj") => load_obj(path)?,
+        Some("gltf") | Some("glb") => load_gltf_model(path)?,
+        _ => return Err(anyhow!("Unsupported model format: {}", path.display()))
+    };
+
+    data.bounds = Bounds::from_vertices(&data.vertices);
+
+    return Ok(data);
+}
 
-    let (models, _) = tobj::load_obj_buf(
+
+fn load_obj(path: &Path) -> Result<ModelData> {
+
+    let mut reader = BufReader::new(File::open(path)?);
+    let directory = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+
+    let (models, materials) = tobj::load_obj_buf(
         &mut reader, 
         &tobj::LoadOptions { triangulate: true, ..Default::default() }, 
-        |_| Ok(Default::default()))?;
+        |p| tobj::load_mtl(directory.join(p)))?;
+
+    let materials = materials.unwrap_or_else(|e| {
+        warn!("Couldn't load the materials of {}, using the default material: {:?}", path.display(), e);
+        vec![]
+    });
+
+    let mut materials = materials.iter().map(|m| Material::from_mtl(m, &directory)).collect::<Vec<_>>();
+    let default_material = materials.len();
+    materials.push(Material::default());
+
+    let mut data = ModelData { materials, default_material: Some(default_material), ..Default::default() };
 
     let mut unique_verticies: HashMap<Vertex, u32> = HashMap::new();
+    let mut material_indicies: Vec<Vec<u32>> = vec![vec![]; data.materials.len()];
+
+    // Faces without texture coordinates get zero ones, faces without normals get them computed from the faces around their vertices.
+    let mut indicies_without_normals = vec![];
 
-    
     for model in &models {
+        let material = model.mesh.material_id.filter(|m| *m < default_material).unwrap_or(default_material);
+        let indicies = &mut material_indicies[material];
+
+        let has_tex_coords = !model.mesh.texcoord_indices.is_empty();
+        let has_normals = !model.mesh.normal_indices.is_empty();
+
         for (i, index) in model.mesh.indices.iter().enumerate() {
             let pos_offset = (3 * index) as usize;
-            let tex_coord_offset = (2 * model.mesh.texcoord_indices[i]) as usize;
-            let normal_offset = (3 * model.mesh.normal_indices[i]) as usize;
 
+            let tex_coord = match has_tex_coords {
+                true => {
+                    let offset = (2 * model.mesh.texcoord_indices[i]) as usize;
+                    glm::vec2(model.mesh.texcoords[offset], 1.0 - model.mesh.texcoords[offset + 1])
+                },
+                false => glm::Vec2::zeros()
+            };
 
+            let normal = match has_normals {
+                true => {
+                    let offset = (3 * model.mesh.normal_indices[i]) as usize;
+                    glm::vec3(model.mesh.normals[offset], model.mesh.normals[offset + 1], model.mesh.normals[offset + 2])
+                },
+                false => glm::Vec3::zeros()
+            };
 
             let vertex = Vertex {
                 pos: glm::vec3(
@@ -79,34 +146,135 @@ pub unsafe fn load_model(data: &mut AppData) -> Result<()> {
                     model.mesh.positions[pos_offset + 2]
                 ),
                 color: glm::vec3(1.0, 0.0, 0.0),
-                tex_coord: glm::vec2(
-                    model.mesh.texcoords[tex_coord_offset],
-                    1.0 - model.mesh.texcoords[tex_coord_offset + 1]
-                ),
-                normal: glm::vec3(
-                    model.mesh.normals[normal_offset],
-                    model.mesh.normals[normal_offset + 1],
-                    model.mesh.normals[normal_offset + 2]
-                )
+                tex_coord,
+                normal
             };
 
-            if let Some(index) = unique_verticies.get(&vertex) {
-                data.indicies.push(*index);
-            } else {
-                let index = data.vertices.len();
-                data.vertices.push(vertex);
-                unique_verticies.insert(vertex, index as u32);
-                data.indicies.push(index as u32);
+            let index = match unique_verticies.get(&vertex) {
+                Some(index) => *index,
+                None => {
+                    let index = data.vertices.len() as u32;
+                    data.vertices.push(vertex);
+                    unique_verticies.insert(vertex, index);
+                    index
+                }
+            };
+
+            indicies.push(index);
+
+            if !has_normals {
+                indicies_without_normals.push(index);
             }
+        }
+    }
+
+    compute_smooth_normals(&mut data.vertices, &indicies_without_normals);
 
+    // Sort the indices by material so that every material is a single draw.
+    for (material, indicies) in material_indicies.into_iter().enumerate() {
+        if indicies.is_empty() {
+            continue;
         }
+
+        data.draw_ranges.push(DrawRange { material, first_index: data.indices.len() as u32, index_count: indicies.len() as u32 });
+        data.indices.extend(indicies);
     }
 
-    /*println!("{:?}", data.vertices);
-    println!("{:?}", data.indicies);*/
+    info!("Loaded {} with {} materials in {} draw ranges", path.display(), data.materials.len(), data.draw_ranges.len());
 
 
-    return Ok(());
+    return Ok(data);
+}
+
+
+/// Loads a glTF scene and flattens all of its mesh instances into a single mesh, with a draw range per material.
+/// The materials' textures are embedded in `gltf_scene`, `Material::from_gltf` doesn't keep them.
+fn load_gltf_model(path: &Path) -> Result<ModelData> {
+
+    let scene = load_gltf(path)?;
+
+    // Primitives without a material use the glTF default material, which is plain white.
+    let mut materials = scene.materials.iter().map(Material::from_gltf).collect::<Vec<_>>();
+    let default_material = materials.len();
+    materials.push(Material::default());
+
+    let mut data = ModelData { materials, default_material: Some(default_material), ..Default::default() };
+
+    let mut material_indicies: Vec<Vec<u32>> = vec![vec![]; data.materials.len()];
+
+    // glTF is Y-up while the camera expects Z-up.
+    let y_up_to_z_up = glm::rotate(
+        &glm::identity(),
+        glm::radians(&glm::vec1(90.0))[0],
+        &glm::vec3(1.0, 0.0, 0.0));
+
+    scene.visit_nodes(|node, world| {
+        let mesh = match node.mesh {
+            Some(mesh) => &scene.meshes[mesh],
+            None => return
+        };
+
+        let transform = y_up_to_z_up * world;
+        let normal_transform = glm::mat4_to_mat3(&glm::transpose(&glm::inverse(&transform)));
+
+        for primitive in &mesh.primitives {
+            let offset = data.vertices.len() as u32;
+
+            data.vertices.extend(primitive.vertices.iter().map(|v| Vertex {
+                pos: (transform * v.pos.push(1.0)).xyz(),
+                normal: {
+                    // Zero normals are left as is, like `compute_smooth_normals` does, normalizing them gives NaN.
+                    let normal = normal_transform * v.normal;
+                    if normal != glm::Vec3::zeros() { glm::normalize(&normal) } else { normal }
+                },
+                ..*v
+            }));
+
+            material_indicies[primitive.material.unwrap_or(default_material)].extend(primitive.indices.iter().map(|i| i + offset));
+        }
+    });
+
+    for (material, indicies) in material_indicies.into_iter().enumerate() {
+        if indicies.is_empty() {
+            continue;
+        }
+
+        data.draw_ranges.push(DrawRange { material, first_index: data.indices.len() as u32, index_count: indicies.len() as u32 });
+        data.indices.extend(indicies);
+    }
+
+    debug!("Flattened glTF scene into {} vertices and {} indices in {} draw ranges", data.vertices.len(), data.indices.len(), data.draw_ranges.len());
+
+    data.gltf_scene = Some(scene);
+
+    return Ok(data);
+}
+
+
+/// Where an uploaded model is in the geometry buffers, drawn one `DrawRange` at a time.
+#[derive(Debug, Default)]
+pub(crate) struct Mesh {
+    /// Added to every index of the mesh.
+    pub vertex_offset: u32,
+    /// Of the mesh's first index in `GeometryBuffers::index_buffer`, `DrawRange::first_index` is relative to it.
+    pub first_index: u32,
+    /// `DrawRange::material` indexes `AppData::materials`.
+    pub draw_ranges: Vec<DrawRange>,
+    /// In the mesh's space, draws are culled against the camera's frustum with it.
+    pub bounds: Bounds
+}
+
+
+/// The vertices and indices of every mesh, so that all draws share the same bindings and can be issued from one indirect buffer.
+/// Both buffers are replaced by twice as large ones when a mesh doesn't fit anymore.
+#[derive(Debug, Default)]
+pub(crate) struct GeometryBuffers {
+    pub vertex_buffer: Buffer,
+    pub index_buffer: Buffer,
+    pub vertex_count: usize,
+    pub index_count: usize,
+    pub vertex_capacity: usize,
+    pub index_capacity: usize
 }
 
 
@@ -152,108 +320,133 @@ impl Vertex {
 }
 
 
-pub unsafe fn create_vertex_buffer(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
-
-    let size = (size_of::<Vertex>() * data.vertices.len()) as u64;
-
-
-    let (staging_buffer, staging_buffer_memory) = create_buffer(
-        size, 
-        vk::BufferUsageFlags::TRANSFER_SRC,
-        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-        device, 
-        instance, 
-        data)?;
-    
-
-    fill_buffer(
-        &staging_buffer, 
-        &staging_buffer_memory, 
-        &size, 
-        data.vertices.as_ptr(), 
-        data.vertices.len(), 
-        device)?;
-    
-
+/// Appends the vertices and indices of a mesh to the shared geometry buffers and returns its vertex offset and first index.
+pub(crate) unsafe fn upload_geometry(instance: &Instance, device: &Device, data: &mut AppData, vertices: &[Vertex], indices: &[u32]) -> Result<(u32, u32)> {
 
-    let (buffer, buffer_memory) = create_buffer(
-        size, 
-        vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER, 
-        vk::MemoryPropertyFlags::DEVICE_LOCAL,
-        device, 
-        instance, 
-        data)?;
-    
-    device.bind_buffer_memory(buffer, buffer_memory, 0)?;
+    let (vertex_offset, first_index) = (data.geometry.vertex_count, data.geometry.index_count);
 
+    if vertex_offset + vertices.len() > data.geometry.vertex_capacity {
+        let capacity = (vertex_offset + vertices.len()).max(data.geometry.vertex_capacity * 2);
+        let old = *data.geometry.vertex_buffer;
 
+        data.geometry.vertex_buffer = grow_buffer(instance, device, data, old, size_of::<Vertex>() * vertex_offset, size_of::<Vertex>() * capacity, vk::BufferUsageFlags::VERTEX_BUFFER)?;
+        data.geometry.vertex_capacity = capacity;
+    }
 
-    copy_buffer(device, data, staging_buffer, buffer, size)?;
+    if first_index + indices.len() > data.geometry.index_capacity {
+        let capacity = (first_index + indices.len()).max(data.geometry.index_capacity * 2);
+        let old = *data.geometry.index_buffer;
 
+        data.geometry.index_buffer = grow_buffer(instance, device, data, old, size_of::<u32>() * first_index, size_of::<u32>() * capacity, vk::BufferUsageFlags::INDEX_BUFFER)?;
+        data.geometry.index_capacity = capacity;
+    }
 
+    let (vertex_buffer, index_buffer) = (*data.geometry.vertex_buffer, *data.geometry.index_buffer);
 
-    device.destroy_buffer(staging_buffer, None);
-    device.free_memory(staging_buffer_memory, None);
+    upload_to_buffer(instance, device, data, vertex_buffer, size_of::<Vertex>() * vertex_offset, vertices)?;
+    upload_to_buffer(instance, device, data, index_buffer, size_of::<u32>() * first_index, indices)?;
 
+    data.geometry.vertex_count += vertices.len();
+    data.geometry.index_count += indices.len();
 
+    debug!("Geometry buffers hold {} vertices and {} indices", data.geometry.vertex_count, data.geometry.index_count);
 
-    data.vertex_buffer = buffer;
-    data.vertex_buffer_memory = buffer_memory;
+    return Ok((vertex_offset as u32, first_index as u32));
+}
 
 
+/// Creates a device local buffer of `size` bytes with the first `used` bytes of `old`.
+/// `old` can be dropped right away, its copy is done when this returns.
+unsafe fn grow_buffer(instance: &Instance, device: &Device, data: &mut AppData, old: vk::Buffer, used: usize, size: usize, usage: vk::BufferUsageFlags) -> Result<Buffer> {
 
+    let buffer = create_buffer(
+        size as u64,
+        vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST | usage,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        device,
+        instance,
+        data)?;
 
-    info!("Created vertex buffer!");
+    if used > 0 {
+        copy_buffer(device, data, old, *buffer, 0, used as u64)?;
+    }
 
-    return Ok(());
+    info!("Grew a geometry buffer to {} bytes", size);
 
+    return Ok(buffer);
 }
 
 
-pub unsafe fn create_index_buffer(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+/// Copies `items` into a device local buffer at `offset` bytes, through a staging buffer.
+unsafe fn upload_to_buffer<T>(instance: &Instance, device: &Device, data: &mut AppData, buffer: vk::Buffer, offset: usize, items: &[T]) -> Result<()> {
 
-    let size = (size_of::<u32>() * data.indicies.len()) as u64;
+    let size = std::mem::size_of_val(items) as u64;
 
-    let (staging_buffer, staging_buffer_memory) = create_buffer(
+    let staging_buffer = create_buffer(
         size, 
-        vk::BufferUsageFlags::TRANSFER_SRC, 
+        vk::BufferUsageFlags::TRANSFER_SRC,
         vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
         device, 
         instance, 
         data)?;
-    
-    fill_buffer(&staging_buffer, &staging_buffer_memory, 
-        &size, 
-        data.indicies.as_ptr(), 
-        data.indicies.len(), 
-        device)?;
-    
-
-    let (buffer, buffer_memory) = create_buffer(
-        size, 
-        vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER, 
-        vk::MemoryPropertyFlags::DEVICE_LOCAL,
-        device, 
-        instance, 
-        data)?;
-    
 
-    device.bind_buffer_memory(buffer, buffer_memory, 0)?;
-    
-    copy_buffer(
-        device, 
-        data, 
-        staging_buffer, 
-        buffer, 
-        size)?;
-    
+    fill_buffer(&staging_buffer.allocation, items.as_ptr(), items.len())?;
+
+    copy_buffer(device, data, *staging_buffer, buffer, offset as u64, size)?;
 
-    device.destroy_buffer(staging_buffer, None);
-    device.free_memory(staging_buffer_memory, None);
+    return Ok(());
+}
 
 
-    data.index_buffer = buffer;
-    data.index_buffer_memory = buffer_memory;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    return Ok(());
-}
\ No newline at end of file
+    #[test]
+    fn obj_faces_without_material_use_the_default_material() {
+        let model = load_model(Path::new("resources/plane.obj")).unwrap();
+
+        assert_eq!(model.vertices.len(), 4);
+        assert_eq!(model.indices.len(), 6);
+        assert_eq!(model.default_material, Some(model.materials.len() - 1));
+        assert_eq!(model.draw_ranges.len(), 1);
+        assert_eq!(model.draw_ranges[0].material, model.materials.len() - 1);
+        assert_eq!(model.draw_ranges[0].index_count, 6);
+        assert_eq!(model.bounds, Bounds::from_vertices(&model.vertices));
+        assert!(model.bounds.sphere.radius > 0.0);
+    }
+
+    #[test]
+    fn obj_files_without_tex_coords_or_normals_are_loaded() {
+        let path = std::env::temp_dir().join(format!("rustbbbb_positions_{}.obj", std::process::id()));
+        std::fs::write(&path, "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let model = load_model(&path);
+        std::fs::remove_file(&path).unwrap();
+        let model = model.unwrap();
+
+        assert_eq!(model.vertices.len(), 3);
+        assert!(model.vertices.iter().all(|v| v.tex_coord == glm::Vec2::zeros()));
+        assert!(model.vertices.iter().all(|v| v.normal == glm::vec3(0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn flattened_gltf_scenes_keep_their_materials() {
+        let path = std::env::temp_dir().join(format!("rustbbbb_model_{}.gltf", std::process::id()));
+        std::fs::write(&path, crate::gltf_loader::tests::TRIANGLE_GLTF).unwrap();
+        let model = load_model(&path);
+        std::fs::remove_file(&path).unwrap();
+        let model = model.unwrap();
+
+        assert_eq!(model.materials.len(), 2);
+        assert_eq!(model.materials[0].name, "red");
+        assert_eq!(model.default_material, Some(1));
+        assert_eq!(model.draw_ranges.len(), 1);
+        assert_eq!(model.draw_ranges[0].material, 0);
+        assert_eq!(model.draw_ranges[0].index_count, 3);
+    }
+
+    #[test]
+    fn unsupported_formats_are_rejected() {
+        assert!(load_model(Path::new("resources/texture.png")).is_err());
+    }
+}